@@ -6,11 +6,13 @@ use crossterm::style::{Color, SetForegroundColor};
 use indicatif::{ProgressBar, ProgressStyle};
 use structopt::StructOpt;
 
-use crate::installation::InstallationContext;
+use crate::installation::{Diagnostic, InstallationContext, LinkExtension};
 use crate::lockfile::{LockPackage, Lockfile};
-use crate::manifest::Manifest;
+use crate::manifest::{LuauVersion, Manifest};
 use crate::package_id::PackageId;
-use crate::package_source::{PackageSource, PackageSourceMap, Registry, TestRegistry};
+use crate::package_source::{
+    GitPackageSource, PackageSource, PackageSourceMap, Registry, TestRegistry,
+};
 use crate::resolution::resolve;
 
 use super::GlobalOptions;
@@ -21,6 +23,133 @@ pub struct InstallSubcommand {
     /// Path to the project to install dependencies for.
     #[structopt(long = "project-path", default_value = ".")]
     pub project_path: PathBuf,
+
+    #[structopt(flatten)]
+    pub options: InstallOptions,
+}
+
+/// Options that change how `install` and `update` install packages.
+#[derive(Debug, Default, StructOpt)]
+pub struct InstallOptions {
+    /// Fail on anything that would otherwise only be a warning.
+    #[structopt(long = "strict")]
+    pub strict: bool,
+
+    /// Features of this package to enable, separated by commas.
+    #[structopt(long = "features", use_delimiter = true)]
+    pub features: Vec<String>,
+
+    /// The extension to write link files with, either `lua` or `luau`.
+    #[structopt(long = "link-extension", parse(try_from_str = parse_link_extension))]
+    pub link_extension: Option<LinkExtension>,
+
+    /// Warn about packages that need a newer Luau release than this one,
+    /// like `0.640`.
+    #[structopt(long = "luau-target")]
+    pub luau_target: Option<LuauVersion>,
+
+    /// Warn about package directories this Rojo project file doesn't map.
+    #[structopt(long = "rojo-project")]
+    pub rojo_project: Option<PathBuf>,
+
+    /// Write a `.gitignore` into each package directory.
+    #[structopt(long = "gitignore")]
+    pub gitignore: bool,
+
+    /// Check that each downloaded package's manifest names the package that
+    /// was asked for.
+    #[structopt(long = "validate-manifests")]
+    pub validate_manifests: bool,
+
+    /// Save each downloaded archive into `packages/.archives`.
+    #[structopt(long = "keep-archives")]
+    pub keep_archives: bool,
+
+    /// Try downloading a package again this many times when it fails.
+    #[structopt(long = "download-retries", default_value = "0")]
+    pub download_retries: u32,
+
+    /// Run the whole install again this many times when packages fail to
+    /// download.
+    #[structopt(long = "install-retries", default_value = "0")]
+    pub install_retries: u32,
+
+    /// Give up on a download once it's taken this many seconds.
+    #[structopt(long = "download-timeout")]
+    pub download_timeout: Option<u64>,
+
+    /// Download at most this many packages at once.
+    #[structopt(long = "max-downloads")]
+    pub max_downloads: Option<usize>,
+
+    /// Stop as soon as any package fails to download.
+    #[structopt(long = "fail-fast")]
+    pub fail_fast: bool,
+}
+
+impl InstallOptions {
+    /// Apply these options to the given context.
+    pub fn apply(&self, context: InstallationContext) -> InstallationContext {
+        let mut context = context
+            .with_strict(self.strict)
+            .with_features(self.features.iter().cloned())
+            .with_gitignore(self.gitignore)
+            .with_manifest_validation(self.validate_manifests)
+            .with_kept_archives(self.keep_archives)
+            .with_download_retries(self.download_retries)
+            .with_install_retries(self.install_retries)
+            .with_fail_fast(self.fail_fast);
+
+        if let Some(link_extension) = self.link_extension {
+            context = context.with_link_extension(link_extension);
+        }
+
+        if let Some(luau_target) = self.luau_target {
+            context = context.with_luau_target(luau_target);
+        }
+
+        if let Some(rojo_project) = &self.rojo_project {
+            context = context.with_rojo_project(rojo_project);
+        }
+
+        if let Some(timeout) = self.download_timeout {
+            context = context.with_download_timeout(Duration::from_secs(timeout));
+        }
+
+        if let Some(max_downloads) = self.max_downloads {
+            context = context.with_max_downloads(max_downloads);
+        }
+
+        context
+    }
+}
+
+fn parse_link_extension(value: &str) -> anyhow::Result<LinkExtension> {
+    match value {
+        "lua" => Ok(LinkExtension::Lua),
+        "luau" => Ok(LinkExtension::Luau),
+        _ => anyhow::bail!("invalid link extension {:?}, expected lua or luau", value),
+    }
+}
+
+/// Print the warnings an install returned once it's done, since the ones
+/// logged while it ran can scroll by among its progress.
+pub(crate) fn print_diagnostics(diagnostics: &[Diagnostic]) {
+    if diagnostics.is_empty() {
+        return;
+    }
+
+    println!(
+        "{}   Finished {}with {} warning{}",
+        SetForegroundColor(Color::DarkYellow),
+        SetForegroundColor(Color::Reset),
+        diagnostics.len(),
+        if diagnostics.len() == 1 { "" } else { "s" }
+    );
+
+    for diagnostic in diagnostics {
+        println!("           - {}", diagnostic);
+    }
 }
 
 impl InstallSubcommand {
@@ -35,12 +164,15 @@ impl InstallSubcommand {
                 &manifest.package.registry,
             )))
         } else {
-            Box::new(PackageSource::Registry(Registry::from_registry_spec(
+            Box::new(PackageSource::Registry(Registry::with_client_config(
                 &manifest.package.registry,
+                &global.client_config(),
             )?))
         };
 
-        let mut package_sources = PackageSourceMap::new(default_registry);
+        let mut package_sources =
+            PackageSourceMap::new(default_registry).with_client_config(global.client_config());
+        package_sources.add_git_sources(&manifest, &GitPackageSource::default_checkouts_dir()?);
         package_sources.add_fallbacks()?;
 
         let mut try_to_use = BTreeSet::new();
@@ -91,19 +223,24 @@ impl InstallSubcommand {
             SetForegroundColor(Color::Reset)
         ));
         let root_package_id = PackageId::new(manifest.package.name, manifest.package.version);
-        let installation = InstallationContext::new(
-            &self.project_path
-        );
-
-        installation.clean()?;
-        progress.println(format!(
-            "{}    Cleaned {}package destination",
-            SetForegroundColor(Color::DarkGreen),
-            SetForegroundColor(Color::Reset)
-        ));
+        let installation = self
+            .options
+            .apply(InstallationContext::new(&self.project_path));
+
+        // Cleaning would undo an install that's already up to date, which
+        // `install` skips.
+        if !installation.is_installed(&resolved, &root_package_id)? {
+            installation.clean()?;
+            progress.println(format!(
+                "{}    Cleaned {}package destination",
+                SetForegroundColor(Color::DarkGreen),
+                SetForegroundColor(Color::Reset)
+            ));
+        }
         progress.finish_and_clear();
 
-        installation.install(package_sources, root_package_id, resolved)?;
+        let diagnostics = installation.install(package_sources, root_package_id, resolved)?;
+        print_diagnostics(&diagnostics);
 
         Ok(())
     }
@@ -9,7 +9,7 @@ mod search;
 mod update;
 
 pub use init::InitSubcommand;
-pub use install::InstallSubcommand;
+pub use install::{InstallOptions, InstallSubcommand};
 pub use login::LoginSubcommand;
 pub use logout::LogoutSubcommand;
 pub use manifest_to_json::ManifestToJsonSubcommand;
@@ -18,7 +18,12 @@ pub use publish::PublishSubcommand;
 pub use search::SearchSubcommand;
 pub use update::{PackageSpec, UpdateSubcommand};
 
+use std::time::Duration;
+
 use structopt::StructOpt;
+use url::Url;
+
+use crate::package_source::ClientConfig;
 
 #[derive(Debug, StructOpt)]
 #[structopt(about = env!("CARGO_PKG_DESCRIPTION"))]
@@ -65,6 +70,32 @@ pub struct GlobalOptions {
     /// Specify if a specific auth token should be provided. Usable only by tests.
     #[structopt(skip)]
     pub check_token: Option<String>,
+
+    /// Send registry requests through this HTTP(S) proxy. Defaults to the
+    /// `HTTP_PROXY` and `HTTPS_PROXY` environment variables.
+    #[structopt(global = true, long = "proxy")]
+    pub proxy: Option<Url>,
+
+    /// Don't fetch registry indexes that were fetched within this many
+    /// seconds.
+    #[structopt(global = true, long = "index-ttl")]
+    pub index_ttl: Option<u64>,
+
+    /// Fetch registry indexes even if they were fetched within the index TTL.
+    #[structopt(global = true, long = "refresh-index")]
+    pub refresh_index: bool,
+}
+
+impl GlobalOptions {
+    /// The `ClientConfig` registries should be created with.
+    pub fn client_config(&self) -> ClientConfig {
+        ClientConfig {
+            proxy: self.proxy.clone(),
+            index_ttl: self.index_ttl.map(Duration::from_secs),
+            refresh_index: self.refresh_index,
+            ..ClientConfig::default()
+        }
+    }
 }
 
 impl Default for GlobalOptions {
@@ -74,6 +105,9 @@ impl Default for GlobalOptions {
             test_registry: false,
             use_temp_index: false,
             check_token: None,
+            proxy: None,
+            index_ttl: None,
+            refresh_index: false,
         }
     }
 }
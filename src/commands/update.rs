@@ -9,8 +9,12 @@ use crate::manifest::Manifest;
 use crate::package_id::PackageId;
 use crate::package_name::PackageName;
 use crate::package_req::PackageReq;
-use crate::package_source::{PackageSource, PackageSourceMap, Registry, TestRegistry};
+use crate::package_source::{
+    GitPackageSource, PackageSource, PackageSourceMap, Registry, TestRegistry,
+};
 use crate::{resolution, GlobalOptions};
+
+use super::install::{print_diagnostics, InstallOptions};
 use crossterm::style::{Attribute, Color, SetAttribute, SetForegroundColor};
 use indicatif::{ProgressBar, ProgressStyle};
 use structopt::StructOpt;
@@ -22,6 +26,9 @@ pub struct UpdateSubcommand {
     #[structopt(long = "project-path", default_value = ".")]
     pub project_path: PathBuf,
 
+    #[structopt(flatten)]
+    pub options: InstallOptions,
+
     /// An optional list of dependencies to update.
     /// They must be valid package name with an optional version requirement.
     pub package_specs: Vec<PackageSpec>,
@@ -41,12 +48,15 @@ impl UpdateSubcommand {
                 &manifest.package.registry,
             )))
         } else {
-            Box::new(PackageSource::Registry(Registry::from_registry_spec(
+            Box::new(PackageSource::Registry(Registry::with_client_config(
                 &manifest.package.registry,
+                &global.client_config(),
             )?))
         };
 
-        let mut package_sources = PackageSourceMap::new(default_registry);
+        let mut package_sources =
+            PackageSourceMap::new(default_registry).with_client_config(global.client_config());
+        package_sources.add_git_sources(&manifest, &GitPackageSource::default_checkouts_dir()?);
         package_sources.add_fallbacks()?;
 
         // If the user didn't specify any targets, then update all of the packages.
@@ -111,9 +121,9 @@ impl UpdateSubcommand {
         ));
 
         let root_package_id = manifest.package_id();
-        let installation_context = InstallationContext::new(
-            &self.project_path
-        );
+        let installation_context = self
+            .options
+            .apply(InstallationContext::new(&self.project_path));
 
         progress.set_message(format!(
             "{}  Cleaning {}package destination...",
@@ -121,13 +131,17 @@ impl UpdateSubcommand {
             SetForegroundColor(Color::Reset)
         ));
 
-        installation_context.clean()?;
+        // Cleaning would undo an install that's already up to date, which
+        // `install` skips.
+        if !installation_context.is_installed(&resolved_graph, &root_package_id)? {
+            installation_context.clean()?;
 
-        progress.println(format!(
-            "{}    Cleaned {}package destination",
-            SetForegroundColor(Color::DarkGreen),
-            SetForegroundColor(Color::Reset)
-        ));
+            progress.println(format!(
+                "{}    Cleaned {}package destination",
+                SetForegroundColor(Color::DarkGreen),
+                SetForegroundColor(Color::Reset)
+            ));
+        }
 
         progress.finish_with_message(format!(
             "{}{}  Starting installation {}",
@@ -136,7 +150,9 @@ impl UpdateSubcommand {
             SetForegroundColor(Color::Reset)
         ));
 
-        installation_context.install(package_sources, root_package_id, resolved_graph)?;
+        let diagnostics =
+            installation_context.install(package_sources, root_package_id, resolved_graph)?;
+        print_diagnostics(&diagnostics);
 
         Ok(())
     }
@@ -10,7 +10,8 @@ use std::path::Path;
 use anyhow::{bail, format_err, Context};
 use git2::build::RepoBuilder;
 use git2::{
-    Cred, CredentialType, FetchOptions, RemoteCallbacks, Repository, RepositoryInitOptions,
+    Cred, CredentialType, FetchOptions, ProxyOptions, RemoteCallbacks, Repository,
+    RepositoryInitOptions,
 };
 use url::Url;
 use walkdir::WalkDir;
@@ -49,6 +50,20 @@ fn make_credentials_callback(
     }
 }
 
+/// Sends requests through `proxy` if there is one. Otherwise, like the HTTP
+/// client, uses whatever proxy git's configuration or the `HTTP_PROXY` family
+/// of environment variables name.
+fn proxy_options(proxy: Option<&Url>) -> ProxyOptions<'_> {
+    let mut options = ProxyOptions::new();
+
+    match proxy {
+        Some(proxy) => options.url(proxy.as_str()),
+        None => options.auto(),
+    };
+
+    options
+}
+
 /// We want to use a mock repo in tests but we don't want have to manually initialise it
 /// or manage commiting new files. This method will ensure the test repo is a valid git repo
 /// with all files commited to the main branch. Typically test-registries/primary-registry/index.
@@ -94,6 +109,7 @@ pub fn open_or_clone(
     access_token: Option<String>,
     url: &Url,
     path: &Path,
+    proxy: Option<&Url>,
 ) -> anyhow::Result<Repository> {
     let repo = match Repository::open(path) {
         Ok(repo) => repo,
@@ -108,7 +124,7 @@ pub fn open_or_clone(
             }
 
             fs_err::create_dir_all(path)?;
-            clone(access_token, url, path)
+            clone(access_token, url, path, proxy)
                 .with_context(|| format!("Error cloning Git repository {}", url))?
         }
     };
@@ -116,7 +132,12 @@ pub fn open_or_clone(
     Ok(repo)
 }
 
-pub fn clone(access_token: Option<String>, url: &Url, into: &Path) -> anyhow::Result<Repository> {
+pub fn clone(
+    access_token: Option<String>,
+    url: &Url,
+    into: &Path,
+    proxy: Option<&Url>,
+) -> anyhow::Result<Repository> {
     let git_config = git2::Config::open_default()?;
 
     let mut callbacks = RemoteCallbacks::new();
@@ -124,6 +145,7 @@ pub fn clone(access_token: Option<String>, url: &Url, into: &Path) -> anyhow::Re
 
     let mut fetch_options = FetchOptions::new();
     fetch_options.remote_callbacks(callbacks);
+    fetch_options.proxy_options(proxy_options(proxy));
 
     let mut builder = RepoBuilder::new();
     builder.fetch_options(fetch_options);
@@ -190,7 +212,11 @@ pub fn commit_and_push(
     ref_status
 }
 
-pub fn update_index(access_token: Option<String>, repository: &Repository) -> anyhow::Result<()> {
+pub fn update_index(
+    access_token: Option<String>,
+    repository: &Repository,
+    proxy: Option<&Url>,
+) -> anyhow::Result<()> {
     let git_config = git2::Config::open_default()?;
 
     let mut callbacks = RemoteCallbacks::new();
@@ -198,6 +224,7 @@ pub fn update_index(access_token: Option<String>, repository: &Repository) -> an
 
     let mut fetch_options = FetchOptions::new();
     fetch_options.remote_callbacks(callbacks);
+    fetch_options.proxy_options(proxy_options(proxy));
 
     repository
         .find_remote("origin")?
@@ -1,24 +1,352 @@
+mod cache;
+mod diagnostic;
+mod duplicates;
+mod extraction;
+mod filesystem;
+mod links;
+mod lock;
+mod policy;
+mod progress;
+mod relocate;
+mod rojo;
+mod scheduler;
+mod sourcemap;
+#[cfg(test)]
+mod test_support;
+mod throttle;
+mod uninstall;
+mod verification;
+mod workspace;
+
+pub use self::cache::{CachePrunePolicy, PackageCache, PrunedCache};
+pub use self::diagnostic::{Diagnostic, DiagnosticKind};
+pub use self::duplicates::{DuplicateFile, DuplicateReport};
+pub use self::extraction::ExtractionPool;
+#[cfg(any(test, feature = "test-util"))]
+pub use self::filesystem::MemoryFilesystem;
+pub use self::filesystem::{
+    DelegatingFilesystem, DirEntry, FileIdentity, FileLock, Filesystem, FixedTimeFilesystem,
+    PrefixedFilesystem, RealFilesystem,
+};
+use self::links::{names_dir, rojo_instance_path};
+pub use self::lock::InstallLock;
+pub use self::policy::InstallPolicy;
+pub use self::progress::{
+    IndicatifReporter, InstallPhase, InstallStats, ProgressEvent, ProgressReporter, ProgressTheme,
+};
+pub use self::scheduler::DownloadScheduler;
+use self::throttle::Throttle;
+pub use self::verification::BrokenLink;
+
 use std::{
+    collections::{btree_map::Entry, BTreeMap, BTreeSet, HashMap, HashSet},
     fmt::Display,
-    io::{self, Cursor},
-    path::{Path, PathBuf},
-    time::Duration,
+    io::{self, Read},
+    path::{Component, Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
 };
 
-use crossterm::style::{Color, SetForegroundColor};
-use fs_err as fs;
-use indicatif::{ProgressBar, ProgressStyle};
-use indoc::formatdoc;
-use zip::ZipArchive;
+use anyhow::{bail, Context};
+use semver::{Identifier, VersionReq};
+use serde::Serialize;
+use walkdir::WalkDir;
 
 use crate::{
-    manifest::Realm,
-    package_contents::PackageContents,
+    manifest::{LuauVersion, Manifest, Realm, MANIFEST_FILE_NAME},
+    package_contents::{detect_installed_entry_point, NotStreamable, PackageContents},
     package_id::PackageId,
-    package_source::{PackageSourceMap, PackageSourceProvider},
+    package_name::PackageName,
+    package_req::PackageReq,
+    package_source::{
+        InvalidArchive, PackageNotFound, PackageSource, PackageSourceId, PackageSourceMap,
+        PackageSourceProvider,
+    },
     resolution::Resolve,
 };
 
+/// Rewrites the path passed to `require` in generated link files.
+pub type RequireTransform = Arc<dyn Fn(&str) -> String + Send + Sync>;
+
+/// Edits the manifest of a package that was just unpacked, given the package,
+/// the path to its `wally.toml` and the filesystem the install writes
+/// through, which is the only way the manifest can be reached. Files next to
+/// the manifest, like `default.project.json`, may be edited too.
+pub type ManifestRewrite =
+    Arc<dyn Fn(&PackageId, &Path, &dyn Filesystem) -> anyhow::Result<()> + Send + Sync>;
+
+/// Builds the runtime an install downloads packages on, given how many worker
+/// threads it should have.
+pub type RuntimeFactory = Arc<dyn Fn(usize) -> io::Result<tokio::runtime::Runtime> + Send + Sync>;
+
+/// How long to wait before the first retry of a failed download.
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// The longest to wait between retries, however many there have been.
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(30);
+
+/// The error an install fails with when packages couldn't be downloaded and
+/// unpacked. These failures are often down to the network and go away on
+/// their own, so they're the ones `with_install_retries` retries.
+#[derive(Debug)]
+pub struct PackagesFailed {
+    /// The packages that failed, in order.
+    pub packages: Vec<PackageId>,
+
+    message: String,
+}
+
+impl Display for PackagesFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for PackagesFailed {}
+
+/// A package found in one of a project's indexes.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InstalledPackage {
+    pub id: PackageId,
+
+    /// The realm whose index the package is in. Packages in an index shared
+    /// by several realms are attributed to the first of them.
+    pub realm: Realm,
+
+    /// The package's directory in the index.
+    pub path: PathBuf,
+}
+
+/// How package files get into the index.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub enum InstallStrategy {
+    /// Unpack a fresh copy of every package.
+    #[default]
+    Copy,
+
+    /// Hard link files from the unpacked packages kept in the cache, which
+    /// saves writing them again for every project. Packages are copied
+    /// instead when there's no cache or hard links can't be made, such as
+    /// when the cache is on another device.
+    Hardlink,
+}
+
+/// How an install decides that a package already in the index doesn't need
+/// to be unpacked again, such as when another project in a workspace put it
+/// there.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub enum PresenceCheck {
+    /// Trust any directory named for the package's version.
+    #[default]
+    DirName,
+
+    /// Trust directories with a checksum recorded for the package when it was
+    /// unpacked, which rules out unpacks that were interrupted or came from
+    /// something other than wally. Checksums are only recorded by installs
+    /// using this check or `ContentHash`.
+    Checksum,
+
+    /// Like `Checksum`, but also hash the directory's files and compare them
+    /// against the recorded checksum, which catches files that were edited
+    /// since. This reads every file of every package.
+    ContentHash,
+}
+
+/// The file extension that link files are written with.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub enum LinkExtension {
+    #[default]
+    Lua,
+    Luau,
+}
+
+impl LinkExtension {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LinkExtension::Lua => "lua",
+            LinkExtension::Luau => "luau",
+        }
+    }
+
+    fn other(self) -> Self {
+        match self {
+            LinkExtension::Lua => LinkExtension::Luau,
+            LinkExtension::Luau => LinkExtension::Lua,
+        }
+    }
+}
+
+/// How link files locate the packages they point to.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub enum RequirePathStyle {
+    /// Require by a path relative to the link file, like
+    /// `require("../../biff_minimal@0.1.0")`.
+    #[default]
+    Relative,
+
+    /// Require by the instance a package is synced to by Rojo, like
+    /// `require(game.ReplicatedStorage.Packages._index["biff_minimal@0.1.0"])`.
+    /// Each field is the DataModel path of the directory for that realm.
+    AbsoluteRojo {
+        shared: String,
+        server: String,
+        dev: String,
+    },
+}
+
+/// What to do when a link file would replace a file that isn't a link, like
+/// one the user created in a package directory.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub enum LinkConflictPolicy {
+    /// Replace the file with the link.
+    #[default]
+    Overwrite,
+
+    /// Fail the install.
+    Error,
+
+    /// Leave the file alone and don't write the link.
+    Skip,
+}
+
+/// Which link files are written into the root package's package directories.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub enum RootLinkLayout {
+    /// A link file for each dependency.
+    #[default]
+    PerDependency,
+
+    /// A single `index` module returning a table of every dependency, keyed
+    /// by alias, for bundlers that want one module to load.
+    Aggregated,
+
+    /// Both a link file for each dependency and the `index` module.
+    Both,
+}
+
+/// What an install does when a source no longer has a resolved package,
+/// usually because its version was yanked.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub enum FallbackPolicy {
+    /// Fail the install.
+    #[default]
+    Error,
+
+    /// Install the nearest version that's compatible with the missing one
+    /// instead, with a warning. Newer versions are preferred over older
+    /// ones. The replacement is linked to the dependencies resolved for the
+    /// missing version, rather than its own.
+    NearestCompatible,
+}
+
+/// Where a link file lives, which decides how it reaches the index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkOrigin {
+    /// A link in one of the root package's package directories.
+    Root,
+
+    /// A link in the `packages` directory of another package in the same
+    /// index.
+    Sibling,
+}
+
+/// The options of an install that change what it writes or whether it
+/// succeeds. An install is skipped when these and the resolve are the same as
+/// the last one's, so every such option belongs here rather than on
+/// `InstallationContext` itself.
+#[derive(Clone, Serialize)]
+struct InstallOptions {
+    extraction: ExtractionOptions,
+    linking: LinkOptions,
+    production: bool,
+    strict: bool,
+    on_yanked: FallbackPolicy,
+    frozen: bool,
+    rojo_project: Option<PathBuf>,
+    policy: Option<InstallPolicy>,
+    luau_target: Option<LuauVersion>,
+    flat: bool,
+    workspace: bool,
+    sortable_dir_names: bool,
+    features: BTreeSet<String>,
+}
+
+/// The options for how packages are put into the index. Skipped fields can't
+/// be compared between installs; an install with a manifest rewrite is never
+/// skipped, and the rest don't change what's written.
+#[derive(Clone, Default, Serialize)]
+struct ExtractionOptions {
+    strategy: InstallStrategy,
+    junctions: bool,
+    presence_check: PresenceCheck,
+    validate_manifests: bool,
+    keep_archives: bool,
+    incremental_upgrades: bool,
+    init_shims: bool,
+    gitignore: bool,
+    mtime: Option<SystemTime>,
+    #[serde(skip)]
+    manifest_rewrite: Option<ManifestRewrite>,
+    #[serde(skip)]
+    staging_dir: Option<PathBuf>,
+    #[serde(skip)]
+    limit: Option<Arc<Throttle>>,
+    #[serde(skip)]
+    pool: Option<ExtractionPool>,
+}
+
+/// The options for how link files are written. An install with a require
+/// transform is never skipped, since the transform can't be compared between
+/// installs.
+#[derive(Clone, Serialize)]
+struct LinkOptions {
+    require_function: String,
+    require_path_style: RequirePathStyle,
+    link_extension: LinkExtension,
+    link_conflict_policy: LinkConflictPolicy,
+    root_link_layout: RootLinkLayout,
+    submodule_links: bool,
+    links_only: bool,
+    normalize_aliases: bool,
+    package_links_dir: String,
+    #[serde(skip)]
+    require_transform: Option<RequireTransform>,
+}
+
+impl Default for LinkOptions {
+    fn default() -> Self {
+        Self {
+            require_function: "require".to_owned(),
+            require_path_style: RequirePathStyle::default(),
+            link_extension: LinkExtension::default(),
+            link_conflict_policy: LinkConflictPolicy::default(),
+            root_link_layout: RootLinkLayout::default(),
+            submodule_links: false,
+            links_only: false,
+            normalize_aliases: false,
+            package_links_dir: "packages".to_owned(),
+            require_transform: None,
+        }
+    }
+}
+
+/// The options for how packages are downloaded, which never change what an
+/// install writes, so they aren't part of `InstallOptions`.
+#[derive(Clone, Default)]
+struct NetworkOptions {
+    fail_fast: bool,
+    download_retries: u32,
+    install_retries: u32,
+    download_timeout: Option<Duration>,
+    scheduler: Option<DownloadScheduler>,
+    runtime_factory: Option<RuntimeFactory>,
+    source_limits: HashMap<PackageSourceId, Arc<Throttle>>,
+    download_limit: Option<Arc<Throttle>>,
+}
+
 #[derive(Clone)]
 pub struct InstallationContext {
     shared_dir: PathBuf,
@@ -27,6 +355,21 @@ pub struct InstallationContext {
     server_index_dir: PathBuf,
     dev_dir: PathBuf,
     dev_index_dir: PathBuf,
+    lock_path: PathBuf,
+    state_path: PathBuf,
+    options: InstallOptions,
+    network: NetworkOptions,
+    reporter: Arc<dyn ProgressReporter>,
+    filesystem: Arc<dyn Filesystem>,
+    cache: Option<PackageCache>,
+    upgrade_sources: HashMap<PathBuf, PathBuf>,
+    claimed_paths: Arc<Mutex<HashMap<String, PackageId>>>,
+    unpacked_entries: Arc<Mutex<HashMap<String, Option<&'static str>>>>,
+    shipped_links_dirs: Arc<Mutex<HashSet<String>>>,
+    diagnostics: Arc<Mutex<Vec<Diagnostic>>>,
+    stats: Arc<Mutex<InstallStats>>,
+    fetched: Arc<Mutex<HashSet<PackageId>>>,
+    cancelled: Arc<AtomicBool>,
 }
 
 impl InstallationContext {
@@ -40,6 +383,9 @@ impl InstallationContext {
         let server_index_dir = server_dir.join("_index");
         let dev_index_dir = dev_dir.join("_index");
 
+        let lock_path = project_path.join(".wally-lock");
+        let state_path = project_path.join(".wally-install-state");
+
         Self {
             shared_dir,
             shared_index_dir,
@@ -47,279 +393,5729 @@ impl InstallationContext {
             server_index_dir,
             dev_dir,
             dev_index_dir,
+            lock_path,
+            state_path,
+            options: InstallOptions {
+                extraction: ExtractionOptions::default(),
+                linking: LinkOptions::default(),
+                production: false,
+                strict: false,
+                on_yanked: FallbackPolicy::default(),
+                frozen: false,
+                rojo_project: None,
+                policy: None,
+                luau_target: None,
+                flat: false,
+                workspace: false,
+                sortable_dir_names: false,
+                features: BTreeSet::new(),
+            },
+            network: NetworkOptions::default(),
+            reporter: Arc::new(IndicatifReporter::new()),
+            filesystem: Arc::new(RealFilesystem),
+            cache: None,
+            upgrade_sources: HashMap::new(),
+            claimed_paths: Default::default(),
+            unpacked_entries: Default::default(),
+            shipped_links_dirs: Default::default(),
+            diagnostics: Default::default(),
+            stats: Default::default(),
+            fetched: Default::default(),
+            cancelled: Default::default(),
         }
     }
 
+    /// Acquire the lock that stops multiple wally processes from installing
+    /// into the same project at once. `clean` and `install` take it
+    /// themselves.
+    pub fn lock(&self) -> anyhow::Result<InstallLock> {
+        InstallLock::acquire(self.filesystem.clone(), &self.lock_path)
+    }
+
     /// Delete the existing index, if it exists.
     pub fn clean(&self) -> anyhow::Result<()> {
-        fn remove_ignore_not_found(path: &Path) -> io::Result<()> {
-            if let Err(err) = fs::remove_dir_all(path) {
+        let _lock = self.lock()?;
+
+        let remove_ignore_not_found = |path: &Path| -> io::Result<()> {
+            if let Err(err) = self.remove_path(path) {
                 if err.kind() != io::ErrorKind::NotFound {
                     return Err(err);
                 }
             }
 
             Ok(())
-        }
+        };
 
         remove_ignore_not_found(&self.shared_dir)?;
         remove_ignore_not_found(&self.server_dir)?;
         remove_ignore_not_found(&self.dev_dir)?;
 
+        if let Err(err) = self.filesystem.remove_file(&self.state_path) {
+            if err.kind() != io::ErrorKind::NotFound {
+                return Err(err.into());
+            }
+        }
+
         Ok(())
     }
 
-    /// Install all packages from the given `Resolve` into the package that this
-    /// `InstallationContext` was built for.
-    pub fn install(
-        self,
-        sources: PackageSourceMap,
-        root_package_id: PackageId,
-        resolved: Resolve,
-    ) -> anyhow::Result<()> {
-        let mut handles = Vec::new();
-        let resolved_copy = resolved.clone();
-        let bar = ProgressBar::new((resolved_copy.activated.len() - 1) as u64).with_style(
-            ProgressStyle::with_template(
-                "{spinner:.cyan.bold} {pos}/{len} [{wide_bar:.cyan/blue}]",
-            )
-            .unwrap()
-            .tick_chars("⠁⠈⠐⠠⠄⠂ ")
-            .progress_chars("#>-"),
-        );
-        bar.enable_steady_tick(Duration::from_millis(100));
+    /// Whether installing `resolved` would change nothing, because the last
+    /// install was of the same resolve with the same options and everything
+    /// it wrote is still in place. Cleaning the index first would throw that
+    /// install away, so callers that clean before installing check this.
+    pub fn is_installed(
+        &self,
+        resolved: &Resolve,
+        root_package_id: &PackageId,
+    ) -> anyhow::Result<bool> {
+        let resolved = canonical_resolve(resolved.clone());
+        let resolved = enabled_features_resolve(resolved, root_package_id, &self.options.features)?;
 
-        let runtime = tokio::runtime::Builder::new_multi_thread()
-            .worker_threads(50)
-            .enable_all()
-            .build()
-            .unwrap();
+        let hash = match self.resolve_hash(&resolved, root_package_id)? {
+            Some(hash) => hash,
+            None => return Ok(false),
+        };
 
-        for package_id in resolved_copy.activated {
-            log::debug!("Installing {}...", package_id);
+        let expected = self.packages_to_install(&resolved, root_package_id);
+        self.is_up_to_date(&hash, &resolved, root_package_id, &expected)
+    }
 
-            let shared_deps = resolved.shared_dependencies.get(&package_id);
+    /// Lists the packages in this project's indexes, as found on disk, by
+    /// parsing the names of their directories. Anything else in an index is
+    /// ignored. Packages are ordered by realm, then by id.
+    pub fn list_installed(&self) -> anyhow::Result<Vec<InstalledPackage>> {
+        let mut installed = Vec::new();
+        let mut seen_dirs = HashSet::new();
 
-            // We do not need to install the root package, but we should create
-            // package links for its dependencies.
-            if package_id == root_package_id {
-                if let Some(deps) = shared_deps {
-                    self.write_root_package_links(Realm::Shared, deps, &resolved, &sources)?;
-                }
-            } else {
-                // leaving this here for now, but we should probably remove it
-                if let Some(deps) = shared_deps {
-                    self.write_package_links(
-                        &package_id,
-                        Realm::Shared,
-                        deps,
-                        &resolved,
-                        &sources,
-                    )?;
-                }
+        for &realm in &[Realm::Shared, Realm::Server, Realm::Dev] {
+            let index_dir = self.index_dir(realm);
 
-                let source_registry = resolved_copy.metadata[&package_id].source_registry.clone();
-                let source_copy = sources.clone();
-                let context = self.clone();
-                let b = bar.clone();
+            if !seen_dirs.insert(index_dir) || !self.filesystem.is_dir(index_dir) {
+                continue;
+            }
 
-                let handle = runtime.spawn_blocking(move || {
-                    let package_source = source_copy.get(&source_registry).unwrap();
-                    let contents = package_source.download_package(&package_id)?;
-                    b.println(
-                    format!(
-                            "{} Downloaded {}{}",
-                            SetForegroundColor(Color::DarkGreen),
-                            SetForegroundColor(Color::Reset),
-                            package_id
-                        )
-                    );
-                    b.inc(1);
-                    context.write_contents(&package_id, &contents, Realm::Shared)
-                });
+            let mut packages = Vec::new();
+
+            for entry in self.filesystem.read_dir(index_dir)? {
+                let id = entry
+                    .path
+                    .file_name()
+                    .and_then(|name| name.to_str())
+                    .and_then(parse_package_dir_name);
 
-                handles.push(handle);
+                if let (Some(id), true) = (id, entry.is_dir) {
+                    packages.push(InstalledPackage {
+                        id,
+                        realm,
+                        path: entry.path,
+                    });
+                }
             }
+
+            packages.sort_by(|a, b| a.id.cmp(&b.id));
+            installed.extend(packages);
         }
 
-        let num_packages = handles.len();
+        Ok(installed)
+    }
+
+    /// The total size in bytes of every file in this project's package
+    /// directories, as found on disk. Symlinks aren't followed, and files
+    /// hard linked more than once are only counted once.
+    pub fn installed_size(&self) -> anyhow::Result<u64> {
+        let mut size = 0;
+        let mut seen_files = HashSet::new();
+
+        for dir in self.package_dirs() {
+            if !self.filesystem.is_dir(dir) {
+                continue;
+            }
 
-        for handle in handles {
-            runtime
-                .block_on(handle)
-                .expect("Package failed to be installed.")?;
+            for entry in self.filesystem.walk_dir(dir)? {
+                if entry.is_file && seen_files.insert(entry.identity) {
+                    size += entry.len;
+                }
+            }
         }
 
-        bar.finish_and_clear();
-        log::info!("Downloaded {} packages!", num_packages);
+        Ok(size)
+    }
 
-        Ok(())
+    /// Report installation progress to the given `ProgressReporter` instead
+    /// of drawing a progress bar to the terminal.
+    pub fn with_reporter(mut self, reporter: Arc<dyn ProgressReporter>) -> Self {
+        self.reporter = reporter;
+        self
     }
 
-    /// Contents of a package-to-package link within the same index.
-    fn link_sibling_same_index(&self, id: &PackageId, suffix: Option<&str>) -> String {
-        formatdoc!(
-            r#"
-            return require("../../{full_name}{suffix}")
-            "#,
-            full_name = package_id_file_name(id),
-            suffix = suffix.unwrap_or("")
-        )
+    /// Perform all filesystem operations through the given `Filesystem`.
+    pub fn with_filesystem(mut self, filesystem: Arc<dyn Filesystem>) -> Self {
+        self.filesystem = filesystem;
+        self
     }
 
-    /// Contents of a root-to-package link within the same index.
-    fn link_root_same_index(&self, id: &PackageId, suffix: Option<&str>) -> String {
-        formatdoc!(
-            r#"
-            return require("_index/{full_name}{suffix}")
-            "#,
-            full_name = package_id_file_name(id),
-            suffix = suffix.unwrap_or("")
-        )
+    /// Confine everything the install writes to `prefix`, which stands in
+    /// for the root directory, while links are still written as though the
+    /// project were where this context says it is. This wraps the filesystem
+    /// set so far, so call it after `with_filesystem`.
+    ///
+    /// Methods that inspect an install on disk, like `verify_links`, look at
+    /// the project's own path rather than the one under the prefix.
+    pub fn with_fs_prefix(mut self, prefix: &Path) -> Self {
+        self.filesystem = Arc::new(PrefixedFilesystem::new(prefix, self.filesystem));
+        self
     }
 
-    fn write_root_package_links<'a, K: Display>(
-        &self,
-        root_realm: Realm,
-        dependencies: impl IntoIterator<Item = (K, &'a PackageId)>,
-        resolved: &Resolve,
-        sources: &PackageSourceMap,
-    ) -> anyhow::Result<()> {
-        log::debug!("Writing root package links");
+    /// Give every file the install writes the modification time `mtime`
+    /// instead of the time it was written, so build caches keyed on
+    /// modification times see the same files every install. Files hard
+    /// linked from the cache keep the cache's times.
+    pub fn with_mtime(mut self, mtime: SystemTime) -> Self {
+        self.options.extraction.mtime = Some(mtime);
+        self
+    }
 
-        let base_path = match root_realm {
-            Realm::Shared => &self.shared_dir,
-            Realm::Server => &self.server_dir,
-            Realm::Dev => &self.dev_dir,
-        };
+    /// Look for packages in the given cache before downloading them, and add
+    /// any that are downloaded to it unless it's read-only.
+    pub fn with_cache(mut self, cache: PackageCache) -> Self {
+        self.cache = Some(cache);
+        self
+    }
 
-        log::trace!("Creating directory {}", base_path.display());
-        fs::create_dir_all(base_path)?;
+    /// Pass every require path written into a link file through the given
+    /// function first, so that bundlers can remap them to their own module
+    /// paths.
+    pub fn with_require_transform(mut self, transform: RequireTransform) -> Self {
+        self.options.linking.require_transform = Some(transform);
+        self
+    }
 
-        for (dep_name, dep_package_id) in dependencies {
-            let path = base_path.join(format!("{}.lua", dep_name));
+    /// Call the given function on every package's manifest after it's
+    /// unpacked, so tools can patch installed manifests. The install
+    /// fails if the manifest no longer parses or names a different package
+    /// afterwards, or if `default.project.json` no longer parses.
+    pub fn with_manifest_rewrite(mut self, rewrite: ManifestRewrite) -> Self {
+        self.options.extraction.manifest_rewrite = Some(rewrite);
+        self
+    }
 
-            let resolved_copy = resolved.clone();
-            let source_registry = resolved_copy.metadata[&dep_package_id]
-                .source_registry
-                .clone();
-            let source_copy = sources.clone();
-            let package_source = source_copy.get(&source_registry).unwrap();
-            let file = package_source.download_package(&dep_package_id)?;
-            let archive = ZipArchive::new(Cursor::new(file.data()))?;
+    /// Write link files with the given extension instead of `.lua`. Links
+    /// left behind with the other extension by an earlier install are
+    /// removed as their replacements are written, so that Rojo doesn't load
+    /// both.
+    pub fn with_link_extension(mut self, link_extension: LinkExtension) -> Self {
+        self.options.linking.link_extension = link_extension;
+        self
+    }
 
-            // check if this archive contains either init.luau, init.lua, src/init.luau or src/init.lua, in that order.
-            // we need seperate cases for *.lua, lune doesn't require the .lua extension by default
-            let mut suffix = None;
+    /// Choose what happens when a link file would replace a file that isn't
+    /// a link.
+    pub fn with_link_conflict_policy(mut self, policy: LinkConflictPolicy) -> Self {
+        self.options.linking.link_conflict_policy = policy;
+        self
+    }
 
-            for file_name in archive.file_names() {
-                if file_name == "init.luau" {
-                    suffix = Some("");
-                    break;
-                } else if file_name == "init.lua" {
-                    suffix = Some("/init.lua");
-                    break
-                } else if file_name == "src/init.luau" {
-                    suffix = Some("/src");
-                    // don't break here, we want to prioritize files in the root of the archive
-                } else if file_name == "src/init.lua" {
-                    suffix = Some("/src/init.lua");
-                }
-            }
+    /// Write the root package's links as the given layout. The aggregated
+    /// `index` module uses the link extension like any other link. In a flat
+    /// layout, the server and dev realms get `Server_index` and `Dev_index`.
+    pub fn with_root_link_layout(mut self, layout: RootLinkLayout) -> Self {
+        self.options.linking.root_link_layout = layout;
+        self
+    }
 
-            let contents = self.link_root_same_index(dep_package_id, suffix);
+    /// Generate the requires in link files in the given style instead of as
+    /// relative paths.
+    pub fn with_require_path_style(mut self, require_path_style: RequirePathStyle) -> Self {
+        self.options.linking.require_path_style = require_path_style;
+        self
+    }
 
-            log::trace!("Writing {}", path.display());
-            fs::write(path, contents)?;
-        }
+    /// Call the function with the given name in link files instead of
+    /// `require`, for runtimes that load modules some other way.
+    pub fn with_require_function<S: Into<String>>(mut self, name: S) -> Self {
+        self.options.linking.require_function = name.into();
+        self
+    }
 
-        Ok(())
+    /// Build for production, leaving out the root package's dev dependencies
+    /// and anything only they depend on. No `DevPackages` are written at all.
+    ///
+    /// Resolution in this fork never resolves dev dependencies, so this only
+    /// changes anything for resolves produced elsewhere and deserialized.
+    pub fn with_production(mut self, production: bool) -> Self {
+        self.options.production = production;
+        self
     }
 
-    fn write_package_links<'a, K: std::fmt::Display>(
-        &self,
-        package_id: &PackageId,
-        package_realm: Realm,
-        dependencies: impl IntoIterator<Item = (K, &'a PackageId)>,
-        resolved: &Resolve,
-        sources: &PackageSourceMap,
-    ) -> anyhow::Result<()> {
-        log::debug!("Writing package links for {}", package_id);
+    /// Run the whole install again up to this many times when packages fail
+    /// to download, for pipelines that can't have someone rerun it. Packages
+    /// that earlier attempts got into the index aren't downloaded again.
+    /// Only `install` retries; `install_fresh` doesn't.
+    pub fn with_install_retries(mut self, install_retries: u32) -> Self {
+        self.network.install_retries = install_retries;
+        self
+    }
+
+    /// Try downloading a package again up to this many times when it fails,
+    /// waiting longer before each retry.
+    pub fn with_download_retries(mut self, download_retries: u32) -> Self {
+        self.network.download_retries = download_retries;
+        self
+    }
+
+    /// Give up on downloading a package once it's taken longer than
+    /// `timeout`. The source stops the download itself, and downloads that
+    /// time out are retried like any other failure.
+    pub fn with_download_timeout(mut self, timeout: Duration) -> Self {
+        self.network.download_timeout = Some(timeout);
+        self
+    }
+
+    /// Stop installing as soon as any package fails to download, instead of
+    /// letting the rest finish and reporting every failure together.
+    pub fn with_fail_fast(mut self, fail_fast: bool) -> Self {
+        self.network.fail_fast = fail_fast;
+        self
+    }
+
+    /// Choose what happens when a resolved package has been yanked from its
+    /// source since it was resolved.
+    pub fn with_on_yanked(mut self, on_yanked: FallbackPolicy) -> Self {
+        self.options.on_yanked = on_yanked;
+        self
+    }
+
+    /// Link dependencies that declare `modules` in their manifest as a
+    /// directory, with a link for each submodule alongside the `init.lua`
+    /// link to the package itself, so that `require("Packages/Foo/Sub")`
+    /// works.
+    pub fn with_submodule_links(mut self, submodule_links: bool) -> Self {
+        self.options.linking.submodule_links = submodule_links;
+        self
+    }
+
+    /// Install entirely from packages already unpacked into the index,
+    /// failing if any are missing. Nothing is downloaded and package sources
+    /// are never queried, so installs are reproducible without network access.
+    pub fn with_frozen(mut self, frozen: bool) -> Self {
+        self.options.frozen = frozen;
+        self
+    }
+
+    /// Only write link files, both the root package's and those between
+    /// packages, to packages that are already unpacked into the index. This
+    /// is meant for projects that use an index installed by something else,
+    /// like a monorepo's shared index given with `with_workspace_root`.
+    /// Nothing is downloaded or unpacked, and the install fails if any
+    /// package is missing from the index.
+    pub fn with_links_only(mut self, links_only: bool) -> Self {
+        self.options.linking.links_only = links_only;
+        self
+    }
+
+    /// Merge dependency aliases that differ only by case when they point at
+    /// the same package, linking it once under the first spelling in sorted
+    /// order. Without this, such aliases are an error.
+    pub fn with_normalized_aliases(mut self, normalize_aliases: bool) -> Self {
+        self.options.linking.normalize_aliases = normalize_aliases;
+        self
+    }
+
+    /// Run downloads on the given scheduler instead of a pool of threads
+    /// created just for this install.
+    pub fn with_scheduler(mut self, scheduler: DownloadScheduler) -> Self {
+        self.network.scheduler = Some(scheduler);
+        self
+    }
+
+    /// Build the pool of threads an install without a scheduler downloads on
+    /// with the given factory instead of tokio's default runtime builder.
+    pub fn with_runtime_factory(mut self, factory: RuntimeFactory) -> Self {
+        self.network.runtime_factory = Some(factory);
+        self
+    }
+
+    /// Unpack each package into the given directory first, then move it into
+    /// the index once it's complete, so the index never holds a partially
+    /// unpacked package. The directory should be on the same volume as the
+    /// project; if it isn't, packages are unpacked in place instead.
+    pub fn with_staging_dir(mut self, staging_dir: &Path) -> Self {
+        self.options.extraction.staging_dir = Some(staging_dir.to_path_buf());
+        self
+    }
+
+    /// After installing, check that the Rojo project file at `project_file`
+    /// maps every package directory that has packages in it, and warn about
+    /// any it doesn't, since their packages won't be in the game.
+    pub fn with_rojo_project(mut self, project_file: &Path) -> Self {
+        self.options.rojo_project = Some(project_file.to_path_buf());
+        self
+    }
+
+    /// Choose how package files are put into the index.
+    pub fn with_strategy(mut self, strategy: InstallStrategy) -> Self {
+        self.options.extraction.strategy = strategy;
+        self
+    }
+
+    /// On Windows, have `InstallStrategy::Hardlink` link each directory in a
+    /// package from the cache as a directory junction, rather than hard
+    /// linking every file in it. Unlike symlinks, junctions don't need
+    /// privileges to create, but they can only point at directories on a
+    /// local volume. Directories that can't be junctioned, and every
+    /// directory on other platforms, are hard linked file by file as usual.
+    pub fn with_junctions(mut self, junctions: bool) -> Self {
+        self.options.extraction.junctions = junctions;
+        self
+    }
+
+    /// Choose how packages already in the index are recognized, so they
+    /// aren't unpacked again.
+    pub fn with_presence_check(mut self, presence_check: PresenceCheck) -> Self {
+        self.options.extraction.presence_check = presence_check;
+        self
+    }
+
+    /// Refuse to download any package that the given policy doesn't permit.
+    pub fn with_policy(mut self, policy: InstallPolicy) -> Self {
+        self.options.policy = Some(policy);
+        self
+    }
+
+    /// Fail the install on anything it would otherwise only warn about, like
+    /// packages without an entry point or unsatisfied peer dependencies.
+    pub fn with_strict(mut self, strict: bool) -> Self {
+        self.options.strict = strict;
+        self
+    }
+
+    /// Check that the manifest inside each downloaded archive names the
+    /// package and version that was asked for before unpacking it. A
+    /// mismatch means the registry entry is corrupted or spoofed. Packages
+    /// checked this way are never streamed, since the manifest has to be read
+    /// first.
+    pub fn with_manifest_validation(mut self, validate_manifests: bool) -> Self {
+        self.options.extraction.validate_manifests = validate_manifests;
+        self
+    }
+
+    /// Save each downloaded archive into `packages/.archives`, as well as
+    /// unpacking it, which helps with debugging packages and keeps a copy
+    /// around for offline installs. Packages are never streamed when keeping
+    /// archives.
+    pub fn with_kept_archives(mut self, keep_archives: bool) -> Self {
+        self.options.extraction.keep_archives = keep_archives;
+        self
+    }
+
+    /// Install the optional dependencies enabled by these features of the
+    /// root package. Optional dependencies of other packages are never
+    /// installed.
+    pub fn with_features<I, S>(mut self, features: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.options.features = features.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Give packages whose entry point is under `src/` an `init.luau` at
+    /// their root that requires it, so that links can require the package's
+    /// directory itself instead of appending `/src`.
+    pub fn with_init_shims(mut self, init_shims: bool) -> Self {
+        self.options.extraction.init_shims = init_shims;
+        self
+    }
+
+    /// Write a `.gitignore` that ignores everything into each package
+    /// directory the install creates, so installed packages aren't committed
+    /// by accident. A `.gitignore` that's already there is left alone.
+    pub fn with_gitignore(mut self, gitignore: bool) -> Self {
+        self.options.extraction.gitignore = gitignore;
+        self
+    }
+
+    /// Warn about packages whose manifest says they need a newer Luau release
+    /// than `luau`, since they'd fail to parse or run once installed. In
+    /// strict mode, the install fails instead.
+    pub fn with_luau_target(mut self, luau: LuauVersion) -> Self {
+        self.options.luau_target = Some(luau);
+        self
+    }
+
+    /// When a package is installed over an older version of itself left in
+    /// the index by an earlier install, update that version's directory in
+    /// place instead of unpacking from scratch: only files whose contents
+    /// changed are written, and files the new version doesn't have are
+    /// removed. This keeps file watchers quiet on upgrades. Only applies to
+    /// `InstallStrategy::Copy` without a staging directory, outside of
+    /// workspaces, and never streams packages.
+    pub fn with_incremental_upgrades(mut self, incremental_upgrades: bool) -> Self {
+        self.options.extraction.incremental_upgrades = incremental_upgrades;
+        self
+    }
+
+    /// Write the links to each package's dependencies into a directory with
+    /// the given name inside the package, instead of `packages`. Installing
+    /// fails if a package ships its own directory with that name, since its
+    /// files and the links would be mixed together.
+    pub fn with_package_links_dir<S: Into<String>>(mut self, name: S) -> Self {
+        self.options.linking.package_links_dir = name.into();
+        self
+    }
 
-        let mut base_path = match package_realm {
-            Realm::Shared => self.shared_index_dir.clone(),
-            Realm::Server => self.server_index_dir.clone(),
-            Realm::Dev => self.dev_index_dir.clone(),
+    /// Install every realm into the single `packages` directory and its
+    /// `_index`, for projects that aren't split into Roblox's shared, server
+    /// and dev realms. The root package's server and dev dependencies are
+    /// linked as `Server_<alias>.lua` and `Dev_<alias>.lua` so they can't
+    /// collide with its shared ones.
+    pub fn with_flat_layout(mut self, flat: bool) -> Self {
+        self.options.flat = flat;
+        self
+    }
+
+    /// Name the index directory inside the given realm's package directory
+    /// `name` instead of `_index`. With a flat layout, only the shared
+    /// realm's index is used.
+    pub fn with_index_name(mut self, realm: Realm, name: &str) -> Self {
+        let index_dir = match realm {
+            Realm::Shared => &mut self.shared_index_dir,
+            Realm::Server => &mut self.server_index_dir,
+            Realm::Dev => &mut self.dev_index_dir,
         };
 
-        base_path.push(package_id_file_name(package_id));
+        index_dir.set_file_name(name);
+        self
+    }
+
+    /// Name package directories in the index so that listing them in
+    /// lexicographic order lists each package's versions in semver order,
+    /// with pre-releases before their release. Version numbers are padded to
+    /// eight digits and releases end with `~`, so `1.0.0-rc.1` becomes
+    /// `00000001.00000000.00000000-rc.00000001` and `1.0.0` becomes
+    /// `00000001.00000000.00000000~`. The padding can be stripped again to
+    /// get the original version back.
+    pub fn with_sortable_dir_names(mut self, sortable_dir_names: bool) -> Self {
+        self.options.sortable_dir_names = sortable_dir_names;
+        self
+    }
+
+    /// Download at most `max_downloads` packages at once from the given
+    /// source, for registries that can't handle many simultaneous requests.
+    /// Downloads from other sources aren't held up by it.
+    pub fn with_source_limit(mut self, source: PackageSourceId, max_downloads: usize) -> Self {
+        self.network
+            .source_limits
+            .insert(source, Arc::new(Throttle::new(max_downloads)));
+        self
+    }
 
-        log::trace!("Creating directory {}", base_path.display());
-        fs::create_dir_all(&base_path)?;
+    /// Download at most `max_downloads` packages at once across every source.
+    pub fn with_max_downloads(mut self, max_downloads: usize) -> Self {
+        self.network.download_limit = Some(Arc::new(Throttle::new(max_downloads)));
+        self
+    }
 
-        let resolved_copy = resolved.clone();
-        let source_registry = resolved_copy.metadata[&package_id].source_registry.clone();
-        let source_copy = sources.clone();
-        let package_source = source_copy.get(&source_registry).unwrap();
+    /// Unpack at most `max_extractions` packages into the index at once.
+    /// Unpacking is bound by disk and CPU rather than the network, so it
+    /// usually wants a lower limit than downloads. Packages are downloaded in
+    /// full before they're unpacked when this is set, since a package
+    /// unpacked as it streams in would hold on to its download the whole
+    /// time it waited to be unpacked.
+    pub fn with_max_extractions(mut self, max_extractions: usize) -> Self {
+        self.options.extraction.limit = Some(Arc::new(Throttle::new(max_extractions)));
+        self
+    }
 
-        for (dep_name, dep_package_id) in dependencies {
-            fs::create_dir_all(&base_path.join("packages"))?;
-            let path = base_path.join("packages").join(format!("{}.lua", dep_name));
+    /// Unpack packages on the given pool's threads instead of the threads
+    /// that downloaded them, so unpacking can't use more threads than the
+    /// pool has however many downloads run at once.
+    pub fn with_extraction_pool(mut self, pool: ExtractionPool) -> Self {
+        self.options.extraction.pool = Some(pool);
+        self
+    }
 
-            // download each package, check whether the init.luau is located in the root or in a folder called /src
-            let file = package_source.download_package(&dep_package_id)?;
+    /// Install all packages from the given `Resolve` into the package that this
+    /// `InstallationContext` was built for. Returns the warnings logged along
+    /// the way.
+    pub fn install(
+        mut self,
+        sources: PackageSourceMap,
+        root_package_id: PackageId,
+        resolved: Resolve,
+    ) -> anyhow::Result<Vec<Diagnostic>> {
+        let _lock = self.lock()?;
+        self.fetched = Default::default();
 
-            let archive = ZipArchive::new(Cursor::new(file.data()))?;
+        let mut retry = 0;
 
-            // check if this archive contains either init.luau, init.lua, src/init.luau or src/init.lua, in that order.
-            let mut suffix = None;
+        loop {
+            let result = self.clone().install_locked(
+                sources.clone(),
+                root_package_id.clone(),
+                resolved.clone(),
+            );
 
-            for file_name in archive.file_names() {
-                if file_name == "init.luau" || file_name == "init.lua" {
-                    suffix = Some("");
-                    break;
-                } else if file_name == "src/init.luau" || file_name == "src/init.lua" {
-                    suffix = Some("/src");
-                    // don't break here, we want to prioritize files in the root of the archive
+            match result {
+                Err(err)
+                    if retry < self.network.install_retries
+                        && err.is::<PackagesFailed>()
+                        && is_transient(&err) =>
+                {
+                    retry += 1;
+                    log::warn!(
+                        "Install failed, trying again ({} of {}): {:#}",
+                        retry,
+                        self.network.install_retries,
+                        err
+                    );
+                    std::thread::sleep(retry_delay(retry));
                 }
+                result => return result,
             }
+        }
+    }
+
+    /// Like `install`, but starting from empty package directories, as if
+    /// `clean` had been called first. The old package directories are only
+    /// deleted once the install succeeds, and are put back if it fails, so
+    /// the project is never left worse off than before.
+    pub fn install_fresh(
+        mut self,
+        sources: PackageSourceMap,
+        root_package_id: PackageId,
+        resolved: Resolve,
+    ) -> anyhow::Result<Vec<Diagnostic>> {
+        let _lock = self.lock()?;
+        self.fetched = Default::default();
 
-            let contents = self.link_sibling_same_index(dep_package_id, suffix);
+        let mut set_aside = Vec::new();
+        for path in &[
+            &self.shared_dir,
+            &self.server_dir,
+            &self.dev_dir,
+            &self.state_path,
+        ] {
+            let backup = backup_path(path);
+
+            if self.filesystem.exists(&backup) {
+                self.remove_path(&backup)?;
+            }
 
-            log::trace!("Writing {}", path.display());
-            fs::write(path, contents)?;
+            if self.filesystem.exists(path) {
+                log::debug!("Setting {} aside", path.display());
+                self.filesystem.rename(path, &backup)?;
+                set_aside.push((path.to_path_buf(), backup));
+            }
         }
 
-        Ok(())
-    }
+        match self
+            .clone()
+            .install_locked(sources, root_package_id, resolved)
+        {
+            Ok(diagnostics) => {
+                for (_, backup) in &set_aside {
+                    self.remove_path(backup)?;
+                }
 
-    fn write_contents(
-        &self,
-        package_id: &PackageId,
-        contents: &PackageContents,
-        realm: Realm
-    ) -> anyhow::Result<()> {
-        let mut path = match realm {
-            Realm::Shared => self.shared_index_dir.clone(),
-            Realm::Server => self.server_index_dir.clone(),
-            Realm::Dev => self.dev_index_dir.clone(),
-        };
+                Ok(diagnostics)
+            }
+            Err(err) => {
+                for path in &[
+                    &self.shared_dir,
+                    &self.server_dir,
+                    &self.dev_dir,
+                    &self.state_path,
+                ] {
+                    if self.filesystem.exists(path) {
+                        self.remove_path(path)?;
+                    }
+                }
 
-        path.push(package_id_file_name(package_id));
+                for (path, backup) in &set_aside {
+                    log::debug!("Restoring {}", path.display());
+                    self.filesystem.rename(backup, path)?;
+                }
 
-        fs::create_dir_all(&path)?;
-        contents.unpack_into_path(&path)?;
+                Err(err)
+            }
+        }
+    }
 
-        Ok(())
+    /// Remove the file or directory at `path`.
+    fn remove_path(&self, path: &Path) -> io::Result<()> {
+        if self.filesystem.is_dir(path) {
+            self.filesystem.remove_dir_all(path)
+        } else {
+            self.filesystem.remove_file(path)
+        }
     }
-}
 
-/// Creates a suitable name for use in file paths that refer to this package.
-fn package_id_file_name(id: &PackageId) -> String {
-    format!(
-        "{}_{}@{}",
-        id.name().scope(),
-        id.name().name(),
-        id.version()
-    )
+    /// The body of `install`, for callers that already hold the install lock.
+    fn install_locked(
+        mut self,
+        sources: PackageSourceMap,
+        root_package_id: PackageId,
+        resolved: Resolve,
+    ) -> anyhow::Result<Vec<Diagnostic>> {
+        validate_resolve(&resolved)?;
+        let resolved = canonical_resolve(resolved);
+        let resolved =
+            enabled_features_resolve(resolved, &root_package_id, &self.options.features)?;
+
+        // Clones of this context made before the install mustn't see its
+        // warnings or stats.
+        self.diagnostics = Default::default();
+        self.stats = Default::default();
+        self.cancelled = Default::default();
+
+        if let Some(mtime) = self.options.extraction.mtime {
+            self.filesystem = Arc::new(FixedTimeFilesystem::new(mtime, self.filesystem));
+        }
+
+        for warning in peer_dependency_warnings(&resolved) {
+            self.warn(warning)?;
+        }
+
+        for cycle in self.dependency_cycles(&resolved, &root_package_id) {
+            let mut names: Vec<_> = cycle.iter().map(|id| id.to_string()).collect();
+            names.push(cycle[0].to_string());
+
+            let message = format!(
+                "Packages depend on each other in a cycle, so requiring them will loop \
+                 forever: {}",
+                names.join(" -> ")
+            );
+            self.warn(
+                Diagnostic::new(DiagnosticKind::DependencyCycle, message).with_package(&cycle[0]),
+            )?;
+        }
+
+        self.claimed_paths.lock().unwrap().clear();
+        self.unpacked_entries.lock().unwrap().clear();
+        self.shipped_links_dirs.lock().unwrap().clear();
+        self.options.extraction.staging_dir = self.usable_staging_dir()?;
+
+        let to_download = self.packages_to_install(&resolved, &root_package_id);
+
+        let resolve_hash = self.resolve_hash(&resolved, &root_package_id)?;
+
+        if let Some(hash) = &resolve_hash {
+            if self.is_up_to_date(hash, &resolved, &root_package_id, &to_download)? {
+                for package_id in &to_download {
+                    self.reporter
+                        .report(ProgressEvent::Skipped(package_id.clone()));
+                }
+
+                log::info!("Packages are up to date");
+                self.warn_unmapped_dirs()?;
+                return Ok(self.take_diagnostics());
+            }
+        }
+
+        // The tree is about to change, so a failed install mustn't leave an
+        // old hash behind that matches it.
+        if let Err(err) = self.filesystem.remove_file(&self.state_path) {
+            if err.kind() != io::ErrorKind::NotFound {
+                return Err(err.into());
+            }
+        }
+
+        if self.options.frozen || self.options.linking.links_only {
+            let missing = self.missing_packages(&resolved, &to_download);
+
+            if !missing.is_empty() {
+                let missing_names: Vec<_> = missing.iter().map(|id| id.to_string()).collect();
+                let mode = if self.options.frozen {
+                    "frozen"
+                } else {
+                    "links-only"
+                };
+
+                bail!(
+                    "Cannot install in {} mode, because these packages are not in the index: {}",
+                    mode,
+                    missing_names.join(", ")
+                );
+            }
+        }
+
+        // Catch colliding link files before spending time on downloads.
+        for package_id in to_download.iter().chain(Some(&root_package_id)) {
+            self.linked_aliases(&resolved, package_id, &root_package_id)?;
+        }
+
+        let not_indexed;
+        let not_fetched;
+        let to_fetch: &[PackageId] = if self.options.frozen || self.options.linking.links_only {
+            &[]
+        } else if self.options.workspace {
+            // Other projects in the workspace may have unpacked some of these
+            // already.
+            not_indexed = self.missing_packages(&resolved, &to_download);
+            &not_indexed
+        } else if !self.fetched.lock().unwrap().is_empty() {
+            // Earlier attempts at this install unpacked some of these
+            // already, as long as they're still there.
+            let fetched = self.fetched.lock().unwrap().clone();
+            let missing = self.missing_packages(&resolved, &to_download);
+            not_fetched = to_download
+                .iter()
+                .filter(|package_id| !fetched.contains(*package_id) || missing.contains(package_id))
+                .cloned()
+                .collect::<Vec<_>>();
+            &not_fetched
+        } else {
+            &to_download
+        };
+
+        for package_id in &to_download {
+            if !to_fetch.contains(package_id) {
+                self.reporter
+                    .report(ProgressEvent::Skipped(package_id.clone()));
+            }
+        }
+
+        self.upgrade_sources = self.upgrade_sources(&resolved, &to_download, to_fetch)?;
+
+        if let Some(policy) = &self.options.policy {
+            policy.check(to_fetch)?;
+        }
+
+        // Failing here is much clearer than every download task failing on
+        // its own. Probing creates the index directories, so it waits until
+        // the policy has had its say.
+        let target_realms: BTreeSet<_> = to_fetch
+            .iter()
+            .map(|package_id| resolved.metadata[package_id].origin_realm)
+            .collect();
+        for realm in target_realms {
+            self.probe_writable(self.index_dir(realm))?;
+        }
+
+        // Realms the root package has no dependencies in get no links.
+        let root_link_steps = self
+            .linked_aliases(&resolved, &root_package_id, &root_package_id)?
+            .iter()
+            .filter(|(_, deps)| !deps.is_empty())
+            .count() as u64;
+        let link_steps = root_link_steps + to_download.len() as u64;
+
+        // Packages that are already in the index count as done, so resuming an
+        // interrupted install doesn't start the progress over.
+        let already_done = (to_download.len() - to_fetch.len()) as u64;
+        self.reporter.report(ProgressEvent::Started(
+            to_download.len() as u64 + link_steps,
+            already_done,
+        ));
+        self.reporter.report(ProgressEvent::PhaseStarted(
+            InstallPhase::Downloading,
+            to_fetch.len() as u64,
+        ));
+        let downloading_started = Instant::now();
+
+        // Starting a pool of threads takes longer than installing a single
+        // package, so a lone package is installed on this thread instead.
+        let owned_runtime = match &self.network.scheduler {
+            Some(_) => None,
+            None if to_fetch.len() <= 1 => None,
+            None => {
+                let runtime = match &self.network.runtime_factory {
+                    Some(factory) => factory(50),
+                    None => tokio::runtime::Builder::new_multi_thread()
+                        .worker_threads(50)
+                        .enable_all()
+                        .build(),
+                };
+                Some(scheduler::runtime_built(runtime, 50)?)
+            }
+        };
+        let runtime = match &self.network.scheduler {
+            Some(scheduler) => Some(scheduler.runtime()),
+            None => owned_runtime.as_ref(),
+        };
+
+        let mut handles = Vec::new();
+        let (result_sender, results) = mpsc::channel();
+
+        for package_id in to_fetch.iter().cloned() {
+            log::debug!("Installing {}...", package_id);
+
+            let metadata = &resolved.metadata[&package_id];
+            let realm = metadata.origin_realm;
+            let source_registry = metadata.source_registry.clone();
+            let source_copy = sources.clone();
+            let context = self.clone();
+            let result_sender = result_sender.clone();
+
+            let task = move || {
+                if context.is_cancelled() {
+                    return;
+                }
+
+                let result = (|| {
+                    let _permit = context
+                        .network
+                        .source_limits
+                        .get(&source_registry)
+                        .map(|throttle| throttle.acquire());
+
+                    let package_source = source_copy.get(&source_registry).unwrap();
+                    context.download_with_retries(package_source, &package_id, realm)?;
+                    context.fetched.lock().unwrap().insert(package_id.clone());
+                    context
+                        .reporter
+                        .report(ProgressEvent::Downloaded(package_id.clone()));
+
+                    Ok::<_, anyhow::Error>(())
+                })();
+
+                let _ = result_sender.send((package_id, result));
+            };
+
+            match runtime {
+                Some(runtime) => handles.push(runtime.spawn_blocking(task)),
+                None => task(),
+            }
+        }
+
+        drop(result_sender);
+
+        let num_packages = to_fetch.len();
+        let mut failures = Vec::new();
+        let mut first_failure = None;
+
+        for (package_id, result) in results {
+            if let Err(err) = result {
+                if self.network.fail_fast && !self.falls_back(&err) {
+                    // Blocking tasks can't be aborted, so the ones still
+                    // running give up at their next check of the flag. They
+                    // are still waited for below, so that nothing writes
+                    // into the index once the install lock is released.
+                    self.cancelled.store(true, Ordering::SeqCst);
+                    if first_failure.is_none() {
+                        first_failure = Some((package_id, err));
+                    }
+                    continue;
+                }
+
+                failures.push((package_id, err));
+            }
+        }
+
+        if let Some(runtime) = runtime {
+            for handle in handles {
+                runtime
+                    .block_on(handle)
+                    .expect("Package failed to be installed.");
+            }
+        }
+
+        if let Some((package_id, err)) = first_failure {
+            let message = format!(
+                "Failed to install {}, required via {}",
+                package_id,
+                dependency_chain(&resolved, &root_package_id, &package_id)
+            );
+
+            return Err(err.context(PackagesFailed {
+                packages: vec![package_id],
+                message,
+            }));
+        }
+
+        let mut resolved = resolved;
+        let mut to_download = to_download;
+
+        if failures.iter().any(|(_, err)| self.falls_back(err)) {
+            let mut remaining = Vec::new();
+
+            for (yanked, err) in failures {
+                if !self.falls_back(&err) {
+                    remaining.push((yanked, err));
+                    continue;
+                }
+
+                let replacement = match self.install_replacement(&sources, &resolved, &yanked) {
+                    Ok(Some(replacement)) => replacement,
+                    Ok(None) => {
+                        remaining.push((yanked, err));
+                        continue;
+                    }
+                    Err(err) => {
+                        remaining.push((yanked, err));
+                        continue;
+                    }
+                };
+
+                self.warn(
+                    Diagnostic::new(
+                        DiagnosticKind::YankedPackage,
+                        format!(
+                            "{} is no longer available, so {} was installed instead",
+                            yanked, replacement
+                        ),
+                    )
+                    .with_package(&yanked),
+                )?;
+                self.reporter
+                    .report(ProgressEvent::Downloaded(replacement.clone()));
+
+                for package_id in &mut to_download {
+                    if *package_id == yanked {
+                        *package_id = replacement.clone();
+                    }
+                }
+                resolved = replaced_resolve(resolved, &yanked, &replacement);
+            }
+
+            failures = remaining;
+        }
+
+        if !failures.is_empty() {
+            failures.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+            let messages: Vec<_> = failures
+                .iter()
+                .map(|(package_id, err)| {
+                    format!(
+                        "{} (required via {}): {:#}",
+                        package_id,
+                        dependency_chain(&resolved, &root_package_id, package_id),
+                        err
+                    )
+                })
+                .collect();
+
+            let message = format!(
+                "Failed to install {} of {} packages:\n{}",
+                failures.len(),
+                num_packages,
+                messages.join("\n")
+            );
+
+            return Err(PackagesFailed {
+                packages: failures
+                    .into_iter()
+                    .map(|(package_id, _)| package_id)
+                    .collect(),
+                message,
+            }
+            .into());
+        }
+
+        self.stats.lock().unwrap().downloading_phase = downloading_started.elapsed();
+
+        if let Some(luau_target) = self.options.luau_target {
+            self.check_luau_versions(luau_target, &resolved, &to_download)?;
+        }
+
+        self.reporter.report(ProgressEvent::PhaseStarted(
+            InstallPhase::Linking,
+            link_steps,
+        ));
+        let linking_started = Instant::now();
+
+        for (realm, deps) in self.linked_aliases(&resolved, &root_package_id, &root_package_id)? {
+            // An empty realm directory confuses Rojo's sourcemaps, so realms
+            // without dependencies don't get one.
+            if deps.is_empty() {
+                continue;
+            }
+
+            let started = Instant::now();
+            self.write_root_package_links(realm, deps, &resolved)?;
+            self.stats.lock().unwrap().link_time += started.elapsed();
+
+            self.reporter
+                .report(ProgressEvent::Linked(root_package_id.clone()));
+        }
+
+        for package_id in &to_download {
+            let package_realm = resolved.metadata[package_id].origin_realm;
+
+            for (_, deps) in self.linked_aliases(&resolved, package_id, &root_package_id)? {
+                let started = Instant::now();
+                self.write_package_links(package_id, package_realm, deps, &resolved)?;
+                self.stats.lock().unwrap().link_time += started.elapsed();
+            }
+
+            self.reporter
+                .report(ProgressEvent::Linked(package_id.clone()));
+        }
+
+        if self.options.extraction.gitignore {
+            self.write_gitignores()?;
+        }
+
+        let stats = {
+            let mut stats = self.stats.lock().unwrap();
+            stats.linking_phase = linking_started.elapsed();
+            stats.clone()
+        };
+        self.reporter.report(ProgressEvent::Stats(stats));
+        self.reporter.report(ProgressEvent::Finished);
+
+        let missing = self.missing_packages(&resolved, &to_download);
+        if !missing.is_empty() {
+            let missing_names: Vec<_> = missing.iter().map(|id| id.to_string()).collect();
+
+            self.warn(Diagnostic::new(
+                DiagnosticKind::MissingPackages,
+                format!(
+                    "Installed {} of {} packages. These were never written: {}",
+                    to_download.len() - missing.len(),
+                    to_download.len(),
+                    missing_names.join(", ")
+                ),
+            ))?;
+        }
+
+        let unlinked = self.unlinked_packages(&resolved, &root_package_id)?;
+        if !unlinked.is_empty() {
+            let unlinked_names: Vec<_> = unlinked.iter().map(|id| id.to_string()).collect();
+
+            self.warn(Diagnostic::new(
+                DiagnosticKind::UnlinkedPackages,
+                format!(
+                    "These packages were installed, but nothing links to them: {}",
+                    unlinked_names.join(", ")
+                ),
+            ))?;
+        }
+
+        log::info!("{}", install_summary(&resolved, &to_download));
+
+        if let Some(hash) = resolve_hash {
+            if missing.is_empty() {
+                self.filesystem.write(&self.state_path, hash.as_bytes())?;
+            }
+        }
+
+        self.warn_unmapped_dirs()?;
+
+        Ok(self.take_diagnostics())
+    }
+
+    /// Warn about each package in `to_download` whose manifest in the index
+    /// asks for a newer Luau release than `luau_target`.
+    fn check_luau_versions(
+        &self,
+        luau_target: LuauVersion,
+        resolved: &Resolve,
+        to_download: &[PackageId],
+    ) -> anyhow::Result<()> {
+        for package_id in to_download {
+            let realm = resolved.metadata[package_id].origin_realm;
+            let manifest_path = self
+                .index_dir(realm)
+                .join(self.package_dir_name(package_id))
+                .join(MANIFEST_FILE_NAME);
+
+            let manifest = match self.filesystem.read(&manifest_path) {
+                Ok(data) => Manifest::from_slice(&data)?,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => continue,
+                Err(err) => return Err(err.into()),
+            };
+
+            if let Some(luau) = manifest.package.luau {
+                if luau > luau_target {
+                    let message = format!(
+                        "{} needs Luau {} or newer, but Luau {} is targeted",
+                        package_id, luau, luau_target
+                    );
+                    self.warn(
+                        Diagnostic::new(DiagnosticKind::LuauVersion, message)
+                            .with_package(package_id),
+                    )?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Write a `.gitignore` ignoring everything into each package directory
+    /// that exists and doesn't have one yet.
+    fn write_gitignores(&self) -> anyhow::Result<()> {
+        for dir in self.package_dirs() {
+            if !self.filesystem.is_dir(dir) {
+                continue;
+            }
+
+            let path = dir.join(".gitignore");
+            match self.filesystem.create_new(&path, b"*\n") {
+                Ok(()) => log::trace!("Wrote {}", path.display()),
+                Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {}
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Check that packages can be written into `dir` by writing and removing
+    /// an empty file there, creating `dir` if needed.
+    fn probe_writable(&self, dir: &Path) -> anyhow::Result<()> {
+        let probe_path = dir.join(".wally-write-probe");
+        let result = self
+            .create_dir_all(dir)
+            .and_then(|()| self.filesystem.write(&probe_path, b""))
+            .and_then(|()| self.filesystem.remove_file(&probe_path));
+
+        match result {
+            Ok(()) => Ok(()),
+            // 30 is EROFS on both Linux and macOS.
+            Err(err)
+                if err.kind() == io::ErrorKind::PermissionDenied
+                    || (cfg!(unix) && err.raw_os_error() == Some(30)) =>
+            {
+                bail!(
+                    "Install target {} is read-only, so no packages can be installed: {}",
+                    dir.display(),
+                    err
+                )
+            }
+            Err(err) => Err(anyhow::Error::new(err).context(format!(
+                "Could not write to install target {}",
+                dir.display()
+            ))),
+        }
+    }
+
+    /// Whether packages are unpacked into the index over what's already
+    /// there, updating older versions in place.
+    fn updates_in_place(&self) -> bool {
+        self.options.extraction.incremental_upgrades
+            && self.options.extraction.strategy == InstallStrategy::Copy
+            && self.options.extraction.staging_dir.is_none()
+            && !self.options.workspace
+    }
+
+    /// Finds the index directory of an older version of each package in
+    /// `to_fetch` that can be updated in place to become that package. Only
+    /// directories that nothing in `to_download` is installed into are used,
+    /// each for at most one package.
+    fn upgrade_sources(
+        &self,
+        resolved: &Resolve,
+        to_download: &[PackageId],
+        to_fetch: &[PackageId],
+    ) -> anyhow::Result<HashMap<PathBuf, PathBuf>> {
+        let mut upgrade_sources = HashMap::new();
+
+        if !self.updates_in_place() {
+            return Ok(upgrade_sources);
+        }
+
+        let package_path = |package_id: &PackageId| {
+            let realm = resolved.metadata[package_id].origin_realm;
+            self.index_dir(realm)
+                .join(self.package_dir_name(package_id))
+        };
+
+        let targets: HashSet<_> = to_download.iter().map(&package_path).collect();
+        let mut used = HashSet::new();
+
+        for package_id in to_fetch {
+            let path = package_path(package_id);
+            if self.filesystem.exists(&path) {
+                continue;
+            }
+
+            let index_dir = path.parent().unwrap();
+            if !self.filesystem.is_dir(index_dir) {
+                continue;
+            }
+
+            let prefix = format!(
+                "{}_{}@",
+                package_id.name().scope(),
+                package_id.name().name()
+            );
+            let mut older_versions = Vec::new();
+
+            for entry in self.filesystem.read_dir(index_dir)? {
+                let older_path = entry.path;
+                let is_version = older_path
+                    .file_name()
+                    .map_or(false, |name| name.to_string_lossy().starts_with(&prefix));
+
+                if is_version
+                    && entry.is_dir
+                    && !targets.contains(&older_path)
+                    && !used.contains(&older_path)
+                {
+                    older_versions.push(older_path);
+                }
+            }
+
+            older_versions.sort();
+
+            if let Some(older_path) = older_versions.pop() {
+                used.insert(older_path.clone());
+                upgrade_sources.insert(path, older_path);
+            }
+        }
+
+        Ok(upgrade_sources)
+    }
+
+    /// The packages of `resolved` that get unpacked into an index. We do not
+    /// need to install the root package, but we should create package links
+    /// for its dependencies once everything is downloaded. `activated` is a
+    /// set, so no package can be downloaded twice.
+    fn packages_to_install(
+        &self,
+        resolved: &Resolve,
+        root_package_id: &PackageId,
+    ) -> Vec<PackageId> {
+        resolved
+            .activated
+            .iter()
+            .filter(|package_id| *package_id != root_package_id)
+            .filter(|package_id| self.includes_realm(resolved.metadata[*package_id].origin_realm))
+            .cloned()
+            .collect()
+    }
+
+    /// Finds the packages an install of `resolved` puts into an index that no
+    /// link file points to, like transitive dependencies whose parent was
+    /// pruned from the resolve. They're dead weight in the index.
+    pub fn unlinked_packages(
+        &self,
+        resolved: &Resolve,
+        root_package_id: &PackageId,
+    ) -> anyhow::Result<Vec<PackageId>> {
+        let installed = self.packages_to_install(resolved, root_package_id);
+        let mut linked = HashSet::new();
+
+        for package_id in installed.iter().chain(Some(root_package_id)) {
+            for (_, deps) in self.linked_aliases(resolved, package_id, root_package_id)? {
+                linked.extend(deps.into_iter().map(|(_, dep_package_id)| dep_package_id));
+            }
+        }
+
+        Ok(installed
+            .into_iter()
+            .filter(|package_id| !linked.contains(package_id))
+            .collect())
+    }
+
+    /// Returns a stable hash of everything an install of `resolved` depends
+    /// on: the resolve itself, the root package and the install's options.
+    /// There's no hash when a require transform or manifest rewrite is set,
+    /// since closures can't be compared between runs.
+    fn resolve_hash(
+        &self,
+        resolved: &Resolve,
+        root_package_id: &PackageId,
+    ) -> anyhow::Result<Option<String>> {
+        if self.options.linking.require_transform.is_some()
+            || self.options.extraction.manifest_rewrite.is_some()
+        {
+            return Ok(None);
+        }
+
+        let index_names: Vec<_> = [Realm::Shared, Realm::Server, Realm::Dev]
+            .iter()
+            .map(|realm| self.index_name(*realm))
+            .collect();
+
+        // A Rojo project inside the project is hashed by its relative path,
+        // so moving the project doesn't make its install out of date.
+        let project_path = self.state_path.parent().unwrap();
+        let mut options = self.options.clone();
+        if let Some(rojo_project) = &mut options.rojo_project {
+            if let Ok(relative_path) = rojo_project.strip_prefix(project_path) {
+                *rojo_project = relative_path.to_path_buf();
+            }
+        }
+
+        // `Resolve` and the options are made of ordered maps and sets, so
+        // they serialize the same way every run.
+        let description = serde_json::to_string(&(
+            env!("CARGO_PKG_VERSION"),
+            root_package_id,
+            resolved,
+            &options,
+            index_names,
+        ))?;
+
+        Ok(Some(
+            blake3::hash(description.as_bytes()).to_hex().to_string(),
+        ))
+    }
+
+    /// Whether the last install was of a resolve with the same `hash` and
+    /// everything it wrote is still in place.
+    fn is_up_to_date(
+        &self,
+        hash: &str,
+        resolved: &Resolve,
+        root_package_id: &PackageId,
+        expected: &[PackageId],
+    ) -> anyhow::Result<bool> {
+        let previous_hash = match self.filesystem.read(&self.state_path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+            Err(err) => return Err(err.into()),
+        };
+
+        if previous_hash != hash.as_bytes() {
+            return Ok(false);
+        }
+
+        Ok(self.missing_packages(resolved, expected).is_empty()
+            && self.verify_links()?.is_empty()
+            && self.root_links_current(resolved, root_package_id)?)
+    }
+
+    /// Whether every root link requires the index directory of the version
+    /// its alias resolves to now. A link left over from an older install can
+    /// still lead to a module, just not the right version of it.
+    fn root_links_current(
+        &self,
+        resolved: &Resolve,
+        root_package_id: &PackageId,
+    ) -> anyhow::Result<bool> {
+        let extension = self.options.linking.link_extension.as_str();
+
+        for (realm, deps) in self.linked_aliases(resolved, root_package_id, root_package_id)? {
+            for (alias, dep_package_id) in deps {
+                let name = self.root_link_name(realm, alias);
+                let links = [
+                    self.realm_dir(realm)
+                        .join(format!("{}.{}", name, extension)),
+                    self.realm_dir(realm)
+                        .join(&name)
+                        .join(format!("init.{}", extension)),
+                ];
+                let dir_name = self.package_dir_name(dep_package_id);
+
+                let current = links.iter().any(|link| match self.filesystem.read(link) {
+                    Ok(contents) => names_dir(&String::from_utf8_lossy(&contents), &dir_name),
+                    Err(_) => false,
+                });
+
+                if !current {
+                    log::debug!("Link {} doesn't require {}", alias, dep_package_id);
+                    return Ok(false);
+                }
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Install only the given package and its transitive dependencies from
+    /// the `Resolve`, along with the root package's links to it.
+    pub fn install_subtree(
+        &self,
+        sources: PackageSourceMap,
+        root_package_id: PackageId,
+        resolved: Resolve,
+        target_package: &PackageName,
+    ) -> anyhow::Result<Vec<Diagnostic>> {
+        let subtree = subtree_resolve(&resolved, &root_package_id, target_package)?;
+        self.clone().install(sources, root_package_id, subtree)
+    }
+
+    /// The dependencies to write links for from the given package, grouped by
+    /// the directory the links go in. Only the root package has more than one
+    /// group, since its dev dependencies are linked from `DevPackages`.
+    ///
+    /// Fails if two aliases in a group differ only by case, since their link
+    /// files would collide on case-insensitive filesystems, unless aliases
+    /// are being normalized and both point at the same package.
+    fn linked_aliases<'a>(
+        &self,
+        resolved: &'a Resolve,
+        package_id: &PackageId,
+        root_package_id: &PackageId,
+    ) -> anyhow::Result<Vec<(Realm, Vec<(&'a str, &'a PackageId)>)>> {
+        let mut checked = Vec::new();
+
+        for (realm, deps) in self.realm_dependencies(resolved, package_id, root_package_id) {
+            let mut spellings: BTreeMap<String, (&str, &PackageId)> = BTreeMap::new();
+            let mut aliases = Vec::new();
+
+            for (alias, dep_package_id) in deps {
+                match spellings.entry(alias.to_lowercase()) {
+                    Entry::Vacant(entry) => {
+                        entry.insert((alias.as_str(), dep_package_id));
+                    }
+                    Entry::Occupied(entry) => {
+                        let (existing_alias, existing_package_id) = *entry.get();
+
+                        if existing_alias != alias.as_str() {
+                            if self.options.linking.normalize_aliases
+                                && existing_package_id.to_string() == dep_package_id.to_string()
+                            {
+                                log::debug!(
+                                    "Linking {} of {} as {}",
+                                    alias,
+                                    package_id,
+                                    existing_alias
+                                );
+                                continue;
+                            }
+
+                            bail!(
+                                "Dependencies {} and {} of {} differ only by case, so their \
+                                 link files would collide on case-insensitive filesystems",
+                                existing_alias,
+                                alias,
+                                package_id
+                            );
+                        }
+                    }
+                }
+
+                aliases.push((alias.as_str(), dep_package_id));
+            }
+
+            checked.push((realm, aliases));
+        }
+
+        Ok(checked)
+    }
+
+    /// The staging directory to unpack packages into, if one was given and
+    /// moving out of it into the project is atomic.
+    fn usable_staging_dir(&self) -> anyhow::Result<Option<PathBuf>> {
+        let staging_dir = match &self.options.extraction.staging_dir {
+            Some(staging_dir) => staging_dir,
+            None => return Ok(None),
+        };
+
+        let project_path = self.state_path.parent().unwrap();
+        self.create_dir_all(staging_dir)?;
+
+        if self.filesystem.same_volume(staging_dir, project_path)? {
+            Ok(Some(staging_dir.clone()))
+        } else {
+            let message = format!(
+                "Staging directory {} is not on the same volume as {}, so packages will be \
+                 unpacked in place instead",
+                staging_dir.display(),
+                project_path.display()
+            );
+            self.warn(
+                Diagnostic::new(DiagnosticKind::StagingDirectory, message).with_path(staging_dir),
+            )?;
+
+            Ok(None)
+        }
+    }
+
+    /// Returns the packages out of `expected` that have no directory in their
+    /// realm's index.
+    fn missing_packages(&self, resolved: &Resolve, expected: &[PackageId]) -> Vec<PackageId> {
+        expected
+            .iter()
+            .filter(|package_id| {
+                let realm = resolved.metadata[*package_id].origin_realm;
+                let path = self
+                    .index_dir(realm)
+                    .join(self.package_dir_name(package_id));
+
+                !self.is_present(package_id, &path)
+            })
+            .cloned()
+            .collect()
+    }
+
+    /// Whether the package directory at `path` holds `package_id`, according
+    /// to the presence check.
+    fn is_present(&self, package_id: &PackageId, path: &Path) -> bool {
+        if !self.filesystem.exists(path) {
+            return false;
+        }
+
+        if self.options.extraction.presence_check == PresenceCheck::DirName {
+            return true;
+        }
+
+        let recorded = match self.filesystem.read(&checksum_path(path)) {
+            Ok(recorded) => String::from_utf8_lossy(&recorded).into_owned(),
+            Err(_) => return false,
+        };
+        let mut lines = recorded.lines();
+
+        if lines.next() != Some(package_id.to_string().as_str()) {
+            return false;
+        }
+
+        if self.options.extraction.presence_check == PresenceCheck::Checksum {
+            return true;
+        }
+
+        match self.content_hash(path) {
+            Ok(hash) => lines.next() == Some(hash.as_str()),
+            Err(err) => {
+                log::debug!("Couldn't hash {}: {}", path.display(), err);
+                false
+            }
+        }
+    }
+
+    /// Record which package was unpacked into `path` and a hash of its files,
+    /// for presence checks that need them.
+    fn record_checksum(&self, package_id: &PackageId, path: &Path) -> anyhow::Result<()> {
+        if self.options.extraction.presence_check == PresenceCheck::DirName {
+            return Ok(());
+        }
+
+        let contents = format!("{}\n{}\n", package_id, self.content_hash(path)?);
+        self.filesystem
+            .write(&checksum_path(path), contents.as_bytes())?;
+
+        Ok(())
+    }
+
+    /// A hash of the paths and contents of the files in the package directory
+    /// `path`. The links written into it are left out, since they're written
+    /// again by every install.
+    fn content_hash(&self, path: &Path) -> anyhow::Result<String> {
+        let links_dir = path.join(&self.options.linking.package_links_dir);
+        let mut hasher = blake3::Hasher::new();
+
+        for entry in self.filesystem.walk_dir(path)? {
+            if !entry.is_file || entry.path.starts_with(&links_dir) {
+                continue;
+            }
+
+            let relative = entry.path.strip_prefix(path)?;
+            let contents = self.filesystem.read(&entry.path)?;
+
+            hasher.update(relative.to_string_lossy().replace('\\', "/").as_bytes());
+            hasher.update(&[0]);
+            hasher.update(&(contents.len() as u64).to_le_bytes());
+            hasher.update(&contents);
+        }
+
+        Ok(hasher.finalize().to_hex().to_string())
+    }
+
+    /// Create a directory and all of its parents. Tasks installing into the
+    /// same index race to create it, and a concurrent `clean` can remove a
+    /// parent partway through, so one failed attempt is retried before giving
+    /// up. Finding the directory already there is fine.
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        let err = match self.filesystem.create_dir_all(path) {
+            Ok(()) => return Ok(()),
+            Err(err) => err,
+        };
+
+        match err.kind() {
+            io::ErrorKind::AlreadyExists if self.filesystem.is_dir(path) => Ok(()),
+            io::ErrorKind::AlreadyExists | io::ErrorKind::NotFound => {
+                log::debug!("Retrying creation of {}: {}", path.display(), err);
+
+                match self.filesystem.create_dir_all(path) {
+                    Err(err)
+                        if err.kind() == io::ErrorKind::AlreadyExists
+                            && self.filesystem.is_dir(path) =>
+                    {
+                        Ok(())
+                    }
+                    result => result,
+                }
+            }
+            _ => Err(err),
+        }
+    }
+
+    /// Get the contents of a package from the cache, falling back to
+    /// downloading it from its source.
+    fn fetch_package(
+        &self,
+        source: &PackageSource,
+        package_id: &PackageId,
+    ) -> anyhow::Result<PackageContents> {
+        if let Some(cache) = &self.cache {
+            if let Some(contents) = cache.get(package_id)? {
+                log::trace!("Using cached contents of {}", package_id);
+                return Ok(contents);
+            }
+        }
+
+        let contents = source.download_package(package_id)?;
+
+        if let Some(cache) = &self.cache {
+            if let Err(err) = cache.insert(package_id, &contents) {
+                let message = format!("Could not add {} to the package cache: {}", package_id, err);
+                self.warn(
+                    Diagnostic::new(DiagnosticKind::Cache, message).with_package(package_id),
+                )?;
+            }
+        }
+
+        Ok(contents)
+    }
+
+    /// Finds cycles in the dependency graph of `resolved`, each starting from
+    /// its lowest package. Resolution shouldn't produce them, but links are
+    /// written for them all the same. At least one cycle is found through
+    /// each group of packages that depend on each other.
+    fn dependency_cycles(
+        &self,
+        resolved: &Resolve,
+        root_package_id: &PackageId,
+    ) -> Vec<Vec<PackageId>> {
+        fn visit<'a>(
+            package_id: &'a PackageId,
+            edges: &BTreeMap<&'a PackageId, BTreeSet<&'a PackageId>>,
+            stack: &mut Vec<&'a PackageId>,
+            finished: &mut HashSet<&'a PackageId>,
+            cycles: &mut BTreeSet<Vec<PackageId>>,
+        ) {
+            if finished.contains(package_id) {
+                return;
+            }
+
+            if let Some(start) = stack.iter().position(|id| *id == package_id) {
+                let mut cycle: Vec<_> = stack[start..].iter().map(|id| (*id).clone()).collect();
+                let lowest = (0..cycle.len()).min_by_key(|&i| &cycle[i]).unwrap();
+                cycle.rotate_left(lowest);
+                cycles.insert(cycle);
+                return;
+            }
+
+            stack.push(package_id);
+            for dep in edges.get(package_id).into_iter().flatten() {
+                visit(dep, edges, stack, finished, cycles);
+            }
+            stack.pop();
+
+            finished.insert(package_id);
+        }
+
+        let mut edges: BTreeMap<&PackageId, BTreeSet<&PackageId>> = BTreeMap::new();
+        for package_id in &resolved.activated {
+            for (_, deps) in self.realm_dependencies(resolved, package_id, root_package_id) {
+                edges.entry(package_id).or_default().extend(deps.values());
+            }
+        }
+
+        let mut cycles = BTreeSet::new();
+        let mut finished = HashSet::new();
+        for package_id in edges.keys() {
+            visit(
+                package_id,
+                &edges,
+                &mut Vec::new(),
+                &mut finished,
+                &mut cycles,
+            );
+        }
+
+        cycles.into_iter().collect()
+    }
+
+    /// Whether packages are unpacked as they're downloaded, rather than
+    /// downloaded in full first.
+    fn streams_downloads(&self) -> bool {
+        self.cache.is_none()
+            && self.options.extraction.limit.is_none()
+            && !self.options.extraction.validate_manifests
+            && !self.updates_in_place()
+            && !self.options.extraction.keep_archives
+    }
+
+    /// Whether packages placed in the given realm are part of this install.
+    fn includes_realm(&self, realm: Realm) -> bool {
+        !(self.options.production && realm == Realm::Dev)
+    }
+
+    /// The dependencies of a package, along with the realm each group is
+    /// linked from. Resolution puts every package's dependencies in the shared
+    /// graph, so only the root package's server and dev dependencies come from
+    /// anywhere else. Those only appear in resolves produced elsewhere, and
+    /// dev dependencies are left out of production installs.
+    fn realm_dependencies<'a>(
+        &self,
+        resolved: &'a Resolve,
+        package_id: &PackageId,
+        root_package_id: &PackageId,
+    ) -> Vec<(Realm, &'a BTreeMap<String, PackageId>)> {
+        let mut dependencies = Vec::new();
+
+        if let Some(deps) = resolved.shared_dependencies.get(package_id) {
+            dependencies.push((Realm::Shared, deps));
+        }
+
+        if package_id == root_package_id {
+            let graphs = [
+                (Realm::Server, &resolved.server_dependencies),
+                (Realm::Dev, &resolved.dev_dependencies),
+            ];
+
+            for (realm, graph) in graphs.iter() {
+                if !self.includes_realm(*realm) {
+                    continue;
+                }
+
+                if let Some(deps) = graph.get(package_id) {
+                    dependencies.push((*realm, deps));
+                }
+            }
+        }
+
+        dependencies
+    }
+
+    /// The directory that root package links for the given realm live in.
+    fn realm_dir(&self, realm: Realm) -> &Path {
+        if self.options.flat {
+            return &self.shared_dir;
+        }
+
+        match realm {
+            Realm::Shared => &self.shared_dir,
+            Realm::Server => &self.server_dir,
+            Realm::Dev => &self.dev_dir,
+        }
+    }
+
+    /// Each distinct directory that root package links are written to.
+    fn package_dirs(&self) -> Vec<&Path> {
+        let mut dirs = Vec::new();
+
+        for realm in [Realm::Shared, Realm::Server, Realm::Dev].iter() {
+            let dir = self.realm_dir(*realm);
+
+            if !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+
+        dirs
+    }
+
+    /// The name of the root package's link file for a dependency in the given
+    /// realm, without its extension.
+    fn root_link_name(&self, realm: Realm, alias: &str) -> String {
+        match realm {
+            Realm::Server if self.options.flat => format!("Server_{}", alias),
+            Realm::Dev if self.options.flat => format!("Dev_{}", alias),
+            _ => alias.to_owned(),
+        }
+    }
+
+    /// The name of the given package's directory in the index.
+    fn package_dir_name(&self, package_id: &PackageId) -> String {
+        if self.options.sortable_dir_names {
+            sortable_package_id_file_name(package_id)
+        } else {
+            package_id_file_name(package_id)
+        }
+    }
+
+    /// The directory that packages placed in the given realm are unpacked into.
+    fn index_dir(&self, realm: Realm) -> &Path {
+        if self.options.flat {
+            return &self.shared_index_dir;
+        }
+
+        match realm {
+            Realm::Shared => &self.shared_index_dir,
+            Realm::Server => &self.server_index_dir,
+            Realm::Dev => &self.dev_index_dir,
+        }
+    }
+
+    /// The name of the directory that packages placed in the given realm are
+    /// unpacked into.
+    fn index_name(&self, realm: Realm) -> String {
+        let index_dir = match realm {
+            Realm::Shared => &self.shared_index_dir,
+            Realm::Server => &self.server_index_dir,
+            Realm::Dev => &self.dev_index_dir,
+        };
+
+        index_dir
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    /// The suffix that links to the given package need in order to reach its
+    /// entry point. Packages without a clear entry point are linked to
+    /// directly, which is unlikely to be requirable, so we warn about them.
+    fn entry_suffix(
+        &self,
+        package_id: &PackageId,
+        detected: anyhow::Result<&'static str>,
+    ) -> anyhow::Result<Option<&'static str>> {
+        match detected {
+            Ok(suffix) => Ok(Some(suffix)),
+            Err(err) => {
+                let message = format!("{} may not be requirable: {}", package_id, err);
+                self.warn(
+                    Diagnostic::new(DiagnosticKind::EntryPoint, message).with_package(package_id),
+                )?;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Log a warning about the install and keep it to be returned from the
+    /// install, or fail with it in strict mode.
+    fn warn(&self, diagnostic: Diagnostic) -> anyhow::Result<()> {
+        if self.options.strict {
+            bail!("{}", diagnostic);
+        }
+
+        log::warn!("{}", diagnostic);
+        self.diagnostics.lock().unwrap().push(diagnostic);
+        Ok(())
+    }
+
+    /// The warnings logged during the install so far, in the order they
+    /// happened.
+    fn take_diagnostics(&self) -> Vec<Diagnostic> {
+        std::mem::take(&mut *self.diagnostics.lock().unwrap())
+    }
+
+    /// Record that the given package is being unpacked into `path`, failing if
+    /// a different package already claimed it during this install. Paths are
+    /// compared case-insensitively, since ids that differ only by the case of
+    /// their build metadata collide on case-insensitive filesystems.
+    ///
+    /// Ids are compared by their full string form, because `PackageId`
+    /// equality ignores build metadata.
+    fn claim_index_path(&self, path: &Path, package_id: &PackageId) -> anyhow::Result<()> {
+        let key = path.to_string_lossy().to_lowercase();
+        let mut claimed = self.claimed_paths.lock().unwrap();
+
+        match claimed.get(&key) {
+            Some(existing) if existing.to_string() != package_id.to_string() => bail!(
+                "Packages {} and {} would both be installed into {}",
+                existing,
+                package_id,
+                path.display()
+            ),
+            Some(_) => Ok(()),
+            None => {
+                claimed.insert(key, package_id.clone());
+                Ok(())
+            }
+        }
+    }
+
+    /// Put the files of a package into the empty directory `target`, hard
+    /// linking them from the cache when using `InstallStrategy::Hardlink`
+    /// and unpacking a copy otherwise. Returns the number of files placed.
+    fn place_contents(
+        &self,
+        package_id: &PackageId,
+        contents: &PackageContents,
+        target: &Path,
+        progress: &dyn Fn(u64, u64),
+    ) -> anyhow::Result<u64> {
+        let cached = match (self.options.extraction.strategy, &self.cache) {
+            (InstallStrategy::Hardlink, Some(cache)) => cache.unpacked(package_id, contents)?,
+            _ => None,
+        };
+
+        if let Some(cached) = cached {
+            match self.hard_link_tree(&cached, target) {
+                Ok(files) => return Ok(files),
+                Err(err) => {
+                    log::debug!(
+                        "Copying {} because it couldn't be hard linked from the cache: {}",
+                        package_id,
+                        err
+                    );
+
+                    self.filesystem.remove_dir_all(target)?;
+                    self.create_dir_all(target)?;
+                }
+            }
+        }
+
+        if self.updates_in_place() {
+            return contents.update_into(target, self.filesystem.as_ref(), Some(progress));
+        }
+
+        contents.unpack_into(target, self.filesystem.as_ref(), Some(progress))
+    }
+
+    /// Write an `init.luau` into the package unpacked at `path` that requires
+    /// its real entry point at `target`, relative to the package root.
+    fn write_init_shim(&self, path: &Path, target: &str) -> anyhow::Result<()> {
+        let shim_path = path.join("init.luau");
+        let target = match self.options.linking.require_path_style {
+            RequirePathStyle::Relative => target.to_owned(),
+            RequirePathStyle::AbsoluteRojo { .. } => rojo_instance_path("script", target),
+        };
+        let contents = self.link_contents(target);
+
+        log::trace!("Writing {}", shim_path.display());
+        self.filesystem.write(&shim_path, contents.as_bytes())?;
+
+        Ok(())
+    }
+
+    /// Recreate the directory tree at `from` under `to`, hard linking every
+    /// file, or junctioning the directories at the top of the tree when
+    /// using junctions. Returns the number of files linked.
+    fn hard_link_tree(&self, from: &Path, to: &Path) -> anyhow::Result<u64> {
+        let mut files = 0;
+        let mut entries = WalkDir::new(from).min_depth(1).into_iter();
+
+        while let Some(entry) = entries.next() {
+            let entry = entry?;
+            let path = to.join(entry.path().strip_prefix(from)?);
+
+            // Links are written into the links directory after unpacking, and
+            // would end up in the cache if it were junctioned.
+            let junctioned = self.options.extraction.junctions
+                && entry.depth() == 1
+                && entry.file_type().is_dir()
+                && entry.file_name().to_str()
+                    != Some(self.options.linking.package_links_dir.as_str());
+
+            if junctioned {
+                match self.filesystem.junction(entry.path(), &path) {
+                    Ok(()) => {
+                        entries.skip_current_dir();
+                        files += WalkDir::new(entry.path())
+                            .into_iter()
+                            .filter(|entry| {
+                                entry
+                                    .as_ref()
+                                    .map_or(false, |entry| entry.file_type().is_file())
+                            })
+                            .count() as u64;
+                        continue;
+                    }
+                    Err(err) => log::debug!(
+                        "Hard linking {} because it couldn't be junctioned: {}",
+                        entry.path().display(),
+                        err
+                    ),
+                }
+            }
+
+            if entry.file_type().is_dir() {
+                self.create_dir_all(&path)?;
+            } else {
+                self.filesystem.hard_link(entry.path(), &path)?;
+                files += 1;
+            }
+        }
+
+        Ok(files)
+    }
+
+    /// Whether a failed download is of a package its source no longer has,
+    /// which the fallback policy replaces with a compatible version.
+    fn falls_back(&self, err: &anyhow::Error) -> bool {
+        self.options.on_yanked == FallbackPolicy::NearestCompatible
+            && err.downcast_ref::<PackageNotFound>().is_some()
+    }
+
+    /// Install the nearest version compatible with the package `yanked` that
+    /// its source still has, returning the one installed. Versions already
+    /// in the resolve aren't considered, since they're installed anyway.
+    fn install_replacement(
+        &self,
+        sources: &PackageSourceMap,
+        resolved: &Resolve,
+        yanked: &PackageId,
+    ) -> anyhow::Result<Option<PackageId>> {
+        let metadata = &resolved.metadata[yanked];
+        let source = sources.get(&metadata.source_registry).unwrap();
+
+        let version_req = VersionReq::parse(&format!("^{}", yanked.version()))?;
+        let package_req = PackageReq::new(yanked.name().clone(), version_req);
+
+        let mut candidates: Vec<_> = source
+            .query(&package_req)?
+            .iter()
+            .map(Manifest::package_id)
+            .filter(|package_id| !resolved.activated.contains(package_id))
+            .collect();
+
+        // Newer versions from nearest to furthest, then older ones.
+        candidates.sort_by(|a, b| {
+            let a_older = a.version() < yanked.version();
+            let b_older = b.version() < yanked.version();
+
+            a_older.cmp(&b_older).then_with(|| {
+                if a_older {
+                    b.version().cmp(a.version())
+                } else {
+                    a.version().cmp(b.version())
+                }
+            })
+        });
+
+        for candidate in candidates {
+            match self.download_with_retries(source, &candidate, metadata.origin_realm) {
+                Ok(()) => {
+                    self.fetched.lock().unwrap().insert(candidate.clone());
+                    return Ok(Some(candidate));
+                }
+                Err(err) if err.downcast_ref::<PackageNotFound>().is_some() => {
+                    log::debug!("{} is missing too: {:#}", candidate, err);
+                }
+                Err(err) => return Err(err),
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Download a package like `download_contents`, trying again after a
+    /// delay that doubles each time it fails, up to the configured number of
+    /// retries. Failures that would only happen again aren't retried.
+    fn download_with_retries(
+        &self,
+        source: &PackageSource,
+        package_id: &PackageId,
+        realm: Realm,
+    ) -> anyhow::Result<()> {
+        let source = match self.network.download_timeout {
+            Some(timeout) => source.with_download_timeout(timeout),
+            None => source.clone(),
+        };
+        let mut retry = 0;
+
+        loop {
+            self.check_cancelled(package_id)?;
+
+            match self.download_contents(&source, package_id, realm) {
+                Err(err)
+                    if retry < self.network.download_retries
+                        && !self.is_cancelled()
+                        && is_transient(&err) =>
+                {
+                    retry += 1;
+                    log::debug!("Retrying download of {}: {:#}", package_id, err);
+
+                    self.reporter
+                        .report(ProgressEvent::Retrying(package_id.clone(), retry));
+                    std::thread::sleep(retry_delay(retry));
+                }
+                result => return result,
+            }
+        }
+    }
+
+    /// Download a package and unpack it into its realm's index. Without a
+    /// cache, which needs the whole archive, packages are unpacked straight
+    /// from sources that can stream them, so large packages are never held
+    /// in memory all at once. Archives that can't be read as a stream are
+    /// downloaded again in full.
+    fn download_contents(
+        &self,
+        source: &PackageSource,
+        package_id: &PackageId,
+        realm: Realm,
+    ) -> anyhow::Result<()> {
+        if self.streams_downloads() {
+            let _download_permit = self
+                .network
+                .download_limit
+                .as_ref()
+                .map(|limit| limit.acquire());
+
+            let started = Instant::now();
+
+            if let Some(stream) = source.stream_package(package_id)? {
+                self.check_cancelled(package_id)?;
+
+                // Unpacking waits on the stream whenever it reads from it, so
+                // that waiting is counted as downloading.
+                let waited = Arc::new(Mutex::new(Duration::default()));
+                let stream = TimedReader {
+                    inner: CancellableReader {
+                        inner: stream,
+                        cancelled: self.cancelled.clone(),
+                    },
+                    waited: waited.clone(),
+                };
+
+                let unpacking_started = Instant::now();
+                let id = package_id.clone();
+                let result = self.on_extraction_pool(move |context| {
+                    context.write_unpacked(&id, realm, |path| {
+                        PackageContents::unpack_stream(stream, path, context.filesystem.as_ref())
+                    })
+                });
+
+                let unpacking = unpacking_started
+                    .elapsed()
+                    .saturating_sub(*waited.lock().unwrap());
+                {
+                    let mut stats = self.stats.lock().unwrap();
+                    stats.download_time += started.elapsed().saturating_sub(unpacking);
+                    stats.extraction_time += unpacking;
+                }
+
+                match result {
+                    // The archive is downloaded again in full below, so
+                    // whatever was unpacked from it so far goes.
+                    Err(err) if err.is::<NotStreamable>() => {
+                        log::debug!("Downloading {} in full: {:#}", package_id, err);
+
+                        let path = self
+                            .index_dir(realm)
+                            .join(self.package_dir_name(package_id));
+                        if self.filesystem.exists(&path) {
+                            self.filesystem.remove_dir_all(&path)?;
+                        }
+                    }
+                    result => return result,
+                }
+            }
+        }
+
+        let contents = {
+            let _download_permit = self
+                .network
+                .download_limit
+                .as_ref()
+                .map(|limit| limit.acquire());
+            let started = Instant::now();
+            let contents = self.fetch_package(source, package_id)?;
+            self.stats.lock().unwrap().download_time += started.elapsed();
+
+            contents
+        };
+
+        self.check_cancelled(package_id)?;
+        let _extraction_permit = self
+            .options
+            .extraction
+            .limit
+            .as_ref()
+            .map(|limit| limit.acquire());
+
+        if self.options.extraction.validate_manifests {
+            validate_manifest(package_id, &contents)?;
+        }
+
+        if self.options.extraction.keep_archives {
+            self.keep_archive(package_id, &contents)?;
+        }
+
+        let started = Instant::now();
+        let id = package_id.clone();
+        let result =
+            self.on_extraction_pool(move |context| context.write_contents(&id, &contents, realm));
+        self.stats.lock().unwrap().extraction_time += started.elapsed();
+
+        result
+    }
+
+    /// Whether a failure elsewhere cancelled the install, so downloads still
+    /// in progress should give up.
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    fn check_cancelled(&self, package_id: &PackageId) -> anyhow::Result<()> {
+        if self.is_cancelled() {
+            bail!("Installing {} was cancelled", package_id);
+        }
+
+        Ok(())
+    }
+
+    /// Run `extract` on the extraction pool if there is one, or right here
+    /// otherwise.
+    fn on_extraction_pool(
+        &self,
+        extract: impl FnOnce(&Self) -> anyhow::Result<()> + Send + 'static,
+    ) -> anyhow::Result<()> {
+        match &self.options.extraction.pool {
+            Some(pool) => {
+                let context = self.clone();
+                pool.run(move || extract(&context))
+            }
+            None => extract(self),
+        }
+    }
+
+    /// Save a package's archive into `packages/.archives`.
+    fn keep_archive(
+        &self,
+        package_id: &PackageId,
+        contents: &PackageContents,
+    ) -> anyhow::Result<()> {
+        let archives_dir = self.shared_dir.join(".archives");
+        self.create_dir_all(&archives_dir)?;
+
+        let path = archives_dir.join(format!("{}.zip", package_id_file_name(package_id)));
+        log::trace!("Keeping archive {}", path.display());
+        self.filesystem.write(&path, contents.data())?;
+
+        Ok(())
+    }
+
+    fn write_contents(
+        &self,
+        package_id: &PackageId,
+        contents: &PackageContents,
+        realm: Realm,
+    ) -> anyhow::Result<()> {
+        let progress = |written, total| {
+            self.reporter
+                .report(ProgressEvent::Unpacking(package_id.clone(), written, total));
+        };
+
+        self.write_unpacked(package_id, realm, |path| {
+            self.place_contents(package_id, contents, path, &progress)
+        })
+    }
+
+    /// Put a package into its realm's index, where `unpack` writes its files
+    /// into the directory it's given and returns how many it wrote. That's either the package's directory
+    /// in the index or one in the staging directory that's moved there after.
+    fn write_unpacked(
+        &self,
+        package_id: &PackageId,
+        realm: Realm,
+        unpack: impl FnOnce(&Path) -> anyhow::Result<u64>,
+    ) -> anyhow::Result<()> {
+        self.check_cancelled(package_id)?;
+
+        let path = self
+            .index_dir(realm)
+            .join(self.package_dir_name(package_id));
+
+        self.claim_index_path(&path, package_id)?;
+
+        let staging_dir = match &self.options.extraction.staging_dir {
+            Some(staging_dir) => staging_dir,
+            None => {
+                // Writing over files hard linked by an earlier install would
+                // change them in the cache too.
+                if self.options.extraction.strategy == InstallStrategy::Hardlink
+                    && self.filesystem.exists(&path)
+                {
+                    self.filesystem.remove_dir_all(&path)?;
+                }
+
+                if let Some(older_path) = self.upgrade_sources.get(&path) {
+                    log::debug!("Updating {} from {}", package_id, older_path.display());
+                    self.filesystem.rename(older_path, &path)?;
+                }
+
+                // Links from an earlier install are written again after
+                // unpacking, and mustn't be taken for a directory the package
+                // ships.
+                let links_dir = path.join(&self.options.linking.package_links_dir);
+                if self.filesystem.exists(&links_dir) {
+                    self.filesystem.remove_dir_all(&links_dir)?;
+                }
+
+                self.create_dir_all(&path)?;
+                let files = unpack(&path)?;
+                self.check_cancelled(package_id)?;
+                self.finish_unpacking(package_id, &path, files)?;
+                self.record_checksum(package_id, &path)?;
+
+                return Ok(());
+            }
+        };
+
+        // Other installs may share the staging directory.
+        let staged_path = staging_dir.join(format!(
+            "{}.{}",
+            self.package_dir_name(package_id),
+            std::process::id()
+        ));
+
+        if self.filesystem.exists(&staged_path) {
+            self.filesystem.remove_dir_all(&staged_path)?;
+        }
+
+        self.create_dir_all(&staged_path)?;
+        let files = unpack(&staged_path)?;
+        self.check_cancelled(package_id)?;
+        self.finish_unpacking(package_id, &staged_path, files)?;
+
+        if self.filesystem.exists(&path) {
+            self.filesystem.remove_dir_all(&path)?;
+        }
+
+        self.create_dir_all(self.index_dir(realm))?;
+        self.filesystem.rename(&staged_path, &path)?;
+        self.record_checksum(package_id, &path)?;
+
+        Ok(())
+    }
+
+    /// Record the entry suffix of a package that was just unpacked into
+    /// `path` as `files` files, first writing its init shim if it needs one.
+    fn finish_unpacking(
+        &self,
+        package_id: &PackageId,
+        path: &Path,
+        files: u64,
+    ) -> anyhow::Result<()> {
+        self.rewrite_manifest(package_id, path)?;
+
+        if self
+            .filesystem
+            .exists(&path.join(&self.options.linking.package_links_dir))
+        {
+            self.shipped_links_dirs
+                .lock()
+                .unwrap()
+                .insert(package_id.to_string());
+        }
+
+        // Its link will have nothing to point to.
+        if files == 0 {
+            let message = format!(
+                "{} has no files, so it was installed as an empty directory",
+                package_id
+            );
+            self.warn(
+                Diagnostic::new(DiagnosticKind::EmptyPackage, message)
+                    .with_package(package_id)
+                    .with_path(path),
+            )?;
+        }
+
+        let entry = detect_installed_entry_point(path, self.filesystem.as_ref()).ok();
+        if let Some(ambiguity) = entry.as_ref().and_then(|entry| entry.ambiguity.clone()) {
+            let message = format!("{}, so its init.luau is used", ambiguity);
+            self.warn(
+                Diagnostic::new(DiagnosticKind::EntryPoint, message)
+                    .with_package(package_id)
+                    .with_path(path),
+            )?;
+        }
+
+        let mut suffix = entry.map(|entry| entry.suffix);
+
+        // The shim makes the package root its entry point.
+        let shim_target = suffix
+            .filter(|suffix| self.options.extraction.init_shims && suffix.starts_with("/src"))
+            .map(|suffix| suffix.trim_start_matches('/'));
+        if let Some(shim_target) = shim_target {
+            self.write_init_shim(path, shim_target)?;
+            suffix = Some("");
+        }
+
+        self.unpacked_entries
+            .lock()
+            .unwrap()
+            .insert(package_id.to_string(), suffix);
+
+        Ok(())
+    }
+
+    /// Run the manifest rewrite, if there is one, on the package unpacked
+    /// into `path`, and check that what it left behind still makes sense.
+    fn rewrite_manifest(&self, package_id: &PackageId, path: &Path) -> anyhow::Result<()> {
+        let rewrite = match &self.options.extraction.manifest_rewrite {
+            Some(rewrite) => rewrite,
+            None => return Ok(()),
+        };
+
+        let manifest_path = path.join(MANIFEST_FILE_NAME);
+        if !self.filesystem.exists(&manifest_path) {
+            return Ok(());
+        }
+
+        rewrite(package_id, &manifest_path, self.filesystem.as_ref())
+            .with_context(|| format!("Could not rewrite the manifest of {}", package_id))?;
+
+        let manifest = Manifest::from_slice(&self.filesystem.read(&manifest_path)?)
+            .with_context(|| format!("Rewritten manifest of {} is invalid", package_id))?;
+        if manifest.package_id() != *package_id {
+            bail!(
+                "Rewritten manifest of {} is for {} instead",
+                package_id,
+                manifest.package_id()
+            );
+        }
+
+        let project_path = path.join("default.project.json");
+        if self.filesystem.exists(&project_path) {
+            serde_json::from_slice::<serde_json::Value>(&self.filesystem.read(&project_path)?)
+                .with_context(|| {
+                    format!(
+                        "Rewritten default.project.json of {} is invalid",
+                        package_id
+                    )
+                })?;
+        }
+
+        Ok(())
+    }
+}
+
+/// A stream that keeps count of how long reading from it has taken.
+struct TimedReader<R> {
+    inner: R,
+    waited: Arc<Mutex<Duration>>,
+}
+
+impl<R: Read> Read for TimedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let started = Instant::now();
+        let result = self.inner.read(buf);
+        *self.waited.lock().unwrap() += started.elapsed();
+
+        result
+    }
+}
+
+/// A stream that stops giving data once the install it's read for has been
+/// cancelled, so unpacking it stops at its next read.
+struct CancellableReader<R> {
+    inner: R,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl<R: Read> Read for CancellableReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.cancelled.load(Ordering::SeqCst) {
+            return Err(io::Error::new(
+                io::ErrorKind::Other,
+                "the install was cancelled",
+            ));
+        }
+
+        self.inner.read(buf)
+    }
+}
+
+/// Checks that the manifest packed into a downloaded archive is for the
+/// package that was requested.
+fn validate_manifest(package_id: &PackageId, contents: &PackageContents) -> anyhow::Result<()> {
+    let manifest = contents
+        .manifest()
+        .with_context(|| format!("Could not read the manifest of {}", package_id))?;
+    let archive_id = manifest.package_id();
+
+    if archive_id != *package_id {
+        bail!(
+            "Requested {}, but the archive the registry sent is for {}. \
+            The registry entry may be corrupted or spoofed.",
+            package_id,
+            archive_id
+        );
+    }
+
+    Ok(())
+}
+
+/// Checks that every package referenced by a dependency graph in the resolve
+/// was activated. Links to anything else would point at an `_index` directory
+/// that never gets written.
+fn validate_resolve(resolved: &Resolve) -> anyhow::Result<()> {
+    let graphs = [
+        &resolved.shared_dependencies,
+        &resolved.server_dependencies,
+        &resolved.dev_dependencies,
+    ];
+
+    for graph in graphs.iter() {
+        for (package_id, dependencies) in graph.iter() {
+            for (alias, dep_package_id) in dependencies {
+                if !resolved.activated.contains(dep_package_id) {
+                    bail!(
+                        "Dependency {} ({}) of {} was never activated during resolution",
+                        alias,
+                        dep_package_id,
+                        package_id
+                    );
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Narrows a resolve down to the packages named `target` and everything they
+/// depend on. The root package stays activated, but keeps only its
+/// dependencies on `target`.
+fn subtree_resolve(
+    resolved: &Resolve,
+    root_package_id: &PackageId,
+    target: &PackageName,
+) -> anyhow::Result<Resolve> {
+    let mut to_visit: Vec<_> = resolved
+        .activated
+        .iter()
+        .filter(|package_id| package_id.name() == target && *package_id != root_package_id)
+        .cloned()
+        .collect();
+
+    if to_visit.is_empty() {
+        bail!(
+            "Package {} is not part of the resolved dependencies",
+            target
+        );
+    }
+
+    let mut subtree = Resolve {
+        features: resolved.features.clone(),
+        ..Resolve::default()
+    };
+    subtree.activated.insert(root_package_id.clone());
+    subtree.metadata.insert(
+        root_package_id.clone(),
+        resolved.metadata[root_package_id].clone(),
+    );
+
+    while let Some(package_id) = to_visit.pop() {
+        if !subtree.activated.insert(package_id.clone()) {
+            continue;
+        }
+
+        subtree
+            .metadata
+            .insert(package_id.clone(), resolved.metadata[&package_id].clone());
+
+        let graphs = vec![
+            (
+                &resolved.shared_dependencies,
+                &mut subtree.shared_dependencies,
+            ),
+            (
+                &resolved.server_dependencies,
+                &mut subtree.server_dependencies,
+            ),
+            (&resolved.dev_dependencies, &mut subtree.dev_dependencies),
+        ];
+
+        for (graph, subtree_graph) in graphs {
+            if let Some(dependencies) = graph.get(&package_id) {
+                to_visit.extend(dependencies.values().cloned());
+                subtree_graph.insert(package_id.clone(), dependencies.clone());
+            }
+
+            if let Some(root_dependencies) = graph.get(root_package_id) {
+                let targets = root_dependencies
+                    .iter()
+                    .filter(|(_, dep_package_id)| **dep_package_id == package_id)
+                    .map(|(alias, dep_package_id)| (alias.clone(), dep_package_id.clone()));
+
+                subtree_graph
+                    .entry(root_package_id.clone())
+                    .or_default()
+                    .extend(targets);
+            }
+        }
+    }
+
+    Ok(subtree)
+}
+
+/// Puts `replacement` everywhere `yanked` is in the resolve.
+fn replaced_resolve(mut resolved: Resolve, yanked: &PackageId, replacement: &PackageId) -> Resolve {
+    if resolved.activated.remove(yanked) {
+        resolved.activated.insert(replacement.clone());
+    }
+
+    if let Some(metadata) = resolved.metadata.remove(yanked) {
+        resolved.metadata.insert(replacement.clone(), metadata);
+    }
+
+    if let Some(features) = resolved.features.remove(yanked) {
+        resolved.features.insert(replacement.clone(), features);
+    }
+
+    if let Some(peer_dependencies) = resolved.peer_dependencies.remove(yanked) {
+        resolved
+            .peer_dependencies
+            .insert(replacement.clone(), peer_dependencies);
+    }
+
+    let graphs = vec![
+        &mut resolved.shared_dependencies,
+        &mut resolved.server_dependencies,
+        &mut resolved.dev_dependencies,
+    ];
+
+    for graph in graphs {
+        if let Some(dependencies) = graph.remove(yanked) {
+            graph.insert(replacement.clone(), dependencies);
+        }
+
+        for dependencies in graph.values_mut() {
+            for dep_package_id in dependencies.values_mut() {
+                if dep_package_id == yanked {
+                    *dep_package_id = replacement.clone();
+                }
+            }
+        }
+    }
+
+    resolved
+}
+
+/// Points every dependency in `resolved` at the activated package it's equal
+/// to. Ids that differ only in build metadata are equal, but their index
+/// directories are named after the whole version, so links have to use the
+/// id that's installed.
+fn canonical_resolve(mut resolved: Resolve) -> Resolve {
+    let activated = resolved.activated.clone();
+    let graphs = vec![
+        &mut resolved.shared_dependencies,
+        &mut resolved.server_dependencies,
+        &mut resolved.dev_dependencies,
+    ];
+
+    for graph in graphs {
+        for dependencies in graph.values_mut() {
+            for dep_package_id in dependencies.values_mut() {
+                if let Some(activated_id) = activated.get(dep_package_id) {
+                    *dep_package_id = activated_id.clone();
+                }
+            }
+        }
+    }
+
+    resolved
+}
+
+/// Removes the optional dependencies that `features` of the root package
+/// don't enable, along with the packages that only they led to. Other
+/// packages' features can't be requested, so all of their optional
+/// dependencies are removed.
+fn enabled_features_resolve(
+    mut resolved: Resolve,
+    root_package_id: &PackageId,
+    features: &BTreeSet<String>,
+) -> anyhow::Result<Resolve> {
+    let root_features = resolved.features.get(root_package_id);
+
+    for feature in features {
+        if !root_features.map_or(false, |root_features| root_features.contains_key(feature)) {
+            bail!(
+                "Package {} has no feature named {}",
+                root_package_id,
+                feature
+            );
+        }
+    }
+
+    let mut removed_any = false;
+
+    for (package_id, package_features) in &resolved.features {
+        let optional: BTreeSet<&String> = package_features.values().flatten().collect();
+        let enabled: BTreeSet<&String> = package_features
+            .iter()
+            .filter(|(feature, _)| package_id == root_package_id && features.contains(*feature))
+            .flat_map(|(_, aliases)| aliases)
+            .collect();
+
+        let graphs = vec![
+            &mut resolved.shared_dependencies,
+            &mut resolved.server_dependencies,
+            &mut resolved.dev_dependencies,
+        ];
+
+        for graph in graphs {
+            if let Some(dependencies) = graph.get_mut(package_id) {
+                let count = dependencies.len();
+                dependencies
+                    .retain(|alias, _| !optional.contains(alias) || enabled.contains(alias));
+                removed_any |= dependencies.len() != count;
+            }
+        }
+    }
+
+    if !removed_any {
+        return Ok(resolved);
+    }
+
+    let mut reachable = BTreeSet::new();
+    let mut to_visit = vec![root_package_id.clone()];
+
+    while let Some(package_id) = to_visit.pop() {
+        if !reachable.insert(package_id.clone()) {
+            continue;
+        }
+
+        let graphs = [
+            &resolved.shared_dependencies,
+            &resolved.server_dependencies,
+            &resolved.dev_dependencies,
+        ];
+
+        for graph in graphs.iter() {
+            if let Some(dependencies) = graph.get(&package_id) {
+                to_visit.extend(dependencies.values().cloned());
+            }
+        }
+    }
+
+    resolved
+        .activated
+        .retain(|package_id| reachable.contains(package_id));
+    resolved
+        .metadata
+        .retain(|package_id, _| reachable.contains(package_id));
+
+    let graphs = vec![
+        &mut resolved.shared_dependencies,
+        &mut resolved.server_dependencies,
+        &mut resolved.dev_dependencies,
+    ];
+
+    for graph in graphs {
+        graph.retain(|package_id, _| reachable.contains(package_id));
+    }
+
+    Ok(resolved)
+}
+
+/// Describes the shortest path of dependencies from the root package to the
+/// given package, like "Foo (biff/foo@1.0.0) -> Bar (biff/bar@2.0.0)", so
+/// that errors about transitive dependencies say where they came from.
+fn dependency_chain(resolved: &Resolve, root_package_id: &PackageId, target: &PackageId) -> String {
+    let graphs = [
+        &resolved.shared_dependencies,
+        &resolved.server_dependencies,
+        &resolved.dev_dependencies,
+    ];
+
+    // Breadth-first search, remembering how each package was first reached.
+    let mut reached_from: HashMap<&PackageId, (&PackageId, &str)> = HashMap::new();
+    let mut queue = std::collections::VecDeque::new();
+    queue.push_back(root_package_id);
+
+    while let Some(package_id) = queue.pop_front() {
+        if package_id == target {
+            break;
+        }
+
+        for graph in graphs.iter() {
+            for (alias, dep_package_id) in graph.get(package_id).into_iter().flatten() {
+                if dep_package_id != root_package_id && !reached_from.contains_key(dep_package_id) {
+                    reached_from.insert(dep_package_id, (package_id, alias.as_str()));
+                    queue.push_back(dep_package_id);
+                }
+            }
+        }
+    }
+
+    let mut links = Vec::new();
+    let mut current = target;
+
+    while let Some(&(parent, alias)) = reached_from.get(current) {
+        links.push(format!("{} ({})", alias, current));
+        current = parent;
+    }
+
+    if links.is_empty() {
+        return target.to_string();
+    }
+
+    links.reverse();
+    links.join(" -> ")
+}
+
+/// Describes each peer dependency in the resolve that no activated package
+/// satisfies, either because none of them has its name or because all of
+/// them are the wrong version.
+fn peer_dependency_warnings(resolved: &Resolve) -> Vec<Diagnostic> {
+    let mut warnings = Vec::new();
+
+    for (package_id, peer_dependencies) in &resolved.peer_dependencies {
+        if !resolved.activated.contains(package_id) {
+            continue;
+        }
+
+        for package_req in peer_dependencies.values() {
+            let candidates: Vec<_> = resolved
+                .activated
+                .iter()
+                .filter(|candidate| candidate.name() == package_req.name())
+                .collect();
+
+            if candidates
+                .iter()
+                .any(|candidate| package_req.matches_id(candidate))
+            {
+                continue;
+            }
+
+            let found = if candidates.is_empty() {
+                format!("no version of {} was resolved", package_req.name())
+            } else {
+                let names: Vec<_> = candidates.iter().map(|id| id.to_string()).collect();
+                format!("{} was resolved instead", names.join(" and "))
+            };
+
+            let message = format!(
+                "{} has a peer dependency on {}, but {}",
+                package_id, package_req, found
+            );
+            warnings.push(
+                Diagnostic::new(DiagnosticKind::PeerDependency, message).with_package(package_id),
+            );
+        }
+    }
+
+    warnings
+}
+
+/// Describes how many of the given packages were installed into each realm,
+/// like "Installed 12 shared, 3 server, 2 dev packages".
+fn install_summary(resolved: &Resolve, installed: &[PackageId]) -> String {
+    let count = |realm: Realm| {
+        installed
+            .iter()
+            .filter(|package_id| resolved.metadata[*package_id].origin_realm == realm)
+            .count()
+    };
+
+    format!(
+        "Installed {} shared, {} server, {} dev packages",
+        count(Realm::Shared),
+        count(Realm::Server),
+        count(Realm::Dev)
+    )
+}
+
+/// Resolves the `.` and `..` components of `path` without touching the
+/// filesystem.
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(
+                    normalized.components().next_back(),
+                    Some(Component::Normal(_))
+                ) =>
+            {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+
+    normalized
+}
+
+/// Where `install_fresh` keeps the file or directory at `path` until the
+/// install finishes.
+fn backup_path(path: &Path) -> PathBuf {
+    let mut backup_path = path.as_os_str().to_owned();
+    backup_path.push(".wally-backup");
+    PathBuf::from(backup_path)
+}
+
+/// How long to wait before the `retry`th retry, starting at `RETRY_DELAY`
+/// and doubling each time up to `MAX_RETRY_DELAY`.
+fn retry_delay(retry: u32) -> Duration {
+    let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+
+    RETRY_DELAY
+        .checked_mul(factor)
+        .map_or(MAX_RETRY_DELAY, |delay| delay.min(MAX_RETRY_DELAY))
+}
+
+/// Whether a failed download might succeed if it's tried again. Packages
+/// their source doesn't have, and answers that aren't archives, fail the same
+/// way every time.
+fn is_transient(err: &anyhow::Error) -> bool {
+    !err.chain()
+        .any(|cause| cause.is::<PackageNotFound>() || cause.is::<InvalidArchive>())
+}
+
+/// Where the checksum for the package directory at `path` is recorded, next
+/// to the directory so it isn't taken for one of the package's files.
+fn checksum_path(path: &Path) -> PathBuf {
+    let mut checksum_path = path.as_os_str().to_owned();
+    checksum_path.push(".checksum");
+    PathBuf::from(checksum_path)
+}
+
+/// Creates a suitable name for use in file paths that refer to this package.
+fn package_id_file_name(id: &PackageId) -> String {
+    format!(
+        "{}_{}@{}",
+        id.name().scope(),
+        id.name().name(),
+        id.version()
+    )
+}
+
+/// Like `package_id_file_name`, but with the version rewritten to sort in
+/// semver order. Numeric identifiers in pre-releases are padded too, and sort
+/// before alphanumeric ones like semver says, as long as those start with a
+/// letter.
+fn sortable_package_id_file_name(id: &PackageId) -> String {
+    let version = id.version();
+    let mut name = format!(
+        "{}_{}@{:08}.{:08}.{:08}",
+        id.name().scope(),
+        id.name().name(),
+        version.major,
+        version.minor,
+        version.patch
+    );
+
+    if version.pre.is_empty() {
+        name.push('~');
+    } else {
+        let identifiers: Vec<_> = version
+            .pre
+            .iter()
+            .map(|identifier| match identifier {
+                Identifier::Numeric(number) => format!("{:08}", number),
+                Identifier::AlphaNumeric(text) => text.clone(),
+            })
+            .collect();
+
+        name.push('-');
+        name.push_str(&identifiers.join("."));
+    }
+
+    if !version.build.is_empty() {
+        let identifiers: Vec<_> = version.build.iter().map(|id| id.to_string()).collect();
+
+        name.push('+');
+        name.push_str(&identifiers.join("."));
+    }
+
+    name
+}
+
+/// Parses the name of a package's directory in an index, as written by either
+/// `package_id_file_name` or `sortable_package_id_file_name`.
+fn parse_package_dir_name(dir_name: &str) -> Option<PackageId> {
+    let (scope, rest) = dir_name.split_once('_')?;
+    let (name, version) = rest.split_once('@')?;
+
+    // Undo the padding of sortable names, which valid versions never have.
+    let version = version.trim_end_matches('~');
+    let (version, build) = match version.split_once('+') {
+        Some((version, build)) => (version, Some(build)),
+        None => (version, None),
+    };
+
+    let unpad = |identifiers: &str| {
+        identifiers
+            .split('.')
+            .map(|identifier| {
+                if identifier.len() > 1 && identifier.bytes().all(|byte| byte.is_ascii_digit()) {
+                    let trimmed = identifier.trim_start_matches('0');
+                    if trimmed.is_empty() {
+                        "0"
+                    } else {
+                        trimmed
+                    }
+                } else {
+                    identifier
+                }
+            })
+            .collect::<Vec<_>>()
+            .join(".")
+    };
+
+    let mut unpadded = match version.split_once('-') {
+        Some((core, pre)) => format!("{}-{}", unpad(core), unpad(pre)),
+        None => unpad(version),
+    };
+
+    if let Some(build) = build {
+        unpadded.push('+');
+        unpadded.push_str(build);
+    }
+
+    let name = PackageName::new(scope, name).ok()?;
+    let version = unpadded.parse().ok()?;
+
+    Some(PackageId::new(name, version))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use zip::{ZipArchive, ZipWriter};
+
+    use crate::{
+        package_source::{InMemoryRegistry, PackageSourceId},
+        resolution::{resolve, ResolvePackageMetadata},
+        test_package::PackageBuilder,
+    };
+
+    use super::test_support::{
+        minimal_project, one_dependency_project, published_registry, RecordingReporter,
+        TestProject, WriteRecordingFilesystem,
+    };
+
+    #[test]
+    fn phases_reported_in_order() {
+        let (_, project) = one_dependency_project();
+
+        let reporter = Arc::new(RecordingReporter::default());
+        project
+            .install(project.context().with_reporter(reporter.clone()))
+            .unwrap();
+
+        let events = reporter.events.lock().unwrap();
+        let phases: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                ProgressEvent::PhaseStarted(phase, total) => Some((*phase, *total)),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(
+            phases,
+            vec![(InstallPhase::Downloading, 2), (InstallPhase::Linking, 3)]
+        );
+
+        let linking_start = events
+            .iter()
+            .position(|event| *event == ProgressEvent::PhaseStarted(InstallPhase::Linking, 3))
+            .unwrap();
+
+        assert!(events[..linking_start]
+            .iter()
+            .all(|event| !matches!(event, ProgressEvent::Linked(_))));
+        assert!(events[linking_start..]
+            .iter()
+            .all(|event| !matches!(event, ProgressEvent::Downloaded(_))));
+        assert_eq!(events.last(), Some(&ProgressEvent::Finished));
+    }
+
+    #[test]
+    fn production_skips_dev_dependencies() {
+        let registry = published_registry();
+        registry.publish(PackageBuilder::new("biff/server-only@0.1.0").with_file("init.luau", ""));
+        registry.publish(PackageBuilder::new("biff/dev-only@0.1.0").with_file("init.luau", ""));
+
+        let root =
+            || PackageBuilder::new("biff/root@0.1.0").with_dep("Minimal", "biff/minimal@0.1.0");
+
+        let mut normal = TestProject::new(&registry, root());
+        normal.add_dev_dependency("DevOnly", "biff/dev-only@0.1.0");
+        normal.install(normal.context()).unwrap();
+
+        let dev_dir = normal.dir.path().join("DevPackages");
+        assert!(dev_dir.join("DevOnly.lua").is_file());
+        assert!(dev_dir
+            .join("_index/biff_dev-only@0.1.0/init.luau")
+            .is_file());
+
+        let mut production = TestProject::new(&registry, root());
+        production.add_server_dependency("ServerOnly", "biff/server-only@0.1.0");
+        production.add_dev_dependency("DevOnly", "biff/dev-only@0.1.0");
+        production
+            .install(production.context().with_production(true))
+            .unwrap();
+
+        // Only dev dependencies are left out.
+        assert!(!production.dir.path().join("DevPackages").exists());
+        assert!(production.dir.path().join("packages/Minimal.lua").is_file());
+        assert!(production
+            .dir
+            .path()
+            .join("ServerPackages/ServerOnly.lua")
+            .is_file());
+    }
+
+    #[test]
+    fn dangling_dependency_rejected() {
+        let (_, mut project) = minimal_project();
+
+        let dangling: PackageId = "biff/missing@1.0.0".parse().unwrap();
+        project
+            .resolved
+            .shared_dependencies
+            .entry(project.root_package_id.clone())
+            .or_default()
+            .insert("Missing".to_owned(), dangling);
+
+        let err = project.install(project.context()).unwrap_err();
+        assert!(err.to_string().contains("biff/missing@1.0.0"), "{}", err);
+    }
+
+    #[test]
+    fn install_into_memory() {
+        let (_, project) = one_dependency_project();
+
+        let filesystem = Arc::new(MemoryFilesystem::new());
+        let context =
+            InstallationContext::new(Path::new("project")).with_filesystem(filesystem.clone());
+        project.install(context).unwrap();
+
+        let files: Vec<_> = filesystem
+            .files()
+            .into_iter()
+            .map(|(path, _)| path)
+            .collect();
+        let expected: Vec<PathBuf> = vec![
+            "project/packages/OneDependency.lua",
+            "project/packages/_index/biff_minimal@0.1.0/init.luau",
+            "project/packages/_index/biff_minimal@0.1.0/wally.toml",
+            "project/packages/_index/biff_one-dependency@0.1.0/packages/Minimal.lua",
+            "project/packages/_index/biff_one-dependency@0.1.0/src/init.luau",
+            "project/packages/_index/biff_one-dependency@0.1.0/wally.toml",
+        ]
+        .into_iter()
+        .map(PathBuf::from)
+        .collect();
+
+        assert_eq!(files, expected);
+        assert!(!project.dir.path().join("packages").exists());
+
+        let link = filesystem
+            .read(Path::new("project/packages/OneDependency.lua"))
+            .unwrap();
+        assert_eq!(
+            String::from_utf8(link).unwrap(),
+            "return require(\"_index/biff_one-dependency@0.1.0/src\")\n"
+        );
+    }
+
+    #[test]
+    fn concurrent_install_rejected() {
+        let (_, project) = minimal_project();
+
+        let lock_path = project.dir.path().join(".wally-lock");
+
+        let lock = project.context().lock().unwrap();
+        assert!(lock_path.is_file());
+        let err = project.install(project.context()).unwrap_err();
+        assert!(err.to_string().contains("Another wally install"), "{}", err);
+
+        drop(lock);
+        project.install(project.context()).unwrap();
+        assert!(!lock_path.exists());
+
+        // A lock file left behind by a crashed install isn't locked by
+        // anyone, so it doesn't get in the way.
+        fs_err::write(&lock_path, "").unwrap();
+        project.install(project.context()).unwrap();
+        assert!(!lock_path.exists());
+    }
+
+    #[test]
+    fn install_subtree_only() {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/c@1.0.0").with_file("init.luau", ""));
+        registry.publish(PackageBuilder::new("biff/d@1.0.0").with_file("init.luau", ""));
+        registry.publish(
+            PackageBuilder::new("biff/a@1.0.0")
+                .with_dep("C", "biff/c@1.0.0")
+                .with_file("init.luau", ""),
+        );
+        registry.publish(
+            PackageBuilder::new("biff/b@1.0.0")
+                .with_dep("D", "biff/d@1.0.0")
+                .with_file("init.luau", ""),
+        );
+
+        let root = PackageBuilder::new("biff/root@1.0.0")
+            .with_dep("A", "biff/a@1.0.0")
+            .with_dep("B", "biff/b@1.0.0");
+        let project = TestProject::new(&registry, root);
+
+        project
+            .context()
+            .install_subtree(
+                project.sources.clone(),
+                project.root_package_id.clone(),
+                project.resolved.clone(),
+                &"biff/a".parse().unwrap(),
+            )
+            .unwrap();
+
+        let packages = project.dir.path().join("packages");
+        assert!(packages.join("A.lua").is_file());
+        assert!(packages
+            .join("_index/biff_a@1.0.0/packages/C.lua")
+            .is_file());
+        assert!(packages.join("_index/biff_c@1.0.0").is_dir());
+
+        assert!(!packages.join("B.lua").exists());
+        assert!(!packages.join("_index/biff_b@1.0.0").exists());
+        assert!(!packages.join("_index/biff_d@1.0.0").exists());
+    }
+
+    /// Delegates to the real filesystem, except that `hidden` never seems to
+    /// exist, as if whatever wrote it had been skipped.
+    struct HidingFilesystem {
+        hidden: PathBuf,
+    }
+
+    impl DelegatingFilesystem for HidingFilesystem {
+        fn inner(&self) -> &dyn Filesystem {
+            &RealFilesystem
+        }
+
+        fn exists(&self, path: &Path) -> bool {
+            path != self.hidden && RealFilesystem.exists(path)
+        }
+    }
+
+    #[test]
+    fn missing_package_detected() {
+        let registry = published_registry();
+        let root = || {
+            PackageBuilder::new("biff/root@0.1.0")
+                .with_dep("OneDependency", "biff/one-dependency@0.1.0")
+        };
+
+        let complete = TestProject::new(&registry, root());
+        let diagnostics = complete.install(complete.context()).unwrap();
+        assert!(diagnostics
+            .iter()
+            .all(|diagnostic| diagnostic.kind != DiagnosticKind::MissingPackages));
+
+        // Simulate a package being dropped partway through the install.
+        let project = TestProject::new(&registry, root());
+        let filesystem = Arc::new(HidingFilesystem {
+            hidden: project
+                .dir
+                .path()
+                .join("packages/_index/biff_minimal@0.1.0"),
+        });
+        let diagnostics = project
+            .install(project.context().with_filesystem(filesystem))
+            .unwrap();
+
+        let missing: Vec<_> = diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.kind == DiagnosticKind::MissingPackages)
+            .collect();
+        assert_eq!(missing.len(), 1);
+        assert!(
+            missing[0].message.contains("Installed 1 of 2 packages"),
+            "{}",
+            missing[0].message
+        );
+        assert!(
+            missing[0].message.ends_with("biff/minimal@0.1.0"),
+            "{}",
+            missing[0].message
+        );
+    }
+
+    #[test]
+    fn many_packages_share_index() {
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/common@1.0.0").with_file("init.luau", ""));
+
+        let mut root = PackageBuilder::new("biff/root@1.0.0");
+
+        for i in 0..64 {
+            let package_id = format!("biff/package-{}@1.0.0", i);
+
+            registry.publish(
+                PackageBuilder::new(&package_id)
+                    .with_dep("Common", "biff/common@1.0.0")
+                    .with_file("init.luau", ""),
+            );
+            root = root.with_dep(format!("Package{}", i), &package_id);
+        }
+
+        let project = TestProject::new(&registry, root);
+        project.install(project.context()).unwrap();
+
+        let index = project.dir.path().join("packages/_index");
+        for i in 0..64 {
+            let package_dir = index.join(format!("biff_package-{}@1.0.0", i));
+            assert!(package_dir.join("init.luau").is_file());
+            assert!(package_dir.join("packages/Common.lua").is_file());
+        }
+    }
+
+    #[test]
+    fn read_only_cache() {
+        let (registry, mut project) = one_dependency_project();
+
+        let cache_dir = tempfile::tempdir().unwrap();
+        let minimal_id: PackageId = "biff/minimal@0.1.0".parse().unwrap();
+        let one_dependency_id: PackageId = "biff/one-dependency@0.1.0".parse().unwrap();
+
+        let minimal = registry.source().download_package(&minimal_id).unwrap();
+        PackageCache::new(cache_dir.path())
+            .insert(&minimal_id, &minimal)
+            .unwrap();
+
+        // Only one-dependency can be downloaded now, so minimal has to come
+        // from the cache.
+        let partial = InMemoryRegistry::new();
+        partial.publish(
+            PackageBuilder::new("biff/one-dependency@0.1.0")
+                .with_dep("Minimal", "biff/minimal@0.1.0")
+                .with_file("src/init.luau", ""),
+        );
+        project.sources = PackageSourceMap::new(Box::new(partial.source()));
+
+        // The cache is mounted read-only, so it's only read from.
+        let filesystem = Arc::new(ReadOnlyFilesystem {
+            read_only: cache_dir.path().to_path_buf(),
+        });
+        let cache = PackageCache::new(cache_dir.path()).with_filesystem(filesystem);
+        let diagnostics = project
+            .install(project.context().with_cache(cache.clone()))
+            .unwrap();
+        assert!(cache.is_read_only());
+        assert!(diagnostics
+            .iter()
+            .all(|diagnostic| diagnostic.kind != DiagnosticKind::Cache));
+
+        let index = project.dir.path().join("packages/_index");
+        assert!(index.join("biff_minimal@0.1.0/init.luau").is_file());
+        assert!(index
+            .join("biff_one-dependency@0.1.0/src/init.luau")
+            .is_file());
+
+        assert!(cache.path_for(&minimal_id).is_file());
+        assert!(!cache.path_for(&one_dependency_id).exists());
+        assert!(!cache_dir.path().join("biff/one-dependency").exists());
+    }
+
+    #[test]
+    fn require_transform() {
+        let (_, project) = one_dependency_project();
+
+        let context = project
+            .context()
+            .with_require_transform(Arc::new(|path: &str| format!("@wally/{}", path)));
+        project.install(context).unwrap();
+
+        let packages = project.dir.path().join("packages");
+
+        let root_link = fs_err::read_to_string(packages.join("OneDependency.lua")).unwrap();
+        assert_eq!(
+            root_link,
+            "return require(\"@wally/_index/biff_one-dependency@0.1.0/src\")\n"
+        );
+
+        let sibling_link = fs_err::read_to_string(
+            packages.join("_index/biff_one-dependency@0.1.0/packages/Minimal.lua"),
+        )
+        .unwrap();
+        assert_eq!(
+            sibling_link,
+            "return require(\"@wally/../../biff_minimal@0.1.0\")\n"
+        );
+    }
+
+    #[test]
+    fn data_descriptor_archives_installed() {
+        let registry = InMemoryRegistry::new();
+        let (manifest, contents) = PackageBuilder::new("biff/described@0.1.0")
+            .with_file("init.luau", "return 1")
+            .package();
+
+        // Mark every entry as having its sizes in a data descriptor, as
+        // streaming zip writers do, which archives can't be streamed with.
+        let mut data = contents.data().to_vec();
+        for i in 0..data.len().saturating_sub(6) {
+            if data[i..].starts_with(b"PK\x03\x04") {
+                data[i + 6] |= 0x08;
+            }
+        }
+        registry.publish_archive(manifest, PackageContents::from_buffer(data));
+
+        let root =
+            PackageBuilder::new("biff/root@0.1.0").with_dep("Described", "biff/described@0.1.0");
+        let project = TestProject::new(&registry, root);
+        project.install(project.context()).unwrap();
+
+        assert_eq!(registry.downloads_started(), 2);
+        assert_eq!(
+            fs_err::read_to_string(
+                project
+                    .dir
+                    .path()
+                    .join("packages/_index/biff_described@0.1.0/init.luau")
+            )
+            .unwrap(),
+            "return 1"
+        );
+    }
+
+    #[test]
+    fn colliding_index_paths_rejected() {
+        let project = TestProject::new(
+            &published_registry(),
+            PackageBuilder::new("biff/root@0.1.0"),
+        );
+        let context = project.context();
+        let contents = PackageBuilder::new("biff/minimal@1.0.0").contents();
+
+        // These only differ by the case of their build metadata, so they'd
+        // share a directory on a case-insensitive filesystem.
+        let upper: PackageId = "biff/minimal@1.0.0+Build".parse().unwrap();
+        let lower: PackageId = "biff/minimal@1.0.0+build".parse().unwrap();
+
+        context
+            .write_contents(&upper, &contents, Realm::Shared)
+            .unwrap();
+        let err = context
+            .write_contents(&lower, &contents, Realm::Shared)
+            .unwrap_err()
+            .to_string();
+
+        assert!(err.contains("biff/minimal@1.0.0+Build"), "{}", err);
+        assert!(err.contains("biff/minimal@1.0.0+build"), "{}", err);
+    }
+
+    #[test]
+    fn fail_fast_stops_at_first_error() {
+        let registry = published_registry();
+        let root = PackageBuilder::new("biff/root@0.1.0")
+            .with_dep("Minimal", "biff/minimal@0.1.0")
+            .with_dep("OneDependency", "biff/one-dependency@0.1.0");
+        let mut project = TestProject::new(&registry, root);
+
+        // Neither package can be downloaded from an empty registry.
+        project.sources = PackageSourceMap::new(Box::new(InMemoryRegistry::new().source()));
+
+        let mentions = |message: &str| {
+            ["biff/minimal@0.1.0", "biff/one-dependency@0.1.0"]
+                .iter()
+                .filter(|name| message.contains(*name))
+                .count()
+        };
+
+        let err = project.install(project.context()).unwrap_err();
+        assert_eq!(mentions(&format!("{:#}", err)), 2);
+
+        let err = project
+            .install(project.context().with_fail_fast(true))
+            .unwrap_err();
+        assert_eq!(mentions(&format!("{:#}", err)), 1);
+    }
+
+    #[test]
+    fn fail_fast_waits_for_running_downloads() {
+        let registry = published_registry();
+        let root = PackageBuilder::new("biff/root@0.1.0")
+            .with_dep("Minimal", "biff/minimal@0.1.0")
+            .with_dep("OneDependency", "biff/one-dependency@0.1.0");
+        let project = TestProject::new(&registry, root);
+
+        let minimal: PackageId = "biff/minimal@0.1.0".parse().unwrap();
+        let one_dependency: PackageId = "biff/one-dependency@0.1.0".parse().unwrap();
+        registry.fail_downloads(&minimal, 1);
+        registry.delay_download(&one_dependency, Duration::from_millis(300));
+
+        project
+            .install(project.context().with_fail_fast(true))
+            .unwrap_err();
+
+        // The slow download was still arriving when the other one failed.
+        // It has stopped by the time install returns, without being tried
+        // again or unpacked.
+        assert_eq!(registry.downloads_started(), 1);
+        assert_eq!(registry.downloads_in_progress(), 0);
+        assert!(!checksum_path(
+            &project
+                .dir
+                .path()
+                .join("packages/_index/biff_one-dependency@0.1.0")
+        )
+        .exists());
+
+        project.install(project.context()).unwrap();
+    }
+
+    #[test]
+    fn frozen_install_never_downloads() {
+        let (registry, mut project) = one_dependency_project();
+        project.install(project.context()).unwrap();
+
+        // Nothing can be downloaded from an empty registry, so a frozen
+        // install only succeeds by reusing what's already in the index.
+        project.sources = PackageSourceMap::new(Box::new(InMemoryRegistry::new().source()));
+        project
+            .install(project.context().with_frozen(true))
+            .unwrap();
+
+        let minimal_link = project
+            .dir
+            .path()
+            .join("packages/_index/biff_one-dependency@0.1.0/packages/Minimal.lua");
+        assert!(minimal_link.is_file());
+
+        fs_err::remove_dir_all(
+            project
+                .dir
+                .path()
+                .join("packages/_index/biff_minimal@0.1.0"),
+        )
+        .unwrap();
+
+        let err = project
+            .install(project.context().with_frozen(true))
+            .unwrap_err();
+        let message = err.to_string();
+
+        assert!(message.contains("biff/minimal@0.1.0"));
+        assert!(!message.contains("biff/one-dependency@0.1.0"));
+    }
+
+    /// Delegates to the real filesystem, recording the most package manifests
+    /// that were ever being written at once, in total and for each package
+    /// scope. Writes are slowed down so that overlapping downloads are sure to
+    /// be seen.
+    #[derive(Default)]
+    struct ConcurrencyFilesystem {
+        writing: Mutex<(usize, usize)>,
+        writing_by_scope: Mutex<HashMap<String, (usize, usize)>>,
+    }
+
+    impl DelegatingFilesystem for ConcurrencyFilesystem {
+        fn inner(&self) -> &dyn Filesystem {
+            &RealFilesystem
+        }
+
+        fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+            if !path.ends_with("wally.toml") {
+                return RealFilesystem.write(path, contents);
+            }
+
+            let package_dir = path.parent().unwrap().file_name().unwrap();
+            let scope = package_dir
+                .to_string_lossy()
+                .split('_')
+                .next()
+                .unwrap()
+                .to_owned();
+
+            {
+                let mut writing = self.writing.lock().unwrap();
+                writing.0 += 1;
+                writing.1 = writing.1.max(writing.0);
+
+                let mut writing_by_scope = self.writing_by_scope.lock().unwrap();
+                let writing = writing_by_scope.entry(scope.clone()).or_default();
+                writing.0 += 1;
+                writing.1 = writing.1.max(writing.0);
+            }
+
+            std::thread::sleep(std::time::Duration::from_millis(50));
+            let result = RealFilesystem.write(path, contents);
+
+            self.writing.lock().unwrap().0 -= 1;
+            self.writing_by_scope
+                .lock()
+                .unwrap()
+                .get_mut(&scope)
+                .unwrap()
+                .0 -= 1;
+            result
+        }
+    }
+
+    #[test]
+    fn shared_scheduler_caps_downloads() {
+        let registry = published_registry();
+        let scheduler = DownloadScheduler::new(2).unwrap();
+        let filesystem = Arc::new(ConcurrencyFilesystem::default());
+
+        let installs: Vec<_> = (0..3)
+            .map(|_| {
+                let root = PackageBuilder::new("biff/root@0.1.0")
+                    .with_dep("OneDependency", "biff/one-dependency@0.1.0");
+                let project = TestProject::new(&registry, root);
+                let context = project
+                    .context()
+                    .with_scheduler(scheduler.clone())
+                    .with_filesystem(filesystem.clone());
+
+                std::thread::spawn(move || project.install(context))
+            })
+            .collect();
+
+        for install in installs {
+            install.join().unwrap().unwrap();
+        }
+
+        let (writing, most_writing) = *filesystem.writing.lock().unwrap();
+        assert_eq!(writing, 0);
+        assert!(most_writing <= 2, "{} downloads ran at once", most_writing);
+    }
+
+    #[test]
+    fn install_through_staging_dir() {
+        let (_, project) = one_dependency_project();
+
+        let staging = tempfile::tempdir().unwrap();
+        project
+            .install(project.context().with_staging_dir(staging.path()))
+            .unwrap();
+
+        let index = project.dir.path().join("packages/_index");
+        assert!(index.join("biff_minimal@0.1.0/init.luau").is_file());
+        assert!(index
+            .join("biff_one-dependency@0.1.0/src/init.luau")
+            .is_file());
+        assert!(index
+            .join("biff_one-dependency@0.1.0/packages/Minimal.lua")
+            .is_file());
+
+        let leftovers: Vec<_> = fs_err::read_dir(staging.path()).unwrap().collect();
+        assert!(leftovers.is_empty());
+    }
+
+    #[test]
+    fn alias_only_dependency_not_downloaded() {
+        let (registry, mut project) = minimal_project();
+        project.install(project.context()).unwrap();
+
+        // Re-export the package that's already installed under a new alias.
+        let minimal: PackageId = "biff/minimal@0.1.0".parse().unwrap();
+        let mut aliases = BTreeMap::new();
+        aliases.insert("Reexported".to_owned(), minimal.clone());
+
+        // Nothing can be downloaded from an empty registry.
+        project.sources = PackageSourceMap::new(Box::new(InMemoryRegistry::new().source()));
+
+        project
+            .context()
+            .write_root_package_links(Realm::Shared, &aliases, &project.resolved)
+            .unwrap();
+
+        let link =
+            fs_err::read_to_string(project.dir.path().join("packages/Reexported.lua")).unwrap();
+        assert_eq!(link, "return require(\"_index/biff_minimal@0.1.0\")\n");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn hardlink_from_cache() {
+        use std::os::unix::fs::MetadataExt;
+
+        let (_, project) = one_dependency_project();
+
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache = PackageCache::new(cache_dir.path());
+        project
+            .install(
+                project
+                    .context()
+                    .with_cache(cache.clone())
+                    .with_strategy(InstallStrategy::Hardlink),
+            )
+            .unwrap();
+
+        let installed = project
+            .dir
+            .path()
+            .join("packages/_index/biff_one-dependency@0.1.0/src/init.luau");
+        let one_dependency_id: PackageId = "biff/one-dependency@0.1.0".parse().unwrap();
+        let cached = cache
+            .unpacked_path_for(&one_dependency_id)
+            .join("src/init.luau");
+
+        assert_eq!(
+            fs_err::metadata(&installed).unwrap().ino(),
+            fs_err::metadata(&cached).unwrap().ino()
+        );
+
+        // Links written into the package's directory stay out of the cache.
+        assert!(!cache
+            .unpacked_path_for(&one_dependency_id)
+            .join("packages")
+            .exists());
+    }
+
+    #[test]
+    fn summary_counts_each_realm() {
+        let registry = published_registry();
+        registry.publish(PackageBuilder::new("biff/dev-only@0.1.0").with_file("init.luau", ""));
+
+        let root = PackageBuilder::new("biff/root@0.1.0")
+            .with_dep("OneDependency", "biff/one-dependency@0.1.0");
+        let mut project = TestProject::new(&registry, root);
+        project.add_dev_dependency("DevOnly", "biff/dev-only@0.1.0");
+
+        let installed: Vec<_> = project
+            .resolved
+            .activated
+            .iter()
+            .filter(|package_id| **package_id != project.root_package_id)
+            .cloned()
+            .collect();
+
+        assert_eq!(
+            install_summary(&project.resolved, &installed),
+            "Installed 2 shared, 0 server, 1 dev packages"
+        );
+    }
+
+    #[test]
+    fn installed_size_sums_files() {
+        let (registry, project) = minimal_project();
+        project.install(project.context()).unwrap();
+
+        let minimal_id: PackageId = "biff/minimal@0.1.0".parse().unwrap();
+        let minimal = registry.source().download_package(&minimal_id).unwrap();
+        let mut archive = ZipArchive::new(Cursor::new(minimal.data())).unwrap();
+        let unpacked_size: u64 = (0..archive.len())
+            .map(|index| archive.by_index(index).unwrap().size())
+            .sum();
+
+        let link = project
+            .context()
+            .generate_link(LinkOrigin::Root, &minimal_id, Some(""));
+        let expected = unpacked_size + link.len() as u64;
+
+        assert_eq!(project.context().installed_size().unwrap(), expected);
+
+        #[cfg(unix)]
+        {
+            let packages = project.dir.path().join("packages");
+            std::os::unix::fs::symlink(
+                packages.join("_index/biff_minimal@0.1.0/init.luau"),
+                packages.join("Symlinked.luau"),
+            )
+            .unwrap();
+
+            assert_eq!(project.context().installed_size().unwrap(), expected);
+        }
+    }
+
+    #[test]
+    fn reactivated_package_installed_once() {
+        let (_, mut project) = one_dependency_project();
+
+        // Activating a package again, even with different build metadata,
+        // leaves a single entry behind.
+        let minimal: PackageId = "biff/minimal@0.1.0".parse().unwrap();
+        let rebuilt: PackageId = "biff/minimal@0.1.0+rebuilt".parse().unwrap();
+        assert!(!project.resolved.activated.insert(minimal));
+        assert!(!project.resolved.activated.insert(rebuilt));
+
+        let reporter = Arc::new(RecordingReporter::default());
+        project
+            .install(project.context().with_reporter(reporter.clone()))
+            .unwrap();
+
+        let downloads: Vec<_> = reporter
+            .events
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|event| match event {
+                ProgressEvent::Downloaded(package_id) => Some(package_id.to_string()),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(downloads.len(), 2);
+        assert_eq!(
+            downloads
+                .iter()
+                .filter(|id| id.as_str() == "biff/minimal@0.1.0")
+                .count(),
+            1
+        );
+    }
+
+    #[test]
+    fn init_shim_for_src_packages() {
+        let (_, project) = one_dependency_project();
+        project
+            .install(project.context().with_init_shims(true))
+            .unwrap();
+
+        let packages = project.dir.path().join("packages");
+        let read = |path: &str| fs_err::read_to_string(packages.join(path)).unwrap();
+
+        assert_eq!(
+            read("_index/biff_one-dependency@0.1.0/init.luau"),
+            "return require(\"src\")\n"
+        );
+        assert_eq!(
+            read("OneDependency.lua"),
+            "return require(\"_index/biff_one-dependency@0.1.0\")\n"
+        );
+
+        // Packages with an entry point at their root are left alone.
+        assert_eq!(read("_index/biff_minimal@0.1.0/init.luau"), "");
+    }
+
+    #[test]
+    fn errors_show_dependency_chain() {
+        let deep_package = |id: &str, dep: Option<(&str, &str)>| {
+            let mut package = PackageBuilder::new(id).with_file("init.luau", "");
+            if let Some((alias, req)) = dep {
+                package = package.with_dep(alias, req);
+            }
+            package
+        };
+
+        let full = InMemoryRegistry::new();
+        let partial = InMemoryRegistry::new();
+        for registry in [&full, &partial].iter() {
+            registry.publish(deep_package("biff/a@0.1.0", Some(("B", "biff/b@0.1.0"))));
+            registry.publish(deep_package("biff/b@0.1.0", Some(("C", "biff/c@0.1.0"))));
+        }
+        full.publish(deep_package("biff/c@0.1.0", None));
+
+        let root = PackageBuilder::new("biff/root@0.1.0").with_dep("A", "biff/a@0.1.0");
+        let mut project = TestProject::new(&full, root);
+
+        // The deepest package can't be downloaded.
+        project.sources = PackageSourceMap::new(Box::new(partial.source()));
+
+        let err = project.install(project.context()).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("A (biff/a@0.1.0) -> B (biff/b@0.1.0) -> C (biff/c@0.1.0)"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn source_limits_apply_separately() {
+        let alpha = InMemoryRegistry::new();
+        let beta = InMemoryRegistry::new();
+        let mut root = PackageBuilder::new("biff/root@0.1.0");
+
+        for name in ["one", "two", "three"].iter() {
+            alpha.publish(
+                PackageBuilder::new(format!("alpha/{}@0.1.0", name)).with_file("init.luau", ""),
+            );
+            beta.publish(
+                PackageBuilder::new(format!("beta/{}@0.1.0", name)).with_file("init.luau", ""),
+            );
+
+            root = root
+                .with_dep(format!("Alpha{}", name), format!("alpha/{}@0.1.0", name))
+                .with_dep(format!("Beta{}", name), format!("beta/{}@0.1.0", name));
+        }
+
+        let beta_id = PackageSourceId::Git("https://beta.invalid/index".to_owned());
+        let mut sources = PackageSourceMap::new(Box::new(alpha.source()));
+        sources.add_source(beta_id.clone(), Box::new(beta.source()));
+
+        let manifest = root.into_manifest();
+        let resolved = resolve(&manifest, &Default::default(), &sources).unwrap();
+        let beta_one: PackageId = "beta/one@0.1.0".parse().unwrap();
+        assert_eq!(resolved.metadata[&beta_one].source_registry, beta_id);
+
+        let project = TestProject {
+            dir: tempfile::tempdir().unwrap(),
+            sources,
+            root_package_id: manifest.package_id(),
+            resolved,
+        };
+
+        let filesystem = Arc::new(ConcurrencyFilesystem::default());
+        project
+            .install(
+                project
+                    .context()
+                    .with_filesystem(filesystem.clone())
+                    .with_source_limit(PackageSourceId::DefaultRegistry, 1)
+                    .with_source_limit(beta_id, 2),
+            )
+            .unwrap();
+
+        let writing_by_scope = filesystem.writing_by_scope.lock().unwrap();
+        assert_eq!(writing_by_scope["alpha"], (0, 1));
+        assert!(writing_by_scope["beta"].1 <= 2);
+    }
+
+    #[test]
+    fn flat_layout() {
+        let registry = published_registry();
+        registry.publish(PackageBuilder::new("biff/dev-only@0.1.0").with_file("init.luau", ""));
+
+        let root = PackageBuilder::new("biff/root@0.1.0")
+            .with_dep("OneDependency", "biff/one-dependency@0.1.0");
+        let mut project = TestProject::new(&registry, root);
+        project.add_dev_dependency("DevOnly", "biff/dev-only@0.1.0");
+        project
+            .install(project.context().with_flat_layout(true))
+            .unwrap();
+
+        let packages = project.dir.path().join("packages");
+        let read = |path: &str| fs_err::read_to_string(packages.join(path)).unwrap();
+
+        assert_eq!(
+            read("OneDependency.lua"),
+            "return require(\"_index/biff_one-dependency@0.1.0/src\")\n"
+        );
+        assert_eq!(
+            read("Dev_DevOnly.lua"),
+            "return require(\"_index/biff_dev-only@0.1.0\")\n"
+        );
+        assert!(packages
+            .join("_index/biff_dev-only@0.1.0/init.luau")
+            .is_file());
+        assert!(!project.dir.path().join("DevPackages").exists());
+        assert_eq!(
+            project
+                .context()
+                .with_flat_layout(true)
+                .verify_links()
+                .unwrap(),
+            Vec::<BrokenLink>::new()
+        );
+    }
+
+    #[test]
+    fn unchanged_resolve_skips_install() {
+        let (_, project) = one_dependency_project();
+
+        project.install(project.context()).unwrap();
+
+        let reporter = Arc::new(RecordingReporter::default());
+        project
+            .install(project.context().with_reporter(reporter.clone()))
+            .unwrap();
+
+        assert!(!reporter
+            .events
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|event| matches!(event, ProgressEvent::Downloaded(_))));
+
+        // A broken tree is installed again, even with the same resolve.
+        fs_err::remove_dir_all(
+            project
+                .dir
+                .path()
+                .join("packages/_index/biff_minimal@0.1.0"),
+        )
+        .unwrap();
+
+        let reporter = Arc::new(RecordingReporter::default());
+        project
+            .install(project.context().with_reporter(reporter.clone()))
+            .unwrap();
+
+        let downloaded = reporter
+            .events
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|event| matches!(event, ProgressEvent::Downloaded(_)))
+            .count();
+        assert_eq!(downloaded, 2);
+        assert!(project
+            .dir
+            .path()
+            .join("packages/_index/biff_minimal@0.1.0/init.luau")
+            .is_file());
+    }
+
+    #[test]
+    fn no_empty_realm_dirs() {
+        let registry = published_registry();
+        let mut project = TestProject::new(&registry, PackageBuilder::new("biff/root@0.1.0"));
+        project.add_dev_dependency("Minimal", "biff/minimal@0.1.0");
+
+        project.install(project.context()).unwrap();
+
+        assert!(!project.dir.path().join("packages").exists());
+        assert!(!project.dir.path().join("ServerPackages").exists());
+        assert!(project.dir.path().join("DevPackages/Minimal.lua").is_file());
+    }
+
+    #[test]
+    fn optional_dependency_needs_feature() {
+        let registry = published_registry();
+        let root = || {
+            PackageBuilder::new("biff/root@0.1.0")
+                .with_dep("Minimal", "biff/minimal@0.1.0")
+                .with_dep("Extra", "biff/one-dependency@0.1.0")
+                .with_feature("extra", &["Extra"])
+        };
+
+        let project = TestProject::new(&registry, root());
+        project.install(project.context()).unwrap();
+
+        let packages = project.dir.path().join("packages");
+        assert!(packages.join("Minimal.lua").is_file());
+        assert!(!packages.join("Extra.lua").exists());
+        assert!(!packages.join("_index/biff_one-dependency@0.1.0").exists());
+
+        let project = TestProject::new(&registry, root());
+        project
+            .install(project.context().with_features(vec!["extra"]))
+            .unwrap();
+
+        let packages = project.dir.path().join("packages");
+        assert!(packages.join("Extra.lua").is_file());
+        assert!(packages
+            .join("_index/biff_one-dependency@0.1.0/src/init.luau")
+            .is_file());
+
+        let err = project
+            .install(project.context().with_features(vec!["missing"]))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Package biff/root@0.1.0 has no feature named missing"
+        );
+    }
+
+    #[test]
+    fn unsatisfied_peer_dependencies() {
+        let registry = published_registry();
+        registry.publish(
+            PackageBuilder::new("biff/plugin@0.1.0")
+                .with_peer_dep("Minimal", "biff/minimal@0.2.0")
+                .with_peer_dep("Host", "biff/host@1.0.0")
+                .with_file("init.luau", ""),
+        );
+
+        let root = PackageBuilder::new("biff/root@0.1.0")
+            .with_dep("Minimal", "biff/minimal@0.1.0")
+            .with_dep("Plugin", "biff/plugin@0.1.0");
+        let project = TestProject::new(&registry, root);
+
+        let warnings: Vec<_> = peer_dependency_warnings(&project.resolved)
+            .into_iter()
+            .map(|warning| warning.message)
+            .collect();
+        assert_eq!(warnings.len(), 2, "{:?}", warnings);
+
+        assert!(warnings[0].starts_with("biff/plugin@0.1.0 has a peer dependency on biff/host@"));
+        assert!(warnings[0].ends_with("but no version of biff/host was resolved"));
+
+        assert!(warnings[1].starts_with("biff/plugin@0.1.0 has a peer dependency on biff/minimal@"));
+        assert!(warnings[1].contains("0.2.0"));
+        assert!(warnings[1].ends_with("but biff/minimal@0.1.0 was resolved instead"));
+    }
+
+    #[test]
+    fn sortable_dir_names() {
+        let versions = [
+            "0.9.0",
+            "1.0.0-alpha",
+            "1.0.0-alpha.1",
+            "1.0.0-alpha.beta",
+            "1.0.0-beta.2",
+            "1.0.0-beta.11",
+            "1.0.0-rc.1",
+            "1.0.0",
+            "1.2.0",
+            "10.0.0",
+        ];
+
+        let dir = tempfile::tempdir().unwrap();
+        let context = InstallationContext::new(dir.path()).with_sortable_dir_names(true);
+
+        let names: Vec<_> = versions
+            .iter()
+            .map(|version| {
+                let package_id: PackageId = format!("biff/minimal@{}", version).parse().unwrap();
+                context.package_dir_name(&package_id)
+            })
+            .collect();
+
+        let mut sorted_names = names.clone();
+        sorted_names.sort();
+        assert_eq!(sorted_names, names);
+
+        assert_eq!(
+            names[6],
+            "biff_minimal@00000001.00000000.00000000-rc.00000001"
+        );
+        assert_eq!(names[7], "biff_minimal@00000001.00000000.00000000~");
+
+        let (_, project) = one_dependency_project();
+
+        let context = project.context().with_sortable_dir_names(true);
+        project.install(context.clone()).unwrap();
+
+        assert!(project
+            .dir
+            .path()
+            .join("packages/_index/biff_minimal@00000000.00000001.00000000~/init.luau")
+            .is_file());
+        assert_eq!(context.verify_links().unwrap(), Vec::<BrokenLink>::new());
+    }
+
+    #[test]
+    fn strict_mode_fails_on_warnings() {
+        let registry = published_registry();
+        registry.publish(PackageBuilder::new("biff/no-entry@0.1.0").with_file("lib/init.luau", ""));
+
+        let root =
+            || PackageBuilder::new("biff/root@0.1.0").with_dep("NoEntry", "biff/no-entry@0.1.0");
+
+        let project = TestProject::new(&registry, root());
+        project.install(project.context()).unwrap();
+
+        let project = TestProject::new(&registry, root());
+        let err = project
+            .install(project.context().with_strict(true))
+            .unwrap_err();
+
+        assert!(
+            err.to_string()
+                .starts_with("biff/no-entry@0.1.0 may not be requirable"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn install_under_fs_prefix() {
+        let (_, project) = one_dependency_project();
+
+        let prefix = tempfile::tempdir().unwrap();
+        project
+            .install(project.context().with_fs_prefix(prefix.path()))
+            .unwrap();
+
+        assert!(!project.dir.path().join("packages").exists());
+
+        let filesystem = PrefixedFilesystem::new(prefix.path(), Arc::new(RealFilesystem));
+        let packages = filesystem.prefixed(&project.dir.path().join("packages"));
+        assert!(packages.starts_with(prefix.path()));
+
+        let link = fs_err::read_to_string(packages.join("OneDependency.lua")).unwrap();
+        assert_eq!(
+            link,
+            "return require(\"_index/biff_one-dependency@0.1.0/src\")\n"
+        );
+        assert!(packages
+            .join("_index/biff_minimal@0.1.0/init.luau")
+            .is_file());
+    }
+
+    #[test]
+    fn manifest_mismatch_rejected() {
+        let registry = InMemoryRegistry::new();
+        let (_, spoofed) = PackageBuilder::new("biff/minimal@0.2.0")
+            .with_file("init.luau", "")
+            .package();
+        let manifest = PackageBuilder::new("biff/minimal@0.1.0").into_manifest();
+        registry.publish_archive(manifest, spoofed);
+
+        let root = PackageBuilder::new("biff/root@0.1.0").with_dep("Minimal", "biff/minimal@0.1.0");
+        let project = TestProject::new(&registry, root);
+
+        let err = project
+            .install(project.context().with_manifest_validation(true))
+            .unwrap_err();
+        let message = format!("{:#}", err);
+        assert!(
+            message.contains("Requested biff/minimal@0.1.0"),
+            "{}",
+            message
+        );
+        assert!(message.contains("biff/minimal@0.2.0"), "{}", message);
+
+        project.install(project.context()).unwrap();
+    }
+
+    #[test]
+    fn unlinked_packages_reported() {
+        let (_, mut project) = minimal_project();
+
+        // Activated, but no package depends on it.
+        let orphan: PackageId = "biff/one-dependency@0.1.0".parse().unwrap();
+        project.resolved.activated.insert(orphan.clone());
+        project.resolved.metadata.insert(
+            orphan.clone(),
+            ResolvePackageMetadata {
+                realm: Realm::Shared,
+                origin_realm: Realm::Shared,
+                source_registry: PackageSourceId::DefaultRegistry,
+            },
+        );
+
+        let unlinked = project
+            .context()
+            .unlinked_packages(&project.resolved, &project.root_package_id)
+            .unwrap();
+        assert_eq!(unlinked, vec![orphan]);
+
+        let err = project
+            .install(project.context().with_strict(true))
+            .unwrap_err();
+        assert!(
+            err.to_string().contains("biff/one-dependency@0.1.0"),
+            "{}",
+            err
+        );
+
+        project.install(project.context()).unwrap();
+    }
+
+    #[test]
+    fn install_deserialized_resolve() {
+        let (_, mut project) = one_dependency_project();
+
+        let mut serialized = Vec::new();
+        project.resolved.to_writer(&mut serialized).unwrap();
+        let deserialized = Resolve::from_reader(serialized.as_slice()).unwrap();
+        assert_eq!(
+            format!("{:?}", deserialized),
+            format!("{:?}", project.resolved)
+        );
+
+        project.resolved = deserialized;
+        project.install(project.context()).unwrap();
+
+        let packages = project.dir.path().join("packages");
+        assert!(packages.join("OneDependency.lua").is_file());
+        assert!(packages
+            .join("_index/biff_one-dependency@0.1.0/packages/Minimal.lua")
+            .is_file());
+    }
+
+    #[test]
+    fn empty_package_warned() {
+        let registry = published_registry();
+
+        let mut buffer = Vec::new();
+        ZipWriter::new(Cursor::new(&mut buffer)).finish().unwrap();
+        let manifest = PackageBuilder::new("biff/empty@0.1.0").into_manifest();
+        registry.publish_archive(manifest, PackageContents::from_buffer(buffer));
+
+        let root = PackageBuilder::new("biff/root@0.1.0").with_dep("Empty", "biff/empty@0.1.0");
+        let project = TestProject::new(&registry, root);
+
+        let err = project
+            .install(project.context().with_strict(true))
+            .unwrap_err();
+        let message = format!("{:#}", err);
+        assert!(
+            message.contains("biff/empty@0.1.0 has no files"),
+            "{}",
+            message
+        );
+
+        project.install(project.context()).unwrap();
+        assert!(project
+            .dir
+            .path()
+            .join("packages/_index/biff_empty@0.1.0")
+            .is_dir());
+    }
+
+    #[test]
+    fn incremental_upgrade_writes_changed_files() {
+        let registry = InMemoryRegistry::new();
+        registry.publish(
+            PackageBuilder::new("biff/changing@1.0.0")
+                .with_file("init.luau", "return 1")
+                .with_file("Same.lua", "same")
+                .with_file("Changed.lua", "before")
+                .with_file("old/Removed.lua", "removed"),
+        );
+        registry.publish(
+            PackageBuilder::new("biff/changing@1.1.0")
+                .with_file("init.luau", "return 1")
+                .with_file("Same.lua", "same")
+                .with_file("Changed.lua", "after"),
+        );
+
+        let old_root =
+            PackageBuilder::new("biff/root@0.1.0").with_dep("Changing", "biff/changing@1.0.0");
+        let project = TestProject::new(&registry, old_root);
+        project
+            .install(project.context().with_incremental_upgrades(true))
+            .unwrap();
+
+        let new_root =
+            PackageBuilder::new("biff/root@0.1.0").with_dep("Changing", "biff/changing@1.1.0");
+        let upgraded = TestProject::new(&registry, new_root);
+
+        let filesystem = Arc::new(WriteRecordingFilesystem::default());
+        project
+            .context()
+            .with_incremental_upgrades(true)
+            .with_filesystem(filesystem.clone())
+            .install(
+                upgraded.sources.clone(),
+                upgraded.root_package_id.clone(),
+                upgraded.resolved.clone(),
+            )
+            .unwrap();
+
+        let index = project.dir.path().join("packages/_index");
+        let package_dir = index.join("biff_changing@1.1.0");
+
+        let mut written: Vec<_> = filesystem
+            .written
+            .lock()
+            .unwrap()
+            .iter()
+            .filter_map(|path| path.strip_prefix(&package_dir).ok())
+            .map(|path| path.to_path_buf())
+            .collect();
+        written.sort();
+
+        // The manifest differs too, since it names the new version.
+        assert_eq!(
+            written,
+            vec![PathBuf::from("Changed.lua"), PathBuf::from("wally.toml")]
+        );
+
+        assert!(!index.join("biff_changing@1.0.0").exists());
+        assert_eq!(
+            fs_err::read_to_string(package_dir.join("Changed.lua")).unwrap(),
+            "after"
+        );
+        assert!(package_dir.join("Same.lua").is_file());
+        assert!(!package_dir.join("old").exists());
+    }
+
+    #[test]
+    fn incremental_upgrade_in_memory() {
+        let registry = InMemoryRegistry::new();
+        registry.publish(
+            PackageBuilder::new("biff/changing@1.0.0")
+                .with_file("init.luau", "return 1")
+                .with_file("old/Removed.lua", "removed"),
+        );
+        registry
+            .publish(PackageBuilder::new("biff/changing@1.1.0").with_file("init.luau", "return 2"));
+
+        let old_root =
+            PackageBuilder::new("biff/root@0.1.0").with_dep("Changing", "biff/changing@1.0.0");
+        let new_root =
+            PackageBuilder::new("biff/root@0.1.0").with_dep("Changing", "biff/changing@1.1.0");
+        let old = TestProject::new(&registry, old_root);
+        let upgraded = TestProject::new(&registry, new_root);
+
+        let filesystem = Arc::new(MemoryFilesystem::new());
+        let context = InstallationContext::new(Path::new("project"))
+            .with_filesystem(filesystem.clone())
+            .with_incremental_upgrades(true);
+        old.install(context.clone()).unwrap();
+        upgraded.install(context).unwrap();
+
+        let index = Path::new("project/packages/_index");
+        let files: Vec<_> = filesystem
+            .files()
+            .into_keys()
+            .filter(|path| path.starts_with(index))
+            .collect();
+        assert_eq!(
+            files,
+            vec![
+                index.join("biff_changing@1.1.0/init.luau"),
+                index.join("biff_changing@1.1.0/wally.toml"),
+            ]
+        );
+    }
+
+    #[test]
+    fn warm_install_reports_skipped_packages() {
+        let (_, project) = one_dependency_project();
+        project.install(project.context()).unwrap();
+
+        let reporter = Arc::new(RecordingReporter::default());
+        project
+            .install(project.context().with_reporter(reporter.clone()))
+            .unwrap();
+
+        let events = reporter.events.lock().unwrap();
+        let minimal: PackageId = "biff/minimal@0.1.0".parse().unwrap();
+        let one_dependency: PackageId = "biff/one-dependency@0.1.0".parse().unwrap();
+
+        assert!(events.contains(&ProgressEvent::Skipped(minimal)));
+        assert!(events.contains(&ProgressEvent::Skipped(one_dependency)));
+        assert!(!events
+            .iter()
+            .any(|event| matches!(event, ProgressEvent::Downloaded(_))));
+    }
+
+    #[test]
+    fn install_options_part_of_install_state() {
+        let (_, project) = one_dependency_project();
+
+        let is_installed = |context: InstallationContext| {
+            context
+                .is_installed(&project.resolved, &project.root_package_id)
+                .unwrap()
+        };
+
+        assert!(!is_installed(project.context()));
+        project.install(project.context()).unwrap();
+        assert!(is_installed(project.context()));
+
+        // Every option that changes an install changes its state, without
+        // having to be listed anywhere.
+        assert!(!is_installed(
+            project
+                .context()
+                .with_presence_check(PresenceCheck::Checksum)
+        ));
+        assert!(!is_installed(project.context().with_policy(
+            InstallPolicy::new().deny("biff/left-pad").unwrap()
+        )));
+
+        project.context().clean().unwrap();
+        assert!(!is_installed(project.context()));
+    }
+
+    #[test]
+    fn failed_downloads_retried() {
+        let registry = published_registry();
+        let minimal: PackageId = "biff/minimal@0.1.0".parse().unwrap();
+
+        let root = PackageBuilder::new("biff/root@0.1.0").with_dep("Minimal", "biff/minimal@0.1.0");
+        let project = TestProject::new(&registry, root);
+
+        registry.fail_downloads(&minimal, 1);
+        project.install(project.context()).unwrap_err();
+
+        registry.fail_downloads(&minimal, 1);
+        let reporter = Arc::new(RecordingReporter::default());
+        project
+            .install(
+                project
+                    .context()
+                    .with_download_retries(2)
+                    .with_reporter(reporter.clone()),
+            )
+            .unwrap();
+
+        let events = reporter.events.lock().unwrap();
+        let position = |event: ProgressEvent| events.iter().position(|e| *e == event);
+
+        let retrying = position(ProgressEvent::Retrying(minimal.clone(), 1)).unwrap();
+        let downloaded = position(ProgressEvent::Downloaded(minimal.clone())).unwrap();
+        assert!(retrying < downloaded);
+        assert_eq!(position(ProgressEvent::Retrying(minimal, 2)), None);
+    }
+
+    #[test]
+    fn missing_package_not_retried() {
+        let registry = published_registry();
+        let minimal: PackageId = "biff/minimal@0.1.0".parse().unwrap();
+        registry.yank(&minimal);
+
+        let root = PackageBuilder::new("biff/root@0.1.0").with_dep("Minimal", "biff/minimal@0.1.0");
+        let project = TestProject::new(&registry, root);
+
+        let reporter = Arc::new(RecordingReporter::default());
+        project
+            .install(
+                project
+                    .context()
+                    .with_download_retries(40)
+                    .with_reporter(reporter.clone()),
+            )
+            .unwrap_err();
+
+        assert_eq!(registry.downloads_started(), 1);
+        assert!(!reporter
+            .events
+            .lock()
+            .unwrap()
+            .contains(&ProgressEvent::Retrying(minimal, 1)));
+    }
+
+    #[test]
+    fn retry_delay_capped() {
+        assert_eq!(retry_delay(1), RETRY_DELAY);
+        assert_eq!(retry_delay(3), RETRY_DELAY * 4);
+        assert_eq!(retry_delay(40), MAX_RETRY_DELAY);
+        assert_eq!(retry_delay(u32::MAX), MAX_RETRY_DELAY);
+    }
+
+    /// Delegates to the real filesystem, except that nothing under
+    /// `read_only` can be changed.
+    struct ReadOnlyFilesystem {
+        read_only: PathBuf,
+    }
+
+    impl ReadOnlyFilesystem {
+        fn check(&self, path: &Path) -> io::Result<()> {
+            if path.starts_with(&self.read_only) {
+                return Err(io::Error::new(
+                    io::ErrorKind::PermissionDenied,
+                    "read-only file system",
+                ));
+            }
+
+            Ok(())
+        }
+    }
+
+    impl DelegatingFilesystem for ReadOnlyFilesystem {
+        fn inner(&self) -> &dyn Filesystem {
+            &RealFilesystem
+        }
+
+        fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+            self.check(path)?;
+            RealFilesystem.create_dir_all(path)
+        }
+
+        fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+            self.check(path)?;
+            RealFilesystem.write(path, contents)
+        }
+
+        fn create_new(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+            self.check(path)?;
+            RealFilesystem.create_new(path, contents)
+        }
+
+        fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+            self.check(to)?;
+            RealFilesystem.rename(from, to)
+        }
+
+        fn remove_file(&self, path: &Path) -> io::Result<()> {
+            self.check(path)?;
+            RealFilesystem.remove_file(path)
+        }
+
+        fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+            self.check(path)?;
+            RealFilesystem.remove_dir_all(path)
+        }
+
+        fn hard_link(&self, from: &Path, to: &Path) -> io::Result<()> {
+            self.check(to)?;
+            RealFilesystem.hard_link(from, to)
+        }
+
+        fn junction(&self, from: &Path, to: &Path) -> io::Result<()> {
+            self.check(to)?;
+            RealFilesystem.junction(from, to)
+        }
+
+        fn set_modified(&self, path: &Path, time: SystemTime) -> io::Result<()> {
+            self.check(path)?;
+            RealFilesystem.set_modified(path, time)
+        }
+    }
+
+    #[test]
+    fn read_only_target_fails_early() {
+        let (_, project) = one_dependency_project();
+
+        let filesystem = Arc::new(ReadOnlyFilesystem {
+            read_only: project.dir.path().join("packages"),
+        });
+        let reporter = Arc::new(RecordingReporter::default());
+
+        let err = project
+            .install(
+                project
+                    .context()
+                    .with_filesystem(filesystem)
+                    .with_reporter(reporter.clone()),
+            )
+            .unwrap_err();
+
+        let index = project.dir.path().join("packages").join("_index");
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "Install target {} is read-only, so no packages can be installed: \
+                 read-only file system",
+                index.display()
+            )
+        );
+
+        // Nothing was downloaded.
+        assert!(reporter.events.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn list_installed_packages() {
+        let registry = published_registry();
+        registry.publish(PackageBuilder::new("biff/dev-only@0.1.0").with_file("init.luau", ""));
+
+        let root = PackageBuilder::new("biff/root@0.1.0")
+            .with_dep("OneDependency", "biff/one-dependency@0.1.0");
+        let mut project = TestProject::new(&registry, root);
+        project.add_dev_dependency("DevOnly", "biff/dev-only@0.1.0");
+        project.install(project.context()).unwrap();
+
+        let dir = project.dir.path();
+        let installed = |id: &str, realm, path: &str| InstalledPackage {
+            id: id.parse().unwrap(),
+            realm,
+            path: dir.join(path),
+        };
+
+        assert_eq!(
+            project.context().list_installed().unwrap(),
+            vec![
+                installed(
+                    "biff/minimal@0.1.0",
+                    Realm::Shared,
+                    "packages/_index/biff_minimal@0.1.0"
+                ),
+                installed(
+                    "biff/one-dependency@0.1.0",
+                    Realm::Shared,
+                    "packages/_index/biff_one-dependency@0.1.0"
+                ),
+                installed(
+                    "biff/dev-only@0.1.0",
+                    Realm::Dev,
+                    "DevPackages/_index/biff_dev-only@0.1.0"
+                ),
+            ]
+        );
+
+        let sortable = TestProject::new(
+            &registry,
+            PackageBuilder::new("biff/root@0.1.0").with_dep("Minimal", "biff/minimal@0.1.0"),
+        );
+        let context = sortable.context().with_sortable_dir_names(true);
+        sortable.install(context.clone()).unwrap();
+
+        let ids: Vec<_> = context
+            .list_installed()
+            .unwrap()
+            .into_iter()
+            .map(|package| package.id.to_string())
+            .collect();
+        assert_eq!(ids, vec!["biff/minimal@0.1.0"]);
+    }
+
+    #[test]
+    fn download_and_extraction_limits_apply_separately() {
+        let registry = InMemoryRegistry::new();
+        let mut root = PackageBuilder::new("biff/root@0.1.0");
+
+        for index in 0..6 {
+            let name = format!("biff/package-{}@0.1.0", index);
+            registry.publish(PackageBuilder::new(&name).with_file("init.luau", ""));
+            root = root.with_dep(format!("Package{}", index), name);
+        }
+
+        registry.delay_downloads(std::time::Duration::from_millis(50));
+        let project = TestProject::new(&registry, root);
+
+        let filesystem = Arc::new(ConcurrencyFilesystem::default());
+        project
+            .install(
+                project
+                    .context()
+                    .with_filesystem(filesystem.clone())
+                    .with_max_downloads(3)
+                    .with_max_extractions(1),
+            )
+            .unwrap();
+
+        // Bodies only arrive once they're read, so downloads left waiting on
+        // the single extraction would show up here as one at a time.
+        let most_downloads = registry.most_concurrent_downloads();
+        assert!(
+            most_downloads > 1 && most_downloads <= 3,
+            "{}",
+            most_downloads
+        );
+
+        let (_, most_extractions) = *filesystem.writing.lock().unwrap();
+        assert_eq!(most_extractions, 1);
+    }
+
+    #[test]
+    fn slow_download_times_out() {
+        let registry = published_registry();
+        registry.publish(PackageBuilder::new("biff/slow@0.1.0").with_file("init.luau", ""));
+        let slow: PackageId = "biff/slow@0.1.0".parse().unwrap();
+        registry.delay_download(&slow, Duration::from_secs(5));
+
+        let root = PackageBuilder::new("biff/root@0.1.0")
+            .with_dep("Minimal", "biff/minimal@0.1.0")
+            .with_dep("Slow", "biff/slow@0.1.0");
+        let project = TestProject::new(&registry, root);
+
+        let started = Instant::now();
+        let err = project
+            .install(
+                project
+                    .context()
+                    .with_download_timeout(Duration::from_millis(200)),
+            )
+            .unwrap_err();
+
+        // The download is stopped rather than left running, so the install
+        // doesn't wait for it.
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        let message = format!("{:#}", err);
+        assert!(
+            message.contains("Failed to install 1 of 2 packages"),
+            "{}",
+            message
+        );
+        assert!(
+            message.contains("Downloading biff/slow@0.1.0 timed out"),
+            "{}",
+            message
+        );
+
+        let index = project.dir.path().join("packages/_index");
+        assert!(index.join("biff_minimal@0.1.0/init.luau").is_file());
+    }
+
+    #[test]
+    fn presence_checks() {
+        let registry = published_registry();
+        let sources = PackageSourceMap::new(Box::new(registry.source()));
+        let manifest = PackageBuilder::new("biff/root@0.1.0")
+            .with_dep("OneDependency", "biff/one-dependency@0.1.0")
+            .into_manifest();
+        let resolved = resolve(&manifest, &Default::default(), &sources).unwrap();
+
+        let workspace = tempfile::tempdir().unwrap();
+        let install = |project: &str, presence_check| {
+            let project_path = workspace.path().join(project);
+            fs_err::create_dir(&project_path).unwrap();
+
+            let reporter = Arc::new(RecordingReporter::default());
+            InstallationContext::new(&project_path)
+                .with_workspace_root(workspace.path())
+                .with_presence_check(presence_check)
+                .with_reporter(reporter.clone())
+                .install(sources.clone(), manifest.package_id(), resolved.clone())
+                .unwrap();
+
+            let events = reporter.events.lock().unwrap();
+            let downloaded: Vec<_> = events
+                .iter()
+                .filter_map(|event| match event {
+                    ProgressEvent::Downloaded(package_id) => Some(package_id.to_string()),
+                    _ => None,
+                })
+                .collect();
+            downloaded
+        };
+
+        assert_eq!(install("a", PresenceCheck::ContentHash).len(), 2);
+
+        let init = workspace
+            .path()
+            .join("packages/_index/biff_minimal@0.1.0/init.luau");
+        fs_err::write(&init, "return 'edited'").unwrap();
+
+        assert_eq!(install("b", PresenceCheck::DirName), Vec::<String>::new());
+        assert_eq!(install("c", PresenceCheck::Checksum), Vec::<String>::new());
+        assert_eq!(fs_err::read_to_string(&init).unwrap(), "return 'edited'");
+
+        assert_eq!(
+            install("d", PresenceCheck::ContentHash),
+            vec!["biff/minimal@0.1.0"]
+        );
+        assert_eq!(fs_err::read_to_string(&init).unwrap(), "");
+    }
+
+    #[test]
+    fn progress_total_covers_every_realm() {
+        let registry = published_registry();
+        registry.publish(PackageBuilder::new("biff/server-only@0.1.0").with_file("init.luau", ""));
+        registry.publish(PackageBuilder::new("biff/dev-only@0.1.0").with_file("init.luau", ""));
+
+        let root = PackageBuilder::new("biff/root@0.1.0")
+            .with_dep("OneDependency", "biff/one-dependency@0.1.0")
+            .with_server_dep("ServerOnly", "biff/server-only@0.1.0");
+        let mut project = TestProject::new(&registry, root);
+        project.add_dev_dependency("DevOnly", "biff/dev-only@0.1.0");
+
+        let reporter = Arc::new(RecordingReporter::default());
+        project
+            .install(project.context().with_reporter(reporter.clone()))
+            .unwrap();
+
+        let events = reporter.events.lock().unwrap();
+        let totals: Vec<_> = events
+            .iter()
+            .filter_map(|event| match event {
+                ProgressEvent::Started(total, _) => Some(*total),
+                _ => None,
+            })
+            .collect();
+        let position = events
+            .iter()
+            .filter(|event| {
+                matches!(
+                    event,
+                    ProgressEvent::Downloaded(_) | ProgressEvent::Linked(_)
+                )
+            })
+            .count() as u64;
+
+        // Four downloads, a link step for each of them, and one for each of
+        // the root package's three realms.
+        assert_eq!(totals, vec![11]);
+        assert_eq!(position, 11);
+    }
+
+    #[test]
+    fn kept_archives_match_downloads() {
+        let (registry, project) = one_dependency_project();
+
+        project
+            .install(project.context().with_kept_archives(true))
+            .unwrap();
+
+        let archives = project.dir.path().join("packages/.archives");
+        for id in &["biff/minimal@0.1.0", "biff/one-dependency@0.1.0"] {
+            let package_id: PackageId = id.parse().unwrap();
+            let path = archives.join(format!("{}.zip", package_id_file_name(&package_id)));
+            let kept = fs_err::read(&path).unwrap();
+
+            let downloaded = registry.source().download_package(&package_id).unwrap();
+            assert_eq!(kept, downloaded.data());
+            ZipArchive::new(Cursor::new(kept)).unwrap();
+        }
+    }
+
+    #[test]
+    fn warnings_returned_as_diagnostics() {
+        let registry = published_registry();
+
+        let mut buffer = Vec::new();
+        ZipWriter::new(Cursor::new(&mut buffer)).finish().unwrap();
+        let manifest = PackageBuilder::new("biff/empty@0.1.0").into_manifest();
+        registry.publish_archive(manifest, PackageContents::from_buffer(buffer));
+
+        let root = PackageBuilder::new("biff/root@0.1.0").with_dep("Empty", "biff/empty@0.1.0");
+        let project = TestProject::new(&registry, root);
+
+        let diagnostics = project.install(project.context()).unwrap();
+        let empty: PackageId = "biff/empty@0.1.0".parse().unwrap();
+
+        let kinds: Vec<_> = diagnostics
+            .iter()
+            .map(|diagnostic| diagnostic.kind)
+            .collect();
+        assert_eq!(
+            kinds,
+            vec![DiagnosticKind::EmptyPackage, DiagnosticKind::EntryPoint]
+        );
+        assert!(diagnostics
+            .iter()
+            .all(|diagnostic| diagnostic.package_id.as_ref() == Some(&empty)));
+        assert_eq!(
+            diagnostics[0].path,
+            Some(project.dir.path().join("packages/_index/biff_empty@0.1.0"))
+        );
+        assert!(diagnostics[1]
+            .message
+            .starts_with("biff/empty@0.1.0 may not be requirable"));
+    }
+
+    #[test]
+    fn per_realm_index_names() {
+        let registry = published_registry();
+        registry.publish(PackageBuilder::new("biff/server-only@0.1.0").with_file("init.luau", ""));
+        registry.publish(PackageBuilder::new("biff/dev-only@0.1.0").with_file("init.luau", ""));
+
+        let root = PackageBuilder::new("biff/root@0.1.0").with_dep("Minimal", "biff/minimal@0.1.0");
+        let mut project = TestProject::new(&registry, root);
+        project.add_server_dependency("ServerOnly", "biff/server-only@0.1.0");
+        project.add_dev_dependency("DevOnly", "biff/dev-only@0.1.0");
+
+        let context = project
+            .context()
+            .with_index_name(Realm::Shared, "_Index")
+            .with_index_name(Realm::Server, "_server_index")
+            .with_index_name(Realm::Dev, "_dev_index");
+        project.install(context.clone()).unwrap();
+
+        let dir = project.dir.path();
+        let shared_link = fs_err::read_to_string(dir.join("packages/Minimal.lua")).unwrap();
+        assert_eq!(
+            shared_link,
+            "return require(\"_Index/biff_minimal@0.1.0\")\n"
+        );
+
+        let server_link =
+            fs_err::read_to_string(dir.join("ServerPackages/ServerOnly.lua")).unwrap();
+        assert_eq!(
+            server_link,
+            "return require(\"_server_index/biff_server-only@0.1.0\")\n"
+        );
+
+        let dev_link = fs_err::read_to_string(dir.join("DevPackages/DevOnly.lua")).unwrap();
+        assert_eq!(
+            dev_link,
+            "return require(\"_dev_index/biff_dev-only@0.1.0\")\n"
+        );
+
+        assert!(dir
+            .join("packages/_Index/biff_minimal@0.1.0/init.luau")
+            .is_file());
+        assert!(!dir.join("packages/_index").exists());
+        assert_eq!(context.verify_links().unwrap(), Vec::<BrokenLink>::new());
+
+        let rojo = project
+            .context()
+            .with_index_name(Realm::Server, "_server_index")
+            .with_index_name(Realm::Dev, "_dev_index")
+            .with_require_path_style(RequirePathStyle::AbsoluteRojo {
+                shared: "game.ReplicatedStorage.Packages".to_owned(),
+                server: "game.ServerScriptService.ServerPackages".to_owned(),
+                dev: "game.ReplicatedStorage.DevPackages".to_owned(),
+            });
+        project.install(rojo).unwrap();
+
+        let server_link =
+            fs_err::read_to_string(dir.join("ServerPackages/ServerOnly.lua")).unwrap();
+        assert_eq!(
+            server_link,
+            "return require(game.ServerScriptService.ServerPackages._server_index[\"biff_server-only@0.1.0\"])\n"
+        );
+
+        let dev_link = fs_err::read_to_string(dir.join("DevPackages/DevOnly.lua")).unwrap();
+        assert_eq!(
+            dev_link,
+            "return require(game.ReplicatedStorage.DevPackages._dev_index[\"biff_dev-only@0.1.0\"])\n"
+        );
+    }
+
+    #[test]
+    fn failed_fresh_install_restores_packages() {
+        let (registry, project) = one_dependency_project();
+        project.install(project.context()).unwrap();
+
+        let packages = project.dir.path().join("packages");
+        fs_err::write(packages.join("Stale.lua"), "return nil\n").unwrap();
+        let link = fs_err::read_to_string(packages.join("OneDependency.lua")).unwrap();
+
+        let minimal: PackageId = "biff/minimal@0.1.0".parse().unwrap();
+        registry.fail_downloads(&minimal, 1);
+
+        let install_fresh = || {
+            project.context().install_fresh(
+                project.sources.clone(),
+                project.root_package_id.clone(),
+                project.resolved.clone(),
+            )
+        };
+
+        install_fresh().unwrap_err();
+        assert_eq!(
+            fs_err::read_to_string(packages.join("OneDependency.lua")).unwrap(),
+            link
+        );
+        assert!(packages.join("Stale.lua").is_file());
+        assert!(packages
+            .join("_index/biff_minimal@0.1.0/init.luau")
+            .is_file());
+        assert!(!project.dir.path().join("packages.wally-backup").exists());
+
+        install_fresh().unwrap();
+        assert!(!packages.join("Stale.lua").exists());
+        assert!(packages
+            .join("_index/biff_minimal@0.1.0/init.luau")
+            .is_file());
+        assert!(!project.dir.path().join("packages.wally-backup").exists());
+    }
+
+    #[test]
+    fn dependency_cycle_warned() {
+        let (_, mut project) = one_dependency_project();
+
+        let minimal: PackageId = "biff/minimal@0.1.0".parse().unwrap();
+        let one_dependency: PackageId = "biff/one-dependency@0.1.0".parse().unwrap();
+        project
+            .resolved
+            .shared_dependencies
+            .entry(minimal.clone())
+            .or_default()
+            .insert("OneDependency".to_owned(), one_dependency);
+
+        let err = project
+            .install(project.context().with_strict(true))
+            .unwrap_err();
+        let expected = "Packages depend on each other in a cycle, so requiring them will loop \
+                        forever: biff/minimal@0.1.0 -> biff/one-dependency@0.1.0 -> \
+                        biff/minimal@0.1.0";
+        assert_eq!(err.to_string(), expected);
+
+        let diagnostics = project.install(project.context()).unwrap();
+        let cycles: Vec<_> = diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.kind == DiagnosticKind::DependencyCycle)
+            .collect();
+        assert_eq!(cycles.len(), 1, "{:?}", diagnostics);
+        assert_eq!(cycles[0].message, expected);
+        assert_eq!(cycles[0].package_id, Some(minimal));
+    }
+
+    #[test]
+    fn links_only_install_writes_only_links() {
+        let registry = published_registry();
+        let sources = PackageSourceMap::new(Box::new(registry.source()));
+        let manifest = PackageBuilder::new("biff/root@0.1.0")
+            .with_dep("OneDependency", "biff/one-dependency@0.1.0")
+            .into_manifest();
+        let resolved = resolve(&manifest, &Default::default(), &sources).unwrap();
+
+        let workspace = tempfile::tempdir().unwrap();
+        let context = |project: &str| {
+            let project_path = workspace.path().join(project);
+            fs_err::create_dir_all(&project_path).unwrap();
+            InstallationContext::new(&project_path).with_workspace_root(workspace.path())
+        };
+
+        let missing = context("a")
+            .with_links_only(true)
+            .install(sources.clone(), manifest.package_id(), resolved.clone())
+            .unwrap_err();
+        assert!(
+            missing.to_string().contains("links-only mode"),
+            "{}",
+            missing
+        );
+
+        context("a")
+            .install(sources.clone(), manifest.package_id(), resolved.clone())
+            .unwrap();
+
+        let filesystem = Arc::new(WriteRecordingFilesystem::default());
+        let reporter = Arc::new(RecordingReporter::default());
+        context("b")
+            .with_links_only(true)
+            .with_filesystem(filesystem.clone())
+            .with_reporter(reporter.clone())
+            .install(sources, manifest.package_id(), resolved)
+            .unwrap();
+
+        assert!(reporter
+            .events
+            .lock()
+            .unwrap()
+            .iter()
+            .all(|event| !matches!(event, ProgressEvent::Downloaded(_))));
+
+        let project_path = workspace.path().join("b");
+        let index = workspace.path().join("packages/_index");
+        let written = filesystem.written.lock().unwrap();
+        let links: Vec<_> = written
+            .iter()
+            .filter(|path| path.extension() == Some("lua".as_ref()))
+            .collect();
+        assert_eq!(
+            links,
+            vec![
+                &project_path.join("packages/OneDependency.lua"),
+                &index.join("biff_one-dependency@0.1.0/packages/Minimal.lua"),
+            ]
+        );
+
+        // Other than links, only the install's own bookkeeping is written.
+        let bookkeeping_dirs = [project_path.as_path(), workspace.path()];
+        assert!(written
+            .iter()
+            .filter(|path| path.extension() != Some("lua".as_ref()))
+            .all(|path| bookkeeping_dirs.contains(&path.parent().unwrap())));
+    }
+
+    #[test]
+    fn phase_timings_reported() {
+        let registry = published_registry();
+        registry.delay_downloads(Duration::from_millis(50));
+
+        let root = PackageBuilder::new("biff/root@0.1.0")
+            .with_dep("OneDependency", "biff/one-dependency@0.1.0");
+        let project = TestProject::new(&registry, root);
+
+        // Packages are streamed by default, and their unpacking is still timed
+        // apart from downloading them.
+        let reporter = Arc::new(RecordingReporter::default());
+        project
+            .install(project.context().with_reporter(reporter.clone()))
+            .unwrap();
+
+        let events = reporter.events.lock().unwrap();
+        let stats = events
+            .iter()
+            .find_map(|event| match event {
+                ProgressEvent::Stats(stats) => Some(stats.clone()),
+                _ => None,
+            })
+            .unwrap();
+
+        // Both packages were downloaded, each taking at least the delay.
+        assert!(
+            stats.download_time >= Duration::from_millis(100),
+            "{:?}",
+            stats
+        );
+        assert!(
+            stats.extraction_time > Duration::from_secs(0),
+            "{:?}",
+            stats
+        );
+        assert!(stats.link_time > Duration::from_secs(0), "{:?}", stats);
+
+        // Downloads overlap, so the phase can be shorter than their sum, but
+        // never shorter than a single download.
+        assert!(
+            stats.downloading_phase >= Duration::from_millis(50),
+            "{:?}",
+            stats
+        );
+        assert!(stats.linking_phase >= stats.link_time, "{:?}", stats);
+    }
+
+    #[test]
+    fn manifest_rewrite_stamps_version() {
+        let (registry, project) = minimal_project();
+
+        let stamp: ManifestRewrite = Arc::new(
+            |package_id: &PackageId, manifest_path: &Path, filesystem: &dyn Filesystem| {
+                let manifest = String::from_utf8(filesystem.read(manifest_path)?)?;
+                let stamped = manifest.replacen(
+                    "[package]\n",
+                    &format!("[package]\ndescription = \"Installed as {}\"\n", package_id),
+                    1,
+                );
+                filesystem.write(manifest_path, stamped.as_bytes())?;
+
+                Ok(())
+            },
+        );
+        project
+            .install(project.context().with_manifest_rewrite(stamp.clone()))
+            .unwrap();
+
+        let installed = Manifest::load(
+            &project
+                .dir
+                .path()
+                .join("packages/_index/biff_minimal@0.1.0"),
+        )
+        .unwrap();
+        assert_eq!(
+            installed.package.description.as_deref(),
+            Some("Installed as biff/minimal@0.1.0")
+        );
+
+        // Manifests are rewritten wherever the install puts them.
+        let filesystem = Arc::new(MemoryFilesystem::new());
+        project
+            .install(
+                InstallationContext::new(Path::new("project"))
+                    .with_filesystem(filesystem.clone())
+                    .with_manifest_rewrite(stamp),
+            )
+            .unwrap();
+        let manifest = Path::new("project/packages/_index/biff_minimal@0.1.0/wally.toml");
+        let installed = Manifest::from_slice(&filesystem.files()[manifest]).unwrap();
+        assert_eq!(
+            installed.package.description.as_deref(),
+            Some("Installed as biff/minimal@0.1.0")
+        );
+
+        // A rewrite that leaves the manifest broken fails the install.
+        let root = PackageBuilder::new("biff/root@0.1.0").with_dep("Minimal", "biff/minimal@0.1.0");
+        let project = TestProject::new(&registry, root);
+        let break_manifest: ManifestRewrite = Arc::new(
+            |_: &PackageId, manifest_path: &Path, filesystem: &dyn Filesystem| {
+                filesystem.write(manifest_path, b"not a manifest")?;
+                Ok(())
+            },
+        );
+        let err = project
+            .install(project.context().with_manifest_rewrite(break_manifest))
+            .unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("Rewritten manifest of biff/minimal@0.1.0 is invalid"),
+            "{:#}",
+            err
+        );
+    }
+
+    #[test]
+    fn failed_install_retried() {
+        let (registry, project) = one_dependency_project();
+
+        let minimal: PackageId = "biff/minimal@0.1.0".parse().unwrap();
+        let one_dependency: PackageId = "biff/one-dependency@0.1.0".parse().unwrap();
+
+        registry.fail_downloads(&minimal, 1);
+        let err = project.install(project.context()).unwrap_err();
+        assert_eq!(
+            err.downcast_ref::<PackagesFailed>().unwrap().packages,
+            vec![minimal.clone()]
+        );
+
+        registry.fail_downloads(&minimal, 1);
+        let reporter = Arc::new(RecordingReporter::default());
+        project
+            .install(
+                project
+                    .context()
+                    .with_install_retries(1)
+                    .with_reporter(reporter.clone()),
+            )
+            .unwrap();
+
+        let events = reporter.events.lock().unwrap();
+        let count = |event: ProgressEvent| events.iter().filter(|e| **e == event).count();
+
+        // The first attempt got only one package down, and the second one
+        // didn't download it again.
+        assert_eq!(count(ProgressEvent::Downloaded(one_dependency.clone())), 1);
+        assert_eq!(count(ProgressEvent::Skipped(one_dependency)), 1);
+        assert_eq!(count(ProgressEvent::Downloaded(minimal)), 1);
+        assert!(project
+            .dir
+            .path()
+            .join("packages/_index/biff_minimal@0.1.0/init.luau")
+            .is_file());
+    }
+
+    #[test]
+    fn build_metadata_ignored_when_matching_ids() {
+        let registry = InMemoryRegistry::new();
+        registry
+            .publish(PackageBuilder::new("biff/minimal@0.1.0+build").with_file("init.luau", ""));
+
+        let root = PackageBuilder::new("biff/root@0.1.0").with_dep("Minimal", "biff/minimal@0.1.0");
+        let mut project = TestProject::new(&registry, root);
+
+        // Ids written down without the build metadata, like a root id from a
+        // manifest that left it off, still refer to the same packages.
+        let minimal: PackageId = "biff/minimal@0.1.0".parse().unwrap();
+        project
+            .resolved
+            .shared_dependencies
+            .get_mut(&project.root_package_id)
+            .unwrap()
+            .insert("Minimal".to_owned(), minimal);
+        project.root_package_id = "biff/root@0.1.0+local".parse().unwrap();
+
+        project.install(project.context()).unwrap();
+
+        let packages = project.dir.path().join("packages");
+        assert_eq!(
+            fs_err::read_to_string(packages.join("Minimal.lua")).unwrap(),
+            "return require(\"_index/biff_minimal@0.1.0+build\")\n"
+        );
+        let index: Vec<_> = fs_err::read_dir(packages.join("_index"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(index, vec!["biff_minimal@0.1.0+build"]);
+    }
+
+    #[test]
+    fn resumed_install_progress_starts_partway() {
+        let (_, project) = one_dependency_project();
+
+        let workspace = tempfile::tempdir().unwrap();
+        let context = || project.context().with_workspace_root(workspace.path());
+        project.install(context()).unwrap();
+
+        // Only get halfway, as if the install was interrupted.
+        fs_err::remove_dir_all(
+            workspace
+                .path()
+                .join("packages/_index/biff_one-dependency@0.1.0"),
+        )
+        .unwrap();
+
+        let reporter = Arc::new(RecordingReporter::default());
+        project
+            .install(context().with_reporter(reporter.clone()))
+            .unwrap();
+
+        let events = reporter.events.lock().unwrap();
+
+        // Two packages and three sets of links, one of the packages already
+        // there.
+        let started = events
+            .iter()
+            .find(|event| matches!(event, ProgressEvent::Started(..)));
+        assert_eq!(started, Some(&ProgressEvent::Started(5, 1)));
+
+        let steps = events
+            .iter()
+            .filter(|event| {
+                matches!(
+                    event,
+                    ProgressEvent::Downloaded(_) | ProgressEvent::Linked(_)
+                )
+            })
+            .count();
+        assert_eq!(steps, 4);
+    }
+
+    #[test]
+    fn extraction_runs_on_pool() {
+        let registry = published_registry();
+        for version in &["0.1.0", "0.2.0", "0.3.0", "0.4.0"] {
+            registry.publish(
+                PackageBuilder::new(&format!("biff/extra@{}", version)).with_file("init.luau", ""),
+            );
+        }
+
+        let root = PackageBuilder::new("biff/root@0.1.0")
+            .with_dep("OneDependency", "biff/one-dependency@0.1.0")
+            .with_dep("Extra", "biff/extra@0.4.0");
+        let project = TestProject::new(&registry, root);
+
+        let filesystem = Arc::new(WriteRecordingFilesystem::default());
+        let pool = ExtractionPool::new(2).unwrap();
+        project
+            .install(
+                project
+                    .context()
+                    .with_filesystem(filesystem.clone())
+                    .with_extraction_pool(pool.clone()),
+            )
+            .unwrap();
+
+        let written = filesystem.written.lock().unwrap();
+        let writers = filesystem.writers.lock().unwrap();
+        let unpacking_threads: HashSet<_> = written
+            .iter()
+            .zip(writers.iter())
+            .filter(|(path, _)| path.ends_with("init.luau"))
+            .map(|(_, writer)| writer.clone().unwrap_or_default())
+            .collect();
+
+        assert!(!unpacking_threads.is_empty());
+        assert!(
+            unpacking_threads.len() <= pool.threads(),
+            "{:?}",
+            unpacking_threads
+        );
+        for thread in unpacking_threads {
+            assert!(thread.starts_with("wally-extract-"), "{}", thread);
+        }
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn junction_from_cache() {
+        let (_, project) = one_dependency_project();
+
+        let cache_dir = tempfile::tempdir().unwrap();
+        let cache = PackageCache::new(cache_dir.path());
+        project
+            .install(
+                project
+                    .context()
+                    .with_cache(cache.clone())
+                    .with_strategy(InstallStrategy::Hardlink)
+                    .with_junctions(true),
+            )
+            .unwrap();
+
+        let one_dependency_id: PackageId = "biff/one-dependency@0.1.0".parse().unwrap();
+        let installed = project
+            .dir
+            .path()
+            .join("packages/_index/biff_one-dependency@0.1.0");
+        let cached = cache.unpacked_path_for(&one_dependency_id);
+
+        assert!(fs_err::symlink_metadata(installed.join("src"))
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(
+            fs_err::canonicalize(installed.join("src")).unwrap(),
+            fs_err::canonicalize(cached.join("src")).unwrap()
+        );
+        assert!(installed.join("src/init.luau").is_file());
+
+        // The links directory is a real one, so links stay out of the cache.
+        assert!(!fs_err::symlink_metadata(installed.join("packages"))
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert!(!cached.join("packages").exists());
+    }
+
+    #[test]
+    fn single_package_installed_without_runtime() {
+        let (registry, project) = minimal_project();
+
+        let runtimes_built = Arc::new(Mutex::new(Vec::new()));
+        let factory: RuntimeFactory = {
+            let runtimes_built = runtimes_built.clone();
+            Arc::new(move |threads| {
+                runtimes_built.lock().unwrap().push(threads);
+                tokio::runtime::Builder::new_multi_thread()
+                    .worker_threads(threads)
+                    .enable_all()
+                    .build()
+            })
+        };
+
+        let inline = Arc::new(MemoryFilesystem::new());
+        project
+            .install(
+                InstallationContext::new(Path::new("project"))
+                    .with_filesystem(inline.clone())
+                    .with_runtime_factory(factory.clone()),
+            )
+            .unwrap();
+        assert!(runtimes_built.lock().unwrap().is_empty());
+
+        let pooled = Arc::new(MemoryFilesystem::new());
+        project
+            .install(
+                InstallationContext::new(Path::new("project"))
+                    .with_filesystem(pooled.clone())
+                    .with_scheduler(DownloadScheduler::new(4).unwrap()),
+            )
+            .unwrap();
+        assert_eq!(inline.files(), pooled.files());
+
+        // Installs with more packages still get a pool.
+        let bigger = TestProject::new(
+            &registry,
+            PackageBuilder::new("biff/root@0.1.0")
+                .with_dep("OneDependency", "biff/one-dependency@0.1.0"),
+        );
+        bigger
+            .install(bigger.context().with_runtime_factory(factory))
+            .unwrap();
+        assert_eq!(*runtimes_built.lock().unwrap(), vec![50]);
+    }
+
+    #[test]
+    fn yanked_package_falls_back() {
+        let (registry, project) = one_dependency_project();
+
+        let yanked: PackageId = "biff/minimal@0.1.0".parse().unwrap();
+        registry.publish(PackageBuilder::new("biff/minimal@0.1.2").with_file("init.luau", ""));
+        registry.publish(PackageBuilder::new("biff/minimal@0.1.1").with_file("init.luau", ""));
+        registry.yank(&yanked);
+
+        let err = project.install(project.context()).unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("biff/minimal@0.1.0"),
+            "{:#}",
+            err
+        );
+
+        let diagnostics = project
+            .install(
+                project
+                    .context()
+                    .with_on_yanked(FallbackPolicy::NearestCompatible),
+            )
+            .unwrap();
+
+        let index = project.dir.path().join("packages/_index");
+        assert!(index.join("biff_minimal@0.1.1/init.luau").exists());
+        assert!(!index.join("biff_minimal@0.1.0").exists());
+        assert!(!index.join("biff_minimal@0.1.2").exists());
+
+        let link =
+            fs_err::read_to_string(index.join("biff_one-dependency@0.1.0/packages/Minimal.lua"))
+                .unwrap();
+        assert!(link.contains("biff_minimal@0.1.1"), "{}", link);
+
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].kind, DiagnosticKind::YankedPackage);
+        assert_eq!(diagnostics[0].package_id, Some(yanked));
+    }
+
+    #[test]
+    fn gitignore_written_into_package_dirs() {
+        let registry = published_registry();
+        registry.publish(PackageBuilder::new("biff/dev-only@0.1.0").with_file("init.luau", ""));
+
+        let root = PackageBuilder::new("biff/root@0.1.0")
+            .with_dep("OneDependency", "biff/one-dependency@0.1.0");
+        let mut project = TestProject::new(&registry, root);
+        project.add_dev_dependency("DevOnly", "biff/dev-only@0.1.0");
+
+        let dir = project.dir.path();
+        fs_err::create_dir_all(dir.join("packages")).unwrap();
+        fs_err::write(dir.join("packages/.gitignore"), "_index/\n").unwrap();
+
+        project
+            .install(project.context().with_gitignore(true))
+            .unwrap();
+
+        assert_eq!(
+            fs_err::read_to_string(dir.join("DevPackages/.gitignore")).unwrap(),
+            "*\n"
+        );
+        assert_eq!(
+            fs_err::read_to_string(dir.join("packages/.gitignore")).unwrap(),
+            "_index/\n"
+        );
+        assert!(!dir.join("ServerPackages").exists());
+    }
+
+    #[test]
+    fn newer_luau_version_warned() {
+        let registry = published_registry();
+        registry.publish(
+            PackageBuilder::new("biff/modern@0.1.0")
+                .with_luau("0.650")
+                .with_file("init.luau", ""),
+        );
+        registry.publish(
+            PackageBuilder::new("biff/older@0.1.0")
+                .with_luau("0.600")
+                .with_file("init.luau", ""),
+        );
+
+        let root = || {
+            PackageBuilder::new("biff/root@0.1.0")
+                .with_dep("Modern", "biff/modern@0.1.0")
+                .with_dep("Older", "biff/older@0.1.0")
+        };
+        let project = TestProject::new(&registry, root());
+        let target: LuauVersion = "0.640".parse().unwrap();
+
+        let diagnostics = project
+            .install(project.context().with_luau_target(target))
+            .unwrap();
+
+        let modern: PackageId = "biff/modern@0.1.0".parse().unwrap();
+        assert_eq!(diagnostics.len(), 1);
+        assert_eq!(diagnostics[0].kind, DiagnosticKind::LuauVersion);
+        assert_eq!(diagnostics[0].package_id, Some(modern));
+        assert!(
+            diagnostics[0].message.contains("Luau 0.650"),
+            "{}",
+            diagnostics[0].message
+        );
+
+        // A separate project, since the first is up to date.
+        let strict_project = TestProject::new(&registry, root());
+        let err = strict_project
+            .install(
+                strict_project
+                    .context()
+                    .with_luau_target(target)
+                    .with_strict(true),
+            )
+            .unwrap_err();
+        assert!(err.to_string().contains("biff/modern@0.1.0"), "{}", err);
+    }
+
+    #[test]
+    fn files_written_with_fixed_mtime() {
+        let (_, project) = one_dependency_project();
+
+        // The filesystem is set afterwards, and still gets the times.
+        let mtime = SystemTime::UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        let filesystem = Arc::new(WriteRecordingFilesystem::default());
+        project
+            .install(
+                project
+                    .context()
+                    .with_mtime(mtime)
+                    .with_filesystem(filesystem.clone()),
+            )
+            .unwrap();
+        assert!(!filesystem.written.lock().unwrap().is_empty());
+
+        let packages = project.dir.path().join("packages");
+        for path in &[
+            "OneDependency.lua",
+            "_index/biff_minimal@0.1.0/init.luau",
+            "_index/biff_one-dependency@0.1.0/src/init.luau",
+            "_index/biff_one-dependency@0.1.0/packages/Minimal.lua",
+        ] {
+            let modified = fs_err::metadata(packages.join(path))
+                .unwrap()
+                .modified()
+                .unwrap();
+            assert_eq!(modified, mtime, "{}", path);
+        }
+    }
+
+    #[test]
+    fn installed_packages_read_through_filesystem() {
+        let (_, project) = one_dependency_project();
+
+        let filesystem = Arc::new(MemoryFilesystem::new());
+        let context =
+            InstallationContext::new(Path::new("project")).with_filesystem(filesystem.clone());
+        project.install(context.clone()).unwrap();
+
+        let installed: Vec<_> = context
+            .list_installed()
+            .unwrap()
+            .into_iter()
+            .map(|package| package.id.to_string())
+            .collect();
+        assert_eq!(
+            installed,
+            vec!["biff/minimal@0.1.0", "biff/one-dependency@0.1.0"]
+        );
+
+        let expected: usize = filesystem
+            .files()
+            .iter()
+            .filter(|(path, _)| path.starts_with("project/packages"))
+            .map(|(_, contents)| contents.len())
+            .sum();
+        assert_eq!(context.installed_size().unwrap(), expected as u64);
+    }
+
+    #[test]
+    fn ambiguous_entry_point_uses_luau() {
+        let registry = published_registry();
+        registry.publish(
+            PackageBuilder::new("biff/both@0.1.0")
+                .with_file("init.luau", "")
+                .with_file("init.lua", ""),
+        );
+        registry.publish(PackageBuilder::new("biff/lua-only@0.1.0").with_file("init.lua", ""));
+        registry.publish(
+            PackageBuilder::new("biff/parent@0.1.0")
+                .with_file("init.luau", "")
+                .with_dep("LuaOnly", "biff/lua-only@0.1.0"),
+        );
+
+        let root = PackageBuilder::new("biff/root@0.1.0")
+            .with_dep("Both", "biff/both@0.1.0")
+            .with_dep("Parent", "biff/parent@0.1.0");
+        let project = TestProject::new(&registry, root);
+
+        let diagnostics = project.install(project.context()).unwrap();
+        let packages = project.dir.path().join("packages");
+
+        assert_eq!(
+            fs_err::read_to_string(packages.join("Both.lua")).unwrap(),
+            "return require(\"_index/biff_both@0.1.0\")\n"
+        );
+        assert_eq!(
+            fs_err::read_to_string(packages.join("_index/biff_parent@0.1.0/packages/LuaOnly.lua"))
+                .unwrap(),
+            "return require(\"../../biff_lua-only@0.1.0\")\n"
+        );
+
+        let both: PackageId = "biff/both@0.1.0".parse().unwrap();
+        let entry_points: Vec<_> = diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.kind == DiagnosticKind::EntryPoint)
+            .collect();
+        assert_eq!(entry_points.len(), 1, "{:?}", diagnostics);
+        assert_eq!(entry_points[0].package_id, Some(both));
+        assert!(
+            entry_points[0].message.contains("so its init.luau is used"),
+            "{}",
+            entry_points[0].message
+        );
+    }
 }
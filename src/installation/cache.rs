@@ -0,0 +1,415 @@
+//! A cache of downloaded package archives that can be shared between projects.
+
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::bail;
+use once_cell::sync::OnceCell;
+
+use crate::package_contents::PackageContents;
+use crate::package_id::PackageId;
+
+use super::filesystem::{Filesystem, RealFilesystem};
+
+/// How `PackageCache::prune_cache` picks which packages to evict.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CachePrunePolicy {
+    /// Evict the least recently used packages until the cache takes up no
+    /// more than this many bytes.
+    MaxSize(u64),
+
+    /// Evict every package that hasn't been used for longer than this.
+    MaxAge(Duration),
+}
+
+/// What `PackageCache::prune_cache` evicted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PrunedCache {
+    /// The evicted packages, least recently used first.
+    pub evicted: Vec<PackageId>,
+
+    /// The total size of the files that were removed.
+    pub bytes_freed: u64,
+}
+
+/// A package in the cache, as found by `PackageCache::prune_cache`.
+struct CacheEntry {
+    package_id: PackageId,
+    last_used: SystemTime,
+    size: u64,
+}
+
+/// Stores package archives on disk, keyed by `PackageId`.
+///
+/// A cache that can't be written to is only ever read from. This happens when
+/// the cache is populated ahead of time and mounted somewhere installs can't
+/// write to.
+#[derive(Clone)]
+pub struct PackageCache {
+    root: PathBuf,
+    filesystem: Arc<dyn Filesystem>,
+
+    /// Whether the cache can be written to, found out the first time it's
+    /// needed.
+    writable: Arc<OnceCell<bool>>,
+}
+
+impl PackageCache {
+    /// Use the given directory as a cache, adding packages to it as they're
+    /// downloaded if it can be written to.
+    pub fn new<P: Into<PathBuf>>(root: P) -> Self {
+        Self {
+            root: root.into(),
+            filesystem: Arc::new(RealFilesystem),
+            writable: Default::default(),
+        }
+    }
+
+    /// Read and write the cache through the given filesystem instead of the
+    /// real one.
+    pub fn with_filesystem(mut self, filesystem: Arc<dyn Filesystem>) -> Self {
+        self.filesystem = filesystem;
+        self.writable = Default::default();
+        self
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// Whether the cache can't be written to, in which case it's only read
+    /// from.
+    pub fn is_read_only(&self) -> bool {
+        !*self.writable.get_or_init(|| match self.probe_writable() {
+            Ok(()) => true,
+            Err(err) => {
+                log::debug!(
+                    "Only reading from the package cache at {}: {}",
+                    self.root.display(),
+                    err
+                );
+                false
+            }
+        })
+    }
+
+    /// Check that the cache can be written to by writing and removing an
+    /// empty file in it, creating it if needed.
+    fn probe_writable(&self) -> io::Result<()> {
+        let probe_path = self
+            .root
+            .join(format!(".wally-write-probe.{}", std::process::id()));
+
+        self.filesystem.create_dir_all(&self.root)?;
+        self.filesystem.write(&probe_path, b"")?;
+        self.filesystem.remove_file(&probe_path)
+    }
+
+    /// The path that the archive for the given package is stored at.
+    pub fn path_for(&self, package_id: &PackageId) -> PathBuf {
+        self.root
+            .join(package_id.name().scope())
+            .join(package_id.name().name())
+            .join(format!("{}.zip", package_id.version()))
+    }
+
+    /// The directory that the unpacked files of the given package are stored
+    /// in, next to its archive.
+    pub fn unpacked_path_for(&self, package_id: &PackageId) -> PathBuf {
+        self.root
+            .join(package_id.name().scope())
+            .join(package_id.name().name())
+            .join(package_id.version().to_string())
+    }
+
+    /// The file recording when the given package was last used, next to its
+    /// archive.
+    fn used_path_for(&self, package_id: &PackageId) -> PathBuf {
+        self.path_for(package_id).with_extension("used")
+    }
+
+    /// Record that the given package was used at `time`, as seconds since
+    /// the Unix epoch. Pruning evicts the packages used longest ago first.
+    fn record_use(&self, package_id: &PackageId, time: SystemTime) -> anyhow::Result<()> {
+        let seconds = time.duration_since(UNIX_EPOCH)?.as_secs();
+        self.filesystem.write(
+            &self.used_path_for(package_id),
+            seconds.to_string().as_bytes(),
+        )?;
+
+        Ok(())
+    }
+
+    /// Record that the given package was used just now, which is only worth a
+    /// warning if it fails.
+    fn record_use_now(&self, package_id: &PackageId) {
+        if self.is_read_only() {
+            return;
+        }
+
+        if let Err(err) = self.record_use(package_id, SystemTime::now()) {
+            log::warn!("Could not record use of cached {}: {}", package_id, err);
+        }
+    }
+
+    /// When the given package was last used. Packages cached before uses were
+    /// recorded fall back to when their archive was written.
+    fn last_used(&self, package_id: &PackageId) -> anyhow::Result<SystemTime> {
+        match self.filesystem.read(&self.used_path_for(package_id)) {
+            Ok(contents) => {
+                if let Ok(seconds) = String::from_utf8_lossy(&contents).trim().parse() {
+                    return Ok(UNIX_EPOCH + Duration::from_secs(seconds));
+                }
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+
+        Ok(self.filesystem.modified(&self.path_for(package_id))?)
+    }
+
+    /// Every package in the cache. Partially written archives and anything
+    /// else that isn't named like a cached package is skipped.
+    fn entries(&self) -> anyhow::Result<Vec<CacheEntry>> {
+        let mut entries = Vec::new();
+
+        for entry in self.filesystem.walk_dir(&self.root)? {
+            let path = entry.path.as_path();
+            let depth = path.strip_prefix(&self.root).unwrap().components().count();
+
+            if depth != 3 || !entry.is_file || path.extension() != Some("zip".as_ref()) {
+                continue;
+            }
+
+            let component = |path: &Path| path.file_name().unwrap().to_string_lossy().into_owned();
+            let name_dir = path.parent().unwrap();
+            let package_id = format!(
+                "{}/{}@{}",
+                component(name_dir.parent().unwrap()),
+                component(name_dir),
+                path.file_stem().unwrap().to_string_lossy()
+            );
+
+            let package_id: PackageId = match package_id.parse() {
+                Ok(package_id) => package_id,
+                Err(_) => continue,
+            };
+
+            let mut size = entry.len;
+
+            let unpacked_path = self.unpacked_path_for(&package_id);
+            if self.filesystem.is_dir(&unpacked_path) {
+                for file in self.filesystem.walk_dir(&unpacked_path)? {
+                    if file.is_file {
+                        size += file.len;
+                    }
+                }
+            }
+
+            entries.push(CacheEntry {
+                last_used: self.last_used(&package_id)?,
+                package_id,
+                size,
+            });
+        }
+
+        Ok(entries)
+    }
+
+    /// Evict packages from the cache according to `policy`, removing their
+    /// archives and unpacked files. Read-only caches can't be pruned.
+    pub fn prune_cache(&self, policy: CachePrunePolicy) -> anyhow::Result<PrunedCache> {
+        if self.is_read_only() {
+            bail!(
+                "Cannot prune the package cache at {}, because it is read-only",
+                self.root.display()
+            );
+        }
+
+        let mut entries = self.entries()?;
+        entries.sort_by_key(|entry| entry.last_used);
+
+        let now = SystemTime::now();
+        let mut remaining_size: u64 = entries.iter().map(|entry| entry.size).sum();
+        let mut pruned = PrunedCache::default();
+
+        for entry in entries {
+            let evict = match policy {
+                CachePrunePolicy::MaxSize(max_size) => remaining_size > max_size,
+                CachePrunePolicy::MaxAge(max_age) => now
+                    .duration_since(entry.last_used)
+                    .map_or(false, |age| age > max_age),
+            };
+
+            if !evict {
+                continue;
+            }
+
+            let unpacked_path = self.unpacked_path_for(&entry.package_id);
+            if self.filesystem.is_dir(&unpacked_path) {
+                self.filesystem.remove_dir_all(&unpacked_path)?;
+            }
+
+            self.filesystem
+                .remove_file(&self.path_for(&entry.package_id))?;
+
+            if let Err(err) = self
+                .filesystem
+                .remove_file(&self.used_path_for(&entry.package_id))
+            {
+                if err.kind() != io::ErrorKind::NotFound {
+                    return Err(err.into());
+                }
+            }
+
+            remaining_size -= entry.size;
+            pruned.bytes_freed += entry.size;
+            pruned.evicted.push(entry.package_id);
+        }
+
+        Ok(pruned)
+    }
+
+    /// Returns a directory holding the unpacked files of the given package,
+    /// unpacking `contents` into the cache first if they aren't there yet.
+    /// Returns `None` if they aren't there and the cache is read-only.
+    pub fn unpacked(
+        &self,
+        package_id: &PackageId,
+        contents: &PackageContents,
+    ) -> anyhow::Result<Option<PathBuf>> {
+        let path = self.unpacked_path_for(package_id);
+
+        if self.filesystem.is_dir(&path) {
+            self.record_use_now(package_id);
+            return Ok(Some(path));
+        }
+
+        if self.is_read_only() {
+            return Ok(None);
+        }
+
+        // Like archives, unpacked packages are only moved into place once
+        // they're complete.
+        let temp_path = path.with_file_name(format!(
+            "{}.{}.tmp",
+            package_id.version(),
+            std::process::id()
+        ));
+
+        if self.filesystem.exists(&temp_path) {
+            self.filesystem.remove_dir_all(&temp_path)?;
+        }
+
+        self.filesystem.create_dir_all(&temp_path)?;
+        contents.unpack_into(&temp_path, self.filesystem.as_ref(), None)?;
+
+        if let Err(err) = self.filesystem.rename(&temp_path, &path) {
+            // Another install may have unpacked it first.
+            self.filesystem.remove_dir_all(&temp_path)?;
+
+            if !self.filesystem.is_dir(&path) {
+                return Err(err.into());
+            }
+        }
+
+        Ok(Some(path))
+    }
+
+    /// Returns the cached contents of the given package, if there are any.
+    pub fn get(&self, package_id: &PackageId) -> anyhow::Result<Option<PackageContents>> {
+        match self.filesystem.read(&self.path_for(package_id)) {
+            Ok(data) => {
+                self.record_use_now(package_id);
+                Ok(Some(PackageContents::from_buffer(data)))
+            }
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Adds the contents of a package to the cache. Does nothing if the cache
+    /// is read-only.
+    pub fn insert(&self, package_id: &PackageId, contents: &PackageContents) -> anyhow::Result<()> {
+        if self.is_read_only() {
+            return Ok(());
+        }
+
+        let path = self.path_for(package_id);
+        self.filesystem.create_dir_all(path.parent().unwrap())?;
+
+        // Write to a temporary file first so that other installs reading the
+        // cache never see a partially written archive.
+        let temp_path = path.with_extension(format!("zip.{}.tmp", std::process::id()));
+        self.filesystem.write(&temp_path, contents.data())?;
+        self.filesystem.rename(&temp_path, &path)?;
+        self.record_use_now(package_id);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_package::PackageBuilder;
+
+    #[test]
+    fn prune_by_age_and_size() {
+        let ids = ["biff/old@0.1.0", "biff/middle@0.1.0", "biff/new@0.1.0"];
+        let days = |days: u64| Duration::from_secs(days * 24 * 60 * 60);
+
+        let populate = || {
+            let dir = tempfile::tempdir().unwrap();
+            let cache = PackageCache::new(dir.path());
+            let mut sizes = Vec::new();
+
+            // Used 30, 10 and 0 days ago.
+            for (id, age) in ids.iter().zip(&[30, 10, 0]) {
+                let package_id: PackageId = id.parse().unwrap();
+                let contents = PackageBuilder::new(*id)
+                    .with_file("init.luau", "return {}")
+                    .contents();
+
+                cache.insert(&package_id, &contents).unwrap();
+                cache
+                    .record_use(&package_id, SystemTime::now() - days(*age))
+                    .unwrap();
+
+                sizes.push(contents.data().len() as u64);
+            }
+
+            (dir, cache, sizes)
+        };
+
+        let evicted_names = |pruned: &PrunedCache| {
+            pruned
+                .evicted
+                .iter()
+                .map(|id| id.name().name().to_owned())
+                .collect::<Vec<_>>()
+        };
+
+        let (_dir, cache, sizes) = populate();
+        let pruned = cache
+            .prune_cache(CachePrunePolicy::MaxAge(days(7)))
+            .unwrap();
+        assert_eq!(evicted_names(&pruned), vec!["old", "middle"]);
+        assert_eq!(pruned.bytes_freed, sizes[0] + sizes[1]);
+        assert!(!cache.path_for(&ids[0].parse().unwrap()).exists());
+        assert!(cache.get(&ids[2].parse().unwrap()).unwrap().is_some());
+
+        let (_dir, cache, sizes) = populate();
+        let pruned = cache
+            .prune_cache(CachePrunePolicy::MaxSize(sizes[1] + sizes[2]))
+            .unwrap();
+        assert_eq!(evicted_names(&pruned), vec!["old"]);
+        assert_eq!(pruned.bytes_freed, sizes[0]);
+
+        let pruned = cache.prune_cache(CachePrunePolicy::MaxSize(0)).unwrap();
+        assert_eq!(evicted_names(&pruned), vec!["middle", "new"]);
+    }
+}
@@ -0,0 +1,88 @@
+//! Structured warnings about an install.
+//!
+//! Warnings are logged as they happen, but tools like editors can't parse
+//! log lines, so installs also return each warning as a `Diagnostic`.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::package_id::PackageId;
+
+/// What a `Diagnostic` is about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DiagnosticKind {
+    /// A peer dependency isn't satisfied by the resolved packages.
+    PeerDependency,
+
+    /// Packages depend on each other in a cycle.
+    DependencyCycle,
+
+    /// The staging directory couldn't be used.
+    StagingDirectory,
+
+    /// A package couldn't be added to the package cache.
+    Cache,
+
+    /// A package has no clear entry point, so its links may not work.
+    EntryPoint,
+
+    /// A package has no files.
+    EmptyPackage,
+
+    /// Packages are missing from the index after installing.
+    MissingPackages,
+
+    /// Packages were installed that nothing links to.
+    UnlinkedPackages,
+
+    /// A package depends on one that was uninstalled.
+    DanglingDependency,
+
+    /// Packages were installed into a directory the Rojo project doesn't
+    /// map, so they won't be in the game.
+    UnmappedDirectory,
+
+    /// A package was yanked from its source, so a compatible version was
+    /// installed instead.
+    YankedPackage,
+
+    /// A package needs a newer Luau release than the one being targeted.
+    LuauVersion,
+}
+
+/// A warning about an install, with the package and path it's about when
+/// there's one in particular.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Diagnostic {
+    pub kind: DiagnosticKind,
+    pub message: String,
+    pub package_id: Option<PackageId>,
+    pub path: Option<PathBuf>,
+}
+
+impl Diagnostic {
+    pub fn new(kind: DiagnosticKind, message: String) -> Self {
+        Self {
+            kind,
+            message,
+            package_id: None,
+            path: None,
+        }
+    }
+
+    pub fn with_package(mut self, package_id: &PackageId) -> Self {
+        self.package_id = Some(package_id.clone());
+        self
+    }
+
+    pub fn with_path(mut self, path: &Path) -> Self {
+        self.path = Some(path.to_path_buf());
+        self
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
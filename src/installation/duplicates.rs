@@ -0,0 +1,160 @@
+//! Finding files that are the same in more than one package.
+
+use std::{
+    collections::{HashMap, HashSet},
+    path::PathBuf,
+};
+
+use super::InstallationContext;
+
+/// Files in different package directories with the same contents, which
+/// could be hard linked to one copy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateFile {
+    /// The size in bytes of each copy.
+    pub size: u64,
+
+    /// Every copy, sorted. Paths already hard linked to another copy aren't
+    /// listed.
+    pub paths: Vec<PathBuf>,
+}
+
+impl DuplicateFile {
+    /// The bytes freed by keeping only one copy.
+    pub fn savings(&self) -> u64 {
+        self.size * (self.paths.len() as u64 - 1)
+    }
+}
+
+/// The contents shared between package directories in a project's indexes.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DuplicateReport {
+    /// Each set of identical files, largest savings first.
+    pub duplicates: Vec<DuplicateFile>,
+}
+
+impl DuplicateReport {
+    /// The bytes freed by keeping only one copy of every duplicate.
+    pub fn savings(&self) -> u64 {
+        self.duplicates.iter().map(DuplicateFile::savings).sum()
+    }
+}
+
+impl InstallationContext {
+    /// Find files that are byte for byte the same in more than one package
+    /// directory, like files that didn't change between two versions of a
+    /// package. Files are only read when another file has the same size.
+    pub fn find_duplicate_files(&self) -> anyhow::Result<DuplicateReport> {
+        let mut by_size: HashMap<u64, Vec<(PathBuf, PathBuf)>> = HashMap::new();
+        let mut seen_files = HashSet::new();
+
+        for package in self.list_installed()? {
+            for entry in self.filesystem.walk_dir(&package.path)? {
+                if !entry.is_file || entry.len == 0 || !seen_files.insert(entry.identity) {
+                    continue;
+                }
+
+                by_size
+                    .entry(entry.len)
+                    .or_default()
+                    .push((package.path.clone(), entry.path));
+            }
+        }
+
+        let mut duplicates = Vec::new();
+
+        for (size, files) in by_size {
+            if files.len() < 2 {
+                continue;
+            }
+
+            let mut by_hash: HashMap<blake3::Hash, Vec<(PathBuf, PathBuf)>> = HashMap::new();
+            for (package_dir, path) in files {
+                let hash = blake3::hash(&self.filesystem.read(&path)?);
+                by_hash.entry(hash).or_default().push((package_dir, path));
+            }
+
+            for files in by_hash.values() {
+                let package_dirs: HashSet<_> = files.iter().map(|(dir, _)| dir).collect();
+                if package_dirs.len() < 2 {
+                    continue;
+                }
+
+                let mut paths: Vec<_> = files.iter().map(|(_, path)| path.clone()).collect();
+                paths.sort();
+                duplicates.push(DuplicateFile { size, paths });
+            }
+        }
+
+        duplicates.sort_by(|a, b| {
+            b.savings()
+                .cmp(&a.savings())
+                .then_with(|| a.paths.cmp(&b.paths))
+        });
+
+        Ok(DuplicateReport { duplicates })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{path::Path, sync::Arc};
+
+    use crate::test_package::PackageBuilder;
+
+    use super::super::{
+        test_support::{published_registry, TestProject},
+        MemoryFilesystem,
+    };
+
+    #[test]
+    fn duplicate_files_reported() {
+        let large = "-- shared\n".repeat(10_000);
+
+        let registry = published_registry();
+        for version in &["0.1.0", "0.2.0"] {
+            registry.publish(
+                PackageBuilder::new(&format!("biff/big@{}", version))
+                    .with_file("init.luau", &format!("return \"{}\"", version))
+                    .with_file("Data.luau", &large),
+            );
+        }
+
+        registry.publish(
+            PackageBuilder::new("biff/old-user@0.1.0")
+                .with_dep("Big", "biff/big@0.1.0")
+                .with_file("init.luau", ""),
+        );
+
+        let root = PackageBuilder::new("biff/root@0.1.0")
+            .with_dep("Big", "biff/big@0.2.0")
+            .with_dep("OldUser", "biff/old-user@0.1.0");
+        let project = TestProject::new(&registry, root);
+        project.install(project.context()).unwrap();
+
+        let report = project.context().find_duplicate_files().unwrap();
+        let index = project.dir.path().join("packages/_index");
+
+        assert_eq!(
+            report.duplicates,
+            vec![DuplicateFile {
+                size: large.len() as u64,
+                paths: vec![
+                    index.join("biff_big@0.1.0/Data.luau"),
+                    index.join("biff_big@0.2.0/Data.luau"),
+                ],
+            }]
+        );
+        assert_eq!(report.savings(), large.len() as u64);
+
+        let filesystem = Arc::new(MemoryFilesystem::new());
+        let context =
+            InstallationContext::new(Path::new("project")).with_filesystem(filesystem.clone());
+        project.install(context.clone()).unwrap();
+
+        let report = context.find_duplicate_files().unwrap();
+        assert_eq!(report.savings(), large.len() as u64);
+    }
+}
@@ -0,0 +1,108 @@
+//! A dedicated pool of threads for unpacking packages.
+
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use anyhow::{anyhow, Context};
+
+type Job = Box<dyn FnOnce() + Send>;
+
+/// A fixed set of threads that installs unpack packages on, apart from the
+/// threads that download them. Unpacking is bound by CPU and disk rather than
+/// the network, so on constrained machines it can be given fewer threads than
+/// downloads without holding downloads back. Installs given the same pool
+/// share its threads.
+#[derive(Clone)]
+pub struct ExtractionPool {
+    jobs: Arc<Mutex<mpsc::Sender<Job>>>,
+    threads: usize,
+}
+
+impl ExtractionPool {
+    /// Start a pool of `threads` threads. They stop once every clone of the
+    /// pool is dropped.
+    pub fn new(threads: usize) -> anyhow::Result<Self> {
+        let threads = threads.max(1);
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+
+        for index in 0..threads {
+            let receiver = receiver.clone();
+
+            thread::Builder::new()
+                .name(format!("wally-extract-{}", index))
+                .spawn(move || loop {
+                    // The lock is only held while waiting for a job, not
+                    // while running it.
+                    let job = receiver.lock().unwrap().recv();
+
+                    match job {
+                        Ok(job) => job(),
+                        Err(_) => break,
+                    }
+                })
+                .with_context(|| {
+                    format!(
+                        "Could not start {} threads to unpack packages with",
+                        threads
+                    )
+                })?;
+        }
+
+        Ok(Self {
+            jobs: Arc::new(Mutex::new(sender)),
+            threads,
+        })
+    }
+
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Run `job` on one of the pool's threads, waiting for it to finish.
+    pub(crate) fn run<T: Send + 'static>(
+        &self,
+        job: impl FnOnce() -> anyhow::Result<T> + Send + 'static,
+    ) -> anyhow::Result<T> {
+        let (result_sender, result) = mpsc::channel();
+
+        self.jobs
+            .lock()
+            .unwrap()
+            .send(Box::new(move || {
+                let _ = result_sender.send(job());
+            }))
+            .map_err(|_| anyhow!("The extraction pool has stopped"))?;
+
+        result
+            .recv()
+            .map_err(|_| anyhow!("Unpacking stopped without finishing"))?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashSet;
+
+    #[test]
+    fn jobs_run_on_pool_threads() {
+        let pool = ExtractionPool::new(2).unwrap();
+
+        let names: HashSet<_> = (0..8)
+            .map(|_| {
+                pool.run(|| Ok(thread::current().name().map(str::to_owned)))
+                    .unwrap()
+            })
+            .collect();
+
+        assert!(names.len() <= 2, "{:?}", names);
+        for name in names {
+            assert!(name.unwrap().starts_with("wally-extract-"));
+        }
+
+        let err = pool.run(|| -> anyhow::Result<()> { Err(anyhow!("bad archive")) });
+        assert_eq!(err.unwrap_err().to_string(), "bad archive");
+    }
+}
@@ -0,0 +1,1087 @@
+//! Abstracts the filesystem operations performed during installation, so that
+//! installs can be directed somewhere other than the disk.
+
+use std::io::{self, Write};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::SystemTime;
+
+#[cfg(any(test, feature = "test-util"))]
+pub use self::memory::MemoryFilesystem;
+
+/// The filesystem operations an `InstallationContext` performs. Paths are
+/// passed exactly as the context computes them.
+pub trait Filesystem: Send + Sync {
+    fn create_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()>;
+
+    /// Like `write`, but fails with `AlreadyExists` if the file already exists.
+    fn create_new(&self, path: &Path, contents: &[u8]) -> io::Result<()>;
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>>;
+
+    fn exists(&self, path: &Path) -> bool;
+
+    fn is_dir(&self, path: &Path) -> bool;
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    fn remove_file(&self, path: &Path) -> io::Result<()>;
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()>;
+
+    /// Remove the directory at `path`, failing if anything is in it.
+    fn remove_dir(&self, path: &Path) -> io::Result<()>;
+
+    /// Take an exclusive advisory lock on the file at `path`, creating it if
+    /// needed, or return `None` if another process holds it. The lock is
+    /// released when the returned `FileLock` is dropped, or when the process
+    /// exits. Filesystems no other process can see have nothing to lock
+    /// against, so by default the file is only created.
+    fn try_lock(&self, path: &Path) -> io::Result<Option<FileLock>> {
+        match self.create_new(path, b"") {
+            Ok(()) => {}
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(err) => return Err(err),
+        }
+
+        Ok(Some(FileLock { _file: None }))
+    }
+
+    /// Create a hard link at `to` to the file at `from`, which is always on
+    /// the real filesystem.
+    fn hard_link(&self, from: &Path, to: &Path) -> io::Result<()>;
+
+    /// Create a directory junction at `to` to the directory at `from`, which
+    /// is always on the real filesystem. Only Windows has junctions, so this
+    /// fails unless a filesystem supports them.
+    fn junction(&self, _from: &Path, to: &Path) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("can't create a junction at {}", to.display()),
+        ))
+    }
+
+    /// Whether both paths, which must exist, are on the same volume, so that
+    /// renaming from one to the other is atomic.
+    fn same_volume(&self, a: &Path, b: &Path) -> io::Result<bool>;
+
+    /// Set the modification time of the file at `path`. This fails unless a
+    /// filesystem supports it.
+    fn set_modified(&self, path: &Path, _time: SystemTime) -> io::Result<()> {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("can't set the modification time of {}", path.display()),
+        ))
+    }
+
+    /// The modification time of the file at `path`. This fails unless a
+    /// filesystem supports it.
+    fn modified(&self, path: &Path) -> io::Result<SystemTime> {
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            format!("can't get the modification time of {}", path.display()),
+        ))
+    }
+
+    /// The entries directly inside the directory at `path`, ordered by name.
+    /// Symlinks aren't followed.
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>>;
+
+    /// Every entry beneath the directory at `path`, with each directory's
+    /// entries following it and ordered by name, like `read_dir`.
+    fn walk_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        let mut entries = Vec::new();
+
+        for entry in self.read_dir(path)? {
+            let children = if entry.is_dir {
+                self.walk_dir(&entry.path)?
+            } else {
+                Vec::new()
+            };
+
+            entries.push(entry);
+            entries.extend(children);
+        }
+
+        Ok(entries)
+    }
+}
+
+/// An entry in a directory, as listed by `Filesystem::read_dir`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DirEntry {
+    pub path: PathBuf,
+    pub is_dir: bool,
+    pub is_file: bool,
+
+    /// The size of the file in bytes.
+    pub len: u64,
+
+    /// Tells files apart, so that one file hard linked into several places
+    /// is only counted once.
+    pub identity: FileIdentity,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum FileIdentity {
+    /// A device and inode number.
+    Inode(u64, u64),
+
+    /// Where there's nothing better, files are told apart by path.
+    Path(PathBuf),
+}
+
+/// An advisory lock on a file, taken by `Filesystem::try_lock` and held
+/// until this is dropped.
+pub struct FileLock {
+    _file: Option<std::fs::File>,
+}
+
+/// Performs operations against the real filesystem.
+pub struct RealFilesystem;
+
+impl Filesystem for RealFilesystem {
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs_err::create_dir_all(path)
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        fs_err::write(path, contents)
+    }
+
+    fn create_new(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        let mut file = fs_err::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)?;
+
+        file.write_all(contents)
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        fs_err::read(path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        path.exists()
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        path.is_dir()
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs_err::rename(from, to)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        fs_err::remove_file(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        fs_err::remove_dir_all(path)
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        fs_err::remove_dir(path)
+    }
+
+    /// Whoever held the lock before may have removed the file on its way
+    /// out, after it was opened here, so the lock only counts if `path` still
+    /// names the locked file.
+    fn try_lock(&self, path: &Path) -> io::Result<Option<FileLock>> {
+        use fs2::FileExt;
+
+        loop {
+            let file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .open(path)
+                .map_err(|err| {
+                    io::Error::new(
+                        err.kind(),
+                        format!("failed to open {}: {}", path.display(), err),
+                    )
+                })?;
+
+            if let Err(err) = file.try_lock_exclusive() {
+                if err.raw_os_error() == fs2::lock_contended_error().raw_os_error() {
+                    return Ok(None);
+                }
+
+                return Err(err);
+            }
+
+            if names_file(path, &file) {
+                return Ok(Some(FileLock { _file: Some(file) }));
+            }
+        }
+    }
+
+    fn hard_link(&self, from: &Path, to: &Path) -> io::Result<()> {
+        fs_err::hard_link(from, to)
+    }
+
+    /// The standard library can't create junctions, but a junction is just an
+    /// empty directory with a mount point reparse point, which setting
+    /// doesn't need any privileges.
+    #[cfg(windows)]
+    fn junction(&self, from: &Path, to: &Path) -> io::Result<()> {
+        use std::os::windows::fs::OpenOptionsExt;
+        use std::os::windows::io::AsRawHandle;
+        use winapi::um::ioapiset::DeviceIoControl;
+        use winapi::um::winbase::{FILE_FLAG_BACKUP_SEMANTICS, FILE_FLAG_OPEN_REPARSE_POINT};
+        use winapi::um::winioctl::FSCTL_SET_REPARSE_POINT;
+
+        let data = mount_point_reparse_data(&fs_err::canonicalize(from)?)?;
+        fs_err::create_dir(to)?;
+
+        let result = std::fs::OpenOptions::new()
+            .write(true)
+            .custom_flags(FILE_FLAG_OPEN_REPARSE_POINT | FILE_FLAG_BACKUP_SEMANTICS)
+            .open(to)
+            .and_then(|dir| {
+                let mut returned = 0;
+
+                // SAFETY: the handle stays open for as long as `dir` does, and
+                // `data` is a complete reparse data buffer of the given length.
+                let result = unsafe {
+                    DeviceIoControl(
+                        dir.as_raw_handle() as _,
+                        FSCTL_SET_REPARSE_POINT,
+                        data.as_ptr() as _,
+                        data.len() as u32,
+                        std::ptr::null_mut(),
+                        0,
+                        &mut returned,
+                        std::ptr::null_mut(),
+                    )
+                };
+
+                if result == 0 {
+                    Err(io::Error::last_os_error())
+                } else {
+                    Ok(())
+                }
+            });
+
+        if let Err(err) = result {
+            let _ = fs_err::remove_dir(to);
+
+            return Err(io::Error::new(
+                err.kind(),
+                format!("could not create a junction at {}: {}", to.display(), err),
+            ));
+        }
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    fn same_volume(&self, a: &Path, b: &Path) -> io::Result<bool> {
+        use std::os::unix::fs::MetadataExt;
+
+        Ok(fs_err::metadata(a)?.dev() == fs_err::metadata(b)?.dev())
+    }
+
+    #[cfg(not(unix))]
+    fn same_volume(&self, a: &Path, b: &Path) -> io::Result<bool> {
+        let a = fs_err::canonicalize(a)?;
+        let b = fs_err::canonicalize(b)?;
+
+        Ok(a.components().next() == b.components().next())
+    }
+
+    /// Access times are kept as they were.
+    #[cfg(unix)]
+    fn set_modified(&self, path: &Path, time: SystemTime) -> io::Result<()> {
+        use std::ffi::CString;
+        use std::os::unix::ffi::OsStrExt;
+
+        let accessed = fs_err::metadata(path)?.accessed().unwrap_or(time);
+        let times = [timeval(accessed)?, timeval(time)?];
+        let c_path = CString::new(path.as_os_str().as_bytes())?;
+
+        // SAFETY: `c_path` is a valid C string and `times` holds the two
+        // values `utimes` reads.
+        if unsafe { libc::utimes(c_path.as_ptr(), times.as_ptr()) } != 0 {
+            return Err(modified_error(path));
+        }
+
+        Ok(())
+    }
+
+    /// Only the file's attributes are opened for writing, which read-only
+    /// files allow.
+    #[cfg(windows)]
+    fn set_modified(&self, path: &Path, time: SystemTime) -> io::Result<()> {
+        use std::os::windows::fs::OpenOptionsExt;
+        use std::os::windows::io::AsRawHandle;
+        use winapi::shared::minwindef::FILETIME;
+        use winapi::um::fileapi::SetFileTime;
+        use winapi::um::winnt::FILE_WRITE_ATTRIBUTES;
+
+        // File times count 100 nanosecond intervals since 1601.
+        let since_epoch = since_unix_epoch(time)?;
+        let intervals = (since_epoch.as_secs() + 11_644_473_600) * 10_000_000
+            + u64::from(since_epoch.subsec_nanos() / 100);
+        let modified = FILETIME {
+            dwLowDateTime: intervals as u32,
+            dwHighDateTime: (intervals >> 32) as u32,
+        };
+
+        let file = std::fs::OpenOptions::new()
+            .access_mode(FILE_WRITE_ATTRIBUTES)
+            .open(path)
+            .map_err(|err| {
+                io::Error::new(
+                    err.kind(),
+                    format!("failed to open {}: {}", path.display(), err),
+                )
+            })?;
+
+        // SAFETY: the handle stays open for as long as `file` does, and the
+        // null creation and access times are left alone.
+        let result = unsafe {
+            SetFileTime(
+                file.as_raw_handle() as _,
+                std::ptr::null(),
+                std::ptr::null(),
+                &modified,
+            )
+        };
+
+        if result == 0 {
+            return Err(modified_error(path));
+        }
+
+        Ok(())
+    }
+
+    fn modified(&self, path: &Path) -> io::Result<SystemTime> {
+        fs_err::metadata(path)?.modified()
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        let mut entries = Vec::new();
+
+        for entry in fs_err::read_dir(path)? {
+            let path = entry?.path();
+            let metadata = fs_err::symlink_metadata(&path)?;
+
+            entries.push(DirEntry {
+                is_dir: metadata.is_dir(),
+                is_file: metadata.is_file(),
+                len: metadata.len(),
+                identity: file_identity(&path, &metadata),
+                path,
+            });
+        }
+
+        entries.sort_by(|a, b| a.path.cmp(&b.path));
+        Ok(entries)
+    }
+}
+
+#[cfg(any(unix, windows))]
+fn since_unix_epoch(time: SystemTime) -> io::Result<std::time::Duration> {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))
+}
+
+/// Builds the reparse data buffer that makes a directory a junction to the
+/// absolute path `target`.
+#[cfg(windows)]
+fn mount_point_reparse_data(target: &Path) -> io::Result<Vec<u8>> {
+    use std::os::windows::ffi::OsStrExt;
+    use winapi::um::winnt::IO_REPARSE_TAG_MOUNT_POINT;
+
+    // Canonical paths are verbatim, but a junction's target is written with
+    // the NT object prefix instead, and shown without any prefix.
+    let wide: Vec<u16> = target.as_os_str().encode_wide().collect();
+    let verbatim: Vec<u16> = r"\\?\".encode_utf16().collect();
+    let print_name = wide.strip_prefix(&verbatim[..]).unwrap_or(&wide);
+
+    if print_name.get(1) != Some(&u16::from(b':')) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!(
+                "junctions can only point to local drives, not {}",
+                target.display()
+            ),
+        ));
+    }
+
+    let substitute_name: Vec<u16> = r"\??\"
+        .encode_utf16()
+        .chain(print_name.iter().copied())
+        .collect();
+
+    // Both names are followed by a null terminator their lengths leave out.
+    let substitute_len = substitute_name.len() * 2;
+    let print_len = print_name.len() * 2;
+    let data_len = 8 + substitute_len + 2 + print_len + 2;
+
+    if data_len > usize::from(u16::MAX) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("{} is too long to be a junction target", target.display()),
+        ));
+    }
+
+    let mut data = Vec::with_capacity(8 + data_len);
+    data.extend_from_slice(&IO_REPARSE_TAG_MOUNT_POINT.to_le_bytes());
+
+    // The data length and reserved field, then the offset and length of each
+    // name within the path buffer.
+    for field in [
+        data_len,
+        0,
+        0,
+        substitute_len,
+        substitute_len + 2,
+        print_len,
+    ] {
+        data.extend_from_slice(&(field as u16).to_le_bytes());
+    }
+
+    let names = substitute_name
+        .iter()
+        .chain(&[0])
+        .chain(print_name)
+        .chain(&[0]);
+    for unit in names {
+        data.extend_from_slice(&unit.to_le_bytes());
+    }
+
+    Ok(data)
+}
+
+#[cfg(unix)]
+fn timeval(time: SystemTime) -> io::Result<libc::timeval> {
+    let since_epoch = since_unix_epoch(time)?;
+
+    Ok(libc::timeval {
+        tv_sec: since_epoch.as_secs() as libc::time_t,
+        tv_usec: since_epoch.subsec_micros() as libc::suseconds_t,
+    })
+}
+
+#[cfg(any(unix, windows))]
+fn modified_error(path: &Path) -> io::Error {
+    let err = io::Error::last_os_error();
+
+    io::Error::new(
+        err.kind(),
+        format!(
+            "failed to set the modification time of {}: {}",
+            path.display(),
+            err
+        ),
+    )
+}
+
+/// Whether `path` still names the open `file`.
+#[cfg(unix)]
+fn names_file(path: &Path, file: &std::fs::File) -> bool {
+    use std::os::unix::fs::MetadataExt;
+
+    match (std::fs::metadata(path), file.metadata()) {
+        (Ok(path_metadata), Ok(file_metadata)) => {
+            (path_metadata.dev(), path_metadata.ino()) == (file_metadata.dev(), file_metadata.ino())
+        }
+        _ => false,
+    }
+}
+
+/// Open files can't be removed here, so `path` names `file` as long as it
+/// exists.
+#[cfg(not(unix))]
+fn names_file(path: &Path, _file: &std::fs::File) -> bool {
+    path.exists()
+}
+
+#[cfg(unix)]
+fn file_identity(_path: &Path, metadata: &std::fs::Metadata) -> FileIdentity {
+    use std::os::unix::fs::MetadataExt;
+
+    FileIdentity::Inode(metadata.dev(), metadata.ino())
+}
+
+#[cfg(not(unix))]
+fn file_identity(path: &Path, _metadata: &std::fs::Metadata) -> FileIdentity {
+    FileIdentity::Path(path.to_path_buf())
+}
+
+/// A filesystem that passes every operation through to `inner`, except
+/// those it overrides. Wrappers that only change or observe a few
+/// operations implement this instead of `Filesystem`, which every
+/// `DelegatingFilesystem` implements.
+pub trait DelegatingFilesystem: Send + Sync {
+    fn inner(&self) -> &dyn Filesystem;
+
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.inner().create_dir_all(path)
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        self.inner().write(path, contents)
+    }
+
+    fn create_new(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        self.inner().create_new(path, contents)
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.inner().read(path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.inner().exists(path)
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.inner().is_dir(path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.inner().rename(from, to)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.inner().remove_file(path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.inner().remove_dir_all(path)
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        self.inner().remove_dir(path)
+    }
+
+    fn try_lock(&self, path: &Path) -> io::Result<Option<FileLock>> {
+        self.inner().try_lock(path)
+    }
+
+    fn hard_link(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.inner().hard_link(from, to)
+    }
+
+    fn junction(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.inner().junction(from, to)
+    }
+
+    fn same_volume(&self, a: &Path, b: &Path) -> io::Result<bool> {
+        self.inner().same_volume(a, b)
+    }
+
+    fn set_modified(&self, path: &Path, time: SystemTime) -> io::Result<()> {
+        self.inner().set_modified(path, time)
+    }
+
+    fn modified(&self, path: &Path) -> io::Result<SystemTime> {
+        self.inner().modified(path)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        self.inner().read_dir(path)
+    }
+
+    fn walk_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        self.inner().walk_dir(path)
+    }
+}
+
+impl<T: DelegatingFilesystem> Filesystem for T {
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        DelegatingFilesystem::create_dir_all(self, path)
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        DelegatingFilesystem::write(self, path, contents)
+    }
+
+    fn create_new(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        DelegatingFilesystem::create_new(self, path, contents)
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        DelegatingFilesystem::read(self, path)
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        DelegatingFilesystem::exists(self, path)
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        DelegatingFilesystem::is_dir(self, path)
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        DelegatingFilesystem::rename(self, from, to)
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        DelegatingFilesystem::remove_file(self, path)
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        DelegatingFilesystem::remove_dir_all(self, path)
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        DelegatingFilesystem::remove_dir(self, path)
+    }
+
+    fn try_lock(&self, path: &Path) -> io::Result<Option<FileLock>> {
+        DelegatingFilesystem::try_lock(self, path)
+    }
+
+    fn hard_link(&self, from: &Path, to: &Path) -> io::Result<()> {
+        DelegatingFilesystem::hard_link(self, from, to)
+    }
+
+    fn junction(&self, from: &Path, to: &Path) -> io::Result<()> {
+        DelegatingFilesystem::junction(self, from, to)
+    }
+
+    fn same_volume(&self, a: &Path, b: &Path) -> io::Result<bool> {
+        DelegatingFilesystem::same_volume(self, a, b)
+    }
+
+    fn set_modified(&self, path: &Path, time: SystemTime) -> io::Result<()> {
+        DelegatingFilesystem::set_modified(self, path, time)
+    }
+
+    fn modified(&self, path: &Path) -> io::Result<SystemTime> {
+        DelegatingFilesystem::modified(self, path)
+    }
+
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        DelegatingFilesystem::read_dir(self, path)
+    }
+
+    fn walk_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        DelegatingFilesystem::walk_dir(self, path)
+    }
+}
+
+/// Performs the operations of another filesystem beneath `prefix`, so that
+/// an install only ever touches paths under it. Paths are moved beneath the
+/// prefix as if it were the root directory, and can't climb out of it.
+pub struct PrefixedFilesystem {
+    prefix: PathBuf,
+    inner: Arc<dyn Filesystem>,
+}
+
+impl PrefixedFilesystem {
+    pub fn new<P: Into<PathBuf>>(prefix: P, inner: Arc<dyn Filesystem>) -> Self {
+        Self {
+            prefix: prefix.into(),
+            inner,
+        }
+    }
+
+    /// The path under the prefix that stands in for `path`.
+    pub fn prefixed(&self, path: &Path) -> PathBuf {
+        let mut relative_path = PathBuf::new();
+
+        for component in path.components() {
+            match component {
+                Component::Normal(part) => relative_path.push(part),
+                Component::ParentDir => {
+                    relative_path.pop();
+                }
+                Component::Prefix(_) | Component::RootDir | Component::CurDir => {}
+            }
+        }
+
+        self.prefix.join(relative_path)
+    }
+}
+
+impl Filesystem for PrefixedFilesystem {
+    fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.inner.create_dir_all(&self.prefixed(path))
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        self.inner.write(&self.prefixed(path), contents)
+    }
+
+    fn create_new(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        self.inner.create_new(&self.prefixed(path), contents)
+    }
+
+    fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+        self.inner.read(&self.prefixed(path))
+    }
+
+    fn exists(&self, path: &Path) -> bool {
+        self.inner.exists(&self.prefixed(path))
+    }
+
+    fn is_dir(&self, path: &Path) -> bool {
+        self.inner.is_dir(&self.prefixed(path))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.inner.rename(&self.prefixed(from), &self.prefixed(to))
+    }
+
+    fn remove_file(&self, path: &Path) -> io::Result<()> {
+        self.inner.remove_file(&self.prefixed(path))
+    }
+
+    fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+        self.inner.remove_dir_all(&self.prefixed(path))
+    }
+
+    fn remove_dir(&self, path: &Path) -> io::Result<()> {
+        self.inner.remove_dir(&self.prefixed(path))
+    }
+
+    fn try_lock(&self, path: &Path) -> io::Result<Option<FileLock>> {
+        self.inner.try_lock(&self.prefixed(path))
+    }
+
+    fn hard_link(&self, from: &Path, to: &Path) -> io::Result<()> {
+        // Only the link is written, and `from` is always a real path.
+        self.inner.hard_link(from, &self.prefixed(to))
+    }
+
+    fn junction(&self, from: &Path, to: &Path) -> io::Result<()> {
+        self.inner.junction(from, &self.prefixed(to))
+    }
+
+    fn same_volume(&self, a: &Path, b: &Path) -> io::Result<bool> {
+        self.inner.same_volume(&self.prefixed(a), &self.prefixed(b))
+    }
+
+    fn set_modified(&self, path: &Path, time: SystemTime) -> io::Result<()> {
+        self.inner.set_modified(&self.prefixed(path), time)
+    }
+
+    fn modified(&self, path: &Path) -> io::Result<SystemTime> {
+        self.inner.modified(&self.prefixed(path))
+    }
+
+    /// Entries are listed under `path`, rather than under the prefix.
+    fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+        let prefixed = self.prefixed(path);
+        let mut entries = self.inner.read_dir(&prefixed)?;
+
+        for entry in &mut entries {
+            let relative_path = entry.path.strip_prefix(&prefixed).unwrap().to_path_buf();
+            entry.path = path.join(relative_path);
+        }
+
+        Ok(entries)
+    }
+}
+
+/// Performs the operations of another filesystem, then sets the modification
+/// time of every file it writes to `mtime`, so that the files look the same
+/// to tools keyed on modification times no matter when they were written.
+pub struct FixedTimeFilesystem {
+    mtime: SystemTime,
+    inner: Arc<dyn Filesystem>,
+}
+
+impl FixedTimeFilesystem {
+    pub fn new(mtime: SystemTime, inner: Arc<dyn Filesystem>) -> Self {
+        Self { mtime, inner }
+    }
+}
+
+impl DelegatingFilesystem for FixedTimeFilesystem {
+    fn inner(&self) -> &dyn Filesystem {
+        &*self.inner
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        self.inner.write(path, contents)?;
+        self.inner.set_modified(path, self.mtime)
+    }
+
+    fn create_new(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        self.inner.create_new(path, contents)?;
+        self.inner.set_modified(path, self.mtime)
+    }
+
+    // Hard links share their file's modification time, which is left alone,
+    // since the file belongs to the cache.
+}
+
+#[cfg(any(test, feature = "test-util"))]
+mod memory {
+    use std::collections::BTreeMap;
+    use std::io;
+    use std::path::{Path, PathBuf};
+    use std::sync::Mutex;
+    use std::time::SystemTime;
+
+    use super::{DirEntry, FileIdentity, Filesystem};
+
+    #[derive(Debug, Clone, PartialEq, Eq)]
+    enum Entry {
+        Dir,
+        File(Vec<u8>),
+    }
+
+    /// A filesystem that lives entirely in memory, useful for asserting the
+    /// exact tree an install produces.
+    #[derive(Default)]
+    pub struct MemoryFilesystem {
+        entries: Mutex<BTreeMap<PathBuf, Entry>>,
+    }
+
+    impl MemoryFilesystem {
+        pub fn new() -> Self {
+            Self::default()
+        }
+
+        /// Returns the contents of every file, keyed by path.
+        pub fn files(&self) -> BTreeMap<PathBuf, Vec<u8>> {
+            let entries = self.entries.lock().unwrap();
+
+            entries
+                .iter()
+                .filter_map(|(path, entry)| match entry {
+                    Entry::File(contents) => Some((path.clone(), contents.clone())),
+                    Entry::Dir => None,
+                })
+                .collect()
+        }
+
+        /// Returns the path of every directory.
+        pub fn dirs(&self) -> Vec<PathBuf> {
+            let entries = self.entries.lock().unwrap();
+
+            entries
+                .iter()
+                .filter(|(_, entry)| **entry == Entry::Dir)
+                .map(|(path, _)| path.clone())
+                .collect()
+        }
+    }
+
+    fn not_found(path: &Path) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            format!("{} does not exist", path.display()),
+        )
+    }
+
+    fn parent_exists(entries: &BTreeMap<PathBuf, Entry>, path: &Path) -> bool {
+        match path.parent() {
+            Some(parent) if parent != Path::new("") => entries.get(parent) == Some(&Entry::Dir),
+            _ => true,
+        }
+    }
+
+    impl Filesystem for MemoryFilesystem {
+        fn create_dir_all(&self, path: &Path) -> io::Result<()> {
+            let mut entries = self.entries.lock().unwrap();
+
+            for ancestor in path.ancestors() {
+                if ancestor == Path::new("") {
+                    continue;
+                }
+
+                match entries.get(ancestor) {
+                    Some(Entry::File(_)) => {
+                        return Err(io::Error::new(
+                            io::ErrorKind::AlreadyExists,
+                            format!("{} is a file", ancestor.display()),
+                        ))
+                    }
+                    Some(Entry::Dir) => {}
+                    None => {
+                        entries.insert(ancestor.to_path_buf(), Entry::Dir);
+                    }
+                }
+            }
+
+            Ok(())
+        }
+
+        fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+            let mut entries = self.entries.lock().unwrap();
+
+            if !parent_exists(&entries, path) {
+                return Err(not_found(path));
+            }
+
+            if entries.get(path) == Some(&Entry::Dir) {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} is a directory", path.display()),
+                ));
+            }
+
+            entries.insert(path.to_path_buf(), Entry::File(contents.to_vec()));
+            Ok(())
+        }
+
+        fn create_new(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+            if self.entries.lock().unwrap().contains_key(path) {
+                return Err(io::Error::new(
+                    io::ErrorKind::AlreadyExists,
+                    format!("{} already exists", path.display()),
+                ));
+            }
+
+            self.write(path, contents)
+        }
+
+        fn read(&self, path: &Path) -> io::Result<Vec<u8>> {
+            match self.entries.lock().unwrap().get(path) {
+                Some(Entry::File(contents)) => Ok(contents.clone()),
+                _ => Err(not_found(path)),
+            }
+        }
+
+        fn exists(&self, path: &Path) -> bool {
+            self.entries.lock().unwrap().contains_key(path)
+        }
+
+        fn is_dir(&self, path: &Path) -> bool {
+            self.entries.lock().unwrap().get(path) == Some(&Entry::Dir)
+        }
+
+        fn rename(&self, from: &Path, to: &Path) -> io::Result<()> {
+            let mut entries = self.entries.lock().unwrap();
+
+            if !entries.contains_key(from) {
+                return Err(not_found(from));
+            }
+
+            if !parent_exists(&entries, to) {
+                return Err(not_found(to));
+            }
+
+            let moved: Vec<_> = entries
+                .keys()
+                .filter(|path| path.starts_with(from))
+                .cloned()
+                .collect();
+
+            for path in moved {
+                let entry = entries.remove(&path).unwrap();
+                let relative_path = path.strip_prefix(from).unwrap();
+
+                if relative_path == Path::new("") {
+                    entries.insert(to.to_path_buf(), entry);
+                } else {
+                    entries.insert(to.join(relative_path), entry);
+                }
+            }
+
+            Ok(())
+        }
+
+        fn remove_file(&self, path: &Path) -> io::Result<()> {
+            let mut entries = self.entries.lock().unwrap();
+
+            match entries.get(path) {
+                Some(Entry::File(_)) => {
+                    entries.remove(path);
+                    Ok(())
+                }
+                _ => Err(not_found(path)),
+            }
+        }
+
+        fn remove_dir_all(&self, path: &Path) -> io::Result<()> {
+            let mut entries = self.entries.lock().unwrap();
+
+            if entries.get(path) != Some(&Entry::Dir) {
+                return Err(not_found(path));
+            }
+
+            entries.retain(|entry_path, _| !entry_path.starts_with(path));
+            Ok(())
+        }
+
+        fn remove_dir(&self, path: &Path) -> io::Result<()> {
+            let mut entries = self.entries.lock().unwrap();
+
+            if entries.get(path) != Some(&Entry::Dir) {
+                return Err(not_found(path));
+            }
+
+            if entries
+                .keys()
+                .any(|entry_path| entry_path.parent() == Some(path))
+            {
+                return Err(io::Error::new(
+                    io::ErrorKind::Other,
+                    format!("{} is not empty", path.display()),
+                ));
+            }
+
+            entries.remove(path);
+            Ok(())
+        }
+
+        fn hard_link(&self, _from: &Path, to: &Path) -> io::Result<()> {
+            Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!("cannot hard link {} in memory", to.display()),
+            ))
+        }
+
+        fn same_volume(&self, a: &Path, b: &Path) -> io::Result<bool> {
+            if !self.exists(a) {
+                return Err(not_found(a));
+            }
+
+            if !self.exists(b) {
+                return Err(not_found(b));
+            }
+
+            Ok(true)
+        }
+
+        /// Files in memory have no times, so this only checks that the file
+        /// exists.
+        fn set_modified(&self, path: &Path, _time: SystemTime) -> io::Result<()> {
+            match self.entries.lock().unwrap().get(path) {
+                Some(Entry::File(_)) => Ok(()),
+                _ => Err(not_found(path)),
+            }
+        }
+
+        fn read_dir(&self, path: &Path) -> io::Result<Vec<DirEntry>> {
+            let entries = self.entries.lock().unwrap();
+
+            if entries.get(path) != Some(&Entry::Dir) {
+                return Err(not_found(path));
+            }
+
+            // Entries are kept ordered by path, so children come out ordered
+            // by name.
+            let children = entries
+                .iter()
+                .filter(|(entry_path, _)| entry_path.parent() == Some(path))
+                .map(|(entry_path, entry)| DirEntry {
+                    path: entry_path.clone(),
+                    is_dir: *entry == Entry::Dir,
+                    is_file: *entry != Entry::Dir,
+                    len: match entry {
+                        Entry::File(contents) => contents.len() as u64,
+                        Entry::Dir => 0,
+                    },
+                    identity: FileIdentity::Path(entry_path.clone()),
+                })
+                .collect();
+
+            Ok(children)
+        }
+    }
+}
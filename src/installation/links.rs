@@ -0,0 +1,1159 @@
+//! Writing the link files that let packages require their dependencies.
+
+use std::{
+    fmt::Display,
+    io,
+    path::{Path, PathBuf},
+};
+
+use anyhow::bail;
+use indoc::formatdoc;
+
+use crate::{
+    manifest::{Manifest, Realm, MANIFEST_FILE_NAME},
+    package_contents::detect_installed_entry_point,
+    package_id::PackageId,
+    resolution::Resolve,
+};
+
+use super::{
+    normalize_path, parse_package_dir_name, InstallationContext, LinkConflictPolicy, LinkExtension,
+    LinkOrigin, RequirePathStyle, RootLinkLayout,
+};
+
+impl InstallationContext {
+    /// Find the entry suffix and declared submodules of a dependency that's
+    /// being linked to by reading its directory in the index. Packages are
+    /// all unpacked before any links are written, so linking never downloads
+    /// anything.
+    fn inspect_dependency(
+        &self,
+        package_id: &PackageId,
+        realm: Realm,
+    ) -> anyhow::Result<(Option<&'static str>, Vec<String>)> {
+        let dir = self
+            .index_dir(realm)
+            .join(self.package_dir_name(package_id));
+
+        if !self.filesystem.exists(&dir) {
+            bail!(
+                "Cannot link to {}, because it is not in the index at {}",
+                package_id,
+                dir.display()
+            );
+        }
+
+        let detected = detect_installed_entry_point(&dir, self.filesystem.as_ref());
+        let suffix = self.entry_suffix(package_id, detected.map(|entry| entry.suffix))?;
+
+        // What's in the index should be the archive we unpacked, unless
+        // something else changed it in the meantime.
+        let unpacked_entries = self.unpacked_entries.lock().unwrap();
+        if let Some(unpacked) = unpacked_entries.get(&package_id.to_string()) {
+            if *unpacked != suffix {
+                bail!(
+                    "{} was unpacked with entry suffix {:?}, but its link would use {:?}. \
+                     Its files in {} changed after they were unpacked.",
+                    package_id,
+                    unpacked,
+                    suffix,
+                    dir.display()
+                );
+            }
+        }
+
+        let modules = match self.filesystem.read(&dir.join(MANIFEST_FILE_NAME)) {
+            Ok(data) if self.options.linking.submodule_links => {
+                Manifest::from_slice(&data)?.package.modules
+            }
+            Ok(_) => Vec::new(),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(err) => return Err(err.into()),
+        };
+
+        Ok((suffix, modules))
+    }
+
+    /// Contents of a link file that requires the given path, after passing it
+    /// through the require transform.
+    pub(super) fn link_contents(&self, require_path: String) -> String {
+        formatdoc!(
+            r#"
+            return {require_call}
+            "#,
+            require_call = self.require_call(require_path)
+        )
+    }
+
+    /// A call requiring the given path, after passing it through the require
+    /// transform.
+    fn require_call(&self, require_path: String) -> String {
+        let require_path = match &self.options.linking.require_transform {
+            Some(transform) => transform(&require_path),
+            None => require_path,
+        };
+
+        match self.options.linking.require_path_style {
+            RequirePathStyle::Relative => {
+                format!(
+                    "{}(\"{}\")",
+                    self.options.linking.require_function, require_path
+                )
+            }
+            RequirePathStyle::AbsoluteRojo { .. } => {
+                format!(
+                    "{}({})",
+                    self.options.linking.require_function, require_path
+                )
+            }
+        }
+    }
+
+    /// The require path of a dependency when it's linked from a directory
+    /// one level below where `require_path` was generated for.
+    fn nested_require_path(&self, require_path: String) -> String {
+        match self.options.linking.require_path_style {
+            RequirePathStyle::Relative => format!("../{}", require_path),
+            RequirePathStyle::AbsoluteRojo { .. } => require_path,
+        }
+    }
+
+    /// The instance a package in `dep_realm`'s index is synced to by Rojo,
+    /// or `None` for relative requires.
+    fn absolute_require_path(
+        &self,
+        dep_realm: Realm,
+        id: &PackageId,
+        suffix: Option<&str>,
+    ) -> Option<String> {
+        let (shared, server, dev) = match &self.options.linking.require_path_style {
+            RequirePathStyle::Relative => return None,
+            RequirePathStyle::AbsoluteRojo {
+                shared,
+                server,
+                dev,
+            } => (shared, server, dev),
+        };
+
+        let realm_path = match dep_realm {
+            Realm::Shared => shared,
+            Realm::Server => server,
+            Realm::Dev => dev,
+        };
+
+        let index_name = self.index_name(if self.options.flat {
+            Realm::Shared
+        } else {
+            dep_realm
+        });
+        let index = rojo_instance_path(realm_path, &index_name);
+        let package = rojo_instance_path(&index, &self.package_dir_name(id));
+
+        Some(rojo_instance_path(&package, suffix.unwrap_or("")))
+    }
+
+    /// The contents of the link file that lets a package at `from` require
+    /// `target` from the same index, where `suffix` leads from the root of
+    /// `target` to its entry point. This is exactly what `install` writes.
+    pub fn generate_link(
+        &self,
+        from: LinkOrigin,
+        target: &PackageId,
+        suffix: Option<&str>,
+    ) -> String {
+        let require_path = match from {
+            LinkOrigin::Root => self.link_from_root(Realm::Shared, Realm::Shared, target, suffix),
+            LinkOrigin::Sibling => {
+                self.link_from_package(Realm::Shared, Realm::Shared, target, suffix)
+            }
+        };
+
+        self.link_contents(require_path)
+    }
+
+    /// Require path of a link from the `packages` directory of a package in
+    /// `package_realm`'s index to a package in `dep_realm`'s index. Unlike
+    /// links from the root, these point at a directory with an `init.lua`
+    /// rather than naming the file.
+    fn link_from_package(
+        &self,
+        package_realm: Realm,
+        dep_realm: Realm,
+        id: &PackageId,
+        suffix: Option<&str>,
+    ) -> String {
+        if let Some(path) = self.absolute_require_path(dep_realm, id, suffix) {
+            return path;
+        }
+
+        // Links live in `<index>/<package>/<package links dir>`.
+        let depth = 1 + Path::new(&self.options.linking.package_links_dir)
+            .components()
+            .count();
+        let index = relative_path(self.index_dir(package_realm), self.index_dir(dep_realm));
+
+        format!(
+            "{up}{index}{full_name}{suffix}",
+            up = "../".repeat(depth),
+            index = index,
+            full_name = self.package_dir_name(id),
+            suffix = suffix.unwrap_or("").trim_end_matches("/init.lua")
+        )
+    }
+
+    /// Require path of a link from the root package's directory for
+    /// `root_realm` to a package in `dep_realm`'s index.
+    fn link_from_root(
+        &self,
+        root_realm: Realm,
+        dep_realm: Realm,
+        id: &PackageId,
+        suffix: Option<&str>,
+    ) -> String {
+        if let Some(path) = self.absolute_require_path(dep_realm, id, suffix) {
+            return path;
+        }
+
+        let index = relative_path(self.realm_dir(root_realm), self.index_dir(dep_realm));
+
+        format!(
+            "{index}{full_name}{suffix}",
+            index = index,
+            full_name = self.package_dir_name(id),
+            suffix = suffix.unwrap_or("")
+        )
+    }
+
+    /// Write the link to a dependency named `dep_name` into `base_path`.
+    /// `require_path` gives the path from `base_path` to the dependency with
+    /// the given suffix appended.
+    ///
+    /// Dependencies without submodules get a single `<dep_name>.lua` file.
+    /// The rest get a `<dep_name>` directory holding an `init.lua` link to
+    /// the package and a link for each submodule, all one level deeper. Link
+    /// files use the configured extension rather than always `.lua`.
+    fn write_dependency_link(
+        &self,
+        base_path: &Path,
+        dep_name: &str,
+        require_path: impl Fn(Option<&str>) -> String,
+        suffix: Option<&str>,
+        modules: &[String],
+    ) -> anyhow::Result<()> {
+        if modules.is_empty() {
+            let contents = self.link_contents(require_path(suffix));
+            self.write_link_file(base_path, dep_name, &contents)?;
+
+            return Ok(());
+        }
+
+        let link_dir = base_path.join(dep_name);
+        self.create_dir_all(&link_dir)?;
+
+        let contents = self.link_contents(self.nested_require_path(require_path(suffix)));
+        self.write_link_file(&link_dir, "init", &contents)?;
+
+        let entry_dir = suffix.unwrap_or("").trim_end_matches("/init.lua");
+
+        for module in modules {
+            if module.is_empty() || module.starts_with('.') || module.contains(&['/', '\\'][..]) {
+                bail!("Submodule name {:?} of {} is not valid", module, dep_name);
+            }
+
+            let module_suffix = format!("{}/{}", entry_dir, module);
+            let contents =
+                self.link_contents(self.nested_require_path(require_path(Some(&module_suffix))));
+            self.write_link_file(&link_dir, module, &contents)?;
+        }
+
+        Ok(())
+    }
+
+    /// Write a link file named `name` with the configured extension into
+    /// `dir`, first removing any link of the same name with the other
+    /// extension.
+    fn write_link_file(&self, dir: &Path, name: &str, contents: &str) -> anyhow::Result<()> {
+        let stale_path = dir.join(format!(
+            "{}.{}",
+            name,
+            self.options.linking.link_extension.other().as_str()
+        ));
+        let path = dir.join(format!(
+            "{}.{}",
+            name,
+            self.options.linking.link_extension.as_str()
+        ));
+
+        for existing in [&stale_path, &path].iter() {
+            if !self.may_replace(existing)? {
+                log::info!(
+                    "Not writing link {}, because {} isn't a link",
+                    path.display(),
+                    existing.display()
+                );
+                return Ok(());
+            }
+        }
+
+        match self.filesystem.remove_file(&stale_path) {
+            Ok(()) => log::trace!("Removed {}", stale_path.display()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {}
+            Err(err) => return Err(err.into()),
+        }
+
+        log::trace!("Writing {}", path.display());
+        self.filesystem.write(&path, contents.as_bytes())?;
+
+        Ok(())
+    }
+
+    /// Whether a link may be written over the file at `path`, according to
+    /// the link conflict policy. Links can always be replaced.
+    fn may_replace(&self, path: &Path) -> anyhow::Result<bool> {
+        let contents = match self.filesystem.read(path) {
+            Ok(contents) => contents,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(true),
+            Err(err) => return Err(err.into()),
+        };
+
+        if is_link(&contents) {
+            return Ok(true);
+        }
+
+        match self.options.linking.link_conflict_policy {
+            LinkConflictPolicy::Overwrite => Ok(true),
+            LinkConflictPolicy::Skip => Ok(false),
+            LinkConflictPolicy::Error => bail!(
+                "{} already exists and isn't a link, so no link was written over it",
+                path.display()
+            ),
+        }
+    }
+
+    pub(super) fn write_root_package_links<'a, K: Display>(
+        &self,
+        root_realm: Realm,
+        dependencies: impl IntoIterator<Item = (K, &'a PackageId)>,
+        resolved: &Resolve,
+    ) -> anyhow::Result<()> {
+        log::debug!("Writing root package links");
+
+        let base_path = self.realm_dir(root_realm);
+
+        log::trace!("Creating directory {}", base_path.display());
+        self.create_dir_all(base_path)?;
+
+        let index_name = self.root_link_name(root_realm, "index");
+        let mut index_entries = Vec::new();
+
+        for (dep_name, dep_package_id) in dependencies {
+            let link_name = self.root_link_name(root_realm, &dep_name.to_string());
+            if self.options.linking.root_link_layout == RootLinkLayout::Both
+                && link_name == index_name
+            {
+                bail!(
+                    "The link to {} would be written over the {} module of every dependency. \
+                    Rename its alias, or don't write both kinds of root links.",
+                    dep_package_id,
+                    index_name
+                );
+            }
+
+            let dep_realm = resolved.metadata[dep_package_id].origin_realm;
+            let (suffix, modules) = self.inspect_dependency(dep_package_id, dep_realm)?;
+            let require_path = |suffix: Option<&str>| {
+                self.link_from_root(root_realm, dep_realm, dep_package_id, suffix)
+            };
+
+            if self.options.linking.root_link_layout != RootLinkLayout::PerDependency {
+                let require_call = self.require_call(require_path(suffix));
+                index_entries.push(format!(
+                    "\t{} = {},\n",
+                    lua_table_key(&dep_name.to_string()),
+                    require_call
+                ));
+            }
+
+            if self.options.linking.root_link_layout != RootLinkLayout::Aggregated {
+                self.write_dependency_link(base_path, &link_name, require_path, suffix, &modules)?;
+            }
+        }
+
+        if self.options.linking.root_link_layout != RootLinkLayout::PerDependency {
+            let contents = format!("return {{\n{}}}\n", index_entries.concat());
+            self.write_link_file(base_path, &index_name, &contents)?;
+        }
+
+        Ok(())
+    }
+
+    pub(super) fn write_package_links<'a, K: std::fmt::Display>(
+        &self,
+        package_id: &PackageId,
+        package_realm: Realm,
+        dependencies: impl IntoIterator<Item = (K, &'a PackageId)>,
+        resolved: &Resolve,
+    ) -> anyhow::Result<()> {
+        log::debug!("Writing package links for {}", package_id);
+
+        let base_path = self
+            .index_dir(package_realm)
+            .join(self.package_dir_name(package_id));
+
+        log::trace!("Creating directory {}", base_path.display());
+        self.create_dir_all(&base_path)?;
+
+        if self
+            .shipped_links_dirs
+            .lock()
+            .unwrap()
+            .contains(&package_id.to_string())
+        {
+            bail!(
+                "{} ships its own {} directory, so links to its dependencies would be mixed \
+                into its files. Install with a different package links directory.",
+                package_id,
+                self.options.linking.package_links_dir
+            );
+        }
+
+        for (dep_name, dep_package_id) in dependencies {
+            let packages_path = base_path.join(&self.options.linking.package_links_dir);
+            self.create_dir_all(&packages_path)?;
+
+            // check whether the init.luau is located in the root or in a folder called /src
+            let dep_realm = resolved.metadata[dep_package_id].origin_realm;
+            let (suffix, modules) = self.inspect_dependency(dep_package_id, dep_realm)?;
+            let require_path = |suffix: Option<&str>| {
+                self.link_from_package(package_realm, dep_realm, dep_package_id, suffix)
+            };
+
+            self.write_dependency_link(
+                &packages_path,
+                &dep_name.to_string(),
+                require_path,
+                suffix,
+                &modules,
+            )?;
+        }
+
+        Ok(())
+    }
+
+    /// The link files in `dir`, including those one directory deeper that
+    /// link to submodules. Index directories aren't looked in.
+    pub(super) fn link_files(&self, dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+        let mut links = Vec::new();
+
+        if !self.filesystem.is_dir(dir) {
+            return Ok(links);
+        }
+
+        let index_names: Vec<_> = [Realm::Shared, Realm::Server, Realm::Dev]
+            .iter()
+            .map(|realm| self.index_name(*realm))
+            .collect();
+
+        let mut entries = Vec::new();
+        for entry in self.filesystem.read_dir(dir)? {
+            let is_index = index_names
+                .iter()
+                .any(|index_name| entry.path.file_name() == Some(index_name.as_ref()));
+
+            if entry.is_dir && !is_index {
+                entries.extend(self.filesystem.read_dir(&entry.path)?);
+            }
+
+            entries.push(entry);
+        }
+
+        for entry in entries {
+            let is_link_extension = [LinkExtension::Lua, LinkExtension::Luau]
+                .iter()
+                .any(|extension| entry.path.extension() == Some(extension.as_str().as_ref()));
+
+            if entry.is_file && is_link_extension {
+                links.push(entry.path);
+            }
+        }
+
+        Ok(links)
+    }
+}
+
+/// The require path from directory `from` to directory `to`, ending in a
+/// slash unless they're the same directory. Both paths must be relative to
+/// the same place, but either can have `.` and `..` components, like a
+/// project path starting with `./`.
+fn relative_path(from: &Path, to: &Path) -> String {
+    let (from, to) = (normalize_path(from), normalize_path(to));
+    let from: Vec<_> = from.components().collect();
+    let to: Vec<_> = to.components().collect();
+    let common = from.iter().zip(&to).take_while(|(a, b)| a == b).count();
+
+    let mut path = "../".repeat(from.len() - common);
+    for component in &to[common..] {
+        path.push_str(&component.as_os_str().to_string_lossy());
+        path.push('/');
+    }
+
+    path
+}
+
+/// Appends each component of the `/`-separated `path` to the instance path
+/// `base`, the way Rojo names the instances it syncs. Rojo turns `init.lua`
+/// and `init.luau` into their directory, so those are left out.
+pub(super) fn rojo_instance_path(base: &str, path: &str) -> String {
+    let mut instance_path = base.to_owned();
+
+    for name in path.split('/') {
+        if name.is_empty() || name == "init.lua" || name == "init.luau" {
+            continue;
+        }
+
+        let is_identifier = !name.starts_with(|c: char| c.is_ascii_digit())
+            && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_');
+
+        if is_identifier {
+            instance_path.push('.');
+            instance_path.push_str(name);
+        } else {
+            instance_path.push_str(&format!("[{:?}]", name));
+        }
+    }
+
+    instance_path
+}
+
+/// Splits what follows the base of an instance path written by
+/// `rojo_instance_path` back into the names it appended, or `None` if it
+/// isn't shaped like one.
+pub(super) fn instance_names(mut path: &str) -> Option<Vec<String>> {
+    let mut names = Vec::new();
+
+    while !path.is_empty() {
+        if let Some(rest) = path.strip_prefix('.') {
+            let end = rest
+                .find(|c: char| c == '.' || c == '[')
+                .unwrap_or(rest.len());
+            names.push(rest[..end].to_owned());
+            path = &rest[end..];
+        } else if let Some(rest) = path.strip_prefix("[\"") {
+            let mut name = String::new();
+            let mut chars = rest.char_indices();
+
+            let end = loop {
+                match chars.next()? {
+                    (index, '"') => break index,
+                    (_, '\\') => name.push(chars.next()?.1),
+                    (_, c) => name.push(c),
+                }
+            };
+
+            names.push(name);
+            path = rest[end + 1..].strip_prefix(']')?;
+        } else {
+            return None;
+        }
+    }
+
+    Some(names)
+}
+
+/// Whether `contents` look like a link file wally wrote: a single line
+/// returning the result of a require. The require function and path style
+/// can change between installs, so neither is checked.
+pub(super) fn is_link(contents: &[u8]) -> bool {
+    let contents = String::from_utf8_lossy(contents);
+    let line = contents.trim_end();
+
+    // An aggregated root module, holding a require on each line.
+    if let Some(entries) = line
+        .strip_prefix("return {\n")
+        .and_then(|rest| rest.strip_suffix('}'))
+    {
+        return entries.lines().all(is_aggregate_entry);
+    }
+
+    !line.contains('\n') && line.strip_prefix("return ").map_or(false, is_link_require)
+}
+
+/// Whether `entry` is a line of an aggregated root module, which is written
+/// as a tab, the alias as a table key, ` = ` and the require of its package.
+fn is_aggregate_entry(entry: &str) -> bool {
+    let (key, call) = match entry
+        .strip_prefix('\t')
+        .and_then(|entry| entry.strip_suffix(','))
+        .and_then(|entry| entry.split_once(" = "))
+    {
+        Some(parts) => parts,
+        None => return false,
+    };
+
+    let is_key = is_lua_identifier(key)
+        || key
+            .strip_prefix("[\"")
+            .and_then(|key| key.strip_suffix("\"]"))
+            .is_some();
+
+    is_key && is_link_require(call)
+}
+
+/// Whether `call` requires a package the way links do: a require function
+/// called with either a quoted path or a Rojo instance path, which leads
+/// through the package's directory in an index. Instance paths start from
+/// `script` or whichever instance Rojo syncs the packages to.
+fn is_link_require(call: &str) -> bool {
+    let (function, argument) = match call.strip_suffix(')').and_then(|call| call.split_once('(')) {
+        Some(parts) => parts,
+        None => return false,
+    };
+
+    if !function.split('.').all(is_lua_identifier) {
+        return false;
+    }
+
+    let names = match argument
+        .strip_prefix('"')
+        .and_then(|path| path.strip_suffix('"'))
+    {
+        Some(path) => path.split('/').map(str::to_owned).collect(),
+        None => {
+            let base_end = argument
+                .find(|c: char| c == '.' || c == '[')
+                .unwrap_or(argument.len());
+
+            match instance_names(&argument[base_end..]) {
+                Some(names) if is_lua_identifier(&argument[..base_end]) => names,
+                _ => return false,
+            }
+        }
+    };
+
+    names
+        .iter()
+        .any(|name| parse_package_dir_name(name).is_some())
+}
+
+/// `name` as a key in a Lua table constructor: bare when it's an identifier,
+/// and quoted in brackets otherwise.
+fn lua_table_key(name: &str) -> String {
+    if is_lua_identifier(name) {
+        name.to_owned()
+    } else {
+        format!("[{:?}]", name)
+    }
+}
+
+/// Whether `name` can be written bare in Lua, rather than as a string.
+fn is_lua_identifier(name: &str) -> bool {
+    const KEYWORDS: &[&str] = &[
+        "and", "break", "do", "else", "elseif", "end", "false", "for", "function", "if", "in",
+        "local", "nil", "not", "or", "repeat", "return", "then", "true", "until", "while",
+    ];
+
+    name.chars()
+        .next()
+        .map_or(false, |first| first.is_ascii_alphabetic() || first == '_')
+        && name
+            .chars()
+            .all(|char| char.is_ascii_alphanumeric() || char == '_')
+        && !KEYWORDS.contains(&name)
+}
+
+/// Whether `contents` mentions the index directory `dir_name` as a whole, and
+/// not as the start of a longer name like that of a pre-release.
+pub(super) fn names_dir(contents: &str, dir_name: &str) -> bool {
+    contents.match_indices(dir_name).any(|(index, _)| {
+        !contents[index + dir_name.len()..]
+            .starts_with(|c: char| c.is_ascii_alphanumeric() || "-.+~".contains(c))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::BTreeMap;
+
+    use crate::test_package::PackageBuilder;
+
+    use super::super::{
+        test_support::{minimal_project, one_dependency_project, published_registry, TestProject},
+        BrokenLink,
+    };
+
+    #[test]
+    fn submodule_links() {
+        let registry = published_registry();
+        registry.publish(
+            PackageBuilder::new("biff/multi-module@0.1.0")
+                .with_module("Signal")
+                .with_module("Promise")
+                .with_file("src/init.luau", "")
+                .with_file("src/Signal.luau", "")
+                .with_file("src/Promise.luau", ""),
+        );
+
+        let root = PackageBuilder::new("biff/root@0.1.0")
+            .with_dep("MultiModule", "biff/multi-module@0.1.0")
+            .with_dep("Minimal", "biff/minimal@0.1.0");
+        let project = TestProject::new(&registry, root);
+        project
+            .install(project.context().with_submodule_links(true))
+            .unwrap();
+
+        let packages = project.dir.path().join("packages");
+        let read = |path: &str| fs_err::read_to_string(packages.join(path)).unwrap();
+
+        assert_eq!(
+            read("MultiModule/init.lua"),
+            "return require(\"../_index/biff_multi-module@0.1.0/src\")\n"
+        );
+        assert_eq!(
+            read("MultiModule/Signal.lua"),
+            "return require(\"../_index/biff_multi-module@0.1.0/src/Signal\")\n"
+        );
+        assert_eq!(
+            read("MultiModule/Promise.lua"),
+            "return require(\"../_index/biff_multi-module@0.1.0/src/Promise\")\n"
+        );
+        assert!(!packages.join("MultiModule.lua").exists());
+
+        // Packages without submodules are still linked with a single file.
+        assert!(packages.join("Minimal.lua").is_file());
+    }
+
+    #[test]
+    fn case_variant_aliases() {
+        let registry = published_registry();
+        let root = || {
+            PackageBuilder::new("biff/root@0.1.0")
+                .with_dep("Minimal", "biff/minimal@0.1.0")
+                .with_dep("minimal", "biff/minimal@0.1.0")
+        };
+
+        let project = TestProject::new(&registry, root());
+        let err = project.install(project.context()).unwrap_err();
+        assert!(err.to_string().contains("differ only by case"), "{}", err);
+
+        let project = TestProject::new(&registry, root());
+        project
+            .install(project.context().with_normalized_aliases(true))
+            .unwrap();
+
+        let links: Vec<_> = fs_err::read_dir(project.dir.path().join("packages"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .filter(|name| name != "_index")
+            .collect();
+        assert_eq!(links, vec!["Minimal.lua"]);
+    }
+
+    #[test]
+    fn generated_links_match_install() {
+        let (_, project) = one_dependency_project();
+        project.install(project.context()).unwrap();
+
+        let packages = project.dir.path().join("packages");
+        let context = project.context();
+
+        let one_dependency: PackageId = "biff/one-dependency@0.1.0".parse().unwrap();
+        assert_eq!(
+            fs_err::read(packages.join("OneDependency.lua")).unwrap(),
+            context
+                .generate_link(LinkOrigin::Root, &one_dependency, Some("/src"))
+                .into_bytes()
+        );
+
+        let minimal: PackageId = "biff/minimal@0.1.0".parse().unwrap();
+        assert_eq!(
+            fs_err::read(packages.join("_index/biff_one-dependency@0.1.0/packages/Minimal.lua"))
+                .unwrap(),
+            context
+                .generate_link(LinkOrigin::Sibling, &minimal, Some(""))
+                .into_bytes()
+        );
+    }
+
+    #[test]
+    fn link_suffix_mismatch_detected() {
+        let (registry, project) = minimal_project();
+        let context = project.context();
+
+        let minimal: PackageId = "biff/minimal@0.1.0".parse().unwrap();
+        let contents = registry.source().download_package(&minimal).unwrap();
+        context
+            .write_contents(&minimal, &contents, Realm::Shared)
+            .unwrap();
+
+        // Move the entry point after unpacking, so the index no longer holds
+        // the archive that was unpacked.
+        let unpacked = project
+            .dir
+            .path()
+            .join("packages/_index/biff_minimal@0.1.0");
+        fs_err::create_dir(unpacked.join("src")).unwrap();
+        fs_err::rename(unpacked.join("init.luau"), unpacked.join("src/init.luau")).unwrap();
+
+        let err = context
+            .inspect_dependency(&minimal, Realm::Shared)
+            .unwrap_err();
+
+        assert!(err.to_string().contains("changed after"), "{}", err);
+    }
+
+    #[test]
+    fn custom_require_function() {
+        let (_, project) = one_dependency_project();
+
+        let context = project.context().with_require_function("import");
+        project.install(context.clone()).unwrap();
+
+        let link =
+            fs_err::read_to_string(project.dir.path().join("packages/OneDependency.lua")).unwrap();
+        assert_eq!(
+            link,
+            "return import(\"_index/biff_one-dependency@0.1.0/src\")\n"
+        );
+
+        let sibling_link = fs_err::read_to_string(
+            project
+                .dir
+                .path()
+                .join("packages/_index/biff_one-dependency@0.1.0/packages/Minimal.lua"),
+        )
+        .unwrap();
+        assert_eq!(
+            sibling_link,
+            "return import(\"../../biff_minimal@0.1.0\")\n"
+        );
+
+        assert_eq!(context.verify_links().unwrap(), Vec::<BrokenLink>::new());
+    }
+
+    #[test]
+    fn require_path_styles() {
+        let (_, project) = one_dependency_project();
+
+        let one_dependency: PackageId = "biff/one-dependency@0.1.0".parse().unwrap();
+        let minimal: PackageId = "biff/minimal@0.1.0".parse().unwrap();
+
+        let relative = project.context();
+        assert_eq!(
+            relative.generate_link(LinkOrigin::Root, &one_dependency, Some("/src")),
+            "return require(\"_index/biff_one-dependency@0.1.0/src\")\n"
+        );
+        assert_eq!(
+            relative.generate_link(LinkOrigin::Sibling, &minimal, Some("")),
+            "return require(\"../../biff_minimal@0.1.0\")\n"
+        );
+
+        let absolute = project
+            .context()
+            .with_require_path_style(RequirePathStyle::AbsoluteRojo {
+                shared: "game.ReplicatedStorage.Packages".to_owned(),
+                server: "game.ServerScriptService.ServerPackages".to_owned(),
+                dev: "game.ReplicatedStorage.DevPackages".to_owned(),
+            });
+        assert_eq!(
+            absolute.generate_link(LinkOrigin::Root, &one_dependency, Some("/src")),
+            "return require(game.ReplicatedStorage.Packages._index[\"biff_one-dependency@0.1.0\"].src)\n"
+        );
+        assert_eq!(
+            absolute.generate_link(LinkOrigin::Sibling, &minimal, Some("/src/init.lua")),
+            "return require(game.ReplicatedStorage.Packages._index[\"biff_minimal@0.1.0\"].src)\n"
+        );
+
+        project.install(absolute.clone()).unwrap();
+        assert_eq!(
+            fs_err::read_to_string(project.dir.path().join("packages/OneDependency.lua")).unwrap(),
+            absolute.generate_link(LinkOrigin::Root, &one_dependency, Some("/src"))
+        );
+    }
+
+    #[test]
+    fn link_extension_switch_removes_old_links() {
+        let (_, project) = one_dependency_project();
+
+        let packages = project.dir.path().join("packages");
+        let sibling_links = packages.join("_index/biff_one-dependency@0.1.0/packages");
+
+        project.install(project.context()).unwrap();
+        assert!(packages.join("OneDependency.lua").is_file());
+        assert!(sibling_links.join("Minimal.lua").is_file());
+
+        project
+            .install(project.context().with_link_extension(LinkExtension::Luau))
+            .unwrap();
+        assert!(packages.join("OneDependency.luau").is_file());
+        assert!(!packages.join("OneDependency.lua").exists());
+        assert!(sibling_links.join("Minimal.luau").is_file());
+        assert!(!sibling_links.join("Minimal.lua").exists());
+
+        project
+            .install(project.context().with_link_extension(LinkExtension::Lua))
+            .unwrap();
+        assert!(packages.join("OneDependency.lua").is_file());
+        assert!(!packages.join("OneDependency.luau").exists());
+    }
+
+    #[test]
+    fn stale_root_link_rewritten() {
+        let registry = published_registry();
+        registry.publish(PackageBuilder::new("biff/minimal@0.2.0").with_file("init.luau", ""));
+
+        let old = TestProject::new(
+            &registry,
+            PackageBuilder::new("biff/root@0.1.0").with_dep("Minimal", "biff/minimal@0.1.0"),
+        );
+        old.install(old.context()).unwrap();
+
+        let link = old.dir.path().join("packages/Minimal.lua");
+        let old_link = fs_err::read_to_string(&link).unwrap();
+        assert!(old_link.contains("biff_minimal@0.1.0"), "{}", old_link);
+
+        let new = TestProject::new(
+            &registry,
+            PackageBuilder::new("biff/root@0.1.0").with_dep("Minimal", "biff/minimal@0.2.0"),
+        );
+        let install_new = || {
+            old.context()
+                .install(
+                    new.sources.clone(),
+                    new.root_package_id.clone(),
+                    new.resolved.clone(),
+                )
+                .unwrap()
+        };
+
+        install_new();
+        let new_link = fs_err::read_to_string(&link).unwrap();
+        assert!(new_link.contains("biff_minimal@0.2.0"), "{}", new_link);
+
+        // The old version's directory is still there, so the stale link
+        // still leads to a module.
+        fs_err::write(&link, &old_link).unwrap();
+        install_new();
+        assert_eq!(fs_err::read_to_string(&link).unwrap(), new_link);
+    }
+
+    #[test]
+    fn link_conflict_policies() {
+        let registry = published_registry();
+        let root =
+            || PackageBuilder::new("biff/root@0.1.0").with_dep("Minimal", "biff/minimal@0.1.0");
+        let user_file = "-- my own module\nreturn {}\n";
+
+        let install = |policy| {
+            let project = TestProject::new(&registry, root());
+            let link = project.dir.path().join("packages/Minimal.lua");
+            fs_err::create_dir_all(link.parent().unwrap()).unwrap();
+            fs_err::write(&link, user_file).unwrap();
+
+            let result = project.install(project.context().with_link_conflict_policy(policy));
+            (result, fs_err::read_to_string(&link).unwrap(), project)
+        };
+
+        let (result, link, _project) = install(LinkConflictPolicy::Overwrite);
+        result.unwrap();
+        assert!(link.starts_with("return require("), "{}", link);
+
+        let (result, link, _project) = install(LinkConflictPolicy::Skip);
+        result.unwrap();
+        assert_eq!(link, user_file);
+
+        let (result, link, _project) = install(LinkConflictPolicy::Error);
+        let err = format!("{:#}", result.unwrap_err());
+        assert!(err.contains("isn't a link"), "{}", err);
+        assert_eq!(link, user_file);
+
+        // Links from an earlier install are never conflicts.
+        let project = TestProject::new(&registry, root());
+        project.install(project.context()).unwrap();
+        project
+            .install(
+                project
+                    .context()
+                    .with_link_conflict_policy(LinkConflictPolicy::Error)
+                    .with_link_extension(LinkExtension::Luau),
+            )
+            .unwrap();
+        assert!(project.dir.path().join("packages/Minimal.luau").is_file());
+    }
+
+    #[test]
+    fn aggregated_root_links() {
+        let registry = published_registry();
+        let root = PackageBuilder::new("biff/root@0.1.0")
+            .with_dep("Minimal", "biff/minimal@0.1.0")
+            .with_dep("one-dependency", "biff/one-dependency@0.1.0");
+        let project = TestProject::new(&registry, root);
+        let packages = project.dir.path().join("packages");
+
+        project
+            .install(
+                project
+                    .context()
+                    .with_root_link_layout(RootLinkLayout::Aggregated),
+            )
+            .unwrap();
+
+        assert_eq!(
+            fs_err::read_to_string(packages.join("index.lua")).unwrap(),
+            "return {\n\
+             \tMinimal = require(\"_index/biff_minimal@0.1.0\"),\n\
+             \t[\"one-dependency\"] = require(\"_index/biff_one-dependency@0.1.0/src\"),\n\
+             }\n"
+        );
+        assert!(!packages.join("Minimal.lua").exists());
+        assert!(!packages.join("one-dependency.lua").exists());
+
+        // The module is taken for a link, so it can be written again even
+        // when other files can't be written over.
+        project
+            .install(
+                project
+                    .context()
+                    .with_root_link_layout(RootLinkLayout::Both)
+                    .with_link_conflict_policy(LinkConflictPolicy::Error),
+            )
+            .unwrap();
+
+        assert!(packages.join("index.lua").is_file());
+        assert!(packages.join("Minimal.lua").is_file());
+        assert!(packages.join("one-dependency.lua").is_file());
+    }
+
+    #[test]
+    fn only_generated_links_recognised() {
+        let links: &[&str] = &[
+            "return require(\"_index/biff_minimal@0.1.0\")\n",
+            "return require(\"../../biff_minimal@0.1.0/src\")\n",
+            "return require(\"@wally/_index/biff_minimal@0.1.0/init.lua\")\n",
+            "return require(script.Parent._index[\"biff_minimal@0.1.0\"][\"minimal\"])\n",
+            "return require(game.ReplicatedStorage.Packages._index[\"biff_minimal@0.1.0\"].src)\n",
+            "return shared.require(\"_index/biff_minimal@0.1.0\")\n",
+        ];
+        for link in links {
+            assert!(is_link(link.as_bytes()), "{}", link);
+        }
+
+        let files: &[&str] = &[
+            "return setmetatable({}, {})\n",
+            "return require(script.Parent.Util)\n",
+            "return require(\"./util\")\n",
+            "return require(\"_index/biff_minimal@0.1.0\") or fallback()\n",
+            "local x = 1\nreturn require(\"_index/biff_minimal@0.1.0\")\n",
+        ];
+        for file in files {
+            assert!(!is_link(file.as_bytes()), "{}", file);
+        }
+    }
+
+    #[test]
+    fn only_generated_aggregates_recognised() {
+        let aggregates: &[&str] = &[
+            "return {\n}\n",
+            "return {\n\
+             \tMinimal = require(\"_index/biff_minimal@0.1.0\"),\n\
+             \t[\"one-dependency\"] = require(\"_index/biff_one-dependency@0.1.0/src\"),\n\
+             }\n",
+        ];
+        for aggregate in aggregates {
+            assert!(is_link(aggregate.as_bytes()), "{}", aggregate);
+        }
+
+        let files: &[&str] = &[
+            "return {\n\tUtil = require(script.Util),\n}\n",
+            "return {\n\tvalue = 1,\n}\n",
+            "return {\n  Minimal = require(\"_index/biff_minimal@0.1.0\"),\n}\n",
+            "return {\n\tMinimal = require(\"_index/biff_minimal@0.1.0\")\n}\n",
+        ];
+        for file in files {
+            assert!(!is_link(file.as_bytes()), "{}", file);
+        }
+    }
+
+    #[test]
+    fn links_inspect_unpacked_packages() {
+        let (registry, project) = one_dependency_project();
+        project.install(project.context()).unwrap();
+
+        // Each package is downloaded once to be unpacked, and links to it are
+        // written from what's on disk.
+        assert_eq!(registry.downloads_started(), 2);
+        let link =
+            fs_err::read_to_string(project.dir.path().join("packages/OneDependency.lua")).unwrap();
+        assert_eq!(
+            link,
+            "return require(\"_index/biff_one-dependency@0.1.0/src\")\n"
+        );
+
+        let minimal: PackageId = "biff/minimal@0.1.0".parse().unwrap();
+        fs_err::remove_dir_all(
+            project
+                .dir
+                .path()
+                .join("packages/_index/biff_minimal@0.1.0"),
+        )
+        .unwrap();
+
+        let mut aliases = BTreeMap::new();
+        aliases.insert("Minimal".to_owned(), minimal);
+        let err = project
+            .context()
+            .write_root_package_links(Realm::Shared, &aliases, &project.resolved)
+            .unwrap_err();
+
+        assert!(err.to_string().contains("not in the index"), "{}", err);
+        assert_eq!(registry.downloads_started(), 2);
+    }
+
+    #[test]
+    fn shipped_package_links_dir_collision() {
+        let registry = published_registry();
+        registry.publish(
+            PackageBuilder::new("biff/ships-packages@0.1.0")
+                .with_dep("Minimal", "biff/minimal@0.1.0")
+                .with_file("init.luau", "")
+                .with_file("packages/Thing.lua", "return 1"),
+        );
+
+        let root = PackageBuilder::new("biff/root@0.1.0")
+            .with_dep("ShipsPackages", "biff/ships-packages@0.1.0");
+        let project = TestProject::new(&registry, root);
+
+        let err = project.install(project.context()).unwrap_err();
+        assert!(
+            err.to_string()
+                .contains("biff/ships-packages@0.1.0 ships its own packages directory"),
+            "{}",
+            err
+        );
+
+        let context = project.context().with_package_links_dir("WallyPackages");
+        project.install(context.clone()).unwrap();
+
+        let package_dir = project
+            .dir
+            .path()
+            .join("packages/_index/biff_ships-packages@0.1.0");
+        assert!(package_dir.join("WallyPackages/Minimal.lua").is_file());
+        assert_eq!(
+            fs_err::read_to_string(package_dir.join("packages/Thing.lua")).unwrap(),
+            "return 1"
+        );
+        assert!(!package_dir.join("packages/Minimal.lua").exists());
+        assert!(context.verify_links().unwrap().is_empty());
+
+        let nested = project.context().with_package_links_dir("lib/packages");
+        project.install(nested.clone()).unwrap();
+
+        assert_eq!(
+            fs_err::read_to_string(package_dir.join("lib/packages/Minimal.lua")).unwrap(),
+            "return require(\"../../../biff_minimal@0.1.0\")\n"
+        );
+        assert!(nested.verify_links().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,54 @@
+//! Guards a project against multiple installs running in it at once.
+
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use anyhow::bail;
+
+use super::filesystem::{FileLock, Filesystem};
+
+/// Held for the duration of an install, as an advisory lock on the lock file.
+/// The operating system releases it if the process dies, so a crashed install
+/// never leaves the project locked.
+///
+/// The lock file lives next to the project's install state rather than in
+/// any package directory, so installs can remove and replace those freely.
+/// It's removed when this is dropped.
+pub struct InstallLock {
+    filesystem: Arc<dyn Filesystem>,
+    path: PathBuf,
+    _lock: FileLock,
+}
+
+impl InstallLock {
+    /// Lock the file at the given path, creating it if needed, failing if
+    /// another process already holds it.
+    pub fn acquire(filesystem: Arc<dyn Filesystem>, path: &Path) -> anyhow::Result<Self> {
+        if let Some(parent) = path.parent() {
+            filesystem.create_dir_all(parent)?;
+        }
+
+        match filesystem.try_lock(path)? {
+            Some(lock) => Ok(Self {
+                filesystem,
+                path: path.to_owned(),
+                _lock: lock,
+            }),
+            None => bail!(
+                "Another wally install is running in this project.\n\
+                Wait for it to finish, then try again."
+            ),
+        }
+    }
+}
+
+impl Drop for InstallLock {
+    /// The file is removed while it's still locked, so nobody can lock it in
+    /// between. An install that opened it before then notices it's gone once
+    /// it gets the lock.
+    fn drop(&mut self) {
+        if let Err(err) = self.filesystem.remove_file(&self.path) {
+            log::warn!("Could not remove install lock: {}", err);
+        }
+    }
+}
@@ -0,0 +1,123 @@
+//! Restricting which packages an install is allowed to download.
+
+use anyhow::bail;
+use globset::{GlobBuilder, GlobMatcher};
+use serde::{Serialize, Serializer};
+
+use crate::{package_id::PackageId, package_name::PackageName};
+
+/// Decides which packages may be installed, by matching their names against
+/// glob patterns like `biff/*` or `*/left-pad`.
+///
+/// A package is permitted if it matches no deny pattern and, when there are
+/// any allow patterns, at least one of those.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct InstallPolicy {
+    #[serde(serialize_with = "serialize_patterns")]
+    allow: Vec<GlobMatcher>,
+
+    #[serde(serialize_with = "serialize_patterns")]
+    deny: Vec<GlobMatcher>,
+}
+
+impl InstallPolicy {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Only permit packages matching this pattern, or any other allow
+    /// pattern.
+    pub fn allow(mut self, pattern: &str) -> anyhow::Result<Self> {
+        self.allow.push(compile_pattern(pattern)?);
+        Ok(self)
+    }
+
+    /// Never permit packages matching this pattern.
+    pub fn deny(mut self, pattern: &str) -> anyhow::Result<Self> {
+        self.deny.push(compile_pattern(pattern)?);
+        Ok(self)
+    }
+
+    pub fn permits(&self, name: &PackageName) -> bool {
+        let name = name.to_string();
+
+        let allowed = self.allow.is_empty() || self.allow.iter().any(|glob| glob.is_match(&name));
+        let denied = self.deny.iter().any(|glob| glob.is_match(&name));
+
+        allowed && !denied
+    }
+
+    /// Fails on the first of `packages` this policy doesn't permit, before
+    /// any of them are downloaded.
+    pub(super) fn check(&self, packages: &[PackageId]) -> anyhow::Result<()> {
+        for package_id in packages {
+            if !self.permits(package_id.name()) {
+                bail!("package {} is not permitted by policy", package_id);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn compile_pattern(pattern: &str) -> anyhow::Result<GlobMatcher> {
+    // Keep `*` from matching across the slash between scope and name.
+    let glob = GlobBuilder::new(pattern).literal_separator(true).build()?;
+
+    Ok(glob.compile_matcher())
+}
+
+/// Patterns are compared by the globs they were compiled from.
+fn serialize_patterns<S: Serializer>(
+    patterns: &[GlobMatcher],
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_seq(patterns.iter().map(|pattern| pattern.glob().glob()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+
+    use super::super::test_support::{one_dependency_project, RecordingReporter};
+
+    #[test]
+    fn allow_and_deny() {
+        let policy = InstallPolicy::new()
+            .allow("biff/*")
+            .unwrap()
+            .deny("biff/left-pad")
+            .unwrap();
+
+        let permits = |name: &str| policy.permits(&name.parse().unwrap());
+
+        assert!(permits("biff/minimal"));
+        assert!(!permits("biff/left-pad"));
+        assert!(!permits("other/minimal"));
+    }
+
+    #[test]
+    fn denied_package_never_downloaded() {
+        let (_, project) = one_dependency_project();
+
+        let policy = InstallPolicy::new().deny("biff/minimal").unwrap();
+        let reporter = Arc::new(RecordingReporter::default());
+        let err = project
+            .install(
+                project
+                    .context()
+                    .with_policy(policy)
+                    .with_reporter(reporter.clone()),
+            )
+            .unwrap_err();
+
+        assert_eq!(
+            err.to_string(),
+            "package biff/minimal@0.1.0 is not permitted by policy"
+        );
+        assert!(reporter.events.lock().unwrap().is_empty());
+        assert!(!project.dir.path().join("packages").exists());
+    }
+}
@@ -0,0 +1,229 @@
+//! Progress reporting for package installation.
+//!
+//! Installation reports what it's doing through a `ProgressReporter`, which
+//! lets callers that aren't a terminal (editors, build tools, tests) observe
+//! progress without scraping output.
+
+use std::time::Duration;
+
+use crossterm::style::{Color, SetForegroundColor};
+use indicatif::{ProgressBar, ProgressStyle};
+
+use crate::package_id::PackageId;
+
+/// A distinct stage of getting packages onto disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InstallPhase {
+    /// Picking package versions. Installation itself never reports this phase;
+    /// it's here for callers that resolve before installing.
+    Resolving,
+
+    /// Downloading and unpacking package contents.
+    Downloading,
+
+    /// Writing the link files that make packages requirable.
+    Linking,
+}
+
+impl InstallPhase {
+    fn label(self) -> &'static str {
+        match self {
+            InstallPhase::Resolving => "Resolving",
+            InstallPhase::Downloading => "Downloading",
+            InstallPhase::Linking => "Linking",
+        }
+    }
+}
+
+/// Something that happened during installation.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProgressEvent {
+    /// Installation started, made up of the first number of steps across all
+    /// of its phases. The second number of them were done before it started,
+    /// since their packages were already in the index, which is how far along
+    /// an interrupted install that's run again starts out. Each `Downloaded`
+    /// and `Linked` event is one more step.
+    Started(u64, u64),
+
+    /// A new phase started, made up of the given number of steps.
+    PhaseStarted(InstallPhase, u64),
+
+    /// Some of a package's files were unpacked, out of the given total.
+    Unpacking(PackageId, u64, u64),
+
+    /// A package's contents were downloaded and unpacked.
+    Downloaded(PackageId),
+
+    /// Downloading a package failed, and it's being tried again. The number
+    /// counts retries, starting from 1.
+    Retrying(PackageId, u32),
+
+    /// A package wasn't downloaded, because it was already in the index.
+    Skipped(PackageId),
+
+    /// The links for a package's dependencies were written. The root package
+    /// is linked once for each realm it has dependencies in, since each realm
+    /// has its own directory.
+    Linked(PackageId),
+
+    /// Where the time went during installation, reported just before it
+    /// finishes.
+    Stats(InstallStats),
+
+    /// Installation is complete.
+    Finished,
+}
+
+/// How long an install spent on each kind of work. Downloads and unpacking
+/// happen on many threads at once, so their times are summed across threads
+/// and can add up to more than the phase they happen in. Comparing them
+/// tells network-bound installs apart from disk-bound ones.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct InstallStats {
+    /// Time spent downloading packages. For packages unpacked as they're
+    /// streamed, this is the time spent waiting on the stream.
+    pub download_time: Duration,
+
+    /// Time spent unpacking packages.
+    pub extraction_time: Duration,
+
+    /// Time spent writing link files.
+    pub link_time: Duration,
+
+    /// Wall-clock time of the `Downloading` phase.
+    pub downloading_phase: Duration,
+
+    /// Wall-clock time of the `Linking` phase.
+    pub linking_phase: Duration,
+}
+
+/// Receives `ProgressEvent`s as installation proceeds. Events may be reported
+/// from multiple threads at once.
+pub trait ProgressReporter: Send + Sync {
+    fn report(&self, event: ProgressEvent);
+}
+
+/// Controls how `IndicatifReporter` draws its progress bar.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ProgressTheme {
+    /// An indicatif template string, see `indicatif::ProgressStyle`.
+    pub template: String,
+
+    /// The spinner's animation frames, followed by the frame shown once it
+    /// finishes.
+    pub tick_chars: String,
+
+    /// The characters used for the filled, current and empty parts of the bar.
+    pub progress_chars: String,
+}
+
+impl Default for ProgressTheme {
+    fn default() -> Self {
+        Self {
+            template: "{spinner:.cyan.bold} {msg} {pos}/{len} [{wide_bar:.cyan/blue}]".to_owned(),
+            tick_chars: "⠁⠈⠐⠠⠄⠂ ".to_owned(),
+            progress_chars: "#>-".to_owned(),
+        }
+    }
+}
+
+/// The default reporter, which draws a progress bar to the terminal.
+pub struct IndicatifReporter {
+    bar: ProgressBar,
+}
+
+impl IndicatifReporter {
+    pub fn new() -> Self {
+        Self::with_theme(&ProgressTheme::default()).expect("default progress theme is invalid")
+    }
+
+    /// Create a reporter that draws its progress bar using the given theme.
+    pub fn with_theme(theme: &ProgressTheme) -> anyhow::Result<Self> {
+        let bar = ProgressBar::new(0).with_style(theme_style(theme)?);
+
+        Ok(Self { bar })
+    }
+}
+
+fn theme_style(theme: &ProgressTheme) -> anyhow::Result<ProgressStyle> {
+    Ok(ProgressStyle::with_template(&theme.template)?
+        .tick_chars(&theme.tick_chars)
+        .progress_chars(&theme.progress_chars))
+}
+
+impl Default for IndicatifReporter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ProgressReporter for IndicatifReporter {
+    fn report(&self, event: ProgressEvent) {
+        match event {
+            ProgressEvent::Started(total, done) => {
+                self.bar.enable_steady_tick(Duration::from_millis(100));
+                self.bar.set_length(total);
+                self.bar.set_position(done);
+            }
+            ProgressEvent::PhaseStarted(phase, _) => self.bar.set_message(phase.label()),
+            ProgressEvent::Downloaded(package_id) => {
+                self.bar.println(format!(
+                    "{} Downloaded {}{}",
+                    SetForegroundColor(Color::DarkGreen),
+                    SetForegroundColor(Color::Reset),
+                    package_id
+                ));
+                self.bar.set_message(InstallPhase::Downloading.label());
+                self.bar.inc(1);
+            }
+            ProgressEvent::Unpacking(package_id, written, total) => {
+                self.bar.set_message(format!(
+                    "Unpacking {} ({}/{} files)",
+                    package_id, written, total
+                ));
+            }
+            ProgressEvent::Retrying(package_id, retry) => {
+                self.bar
+                    .set_message(format!("Retrying {} (attempt {})", package_id, retry + 1));
+            }
+            ProgressEvent::Skipped(_) | ProgressEvent::Stats(_) => {}
+            ProgressEvent::Linked(_) => self.bar.inc(1),
+            ProgressEvent::Finished => self.bar.finish_and_clear(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ascii_theme() {
+        let theme = ProgressTheme {
+            template: "{spinner} {msg} {pos}/{len} [{wide_bar}]".to_owned(),
+            tick_chars: "-\\|/ ".to_owned(),
+            progress_chars: "=> ".to_owned(),
+        };
+
+        let style = theme_style(&theme).unwrap();
+
+        for tick in 0..10 {
+            assert!(style.get_tick_str(tick).is_ascii());
+        }
+        assert!(style.get_final_tick_str().is_ascii());
+    }
+
+    #[test]
+    fn bar_starts_at_done_steps() {
+        let reporter = IndicatifReporter {
+            bar: ProgressBar::hidden(),
+        };
+
+        reporter.report(ProgressEvent::Started(5, 2));
+        assert_eq!(reporter.bar.length(), Some(5));
+        assert_eq!(reporter.bar.position(), 2);
+
+        reporter.report(ProgressEvent::Linked("biff/minimal@0.1.0".parse().unwrap()));
+        assert_eq!(reporter.bar.position(), 3);
+    }
+}
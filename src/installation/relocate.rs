@@ -0,0 +1,135 @@
+//! Moving an install along with the project it belongs to.
+
+use std::path::Path;
+
+use anyhow::bail;
+
+use super::InstallationContext;
+
+impl InstallationContext {
+    /// Returns a context for the same install after its project moved from
+    /// `old_root` to `new_root`, with every path it keeps under the old root
+    /// moved to the new one. Nothing is downloaded again.
+    ///
+    /// Link files only use relative paths, so rather than being rewritten
+    /// they're checked to still resolve from the new root. That check is
+    /// skipped when a require transform is set, since it may rewrite paths
+    /// into something that can't be followed on disk.
+    ///
+    /// The install state moves along with the project. Its hash doesn't
+    /// depend on where the project is, and neither do package checksums, so
+    /// the next install with the relocated context has nothing to do.
+    pub fn relocate(&self, old_root: &Path, new_root: &Path) -> anyhow::Result<Self> {
+        if !self.filesystem.is_dir(new_root) {
+            bail!(
+                "Cannot relocate to {}, which is not a directory",
+                new_root.display()
+            );
+        }
+
+        let relocate_path = |path: &Path| match path.strip_prefix(old_root) {
+            Ok(relative_path) => new_root.join(relative_path),
+            Err(_) => path.to_path_buf(),
+        };
+
+        let mut relocated = self.clone();
+
+        let mut paths = [
+            &mut relocated.shared_dir,
+            &mut relocated.shared_index_dir,
+            &mut relocated.server_dir,
+            &mut relocated.server_index_dir,
+            &mut relocated.dev_dir,
+            &mut relocated.dev_index_dir,
+            &mut relocated.lock_path,
+            &mut relocated.state_path,
+        ];
+        for path in paths.iter_mut() {
+            **path = relocate_path(path);
+        }
+
+        if let Some(staging_dir) = &mut relocated.options.extraction.staging_dir {
+            *staging_dir = relocate_path(staging_dir);
+        }
+
+        if let Some(rojo_project) = &mut relocated.options.rojo_project {
+            *rojo_project = relocate_path(rojo_project);
+        }
+
+        // Claims are keyed by the old paths.
+        relocated.claimed_paths = Default::default();
+
+        if relocated.options.linking.require_transform.is_none() {
+            let broken_links = relocated.verify_links()?;
+
+            if !broken_links.is_empty() {
+                let paths: Vec<_> = broken_links
+                    .iter()
+                    .map(|broken| broken.link.display().to_string())
+                    .collect();
+
+                bail!(
+                    "These links no longer resolve after moving to {}: {}",
+                    new_root.display(),
+                    paths.join(", ")
+                );
+            }
+        }
+
+        Ok(relocated)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::{path::PathBuf, sync::Arc};
+
+    use super::super::test_support::{one_dependency_project, WriteRecordingFilesystem};
+
+    #[test]
+    fn relocate_moved_install() {
+        let (registry, project) = one_dependency_project();
+
+        let project_file = project.dir.path().join("default.project.json");
+        fs_err::write(
+            &project_file,
+            r#"{ "name": "game", "tree": { "$path": "packages" } }"#,
+        )
+        .unwrap();
+        let context = || project.context().with_rojo_project(&project_file);
+        project.install(context()).unwrap();
+
+        let new_parent = tempfile::tempdir().unwrap();
+        let new_root = new_parent.path().join("moved");
+        fs_err::rename(project.dir.path(), &new_root).unwrap();
+
+        let relocated = context().relocate(project.dir.path(), &new_root).unwrap();
+
+        assert_eq!(relocated.shared_dir, new_root.join("packages"));
+        assert_eq!(relocated.lock_path, new_root.join(".wally-lock"));
+        assert!(relocated.installed_size().unwrap() > 0);
+
+        // The moved install is still current, so installing again downloads
+        // and writes nothing.
+        assert!(relocated
+            .is_installed(&project.resolved, &project.root_package_id)
+            .unwrap());
+        let downloads = registry.downloads_started();
+        let filesystem = Arc::new(WriteRecordingFilesystem::default());
+        project
+            .install(relocated.clone().with_filesystem(filesystem.clone()))
+            .unwrap();
+        assert_eq!(registry.downloads_started(), downloads);
+        assert_eq!(*filesystem.written.lock().unwrap(), Vec::<PathBuf>::new());
+
+        fs_err::remove_dir_all(new_root.join("packages/_index/biff_minimal@0.1.0")).unwrap();
+
+        let err = project
+            .context()
+            .relocate(project.dir.path(), &new_root)
+            .unwrap_err();
+        assert!(err.to_string().contains("Minimal.lua"), "{}", err);
+    }
+}
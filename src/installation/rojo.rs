@@ -0,0 +1,149 @@
+//! Checking an install against the Rojo project that syncs it.
+
+use std::path::Path;
+
+use anyhow::Context;
+
+use super::{normalize_path, Diagnostic, DiagnosticKind, InstallationContext};
+
+impl InstallationContext {
+    /// Checks the package directories on disk against the Rojo project file
+    /// at `project_file`, returning a warning for each one that no `$path`
+    /// in the project maps, on its own or as part of a mapped directory.
+    /// Paths in the project are relative to the project file, like they are
+    /// for Rojo.
+    pub fn check_rojo_project(&self, project_file: &Path) -> anyhow::Result<Vec<Diagnostic>> {
+        let contents = self.filesystem.read(project_file)?;
+        let project: serde_json::Value = serde_json::from_slice(&contents)
+            .with_context(|| format!("Could not parse Rojo project {}", project_file.display()))?;
+
+        fn collect_paths(node: &serde_json::Value, paths: &mut Vec<String>) {
+            let node = match node.as_object() {
+                Some(node) => node,
+                None => return,
+            };
+
+            for (key, value) in node {
+                if key == "$path" {
+                    // Optional paths are written as `{ "optional": path }`.
+                    let path = value
+                        .as_str()
+                        .or_else(|| value.get("optional").and_then(|path| path.as_str()));
+                    paths.extend(path.map(str::to_owned));
+                } else if !key.starts_with('$') {
+                    collect_paths(value, paths);
+                }
+            }
+        }
+
+        let mut paths = Vec::new();
+        if let Some(tree) = project.get("tree") {
+            collect_paths(tree, &mut paths);
+        }
+
+        let project_dir = project_file.parent().unwrap_or_else(|| Path::new(""));
+        let mapped: Vec<_> = paths
+            .iter()
+            .map(|path| normalize_path(&project_dir.join(path)))
+            .collect();
+
+        let mut diagnostics = Vec::new();
+
+        for dir in self.package_dirs() {
+            if !self.filesystem.is_dir(dir) {
+                continue;
+            }
+
+            let normalized = normalize_path(dir);
+            if !mapped.iter().any(|path| normalized.starts_with(path)) {
+                let message = format!(
+                    "{} isn't mapped by the Rojo project {}, so its packages won't be in the game",
+                    dir.display(),
+                    project_file.display()
+                );
+                diagnostics.push(
+                    Diagnostic::new(DiagnosticKind::UnmappedDirectory, message).with_path(dir),
+                );
+            }
+        }
+
+        Ok(diagnostics)
+    }
+
+    /// Warn about package directories the Rojo project doesn't map, if
+    /// there's a Rojo project to check against.
+    pub(super) fn warn_unmapped_dirs(&self) -> anyhow::Result<()> {
+        if let Some(rojo_project) = &self.options.rojo_project {
+            for diagnostic in self.check_rojo_project(rojo_project)? {
+                self.warn(diagnostic)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::{
+        manifest::Realm, package_id::PackageId, package_source::PackageSourceId,
+        resolution::ResolvePackageMetadata, test_package::PackageBuilder,
+    };
+
+    use super::super::test_support::{published_registry, TestProject};
+
+    #[test]
+    fn unmapped_package_dirs_warned() {
+        let registry = published_registry();
+        let mut project = TestProject::new(
+            &registry,
+            PackageBuilder::new("biff/root@0.1.0").with_dep("Minimal", "biff/minimal@0.1.0"),
+        );
+
+        // Put a package in `ServerPackages` too.
+        let one_dependency: PackageId = "biff/one-dependency@0.1.0".parse().unwrap();
+        project.resolved.activated.insert(one_dependency.clone());
+        project.resolved.metadata.insert(
+            one_dependency.clone(),
+            ResolvePackageMetadata {
+                realm: Realm::Server,
+                origin_realm: Realm::Server,
+                source_registry: PackageSourceId::DefaultRegistry,
+            },
+        );
+        project
+            .resolved
+            .server_dependencies
+            .entry(project.root_package_id.clone())
+            .or_default()
+            .insert("OneDependency".to_owned(), one_dependency);
+
+        let project_file = project.dir.path().join("default.project.json");
+        fs_err::write(
+            &project_file,
+            r#"{
+                "name": "game",
+                "tree": {
+                    "$className": "DataModel",
+                    "ReplicatedStorage": {
+                        "Packages": { "$path": "./packages" }
+                    }
+                }
+            }"#,
+        )
+        .unwrap();
+
+        let diagnostics = project
+            .install(project.context().with_rojo_project(&project_file))
+            .unwrap();
+
+        let unmapped: Vec<_> = diagnostics
+            .iter()
+            .filter(|diagnostic| diagnostic.kind == DiagnosticKind::UnmappedDirectory)
+            .map(|diagnostic| diagnostic.path.clone().unwrap())
+            .collect();
+        assert_eq!(unmapped, vec![project.dir.path().join("ServerPackages")]);
+    }
+}
@@ -0,0 +1,90 @@
+//! Lets several installs share one pool of download threads.
+
+use std::io;
+use std::sync::Arc;
+
+use anyhow::Context;
+use tokio::runtime::{Builder, Runtime};
+
+/// A bounded pool that installs run their downloads on. Installs given the
+/// same scheduler share its limit, so installing many projects at once
+/// doesn't start a separate set of threads for each of them.
+#[derive(Clone)]
+pub struct DownloadScheduler {
+    runtime: Arc<Runtime>,
+    max_downloads: usize,
+}
+
+impl DownloadScheduler {
+    /// Create a scheduler that runs at most `max_downloads` downloads at once
+    /// across every install using it.
+    pub fn new(max_downloads: usize) -> anyhow::Result<Self> {
+        let max_downloads = max_downloads.max(1);
+
+        // Downloads run as blocking tasks, so the blocking pool's size is
+        // what bounds them.
+        let runtime = Builder::new_multi_thread()
+            .worker_threads(1)
+            .max_blocking_threads(max_downloads)
+            .enable_all()
+            .build();
+        let runtime = runtime_built(runtime, max_downloads)?;
+
+        Ok(Self {
+            runtime: Arc::new(runtime),
+            max_downloads,
+        })
+    }
+
+    pub fn max_downloads(&self) -> usize {
+        self.max_downloads
+    }
+
+    pub(crate) fn runtime(&self) -> &Runtime {
+        &self.runtime
+    }
+}
+
+/// Explains a failure to build a runtime with `threads` threads, which
+/// usually means the process isn't allowed to start that many, such as in a
+/// container with a low thread limit.
+pub(crate) fn runtime_built(
+    result: io::Result<Runtime>,
+    threads: usize,
+) -> anyhow::Result<Runtime> {
+    result.with_context(|| {
+        format!(
+            "Could not start {} threads to download packages with. \
+             Try reducing concurrency by installing with a DownloadScheduler \
+             that allows fewer downloads at once.",
+            threads
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runtime_failure_explained() {
+        let failure = io::Error::new(
+            io::ErrorKind::WouldBlock,
+            "Resource temporarily unavailable",
+        );
+        let err = runtime_built(Err(failure), 50).unwrap_err();
+
+        let message = format!("{:#}", err);
+        assert!(
+            message.starts_with("Could not start 50 threads"),
+            "{}",
+            message
+        );
+        assert!(message.contains("reducing concurrency"), "{}", message);
+        assert!(
+            message.ends_with("Resource temporarily unavailable"),
+            "{}",
+            message
+        );
+    }
+}
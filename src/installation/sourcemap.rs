@@ -0,0 +1,183 @@
+//! Describing an install as a fragment of a Rojo sourcemap.
+
+use std::{io::Write, path::Path};
+
+use crate::{manifest::Realm, package_id::PackageId, resolution::Resolve};
+
+use super::InstallationContext;
+
+impl InstallationContext {
+    /// Writes a Rojo sourcemap fragment describing where an install of
+    /// `resolved` puts each package, so tools can merge it into a sourcemap
+    /// instead of walking the package directories. It's a JSON array with a
+    /// `Folder` node for each package directory, holding its index and the
+    /// root package's links. Nodes for packages also carry the package's
+    /// `package` id and `realm`. Paths are relative to the project.
+    ///
+    /// This is worked out from the resolve alone, so it describes what an
+    /// install writes whether or not one has happened yet. Links that get
+    /// split into submodules are described as a single file.
+    pub fn write_sourcemap_fragment(
+        &self,
+        resolved: &Resolve,
+        root_package_id: &PackageId,
+        writer: impl Write,
+    ) -> anyhow::Result<()> {
+        let project_path = self.state_path.parent().unwrap();
+        let file_path = |path: &Path| {
+            let relative = path.strip_prefix(project_path).unwrap_or(path);
+            let components: Vec<_> = relative
+                .components()
+                .map(|component| component.as_os_str().to_string_lossy())
+                .collect();
+            components.join("/")
+        };
+
+        // Each package directory, with the nodes of the packages in its index
+        // and of the links in it.
+        let mut dirs: Vec<(&Path, Vec<serde_json::Value>, Vec<serde_json::Value>)> = self
+            .package_dirs()
+            .into_iter()
+            .map(|dir| (dir, Vec::new(), Vec::new()))
+            .collect();
+
+        for package_id in self.packages_to_install(resolved, root_package_id) {
+            let realm = resolved.metadata[&package_id].origin_realm;
+            let path = self
+                .index_dir(realm)
+                .join(self.package_dir_name(&package_id));
+
+            let dir = self.realm_dir(realm);
+            let (_, packages, _) = dirs.iter_mut().find(|(d, _, _)| *d == dir).unwrap();
+            packages.push(serde_json::json!({
+                "name": self.package_dir_name(&package_id),
+                "className": "Folder",
+                "filePaths": [file_path(&path)],
+                "package": package_id.to_string(),
+                "realm": realm,
+            }));
+        }
+
+        let extension = self.options.linking.link_extension.as_str();
+
+        for (realm, deps) in self.linked_aliases(resolved, root_package_id, root_package_id)? {
+            for (alias, dep_package_id) in deps {
+                let name = self.root_link_name(realm, alias);
+                let path = self
+                    .realm_dir(realm)
+                    .join(format!("{}.{}", name, extension));
+
+                let dir = self.realm_dir(realm);
+                let (_, _, links) = dirs.iter_mut().find(|(d, _, _)| *d == dir).unwrap();
+                links.push(serde_json::json!({
+                    "name": name,
+                    "className": "ModuleScript",
+                    "filePaths": [file_path(&path)],
+                    "package": dep_package_id.to_string(),
+                    "realm": resolved.metadata[dep_package_id].origin_realm,
+                }));
+            }
+        }
+
+        let fragment: Vec<_> = dirs
+            .into_iter()
+            .filter(|(_, packages, links)| !packages.is_empty() || !links.is_empty())
+            .map(|(dir, packages, links)| {
+                let index_dir = [Realm::Shared, Realm::Server, Realm::Dev]
+                    .iter()
+                    .find(|realm| self.realm_dir(**realm) == dir)
+                    .map(|realm| self.index_dir(*realm))
+                    .unwrap();
+                let index = serde_json::json!({
+                    "name": index_dir.file_name().unwrap().to_string_lossy(),
+                    "className": "Folder",
+                    "filePaths": [file_path(index_dir)],
+                    "children": packages,
+                });
+
+                serde_json::json!({
+                    "name": dir.file_name().unwrap().to_string_lossy(),
+                    "className": "Folder",
+                    "filePaths": [file_path(dir)],
+                    "children": Some(index).into_iter().chain(links).collect::<Vec<_>>(),
+                })
+            })
+            .collect();
+
+        serde_json::to_writer_pretty(writer, &fragment)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_package::PackageBuilder;
+
+    use super::super::test_support::{published_registry, TestProject};
+
+    #[test]
+    fn sourcemap_fragment_lists_packages() {
+        let registry = published_registry();
+        registry.publish(PackageBuilder::new("biff/dev-only@0.1.0").with_file("init.luau", ""));
+
+        let root = PackageBuilder::new("biff/root@0.1.0")
+            .with_dep("OneDependency", "biff/one-dependency@0.1.0");
+        let mut project = TestProject::new(&registry, root);
+        project.add_dev_dependency("DevOnly", "biff/dev-only@0.1.0");
+
+        let mut fragment = Vec::new();
+        project
+            .context()
+            .write_sourcemap_fragment(&project.resolved, &project.root_package_id, &mut fragment)
+            .unwrap();
+        let fragment: serde_json::Value = serde_json::from_slice(&fragment).unwrap();
+
+        let mut packages = Vec::new();
+        let mut links = Vec::new();
+
+        for dir in fragment.as_array().unwrap() {
+            for child in dir["children"].as_array().unwrap() {
+                if child["name"] == "_index" {
+                    for package in child["children"].as_array().unwrap() {
+                        packages.push((
+                            package["package"].as_str().unwrap().to_owned(),
+                            package["realm"].as_str().unwrap().to_owned(),
+                            package["filePaths"][0].as_str().unwrap().to_owned(),
+                        ));
+                    }
+                } else {
+                    links.push(child["filePaths"][0].as_str().unwrap().to_owned());
+                }
+            }
+        }
+
+        let expected =
+            |id: &str, realm: &str, path: &str| (id.to_owned(), realm.to_owned(), path.to_owned());
+        assert_eq!(
+            packages,
+            vec![
+                expected(
+                    "biff/minimal@0.1.0",
+                    "shared",
+                    "packages/_index/biff_minimal@0.1.0"
+                ),
+                expected(
+                    "biff/one-dependency@0.1.0",
+                    "shared",
+                    "packages/_index/biff_one-dependency@0.1.0"
+                ),
+                expected(
+                    "biff/dev-only@0.1.0",
+                    "dev",
+                    "DevPackages/_index/biff_dev-only@0.1.0"
+                ),
+            ]
+        );
+        assert_eq!(
+            links,
+            vec!["packages/OneDependency.lua", "DevPackages/DevOnly.lua"]
+        );
+    }
+}
@@ -0,0 +1,172 @@
+//! Fixtures shared by the installation tests.
+
+use std::{
+    io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use tempfile::TempDir;
+
+use crate::{
+    manifest::Realm,
+    package_id::PackageId,
+    package_source::{InMemoryRegistry, PackageSourceId, PackageSourceMap},
+    resolution::{resolve, Resolve, ResolvePackageMetadata},
+    test_package::PackageBuilder,
+};
+
+use super::{
+    DelegatingFilesystem, Diagnostic, Filesystem, InstallationContext, ProgressEvent,
+    ProgressReporter, RealFilesystem,
+};
+
+/// A `ProgressReporter` that remembers every event it receives.
+#[derive(Default)]
+pub(super) struct RecordingReporter {
+    pub(super) events: Mutex<Vec<ProgressEvent>>,
+}
+
+impl ProgressReporter for RecordingReporter {
+    fn report(&self, event: ProgressEvent) {
+        self.events.lock().unwrap().push(event);
+    }
+}
+
+pub(super) struct TestProject {
+    pub(super) dir: TempDir,
+    pub(super) sources: PackageSourceMap,
+    pub(super) root_package_id: PackageId,
+    pub(super) resolved: Resolve,
+}
+
+impl TestProject {
+    pub(super) fn new(registry: &InMemoryRegistry, root: PackageBuilder) -> Self {
+        let sources = PackageSourceMap::new(Box::new(registry.source()));
+        let manifest = root.into_manifest();
+        let resolved = resolve(&manifest, &Default::default(), &sources).unwrap();
+
+        Self {
+            dir: tempfile::tempdir().unwrap(),
+            sources,
+            root_package_id: manifest.package_id(),
+            resolved,
+        }
+    }
+
+    /// Adds a dev dependency of the root package to the resolve, since
+    /// resolution never produces them in this fork.
+    pub(super) fn add_dev_dependency(&mut self, alias: &str, package_id: &str) {
+        self.add_root_dependency(Realm::Dev, alias, package_id);
+    }
+
+    /// Adds a server dependency of the root package to the resolve, for
+    /// the same reason.
+    pub(super) fn add_server_dependency(&mut self, alias: &str, package_id: &str) {
+        self.add_root_dependency(Realm::Server, alias, package_id);
+    }
+
+    pub(super) fn add_root_dependency(
+        &mut self,
+        origin_realm: Realm,
+        alias: &str,
+        package_id: &str,
+    ) {
+        let package_id: PackageId = package_id.parse().unwrap();
+        let graph = match origin_realm {
+            Realm::Shared => &mut self.resolved.shared_dependencies,
+            Realm::Server => &mut self.resolved.server_dependencies,
+            Realm::Dev => &mut self.resolved.dev_dependencies,
+        };
+
+        graph
+            .entry(self.root_package_id.clone())
+            .or_default()
+            .insert(alias.to_owned(), package_id.clone());
+        self.resolved.activated.insert(package_id.clone());
+        self.resolved.metadata.insert(
+            package_id,
+            ResolvePackageMetadata {
+                realm: Realm::Shared,
+                origin_realm,
+                source_registry: PackageSourceId::DefaultRegistry,
+            },
+        );
+    }
+
+    pub(super) fn context(&self) -> InstallationContext {
+        InstallationContext::new(self.dir.path())
+    }
+
+    pub(super) fn install(&self, context: InstallationContext) -> anyhow::Result<Vec<Diagnostic>> {
+        context.install(
+            self.sources.clone(),
+            self.root_package_id.clone(),
+            self.resolved.clone(),
+        )
+    }
+}
+
+pub(super) fn published_registry() -> InMemoryRegistry {
+    let registry = InMemoryRegistry::new();
+    registry.publish(PackageBuilder::new("biff/minimal@0.1.0").with_file("init.luau", ""));
+    registry.publish(
+        PackageBuilder::new("biff/one-dependency@0.1.0")
+            .with_dep("Minimal", "biff/minimal@0.1.0")
+            .with_file("src/init.luau", ""),
+    );
+    registry
+}
+
+/// A project whose root depends on `biff/one-dependency`, which depends on
+/// `biff/minimal`, along with the registry it resolves against.
+pub(super) fn one_dependency_project() -> (InMemoryRegistry, TestProject) {
+    let registry = published_registry();
+    let root = PackageBuilder::new("biff/root@0.1.0")
+        .with_dep("OneDependency", "biff/one-dependency@0.1.0");
+    let project = TestProject::new(&registry, root);
+    (registry, project)
+}
+
+/// A project whose root depends only on `biff/minimal`, along with the
+/// registry it resolves against.
+pub(super) fn minimal_project() -> (InMemoryRegistry, TestProject) {
+    let registry = published_registry();
+    let root = PackageBuilder::new("biff/root@0.1.0").with_dep("Minimal", "biff/minimal@0.1.0");
+    let project = TestProject::new(&registry, root);
+    (registry, project)
+}
+
+/// Delegates to the real filesystem, recording every path written to and
+/// the name of the thread that wrote it.
+#[derive(Default)]
+pub(super) struct WriteRecordingFilesystem {
+    pub(super) written: Mutex<Vec<PathBuf>>,
+    pub(super) writers: Mutex<Vec<Option<String>>>,
+}
+
+impl WriteRecordingFilesystem {
+    fn record(&self, path: &Path) {
+        self.written.lock().unwrap().push(path.to_path_buf());
+        self.writers
+            .lock()
+            .unwrap()
+            .push(std::thread::current().name().map(str::to_owned));
+    }
+}
+
+impl DelegatingFilesystem for WriteRecordingFilesystem {
+    fn inner(&self) -> &dyn Filesystem {
+        &RealFilesystem
+    }
+
+    fn write(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        self.record(path);
+        RealFilesystem.write(path, contents)
+    }
+
+    fn create_new(&self, path: &Path, contents: &[u8]) -> io::Result<()> {
+        self.record(path);
+        RealFilesystem.create_new(path, contents)
+    }
+}
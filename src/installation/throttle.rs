@@ -0,0 +1,44 @@
+//! Limiting how many downloads run against a single package source.
+
+use std::sync::{Condvar, Mutex};
+
+/// A counting semaphore that blocks callers once `limit` permits are out.
+pub(crate) struct Throttle {
+    limit: usize,
+    in_flight: Mutex<usize>,
+    released: Condvar,
+}
+
+impl Throttle {
+    pub(crate) fn new(limit: usize) -> Self {
+        Self {
+            limit: limit.max(1),
+            in_flight: Mutex::new(0),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Wait until fewer than `limit` permits are out, then take one. It's
+    /// given back when the returned permit is dropped.
+    pub(crate) fn acquire(&self) -> ThrottlePermit<'_> {
+        let mut in_flight = self.in_flight.lock().unwrap();
+
+        while *in_flight >= self.limit {
+            in_flight = self.released.wait(in_flight).unwrap();
+        }
+
+        *in_flight += 1;
+        ThrottlePermit { throttle: self }
+    }
+}
+
+pub(crate) struct ThrottlePermit<'a> {
+    throttle: &'a Throttle,
+}
+
+impl Drop for ThrottlePermit<'_> {
+    fn drop(&mut self) {
+        *self.throttle.in_flight.lock().unwrap() -= 1;
+        self.throttle.released.notify_one();
+    }
+}
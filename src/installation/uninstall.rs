@@ -0,0 +1,224 @@
+//! Removing a package from an install without reinstalling anything.
+
+use std::io;
+
+use anyhow::bail;
+
+use crate::{manifest::Realm, package_name::PackageName};
+
+use super::{
+    checksum_path,
+    links::{is_link, names_dir},
+    Diagnostic, DiagnosticKind, InstallationContext, InstalledPackage,
+};
+
+impl InstallationContext {
+    /// Remove every installed version of the package `name` from `realm`'s
+    /// index, along with the links that require it, without reinstalling
+    /// anything. Links are found by the index directory they require. Other
+    /// packages losing their link to it get a warning, since requiring it
+    /// from them will now fail. The aggregated root module only loses the
+    /// entries for it.
+    ///
+    /// Projects in a workspace share its indexes, and only this project's
+    /// links would be checked, so uninstalling from them is refused.
+    pub fn uninstall(&self, name: &PackageName, realm: Realm) -> anyhow::Result<()> {
+        if self.options.workspace {
+            bail!(
+                "Cannot uninstall {}, because this project shares its index with the rest of its workspace",
+                name
+            );
+        }
+
+        let _lock = self.lock()?;
+
+        let index_dir = self.index_dir(realm);
+        let (removed, others): (Vec<_>, Vec<_>) =
+            self.list_installed()?.into_iter().partition(|package| {
+                package.id.name() == name && package.path.parent() == Some(index_dir)
+            });
+
+        if removed.is_empty() {
+            bail!("{} is not installed in {}", name, index_dir.display());
+        }
+
+        let dir_names: Vec<_> = removed
+            .iter()
+            .map(|package| package.path.file_name().unwrap().to_string_lossy())
+            .collect();
+        let requires_removed = |contents: &str| {
+            dir_names
+                .iter()
+                .any(|dir_name| names_dir(contents, dir_name))
+        };
+
+        // Every link file, with the directory it was found in and the package
+        // it belongs to, if it isn't the root package's.
+        let mut links = Vec::new();
+        for dir in self.package_dirs() {
+            for link in self.link_files(dir)? {
+                links.push((link, dir.to_path_buf(), None));
+            }
+        }
+        for package in &others {
+            let links_dir = package.path.join(&self.options.linking.package_links_dir);
+            for link in self.link_files(&links_dir)? {
+                links.push((link, links_dir.clone(), Some(package)));
+            }
+        }
+
+        // Everything is worked out before anything is removed, so strict
+        // installs can refuse to leave packages dangling.
+        let mut to_remove = Vec::new();
+        let mut to_rewrite = Vec::new();
+        let mut dependents: Vec<&InstalledPackage> = Vec::new();
+
+        for (link, base_dir, dependent) in links {
+            let contents = String::from_utf8_lossy(&self.filesystem.read(&link)?).into_owned();
+            if !is_link(contents.as_bytes()) || !requires_removed(&contents) {
+                continue;
+            }
+
+            match contents.strip_prefix("return {\n") {
+                Some(entries) => {
+                    let kept: String = entries
+                        .lines()
+                        .filter(|entry| !requires_removed(entry))
+                        .map(|entry| format!("{}\n", entry))
+                        .collect();
+                    to_rewrite.push((link, format!("return {{\n{}", kept)));
+                }
+                None => to_remove.push((link, base_dir)),
+            }
+
+            if let Some(dependent) = dependent {
+                if !dependents.iter().any(|known| known.path == dependent.path) {
+                    dependents.push(dependent);
+                }
+            }
+        }
+
+        for dependent in dependents {
+            let message = format!(
+                "{} depends on {}, which was uninstalled, so its link to it was removed",
+                dependent.id, name
+            );
+            self.warn(
+                Diagnostic::new(DiagnosticKind::DanglingDependency, message)
+                    .with_package(&dependent.id)
+                    .with_path(&dependent.path),
+            )?;
+        }
+
+        // The tree no longer matches the last install.
+        if let Err(err) = self.filesystem.remove_file(&self.state_path) {
+            if err.kind() != io::ErrorKind::NotFound {
+                return Err(err.into());
+            }
+        }
+
+        for package in &removed {
+            log::debug!("Removing {}", package.path.display());
+            self.filesystem.remove_dir_all(&package.path)?;
+
+            let checksum_path = checksum_path(&package.path);
+            if self.filesystem.exists(&checksum_path) {
+                self.filesystem.remove_file(&checksum_path)?;
+            }
+        }
+
+        for (link, base_dir) in &to_remove {
+            log::trace!("Removing {}", link.display());
+            self.filesystem.remove_file(link)?;
+
+            // Submodule links have a directory to themselves.
+            let parent = link.parent().unwrap();
+            if parent != base_dir && self.filesystem.read_dir(parent)?.is_empty() {
+                self.filesystem.remove_dir_all(parent)?;
+            }
+        }
+
+        for (link, contents) in &to_rewrite {
+            log::trace!("Rewriting {}", link.display());
+            self.filesystem.write(link, contents.as_bytes())?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::test_package::PackageBuilder;
+
+    use super::super::test_support::{minimal_project, published_registry, TestProject};
+
+    #[test]
+    fn uninstall_removes_package_and_links() {
+        let registry = published_registry();
+        registry.publish(PackageBuilder::new("biff/other@0.1.0").with_file("init.luau", ""));
+
+        let root = PackageBuilder::new("biff/root@0.1.0")
+            .with_dep("Minimal", "biff/minimal@0.1.0")
+            .with_dep("OneDependency", "biff/one-dependency@0.1.0")
+            .with_dep("Other", "biff/other@0.1.0");
+        let project = TestProject::new(&registry, root);
+        project.install(project.context()).unwrap();
+
+        let packages = project.dir.path().join("packages");
+        let minimal_name = PackageName::new("biff", "minimal").unwrap();
+
+        // One-dependency would be left requiring a package that's gone.
+        project
+            .context()
+            .with_strict(true)
+            .uninstall(&minimal_name, Realm::Shared)
+            .unwrap_err();
+        assert!(packages.join("_index/biff_minimal@0.1.0").is_dir());
+
+        project
+            .context()
+            .uninstall(&minimal_name, Realm::Shared)
+            .unwrap();
+
+        assert!(!packages.join("_index/biff_minimal@0.1.0").exists());
+        assert!(!packages.join("Minimal.lua").exists());
+        assert!(!packages
+            .join("_index/biff_one-dependency@0.1.0/packages/Minimal.lua")
+            .exists());
+
+        assert!(packages.join("OneDependency.lua").is_file());
+        assert!(packages.join("Other.lua").is_file());
+        assert!(packages
+            .join("_index/biff_one-dependency@0.1.0/src/init.luau")
+            .is_file());
+        assert!(packages.join("_index/biff_other@0.1.0/init.luau").is_file());
+
+        // It's gone now.
+        project
+            .context()
+            .uninstall(&minimal_name, Realm::Shared)
+            .unwrap_err();
+    }
+
+    #[test]
+    fn uninstall_refused_in_workspace() {
+        let (_, project) = minimal_project();
+
+        let workspace = tempfile::tempdir().unwrap();
+        let context = || project.context().with_workspace_root(workspace.path());
+        project.install(context()).unwrap();
+
+        let minimal_name = PackageName::new("biff", "minimal").unwrap();
+        let err = context()
+            .uninstall(&minimal_name, Realm::Shared)
+            .unwrap_err();
+        assert!(err.to_string().contains("workspace"), "{}", err);
+        assert!(workspace
+            .path()
+            .join("packages/_index/biff_minimal@0.1.0")
+            .is_dir());
+    }
+}
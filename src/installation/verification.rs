@@ -0,0 +1,202 @@
+//! Checking that the link files on disk still lead to modules.
+
+use std::path::{Path, PathBuf};
+
+use crate::manifest::Realm;
+
+use super::{links::instance_names, InstallationContext, LinkExtension, RequirePathStyle};
+
+/// A link file whose require path doesn't lead to a module.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BrokenLink {
+    /// The link file itself.
+    pub link: PathBuf,
+
+    /// Where its require path leads, relative to the directory the link is
+    /// in.
+    pub target: PathBuf,
+}
+
+impl InstallationContext {
+    /// Finds the link files in this project's package directories whose
+    /// require path doesn't lead to a module on disk: a `.luau` or `.lua`
+    /// file, or a directory with an `init.luau` or `init.lua`. This catches
+    /// links written with the wrong entry suffix, or to packages whose files
+    /// went missing.
+    ///
+    /// Links requiring Rojo instances are followed back to the directories
+    /// those instances are synced from, and count as broken when they
+    /// require an instance under none of them.
+    ///
+    /// Paths produced by a require transform may not be followable on disk,
+    /// so don't rely on this when one is set.
+    pub fn verify_links(&self) -> anyhow::Result<Vec<BrokenLink>> {
+        let mut broken_links = Vec::new();
+        let link_prefix = format!("return {}(", self.options.linking.require_function);
+        let is_file = |path: &Path| self.filesystem.exists(path) && !self.filesystem.is_dir(path);
+
+        for dir in self.package_dirs() {
+            if !self.filesystem.is_dir(dir) {
+                continue;
+            }
+
+            for entry in self.filesystem.walk_dir(dir)? {
+                let path = &entry.path;
+
+                let is_link_extension = [LinkExtension::Lua, LinkExtension::Luau]
+                    .iter()
+                    .any(|extension| path.extension() == Some(extension.as_str().as_ref()));
+
+                if !entry.is_file || !is_link_extension {
+                    continue;
+                }
+
+                // Packages can contain their own `.lua` files, so only look at
+                // files shaped exactly like the links we write.
+                let contents = self.filesystem.read(path)?;
+                let require_call = std::str::from_utf8(&contents)
+                    .ok()
+                    .and_then(|contents| contents.strip_prefix(&link_prefix))
+                    .and_then(|contents| contents.strip_suffix(")\n"));
+
+                let require_path = match (&self.options.linking.require_path_style, require_call) {
+                    (RequirePathStyle::Relative, Some(require_call)) => require_call
+                        .strip_prefix('"')
+                        .and_then(|require_call| require_call.strip_suffix('"')),
+                    (RequirePathStyle::AbsoluteRojo { .. }, require_call) => require_call,
+                    _ => None,
+                };
+
+                let require_path = match require_path {
+                    Some(require_path) => require_path,
+                    None => continue,
+                };
+
+                let resolves = match self.link_target(path, require_path) {
+                    Some(target) => {
+                        let with_extension = |extension: &str| {
+                            let mut target = target.clone().into_os_string();
+                            target.push(extension);
+                            PathBuf::from(target)
+                        };
+
+                        is_file(&target)
+                            || is_file(&target.join("init.luau"))
+                            || is_file(&target.join("init.lua"))
+                            || is_file(&with_extension(".luau"))
+                            || is_file(&with_extension(".lua"))
+                    }
+                    None => false,
+                };
+
+                if !resolves {
+                    broken_links.push(BrokenLink {
+                        link: path.to_path_buf(),
+                        target: PathBuf::from(require_path),
+                    });
+                }
+            }
+        }
+
+        Ok(broken_links)
+    }
+
+    /// Where the require path of the link file at `link` leads on disk,
+    /// without the extension of the module it names. Rojo instance paths
+    /// lead into the directory of the realm they start from, or the link's
+    /// own directory when they start from `script`.
+    fn link_target(&self, link: &Path, require_path: &str) -> Option<PathBuf> {
+        let link_dir = link.parent().unwrap();
+
+        let (shared, server, dev) = match &self.options.linking.require_path_style {
+            RequirePathStyle::Relative => return Some(link_dir.join(require_path)),
+            RequirePathStyle::AbsoluteRojo {
+                shared,
+                server,
+                dev,
+            } => (shared, server, dev),
+        };
+
+        let bases = [
+            ("script", link_dir),
+            (shared.as_str(), self.realm_dir(Realm::Shared)),
+            (server.as_str(), self.realm_dir(Realm::Server)),
+            (dev.as_str(), self.realm_dir(Realm::Dev)),
+        ];
+
+        bases.iter().find_map(|(base, dir)| {
+            let names = instance_names(require_path.strip_prefix(base)?)?;
+            Some(
+                names
+                    .iter()
+                    .fold(dir.to_path_buf(), |path, name| path.join(name)),
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use super::super::test_support::one_dependency_project;
+
+    #[test]
+    fn verify_links_finds_missing_entry_points() {
+        let (_, project) = one_dependency_project();
+        project.install(project.context()).unwrap();
+
+        assert_eq!(
+            project.context().verify_links().unwrap(),
+            Vec::<BrokenLink>::new()
+        );
+
+        let packages = project.dir.path().join("packages");
+        fs_err::remove_file(packages.join("_index/biff_minimal@0.1.0/init.luau")).unwrap();
+
+        assert_eq!(
+            project.context().verify_links().unwrap(),
+            vec![BrokenLink {
+                link: packages.join("_index/biff_one-dependency@0.1.0/packages/Minimal.lua"),
+                target: PathBuf::from("../../biff_minimal@0.1.0"),
+            }]
+        );
+
+        // Directories with an `init.lua` can be required too.
+        fs_err::write(packages.join("_index/biff_minimal@0.1.0/init.lua"), "").unwrap();
+        assert_eq!(
+            project.context().verify_links().unwrap(),
+            Vec::<BrokenLink>::new()
+        );
+    }
+
+    #[test]
+    fn verify_links_follows_rojo_instances() {
+        let (_, project) = one_dependency_project();
+
+        let context = || {
+            project
+                .context()
+                .with_require_path_style(RequirePathStyle::AbsoluteRojo {
+                    shared: "game.ReplicatedStorage.Packages".to_owned(),
+                    server: "game.ServerScriptService.ServerPackages".to_owned(),
+                    dev: "game.ReplicatedStorage.DevPackages".to_owned(),
+                })
+        };
+        project.install(context()).unwrap();
+        assert_eq!(context().verify_links().unwrap(), Vec::<BrokenLink>::new());
+
+        let packages = project.dir.path().join("packages");
+        fs_err::remove_file(packages.join("_index/biff_minimal@0.1.0/init.luau")).unwrap();
+
+        assert_eq!(
+            context().verify_links().unwrap(),
+            vec![BrokenLink {
+                link: packages.join("_index/biff_one-dependency@0.1.0/packages/Minimal.lua"),
+                target: PathBuf::from(
+                    "game.ReplicatedStorage.Packages._index[\"biff_minimal@0.1.0\"]"
+                ),
+            }]
+        );
+    }
+}
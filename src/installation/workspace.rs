@@ -0,0 +1,122 @@
+//! Sharing one set of indexes between the projects in a workspace.
+
+use std::path::Path;
+
+use crate::manifest::Realm;
+
+use super::InstallationContext;
+
+impl InstallationContext {
+    /// Unpack packages into the indexes of the workspace at `workspace_root`
+    /// instead of this project's own, so that every project in the workspace
+    /// shares one copy of each package. Links in this project are written
+    /// relative to the workspace's indexes, and packages another project
+    /// already put there aren't downloaded again.
+    ///
+    /// The workspace root must be given relative to the same place as this
+    /// project's path. Installs into the workspace lock it as a whole.
+    pub fn with_workspace_root(mut self, workspace_root: &Path) -> Self {
+        self.shared_index_dir = workspace_root
+            .join("packages")
+            .join(self.index_name(Realm::Shared));
+        self.server_index_dir = workspace_root
+            .join("ServerPackages")
+            .join(self.index_name(Realm::Server));
+        self.dev_index_dir = workspace_root
+            .join("DevPackages")
+            .join(self.index_name(Realm::Dev));
+        self.lock_path = workspace_root.join(".wally-lock");
+        self.options.workspace = true;
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::Arc;
+
+    use crate::{
+        package_source::PackageSourceMap, resolution::resolve, test_package::PackageBuilder,
+    };
+
+    use super::super::{
+        test_support::{minimal_project, published_registry, RecordingReporter},
+        BrokenLink, MemoryFilesystem, ProgressEvent,
+    };
+
+    #[test]
+    fn workspace_shares_index() {
+        let registry = published_registry();
+        let sources = PackageSourceMap::new(Box::new(registry.source()));
+        let manifest = PackageBuilder::new("biff/root@0.1.0")
+            .with_dep("OneDependency", "biff/one-dependency@0.1.0")
+            .into_manifest();
+        let resolved = resolve(&manifest, &Default::default(), &sources).unwrap();
+
+        let workspace = tempfile::tempdir().unwrap();
+        let mut downloads = 0;
+
+        for project in ["a", "b"].iter() {
+            let project_path = workspace.path().join(project);
+            fs_err::create_dir(&project_path).unwrap();
+
+            let reporter = Arc::new(RecordingReporter::default());
+            let context = InstallationContext::new(&project_path)
+                .with_workspace_root(workspace.path())
+                .with_reporter(reporter.clone());
+            context
+                .clone()
+                .install(sources.clone(), manifest.package_id(), resolved.clone())
+                .unwrap();
+
+            downloads += reporter
+                .events
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|event| matches!(event, ProgressEvent::Downloaded(_)))
+                .count();
+
+            let link =
+                fs_err::read_to_string(project_path.join("packages/OneDependency.lua")).unwrap();
+            assert!(
+                link.starts_with(
+                    "return require(\"../../packages/_index/biff_one-dependency@0.1.0"
+                ),
+                "{}",
+                link
+            );
+            assert!(!project_path.join("packages/_index").exists());
+            assert_eq!(context.verify_links().unwrap(), Vec::<BrokenLink>::new());
+        }
+
+        assert_eq!(downloads, 2);
+
+        let index = workspace.path().join("packages/_index");
+        assert!(index.join("biff_minimal@0.1.0/init.luau").is_file());
+        assert!(index
+            .join("biff_one-dependency@0.1.0/packages/Minimal.lua")
+            .is_file());
+    }
+
+    #[test]
+    fn workspace_links_from_dot_prefixed_project() {
+        let (_, project) = minimal_project();
+
+        // The leading `./` isn't a directory that links have to climb out of.
+        let filesystem = Arc::new(MemoryFilesystem::new());
+        let context = InstallationContext::new(Path::new("./workspace/a"))
+            .with_workspace_root(Path::new("workspace"))
+            .with_filesystem(filesystem.clone());
+        project.install(context).unwrap();
+
+        let files = filesystem.files();
+        let link = &files[Path::new("./workspace/a/packages/Minimal.lua")];
+        assert_eq!(
+            String::from_utf8_lossy(link),
+            "return require(\"../../packages/_index/biff_minimal@0.1.0\")\n"
+        );
+    }
+}
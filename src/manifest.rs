@@ -1,9 +1,13 @@
 use std::collections::BTreeMap;
+use std::convert::TryFrom;
+use std::fmt;
 use std::path::Path;
+use std::str::FromStr;
 
 use anyhow::Context;
 use semver::Version;
 use serde::{Deserialize, Serialize};
+use url::Url;
 
 use crate::package_id::PackageId;
 use crate::package_name::PackageName;
@@ -28,6 +32,28 @@ pub struct Manifest {
 
     #[serde(default)]
     pub dev_dependencies: BTreeMap<String, PackageReq>,
+
+    /// Packages that this package expects its users to depend on themselves.
+    /// They aren't resolved or linked for this package, but installs warn
+    /// when they're missing.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub peer_dependencies: BTreeMap<String, PackageReq>,
+
+    /// Optional features of the package, each naming the aliases of the
+    /// dependencies it enables. Dependencies named by any feature are
+    /// optional, and are only installed when one of their features is.
+    ///
+    /// Example: `promise = ["Promise"]`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub features: BTreeMap<String, Vec<String>>,
+
+    /// Packages to build straight from a Git repository at a pinned revision
+    /// instead of fetching them from a registry, keyed by package name. The
+    /// packages still need to be depended on like any other.
+    ///
+    /// Example: `"biff/git-package" = { git = "https://github.com/biff/git-package", rev = "v0.1.0" }`
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub git_sources: BTreeMap<PackageName, GitSource>,
 }
 
 impl Manifest {
@@ -126,6 +152,86 @@ pub struct Package {
     /// Example: true
     #[serde(default)]
     pub private: bool,
+
+    /// Submodules next to the package's entry point that consumers can
+    /// require directly, when submodule links are enabled during install.
+    ///
+    /// Example: ["Signal", "Promise"]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub modules: Vec<String>,
+
+    /// A directory holding a subdirectory of files for each platform the
+    /// package supports, named like Rust's `std::env::consts::OS`. Only the
+    /// subdirectory for the platform wally is running on gets installed.
+    ///
+    /// Example: `platform`, with `platform/windows` and `platform/macos`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub platform_dir: Option<String>,
+
+    /// The oldest Luau release the package's code can be parsed and run by,
+    /// for packages that use newer syntax or library functions.
+    ///
+    /// Example: `0.640`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub luau: Option<LuauVersion>,
+}
+
+/// A release of the Luau language, written the way Luau numbers them.
+///
+/// Example: `0.640`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct LuauVersion(pub u32);
+
+impl FromStr for LuauVersion {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        let release = value
+            .strip_prefix("0.")
+            .and_then(|release| release.parse().ok())
+            .with_context(|| {
+                format!("invalid Luau version {:?}, expected one like 0.640", value)
+            })?;
+
+        Ok(LuauVersion(release))
+    }
+}
+
+impl TryFrom<String> for LuauVersion {
+    type Error = anyhow::Error;
+
+    fn try_from(value: String) -> anyhow::Result<Self> {
+        value.parse()
+    }
+}
+
+impl From<LuauVersion> for String {
+    fn from(version: LuauVersion) -> Self {
+        version.to_string()
+    }
+}
+
+impl fmt::Display for LuauVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "0.{}", self.0)
+    }
+}
+
+/// Where to find a package that is built from a Git repository.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct GitSource {
+    /// The URL of the repository, which has the package's `wally.toml` at
+    /// its root.
+    ///
+    /// Example: `https://github.com/biff/git-package`
+    pub git: Url,
+
+    /// The revision to build the package from, which can be anything
+    /// `git rev-parse` understands.
+    ///
+    /// Example: `v0.1.0`, `main`, `4f1c2a9`
+    pub rev: String,
 }
 
 // Metadata we require when this manifest will be used to generate package folders
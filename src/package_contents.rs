@@ -1,13 +1,19 @@
-use std::io::{self, BufRead, BufReader, Cursor};
-use std::path::{Path, PathBuf};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Cursor, Read, Seek};
+use std::ops::Bound;
+use std::path::{Component, Path, PathBuf};
 
-use anyhow::format_err;
+use anyhow::{bail, format_err};
 use fs_err::File;
 use globset::{Glob, GlobSet, GlobSetBuilder};
 use serde_json::json;
 use walkdir::WalkDir;
-use zip::{write::FileOptions, ZipArchive, ZipWriter};
+use zip::{
+    read::read_zipfile_from_stream, result::ZipError, write::FileOptions, ZipArchive, ZipWriter,
+};
 
+use crate::installation::{Filesystem, RealFilesystem};
 use crate::manifest::Manifest;
 
 static EXCLUDED_GLOBS: &[&str] = &[
@@ -18,6 +24,22 @@ static EXCLUDED_GLOBS: &[&str] = &[
     "DevPackages",
 ];
 
+/// The error `unpack_stream` fails with when an archive can only be read
+/// whole, such as when its entries only have their sizes in data descriptors
+/// after their contents. `unpack_into` can still unpack these.
+#[derive(Debug)]
+pub struct NotStreamable {
+    reason: &'static str,
+}
+
+impl fmt::Display for NotStreamable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Archive can't be unpacked as it's read: {}", self.reason)
+    }
+}
+
+impl std::error::Error for NotStreamable {}
+
 /// Container for the contents of a package that have been downloaded.
 #[derive(Clone)]
 pub struct PackageContents {
@@ -87,10 +109,271 @@ impl PackageContents {
     }
 
     /// Unpack the package into the given path on the filesystem.
-    pub fn unpack_into_path(&self, output: &Path) -> anyhow::Result<()> {
+    ///
+    /// If given, `progress` is called after each file is written with the
+    /// number of files written so far and the total number of files. Returns
+    /// the number of files written.
+    pub fn unpack_into_path(
+        &self,
+        output: &Path,
+        progress: Option<&dyn Fn(u64, u64)>,
+    ) -> anyhow::Result<u64> {
+        self.unpack_into(output, &RealFilesystem, progress)
+    }
+
+    /// Unpack the package into the given path of a `Filesystem`, reporting
+    /// progress like `unpack_into_path`.
+    ///
+    /// Entries are extracted in path order rather than archive order, so every
+    /// directory is created before anything inside of it is written. Files for
+    /// platforms other than the current one, as declared by the manifest's
+    /// `platform_dir`, are left out.
+    pub fn unpack_into(
+        &self,
+        output: &Path,
+        filesystem: &dyn Filesystem,
+        progress: Option<&dyn Fn(u64, u64)>,
+    ) -> anyhow::Result<u64> {
+        self.unpack_entries(output, filesystem, progress, false)
+    }
+
+    /// Like `unpack_into`, but for an `output` that already holds another
+    /// version of the package. Only files whose contents differ are written,
+    /// and anything on disk that isn't in the archive is removed. Returns the
+    /// number of files in the package, whether they were written or not.
+    pub fn update_into(
+        &self,
+        output: &Path,
+        filesystem: &dyn Filesystem,
+        progress: Option<&dyn Fn(u64, u64)>,
+    ) -> anyhow::Result<u64> {
+        self.unpack_entries(output, filesystem, progress, true)
+    }
+
+    fn unpack_entries(
+        &self,
+        output: &Path,
+        filesystem: &dyn Filesystem,
+        progress: Option<&dyn Fn(u64, u64)>,
+        only_changed: bool,
+    ) -> anyhow::Result<u64> {
         let mut archive = ZipArchive::new(Cursor::new(self.data.as_slice()))?;
-        archive.extract(output)?;
-        Ok(())
+
+        // Maps each entry's relative path to whether it's a directory and its
+        // index in the archive. Paths sort component-wise, so parents always
+        // come before their children.
+        let mut entries: BTreeMap<PathBuf, (bool, usize)> = BTreeMap::new();
+
+        for index in 0..archive.len() {
+            let file = archive.by_index(index)?;
+            let relative_path = entry_path(file.name())?;
+            let is_dir = file.is_dir();
+
+            if let Some((existing_is_dir, _)) = entries.insert(relative_path, (is_dir, index)) {
+                if existing_is_dir != is_dir {
+                    bail!(
+                        "Archive contains both a file and a directory named {}",
+                        file.name()
+                    );
+                }
+            }
+        }
+
+        for path in entries.keys() {
+            for ancestor in path.ancestors().skip(1) {
+                if let Some((false, _)) = entries.get(ancestor) {
+                    bail!(
+                        "Archive entry {} is nested inside {}, which is a file",
+                        path.display(),
+                        ancestor.display()
+                    );
+                }
+            }
+        }
+
+        if let Some(platform_dir) = self.platform_dir()? {
+            entries.retain(|path, _| other_platform_root(path, &platform_dir).is_none());
+        }
+
+        if only_changed {
+            remove_unlisted(output, &entries, filesystem)?;
+        }
+
+        let total_files = entries.values().filter(|(is_dir, _)| !is_dir).count() as u64;
+        let mut written_files = 0;
+
+        for (relative_path, (is_dir, index)) in entries {
+            let path = output.join(relative_path);
+
+            if is_dir {
+                filesystem.create_dir_all(&path)?;
+                continue;
+            }
+
+            if let Some(parent) = path.parent() {
+                filesystem.create_dir_all(parent)?;
+            }
+
+            let mut file = archive.by_index(index)?;
+            let mut data = Vec::with_capacity(file.size() as usize);
+            io::copy(&mut file, &mut data)?;
+
+            let unchanged = only_changed
+                && filesystem
+                    .read(&path)
+                    .map_or(false, |existing| existing == data);
+
+            if unchanged {
+                log::trace!("Keeping {}, which is unchanged", path.display());
+            } else {
+                log::trace!("Unpacking {} ({} bytes)", path.display(), data.len());
+                filesystem.write(&path, &data)?;
+            }
+
+            written_files += 1;
+            if let Some(progress) = progress {
+                progress(written_files, total_files);
+            }
+        }
+
+        Ok(written_files)
+    }
+
+    /// Unpack a package archive into the given path of a `Filesystem` as it's
+    /// read from `reader`, without ever holding the whole archive in memory.
+    /// Only one file is held at a time.
+    ///
+    /// Unlike `unpack_into`, entries are extracted in archive order, and must
+    /// have their sizes in their local headers, as archives made by
+    /// `pack_from_path` do. Fails with `NotStreamable` at the first entry that
+    /// doesn't, possibly after unpacking some files. The manifest can come
+    /// last, so files for other platforms are removed once everything is
+    /// unpacked rather than skipped.
+    /// Returns the number of files kept.
+    pub fn unpack_stream(
+        mut reader: impl Read,
+        output: &Path,
+        filesystem: &dyn Filesystem,
+    ) -> anyhow::Result<u64> {
+        // Maps each entry's relative path to whether it's a directory.
+        let mut entries: BTreeMap<PathBuf, bool> = BTreeMap::new();
+        let mut written_files = 0;
+
+        loop {
+            let mut file = match read_zipfile_from_stream(&mut reader) {
+                Ok(Some(file)) => file,
+                Ok(None) => break,
+                Err(ZipError::UnsupportedArchive(reason)) => {
+                    return Err(NotStreamable { reason }.into())
+                }
+                Err(err) => return Err(err.into()),
+            };
+
+            let relative_path = entry_path(file.name())?;
+            let is_dir = file.is_dir();
+
+            if let Some(existing_is_dir) = entries.insert(relative_path.clone(), is_dir) {
+                if existing_is_dir != is_dir {
+                    bail!(
+                        "Archive contains both a file and a directory named {}",
+                        file.name()
+                    );
+                }
+            }
+
+            // Entries come in any order, so a file can turn up either above or
+            // below entries that were already unpacked. Paths sort right
+            // before everything inside them, so only the entry after this one
+            // needs checking for the second case.
+            let file_above = relative_path
+                .ancestors()
+                .skip(1)
+                .find(|ancestor| entries.get(*ancestor) == Some(&false));
+            let below = entries
+                .range::<Path, _>((Bound::Excluded(relative_path.as_path()), Bound::Unbounded))
+                .next()
+                .map(|(path, _)| path)
+                .filter(|path| !is_dir && path.starts_with(&relative_path));
+
+            let nesting = match (file_above, below) {
+                (Some(outer), _) => Some((relative_path.as_path(), outer)),
+                (None, Some(inner)) => Some((inner.as_path(), relative_path.as_path())),
+                (None, None) => None,
+            };
+
+            if let Some((inner, outer)) = nesting {
+                bail!(
+                    "Archive entry {} is nested inside {}, which is a file",
+                    inner.display(),
+                    outer.display()
+                );
+            }
+
+            let path = output.join(&relative_path);
+
+            if is_dir {
+                filesystem.create_dir_all(&path)?;
+                continue;
+            }
+
+            if let Some(parent) = path.parent() {
+                filesystem.create_dir_all(parent)?;
+            }
+
+            let mut data = Vec::with_capacity(file.size() as usize);
+            io::copy(&mut file, &mut data)?;
+
+            log::trace!("Unpacking {} ({} bytes)", path.display(), data.len());
+            filesystem.write(&path, &data)?;
+            written_files += 1;
+        }
+
+        let platform_dir = match filesystem.read(&output.join("wally.toml")) {
+            Ok(manifest) => manifest_platform_dir(&manifest),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+            Err(err) => return Err(err.into()),
+        };
+
+        if let Some(platform_dir) = platform_dir {
+            let other_platforms: BTreeSet<_> = entries
+                .keys()
+                .filter_map(|path| other_platform_root(path, &platform_dir))
+                .collect();
+
+            for root in other_platforms {
+                let path = output.join(&root);
+                log::trace!("Removing {}, which is for another platform", path.display());
+
+                if filesystem.is_dir(&path) {
+                    filesystem.remove_dir_all(&path)?;
+                } else {
+                    filesystem.remove_file(&path)?;
+                }
+
+                written_files -= entries
+                    .iter()
+                    .filter(|(entry, is_dir)| !**is_dir && entry.starts_with(&root))
+                    .count() as u64;
+            }
+        }
+
+        Ok(written_files)
+    }
+
+    /// The `platform_dir` of the manifest packed into this archive, if it has
+    /// a manifest that declares one.
+    fn platform_dir(&self) -> anyhow::Result<Option<String>> {
+        let mut archive = ZipArchive::new(Cursor::new(&self.data))?;
+        let mut file = match archive.by_name("wally.toml") {
+            Ok(file) => file,
+            Err(ZipError::FileNotFound) => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+
+        Ok(manifest_platform_dir(&data))
     }
 
     pub fn filtered_contents(input: &Path) -> anyhow::Result<Vec<PathBuf>> {
@@ -134,6 +417,19 @@ impl PackageContents {
             .collect())
     }
 
+    /// Read the manifest that was packed into this archive.
+    pub fn manifest(&self) -> anyhow::Result<Manifest> {
+        let mut archive = ZipArchive::new(Cursor::new(&self.data))?;
+        let mut file = archive
+            .by_name("wally.toml")
+            .map_err(|_| format_err!("package archive has no wally.toml"))?;
+
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+
+        Manifest::from_slice(&data)
+    }
+
     pub fn data(&self) -> &[u8] {
         &self.data
     }
@@ -144,6 +440,191 @@ impl PackageContents {
     }
 }
 
+/// The path an archive entry unpacks to, relative to the package root. Some
+/// Windows tools separate path components with `\`, so those are treated the
+/// same as `/`. Fails for paths that could land outside the package root.
+fn entry_path(name: &str) -> anyhow::Result<PathBuf> {
+    let normalized = name.replace('\\', "/");
+    let path = Path::new(&normalized);
+
+    let enclosed = !normalized.contains('\0')
+        && path
+            .components()
+            .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+
+    if !enclosed {
+        bail!("Archive entry {} has an invalid path", name);
+    }
+
+    Ok(path.to_path_buf())
+}
+
+/// Reads only the `platform-dir` key of a packed manifest, so that packages
+/// whose manifests don't parse as a whole, such as ones written for a newer
+/// wally, still unpack. Those can't have asked for platform selection.
+fn manifest_platform_dir(manifest: &[u8]) -> Option<String> {
+    let manifest: toml::Value = match std::str::from_utf8(manifest)
+        .ok()
+        .and_then(|manifest| toml::from_str(manifest).ok())
+    {
+        Some(manifest) => manifest,
+        None => {
+            log::debug!("Could not parse packed manifest, so not selecting a platform");
+            return None;
+        }
+    };
+
+    manifest
+        .get("package")?
+        .get("platform-dir")?
+        .as_str()
+        .map(str::to_owned)
+}
+
+/// If `path` is inside the directory `platform_dir` holds for a platform other
+/// than the current one, returns the path of that platform's directory.
+fn other_platform_root(path: &Path, platform_dir: &str) -> Option<PathBuf> {
+    let platform = path.strip_prefix(platform_dir).ok()?.components().next()?;
+
+    if platform.as_os_str() == std::env::consts::OS {
+        return None;
+    }
+
+    Some(Path::new(platform_dir).join(platform))
+}
+
+/// Removes everything under `output` that isn't one of the archive `entries`
+/// or a directory holding one, along with anything whose type differs.
+fn remove_unlisted(
+    output: &Path,
+    entries: &BTreeMap<PathBuf, (bool, usize)>,
+    filesystem: &dyn Filesystem,
+) -> anyhow::Result<()> {
+    let mut kept: BTreeMap<&Path, bool> = BTreeMap::new();
+
+    for (path, (is_dir, _)) in entries {
+        kept.insert(path, *is_dir);
+
+        for ancestor in path.ancestors().skip(1) {
+            if !ancestor.as_os_str().is_empty() {
+                kept.insert(ancestor, true);
+            }
+        }
+    }
+
+    let mut dirs = vec![output.to_path_buf()];
+
+    while let Some(dir) = dirs.pop() {
+        for entry in filesystem.read_dir(&dir)? {
+            let relative_path = entry.path.strip_prefix(output)?;
+
+            if kept.get(relative_path) == Some(&entry.is_dir) {
+                if entry.is_dir {
+                    dirs.push(entry.path);
+                }
+
+                continue;
+            }
+
+            log::trace!("Removing {}", entry.path.display());
+
+            if entry.is_dir {
+                filesystem.remove_dir_all(&entry.path)?;
+            } else {
+                filesystem.remove_file(&entry.path)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Finds the path from the root of a package archive to the package's entry
+/// point, which is an `init.luau` or `init.lua` file either at the root or in
+/// `src/`, preferring the root. Lune resolves `init.luau` when requiring a
+/// directory but not `init.lua`, so `.lua` entry points are named explicitly.
+///
+/// This is meant for linting archives, so it fails if no entry point exists,
+/// and also if a directory has both an `init.luau` and an `init.lua`, even
+/// though installing such a package just uses the `init.luau`.
+pub fn detect_entry_suffix<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+) -> anyhow::Result<&'static str> {
+    let entry = find_entry_point("archive", |path| {
+        archive
+            .file_names()
+            .any(|name| name.replace('\\', "/") == path)
+    })?;
+
+    if let Some(ambiguity) = entry.ambiguity {
+        bail!("{}", ambiguity);
+    }
+
+    Ok(entry.suffix)
+}
+
+/// Like `detect_entry_suffix`, but for a package that has already been
+/// unpacked into `dir`. Only a missing entry point is an error here: when a
+/// directory has both an `init.luau` and an `init.lua`, the `init.luau` is
+/// used and the ambiguity is described in the returned entry point instead.
+pub fn detect_installed_entry_point(
+    dir: &Path,
+    filesystem: &dyn Filesystem,
+) -> anyhow::Result<EntryPoint> {
+    find_entry_point(&dir.display().to_string(), |path| {
+        filesystem.exists(&dir.join(path))
+    })
+}
+
+/// A package's entry point, as found by `detect_installed_entry_point`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryPoint {
+    /// The path from the root of the package to its entry point, in the form
+    /// `detect_entry_suffix` returns.
+    pub suffix: &'static str,
+
+    /// Why the entry point could have been another file, if it could.
+    pub ambiguity: Option<String>,
+}
+
+fn find_entry_point(
+    description: &str,
+    has_file: impl Fn(&str) -> bool,
+) -> anyhow::Result<EntryPoint> {
+    let candidates = [("", "", "/init.lua"), ("src/", "/src", "/src/init.lua")];
+
+    for &(dir, luau_suffix, lua_suffix) in candidates.iter() {
+        let luau = format!("{}init.luau", dir);
+        let lua = format!("{}init.lua", dir);
+
+        let entry = match (has_file(&luau), has_file(&lua)) {
+            (true, true) => EntryPoint {
+                suffix: luau_suffix,
+                ambiguity: Some(format!(
+                    "{} contains both {} and {}, so its entry point is ambiguous",
+                    description, luau, lua
+                )),
+            },
+            (true, false) => EntryPoint {
+                suffix: luau_suffix,
+                ambiguity: None,
+            },
+            (false, true) => EntryPoint {
+                suffix: lua_suffix,
+                ambiguity: None,
+            },
+            (false, false) => continue,
+        };
+
+        return Ok(entry);
+    }
+
+    bail!(
+        "{} has no init.luau or init.lua at its root or in src/",
+        description
+    )
+}
+
 fn build_glob_set(patterns: &[String]) -> anyhow::Result<GlobSet> {
     let mut builder = GlobSetBuilder::new();
 
@@ -153,3 +634,251 @@ fn build_glob_set(patterns: &[String]) -> anyhow::Result<GlobSet> {
 
     Ok(builder.build()?)
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Write;
+
+    use crate::test_package::PackageBuilder;
+
+    fn archive(entries: &[(&str, Option<&str>)]) -> PackageContents {
+        let mut data = Vec::new();
+        let mut archive = ZipWriter::new(Cursor::new(&mut data));
+
+        for (name, contents) in entries {
+            match contents {
+                Some(contents) => {
+                    archive.start_file(*name, FileOptions::default()).unwrap();
+                    archive.write_all(contents.as_bytes()).unwrap();
+                }
+                None => archive
+                    .add_directory(*name, FileOptions::default())
+                    .unwrap(),
+            }
+        }
+
+        archive.finish().unwrap();
+        drop(archive);
+
+        PackageContents::from_buffer(data)
+    }
+
+    #[test]
+    fn unpack_file_before_dir() {
+        let contents = archive(&[
+            ("src/init.luau", Some("return {}")),
+            ("src/util/init.luau", Some("return 1")),
+            ("src/util/", None),
+            ("src/", None),
+        ]);
+
+        let dir = tempfile::tempdir().unwrap();
+        contents.unpack_into_path(dir.path(), None).unwrap();
+
+        let init = fs_err::read_to_string(dir.path().join("src/init.luau")).unwrap();
+        assert_eq!(init, "return {}");
+
+        let util = fs_err::read_to_string(dir.path().join("src/util/init.luau")).unwrap();
+        assert_eq!(util, "return 1");
+    }
+
+    #[test]
+    fn unpack_file_and_dir_conflict() {
+        let contents = archive(&[
+            ("src", Some("not a directory")),
+            ("src/init.luau", Some("")),
+        ]);
+
+        let dir = tempfile::tempdir().unwrap();
+        let err = contents.unpack_into_path(dir.path(), None).unwrap_err();
+
+        assert!(err.to_string().contains("which is a file"), "{}", err);
+    }
+
+    #[test]
+    fn entry_suffixes() {
+        let cases: &[(&[&str], Option<&str>)] = &[
+            (&["init.luau"], Some("")),
+            (&["init.lua"], Some("/init.lua")),
+            (&["src/init.luau"], Some("/src")),
+            (&["src/init.lua", "src/util.lua"], Some("/src/init.lua")),
+            (&["src/init.luau", "init.luau"], Some("")),
+            (&["src/init.luau", "init.lua"], Some("/init.lua")),
+            (&["lib/init.luau"], None),
+            (&["init.luau", "init.lua"], None),
+            (&["src/init.luau", "src/init.lua"], None),
+        ];
+
+        for (files, expected) in cases {
+            let entries: Vec<_> = files.iter().map(|name| (*name, Some(""))).collect();
+            let contents = archive(&entries);
+            let mut zip = ZipArchive::new(Cursor::new(contents.data())).unwrap();
+
+            let suffix = detect_entry_suffix(&mut zip).ok();
+            assert_eq!(suffix, *expected, "{:?}", files);
+        }
+    }
+
+    #[test]
+    fn installed_entry_point_prefers_luau() {
+        let contents = archive(&[
+            ("init.luau", Some("")),
+            ("init.lua", Some("")),
+            ("src/init.lua", Some("")),
+        ]);
+
+        let dir = tempfile::tempdir().unwrap();
+        contents.unpack_into_path(dir.path(), None).unwrap();
+
+        let entry = detect_installed_entry_point(dir.path(), &RealFilesystem).unwrap();
+        assert_eq!(entry.suffix, "");
+
+        let ambiguity = entry.ambiguity.unwrap();
+        assert!(
+            ambiguity.contains("entry point is ambiguous"),
+            "{}",
+            ambiguity
+        );
+    }
+
+    #[test]
+    fn unpack_progress() {
+        let contents = archive(&[
+            ("src/", None),
+            ("src/init.luau", Some("")),
+            ("src/a.luau", Some("")),
+            ("src/b.luau", Some("")),
+        ]);
+
+        let calls = std::cell::RefCell::new(Vec::new());
+        let progress = |written, total| calls.borrow_mut().push((written, total));
+
+        let dir = tempfile::tempdir().unwrap();
+        contents
+            .unpack_into_path(dir.path(), Some(&progress))
+            .unwrap();
+
+        assert_eq!(calls.into_inner(), vec![(1, 3), (2, 3), (3, 3)]);
+    }
+
+    #[test]
+    fn unpack_stream() {
+        let contents = archive(&[
+            ("src/init.luau", Some("return {}")),
+            ("src/util/init.luau", Some("return 1")),
+            ("src/util/", None),
+            ("src/", None),
+        ]);
+
+        let dir = tempfile::tempdir().unwrap();
+        PackageContents::unpack_stream(contents.data(), dir.path(), &RealFilesystem).unwrap();
+
+        let init = fs_err::read_to_string(dir.path().join("src/init.luau")).unwrap();
+        assert_eq!(init, "return {}");
+
+        let util = fs_err::read_to_string(dir.path().join("src/util/init.luau")).unwrap();
+        assert_eq!(util, "return 1");
+
+        // A file can turn up after entries inside it, or before them.
+        let orders: &[&[(&str, Option<&str>)]] = &[
+            &[
+                ("src/init.luau", Some("")),
+                ("src", Some("not a directory")),
+            ],
+            &[
+                ("src", Some("not a directory")),
+                ("src/util/init.luau", Some("")),
+            ],
+        ];
+
+        for entries in orders {
+            let contents = archive(entries);
+
+            let dir = tempfile::tempdir().unwrap();
+            let err = PackageContents::unpack_stream(contents.data(), dir.path(), &RealFilesystem)
+                .unwrap_err();
+
+            assert!(err.to_string().contains("which is a file"), "{}", err);
+        }
+    }
+
+    #[test]
+    fn only_current_platform_unpacked() {
+        let contents = PackageBuilder::new("biff/native@0.1.0")
+            .with_platform_dir("platform")
+            .with_file("init.luau", "")
+            .with_file("platform/windows/native.dll", "windows")
+            .with_file("platform/macos/native.dylib", "macos")
+            .with_file("platform/linux/native.so", "linux")
+            .contents();
+
+        let unpacked = tempfile::tempdir().unwrap();
+        let streamed = tempfile::tempdir().unwrap();
+
+        let unpacked_files = contents.unpack_into_path(unpacked.path(), None).unwrap();
+        let streamed_files =
+            PackageContents::unpack_stream(contents.data(), streamed.path(), &RealFilesystem)
+                .unwrap();
+
+        // The package's files, its manifest and one native library.
+        assert_eq!(unpacked_files, 3);
+        assert_eq!(streamed_files, 3);
+
+        for dir in &[unpacked.path(), streamed.path()] {
+            for platform in &["windows", "macos", "linux"] {
+                assert_eq!(
+                    dir.join("platform").join(platform).exists(),
+                    *platform == std::env::consts::OS,
+                    "{}",
+                    platform
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn unparseable_manifest_unpacked() {
+        let contents = archive(&[
+            ("wally.toml", Some("[package\nname = ")),
+            ("init.luau", Some("return {}")),
+        ]);
+
+        let unpacked = tempfile::tempdir().unwrap();
+        let streamed = tempfile::tempdir().unwrap();
+
+        contents.unpack_into_path(unpacked.path(), None).unwrap();
+        PackageContents::unpack_stream(contents.data(), streamed.path(), &RealFilesystem).unwrap();
+
+        for dir in &[unpacked.path(), streamed.path()] {
+            assert!(dir.join("wally.toml").is_file());
+            assert!(dir.join("init.luau").is_file());
+        }
+    }
+
+    #[test]
+    fn backslash_separated_entries() {
+        let contents = archive(&[
+            ("src\\", None),
+            ("src\\init.luau", Some("return {}")),
+            ("src\\util\\init.luau", Some("return 1")),
+        ]);
+
+        let mut zip = ZipArchive::new(Cursor::new(contents.data())).unwrap();
+        assert_eq!(detect_entry_suffix(&mut zip).unwrap(), "/src");
+
+        let dir = tempfile::tempdir().unwrap();
+        contents.unpack_into_path(dir.path(), None).unwrap();
+
+        let init = fs_err::read_to_string(dir.path().join("src/init.luau")).unwrap();
+        assert_eq!(init, "return {}");
+
+        let util = fs_err::read_to_string(dir.path().join("src/util/init.luau")).unwrap();
+        assert_eq!(util, "return 1");
+
+        let streamed = tempfile::tempdir().unwrap();
+        PackageContents::unpack_stream(contents.data(), streamed.path(), &RealFilesystem).unwrap();
+        assert!(streamed.path().join("src/util/init.luau").is_file());
+    }
+}
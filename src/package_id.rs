@@ -16,6 +16,10 @@ use crate::package_name::PackageName;
 /// Examples of package IDs:
 /// * `hello/world@1.2.3`
 /// * `miss-frizz/magic-school-bus@0.2.3-pre1+build102312`
+///
+/// Like SemVer versions, package IDs are compared and hashed without their
+/// build metadata, so `hello/world@1.2.3` and `hello/world@1.2.3+build` are
+/// the same package. Build metadata is still kept, and displayed.
 #[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
 pub struct PackageId {
     name: PackageName,
@@ -157,4 +161,28 @@ mod test {
         let deserialized: PackageId = serde_json::from_str(&serialized).unwrap();
         assert_eq!(deserialized, package_id);
     }
+
+    #[test]
+    fn build_metadata_ignored() {
+        use std::collections::hash_map::DefaultHasher;
+        use std::hash::{Hash, Hasher};
+
+        let plain: PackageId = "hello/world@1.0.0".parse().unwrap();
+        let with_build: PackageId = "hello/world@1.0.0+build".parse().unwrap();
+
+        let hash = |id: &PackageId| {
+            let mut hasher = DefaultHasher::new();
+            id.hash(&mut hasher);
+            hasher.finish()
+        };
+
+        assert_eq!(plain, with_build);
+        assert_eq!(hash(&plain), hash(&with_build));
+        assert_eq!(plain.cmp(&with_build), std::cmp::Ordering::Equal);
+        assert_eq!(with_build.to_string(), "hello/world@1.0.0+build");
+
+        // Prerelease versions are still different packages.
+        let prerelease: PackageId = "hello/world@1.0.0-alpha".parse().unwrap();
+        assert_ne!(plain, prerelease);
+    }
 }
@@ -1,7 +1,8 @@
 use std::collections::HashMap;
 use std::io::{BufReader, ErrorKind, Write};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
 use anyhow::{anyhow, Context};
 use fs_err::{create_dir_all, File, OpenOptions};
@@ -14,6 +15,10 @@ use crate::git_util;
 use crate::manifest::Manifest;
 use crate::package_name::PackageName;
 
+/// The file in an index's git directory that records when the index was last
+/// fetched, as seconds since the Unix epoch.
+const FETCHED_AT_FILE: &str = "wally-fetched-at";
+
 /// Configuration contained in the index's `config.json` file.
 #[derive(Debug, Serialize, Deserialize)]
 pub struct PackageIndexConfig {
@@ -43,6 +48,10 @@ pub struct PackageIndex {
     /// configuration.
     access_token: Option<String>,
 
+    /// Fetch the index through this proxy instead of the one git's
+    /// configuration or the environment name.
+    proxy: Option<Url>,
+
     /// If this index is contained in a temporary location, like when running
     /// tests or a registry server, hold onto it here so that it'll be dropped
     /// at the right time.
@@ -52,8 +61,23 @@ pub struct PackageIndex {
 
 impl PackageIndex {
     pub fn new(index_url: &Url, access_token: Option<String>) -> anyhow::Result<Self> {
+        Self::new_cached(index_url, access_token, Duration::from_secs(0), true, None)
+    }
+
+    /// Like `new`, but only fetches the index if it hasn't been fetched within
+    /// `ttl`, so installs run back to back don't each wait on the remote.
+    /// `force_refresh` fetches it regardless. The index is fetched through
+    /// `proxy` if one is given.
+    pub fn new_cached(
+        index_url: &Url,
+        access_token: Option<String>,
+        ttl: Duration,
+        force_refresh: bool,
+        proxy: Option<&Url>,
+    ) -> anyhow::Result<Self> {
         let path = index_path(index_url)?;
-        let repository = git_util::open_or_clone(access_token.clone(), index_url, &path)?;
+        let repository = git_util::open_or_clone(access_token.clone(), index_url, &path, proxy)?;
+        let fetched_at_path = repository.path().join(FETCHED_AT_FILE);
 
         let index = Self {
             url: index_url.clone(),
@@ -61,10 +85,11 @@ impl PackageIndex {
             repository: Mutex::new(repository),
             package_cache: Mutex::new(HashMap::new()),
             access_token,
+            proxy: proxy.cloned(),
             temp_dir: None,
         };
 
-        index.update()?;
+        fetch_unless_fresh(&fetched_at_path, ttl, force_refresh, || index.update())?;
         Ok(index)
     }
 
@@ -79,7 +104,7 @@ impl PackageIndex {
     pub fn new_temp(index_url: &Url, access_token: Option<String>) -> anyhow::Result<Self> {
         let temp_dir = tempfile::tempdir()?;
         let path = temp_dir.path().to_owned();
-        let repository = git_util::open_or_clone(access_token.clone(), index_url, &path)?;
+        let repository = git_util::open_or_clone(access_token.clone(), index_url, &path, None)?;
 
         let index = Self {
             url: index_url.clone(),
@@ -87,6 +112,7 @@ impl PackageIndex {
             repository: Mutex::new(repository),
             package_cache: Mutex::new(HashMap::new()),
             access_token,
+            proxy: None,
             temp_dir: Some(temp_dir),
         };
 
@@ -101,7 +127,7 @@ impl PackageIndex {
             "Updating package index {}...",
             repository.find_remote("origin")?.url().unwrap()
         );
-        git_util::update_index(self.access_token.clone(), &repository)
+        git_util::update_index(self.access_token.clone(), &repository, self.proxy.as_ref())
             .with_context(|| format!("could not update package index"))?;
 
         Ok(())
@@ -282,3 +308,131 @@ fn index_path(index_url: &Url) -> anyhow::Result<PathBuf> {
 
     Ok(path)
 }
+
+/// Calls `fetch` unless the time recorded at `fetched_at_path` is less than
+/// `ttl` ago, then records the current time there. Returns whether `fetch` was
+/// called.
+fn fetch_unless_fresh(
+    fetched_at_path: &Path,
+    ttl: Duration,
+    force: bool,
+    fetch: impl FnOnce() -> anyhow::Result<()>,
+) -> anyhow::Result<bool> {
+    let now = SystemTime::now();
+
+    if !force {
+        let fetched_at = fs_err::read_to_string(fetched_at_path)
+            .ok()
+            .and_then(|contents| contents.trim().parse::<u64>().ok())
+            .map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+
+        // A time in the future means the clock changed, so it can't be trusted.
+        if let Some(Ok(age)) = fetched_at.map(|fetched_at| now.duration_since(fetched_at)) {
+            if age < ttl {
+                log::debug!("Package index was fetched {:?} ago, not fetching it", age);
+                return Ok(false);
+            }
+        }
+    }
+
+    fetch()?;
+
+    let secs = now.duration_since(UNIX_EPOCH)?.as_secs();
+    fs_err::write(fetched_at_path, secs.to_string())?;
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::cell::Cell;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use std::thread;
+
+    #[test]
+    fn fetches_cached_within_ttl() {
+        let dir = tempfile::tempdir().unwrap();
+        let fetched_at_path = dir.path().join(FETCHED_AT_FILE);
+        let ttl = Duration::from_secs(60 * 60);
+
+        let fetches = Cell::new(0);
+        let fetch = || {
+            fetches.set(fetches.get() + 1);
+            Ok(())
+        };
+
+        // Two installs within the TTL only fetch the index once.
+        assert!(fetch_unless_fresh(&fetched_at_path, ttl, false, fetch).unwrap());
+        assert!(!fetch_unless_fresh(&fetched_at_path, ttl, false, fetch).unwrap());
+        assert_eq!(fetches.get(), 1);
+
+        // Forcing a refresh fetches it anyway.
+        assert!(fetch_unless_fresh(&fetched_at_path, ttl, true, fetch).unwrap());
+        assert_eq!(fetches.get(), 2);
+
+        // As does a TTL the last fetch is older than.
+        assert!(
+            fetch_unless_fresh(&fetched_at_path, Duration::from_secs(0), false, fetch).unwrap()
+        );
+        assert_eq!(fetches.get(), 3);
+    }
+
+    #[test]
+    fn failed_fetch_not_cached() {
+        let dir = tempfile::tempdir().unwrap();
+        let fetched_at_path = dir.path().join(FETCHED_AT_FILE);
+        let ttl = Duration::from_secs(60 * 60);
+
+        fetch_unless_fresh(&fetched_at_path, ttl, false, || anyhow::bail!("offline")).unwrap_err();
+        assert!(!fetched_at_path.exists());
+
+        let fetched = Cell::new(false);
+        fetch_unless_fresh(&fetched_at_path, ttl, false, || {
+            fetched.set(true);
+            Ok(())
+        })
+        .unwrap();
+        assert!(fetched.get());
+    }
+
+    #[test]
+    fn index_fetched_through_proxy() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let proxy_url = Url::parse(&format!("http://{}", listener.local_addr().unwrap())).unwrap();
+
+        let proxy = thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+
+            let mut request = Vec::new();
+            let mut buffer = [0; 1024];
+            while !request.ends_with(b"\r\n\r\n") {
+                let read = stream.read(&mut buffer).unwrap();
+                if read == 0 {
+                    break;
+                }
+                request.extend_from_slice(&buffer[..read]);
+            }
+
+            stream
+                .write_all(
+                    b"HTTP/1.1 502 Bad Gateway\r\nContent-Length: 0\r\nConnection: close\r\n\r\n",
+                )
+                .unwrap();
+
+            String::from_utf8(request).unwrap()
+        });
+
+        // Nothing answers for the index, so cloning it fails, but only after
+        // asking the proxy for it.
+        let dir = tempfile::tempdir().unwrap();
+        let index_url = Url::parse("http://index.invalid/index").unwrap();
+        git_util::clone(None, &index_url, dir.path(), Some(&proxy_url)).unwrap_err();
+
+        let request = proxy.join().unwrap();
+        let request_line = request.lines().next().unwrap_or("");
+        assert!(request_line.contains("index.invalid"), "{}", request);
+    }
+}
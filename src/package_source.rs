@@ -1,33 +1,71 @@
+mod git;
 mod in_memory;
 mod registry;
 mod test_registry;
 
+pub use self::git::GitPackageSource;
 pub use self::in_memory::InMemoryRegistry;
 use self::in_memory::InMemoryRegistrySource;
-pub use self::registry::Registry;
+pub use self::registry::{ClientConfig, Registry};
 pub use self::test_registry::TestRegistry;
 
 use std::collections::HashMap;
-use std::path::PathBuf;
+use std::fmt;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 use crate::manifest::Manifest;
 use crate::package_contents::PackageContents;
 use crate::package_id::PackageId;
 use crate::package_req::PackageReq;
 
-#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum PackageSourceId {
     DefaultRegistry,
     Git(String),
     Path(PathBuf),
+    GitPackage { url: String, rev: String },
 }
 
+/// The error a download fails with when its source doesn't have the package,
+/// such as when the version was yanked from a registry after being resolved.
+#[derive(Debug)]
+pub struct PackageNotFound {
+    pub package_id: PackageId,
+}
+
+impl fmt::Display for PackageNotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Package {} does not exist", self.package_id)
+    }
+}
+
+impl std::error::Error for PackageNotFound {}
+
+/// The error a download fails with when its source answers with something
+/// that isn't a package archive, like an empty body or an HTML error page.
+#[derive(Debug)]
+pub struct InvalidArchive {
+    pub package_id: PackageId,
+    message: String,
+}
+
+impl fmt::Display for InvalidArchive {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for InvalidArchive {}
+
 #[derive(Clone)]
 pub struct PackageSourceMap {
     sources: HashMap<PackageSourceId, Box<PackageSource>>,
     source_order: Vec<PackageSourceId>,
+    client_config: ClientConfig,
 }
 
 impl PackageSourceMap {
@@ -38,9 +76,16 @@ impl PackageSourceMap {
         Self {
             sources,
             source_order: vec![PackageSourceId::DefaultRegistry],
+            client_config: ClientConfig::default(),
         }
     }
 
+    /// Use the given `ClientConfig` for any registries added as fallbacks.
+    pub fn with_client_config(mut self, client_config: ClientConfig) -> Self {
+        self.client_config = client_config;
+        self
+    }
+
     pub fn get(&self, id: &PackageSourceId) -> Option<&PackageSource> {
         self.sources.get(id).map(|source| source.as_ref())
     }
@@ -49,6 +94,36 @@ impl PackageSourceMap {
         &self.source_order
     }
 
+    /// Add a source to search after all of the sources already in the map.
+    pub fn add_source(&mut self, id: PackageSourceId, source: Box<PackageSource>) {
+        if !self.source_order.contains(&id) {
+            self.source_order.push(id.clone());
+        }
+
+        self.sources.insert(id, source);
+    }
+
+    /// Add a source for each of the manifest's Git sources, searched before
+    /// every other source so that a package pinned to a Git revision is never
+    /// fetched from a registry instead. Repositories are checked out inside
+    /// `checkouts_dir`.
+    pub fn add_git_sources(&mut self, manifest: &Manifest, checkouts_dir: &Path) {
+        for (name, git_source) in manifest.git_sources.iter().rev() {
+            let source = GitPackageSource::new(
+                name.clone(),
+                git_source.git.clone(),
+                git_source.rev.clone(),
+                checkouts_dir,
+            );
+            let id = source.id();
+
+            self.source_order.retain(|existing| *existing != id);
+            self.source_order.insert(0, id.clone());
+            self.sources
+                .insert(id, Box::new(PackageSource::Git(source)));
+        }
+    }
+
     /// Searches the current list of sources for fallbacks and adds any not yet in the list, producing
     /// a complete tree of reachable sources for packages.
     /// Sources are searched breadth-first to ensure correct fallback priority.
@@ -62,15 +137,18 @@ impl PackageSourceMap {
                 // Prevent circular references by only adding new sources
                 if !self.source_order.contains(&fallback) {
                     let source: Box<PackageSource> = match &fallback {
-                        PackageSourceId::Git(url) => {
-                            Box::new(PackageSource::Registry(Registry::from_registry_spec(url)?))
-                        }
+                        PackageSourceId::Git(url) => Box::new(PackageSource::Registry(
+                            Registry::with_client_config(url, &self.client_config)?,
+                        )),
                         PackageSourceId::Path(path) => {
                             Box::new(PackageSource::TestRegistry(TestRegistry::new(path.clone())))
                         }
                         PackageSourceId::DefaultRegistry => {
                             panic!("Default registry should never be added as a fallback source!")
                         }
+                        PackageSourceId::GitPackage { .. } => {
+                            panic!("Git packages should never be added as a fallback source!")
+                        }
                     };
 
                     self.sources.insert(fallback.clone(), source);
@@ -97,6 +175,24 @@ pub trait PackageSourceProvider: Sync + Send + Clone {
     /// `PackageId`.
     fn download_package(&self, package_id: &PackageId) -> anyhow::Result<PackageContents>;
 
+    /// Opens the archive of a package given its fully-qualified `PackageId`
+    /// as a stream, so it can be unpacked as it arrives instead of being held
+    /// in memory. Sources that can't stream return `None`, and are downloaded
+    /// with `download_package` instead.
+    fn stream_package(
+        &self,
+        _package_id: &PackageId,
+    ) -> anyhow::Result<Option<Box<dyn Read + Send>>> {
+        Ok(None)
+    }
+
+    /// Returns a copy of this source whose downloads fail once they've taken
+    /// longer than `timeout`. Sources that can't time out a download return
+    /// themselves unchanged.
+    fn with_download_timeout(&self, _timeout: Duration) -> Self {
+        self.clone()
+    }
+
     /// Provide a list of fallback sources to search if this source can't provide a package
     fn fallback_sources(&self) -> anyhow::Result<Vec<PackageSourceId>>;
 }
@@ -106,6 +202,7 @@ pub enum PackageSource {
     InMemory(InMemoryRegistrySource),
     Registry(Registry),
     TestRegistry(TestRegistry),
+    Git(GitPackageSource),
 }
 
 impl PackageSourceProvider for PackageSource {
@@ -114,6 +211,7 @@ impl PackageSourceProvider for PackageSource {
             PackageSource::InMemory(source) => source.update(),
             PackageSource::Registry(source) => source.update(),
             PackageSource::TestRegistry(source) => source.update(),
+            PackageSource::Git(source) => source.update(),
         }
     }
 
@@ -122,6 +220,7 @@ impl PackageSourceProvider for PackageSource {
             PackageSource::InMemory(source) => source.query(package_req),
             PackageSource::Registry(source) => source.query(package_req),
             PackageSource::TestRegistry(source) => source.query(package_req),
+            PackageSource::Git(source) => source.query(package_req),
         }
     }
 
@@ -130,6 +229,34 @@ impl PackageSourceProvider for PackageSource {
             PackageSource::InMemory(source) => source.download_package(package_id),
             PackageSource::Registry(source) => source.download_package(package_id),
             PackageSource::TestRegistry(source) => source.download_package(package_id),
+            PackageSource::Git(source) => source.download_package(package_id),
+        }
+    }
+
+    fn stream_package(
+        &self,
+        package_id: &PackageId,
+    ) -> anyhow::Result<Option<Box<dyn Read + Send>>> {
+        match self {
+            PackageSource::InMemory(source) => source.stream_package(package_id),
+            PackageSource::Registry(source) => source.stream_package(package_id),
+            PackageSource::TestRegistry(source) => source.stream_package(package_id),
+            PackageSource::Git(source) => source.stream_package(package_id),
+        }
+    }
+
+    fn with_download_timeout(&self, timeout: Duration) -> Self {
+        match self {
+            PackageSource::InMemory(source) => {
+                PackageSource::InMemory(source.with_download_timeout(timeout))
+            }
+            PackageSource::Registry(source) => {
+                PackageSource::Registry(source.with_download_timeout(timeout))
+            }
+            PackageSource::TestRegistry(source) => {
+                PackageSource::TestRegistry(source.with_download_timeout(timeout))
+            }
+            PackageSource::Git(source) => PackageSource::Git(source.with_download_timeout(timeout)),
         }
     }
 
@@ -138,6 +265,7 @@ impl PackageSourceProvider for PackageSource {
             PackageSource::InMemory(source) => source.fallback_sources(),
             PackageSource::Registry(source) => source.fallback_sources(),
             PackageSource::TestRegistry(source) => source.fallback_sources(),
+            PackageSource::Git(source) => source.fallback_sources(),
         }
     }
 }
@@ -0,0 +1,357 @@
+//! Defines a package source that builds a single package straight from a Git
+//! repository, for depending on packages that haven't been published.
+
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context};
+use once_cell::sync::OnceCell;
+use url::Url;
+
+use crate::git_util;
+use crate::manifest::Manifest;
+use crate::package_contents::PackageContents;
+use crate::package_id::PackageId;
+use crate::package_name::PackageName;
+use crate::package_req::PackageReq;
+
+use super::{PackageSourceId, PackageSourceProvider};
+
+/// Provides the package found at the root of a Git repository at a pinned
+/// revision. Queries for any other package fail, so that the resolver moves on
+/// to the next source.
+///
+/// Repositories are cloned once into a checkout directory and reused by later
+/// installs. The version of libgit2 we use can't make shallow clones, so the
+/// full history is fetched.
+#[derive(Clone)]
+pub struct GitPackageSource {
+    name: PackageName,
+    url: Url,
+    rev: String,
+    path: PathBuf,
+    checked_out: OnceCell<()>,
+}
+
+impl GitPackageSource {
+    /// Create a source for the package `name` in the repository at `url`,
+    /// pinned to `rev`, which can be anything `git rev-parse` understands. The
+    /// checkout is kept inside `checkouts_dir`.
+    pub fn new(name: PackageName, url: Url, rev: impl Into<String>, checkouts_dir: &Path) -> Self {
+        let rev = rev.into();
+        let key = blake3::hash(format!("{}#{}", url, rev).as_bytes());
+        let path = checkouts_dir.join(key.to_hex().to_string());
+
+        Self {
+            name,
+            url,
+            rev,
+            path,
+            checked_out: OnceCell::new(),
+        }
+    }
+
+    /// The directory in the user's cache that the CLI keeps checkouts in.
+    pub fn default_checkouts_dir() -> anyhow::Result<PathBuf> {
+        let path = dirs::cache_dir()
+            .ok_or_else(|| anyhow!("could not find cache directory"))?
+            .join("wally")
+            .join("git");
+
+        Ok(path)
+    }
+
+    pub fn id(&self) -> PackageSourceId {
+        PackageSourceId::GitPackage {
+            url: self.url.to_string(),
+            rev: self.rev.clone(),
+        }
+    }
+
+    /// Clone the repository if needed and check out the pinned revision,
+    /// returning the path to the working tree.
+    fn checkout(&self) -> anyhow::Result<&Path> {
+        self.checked_out.get_or_try_init(|| {
+            let repository = git_util::open_or_clone(None, &self.url, &self.path, None)?;
+
+            let object = match repository.revparse_single(&self.rev) {
+                Ok(object) => object,
+                Err(_) => {
+                    // Our clone may be older than the revision we're after.
+                    repository
+                        .find_remote("origin")?
+                        .fetch(&["+refs/heads/*:refs/remotes/origin/*"], None, None)
+                        .with_context(|| format!("could not fetch Git repository {}", self.url))?;
+
+                    repository.revparse_single(&self.rev).with_context(|| {
+                        format!("could not find revision {} in {}", self.rev, self.url)
+                    })?
+                }
+            };
+
+            let commit = object.peel_to_commit()?;
+
+            let mut options = git2::build::CheckoutBuilder::new();
+            options.force();
+
+            repository.checkout_tree(commit.as_object(), Some(&mut options))?;
+            repository.set_head_detached(commit.id())?;
+
+            Ok::<_, anyhow::Error>(())
+        })?;
+
+        Ok(&self.path)
+    }
+
+    fn manifest(&self) -> anyhow::Result<Manifest> {
+        let path = self.checkout()?;
+
+        let manifest = Manifest::load(path).with_context(|| {
+            format!(
+                "could not load the manifest of {} at revision {}",
+                self.url, self.rev
+            )
+        })?;
+
+        if manifest.package.name != self.name {
+            bail!(
+                "{} at revision {} contains {}, not {}",
+                self.url,
+                self.rev,
+                manifest.package.name,
+                self.name
+            );
+        }
+
+        Ok(manifest)
+    }
+}
+
+impl PackageSourceProvider for GitPackageSource {
+    fn update(&self) -> anyhow::Result<()> {
+        self.checkout()?;
+        Ok(())
+    }
+
+    fn query(&self, package_req: &PackageReq) -> anyhow::Result<Vec<Manifest>> {
+        if *package_req.name() != self.name {
+            bail!("{} only provides {}", self.url, self.name);
+        }
+
+        let manifest = self.manifest()?;
+
+        if package_req.matches(&manifest.package.name, &manifest.package.version) {
+            Ok(vec![manifest])
+        } else {
+            Ok(Vec::new())
+        }
+    }
+
+    fn download_package(&self, package_id: &PackageId) -> anyhow::Result<PackageContents> {
+        let manifest = self.manifest()?;
+
+        if manifest.package_id() != *package_id {
+            bail!(
+                "{} at revision {} contains {}, not {}",
+                self.url,
+                self.rev,
+                manifest.package_id(),
+                package_id
+            );
+        }
+
+        PackageContents::pack_from_path(self.checkout()?)
+    }
+
+    fn fallback_sources(&self) -> anyhow::Result<Vec<PackageSourceId>> {
+        Ok(Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use git2::build::RepoBuilder;
+    use git2::{Repository, Signature};
+    use tempfile::TempDir;
+
+    use crate::installation::InstallationContext;
+    use crate::manifest::GitSource;
+    use crate::package_source::{InMemoryRegistry, PackageSourceMap};
+    use crate::resolution::resolve;
+    use crate::test_package::PackageBuilder;
+
+    const MANIFEST: &str = r#"
+        [package]
+        name = "biff/git-package"
+        version = "0.1.0"
+        registry = "test"
+        realm = "shared"
+    "#;
+
+    fn commit_file(repository: &Repository, path: &str, contents: &str) -> git2::Oid {
+        let workdir = repository.workdir().unwrap();
+        fs_err::write(workdir.join(path), contents).unwrap();
+
+        let mut index = repository.index().unwrap();
+        index.add_path(Path::new(path)).unwrap();
+        index.write().unwrap();
+
+        let tree = repository.find_tree(index.write_tree().unwrap()).unwrap();
+        let signature = Signature::now("PackageUser", "PackageUser@localhost").unwrap();
+        let parent = repository.head().unwrap().peel_to_commit().unwrap();
+
+        repository
+            .commit(
+                Some("HEAD"),
+                &signature,
+                &signature,
+                path,
+                &tree,
+                &[&parent],
+            )
+            .unwrap()
+    }
+
+    /// Creates a bare repository holding two commits of `biff/git-package`,
+    /// like one a package would be hosted in, returning it along with the
+    /// first commit.
+    fn bare_repository() -> (TempDir, String) {
+        let work_dir = tempfile::tempdir().unwrap();
+        fs_err::write(work_dir.path().join("wally.toml"), MANIFEST).unwrap();
+        fs_err::write(work_dir.path().join("init.luau"), "return \"first\"").unwrap();
+        git_util::init_test_repo(work_dir.path()).unwrap();
+
+        let repository = Repository::open(work_dir.path()).unwrap();
+        let first = repository.head().unwrap().peel_to_commit().unwrap().id();
+        commit_file(&repository, "init.luau", "return \"second\"");
+
+        let bare_dir = tempfile::tempdir().unwrap();
+        let work_url = Url::from_directory_path(work_dir.path()).unwrap();
+        RepoBuilder::new()
+            .bare(true)
+            .clone(work_url.as_str(), bare_dir.path())
+            .unwrap();
+
+        (bare_dir, first.to_string())
+    }
+
+    fn root_manifest(repo_dir: &Path, rev: &str) -> Manifest {
+        let mut root = PackageBuilder::new("biff/root@0.1.0")
+            .with_dep("GitPackage", "biff/git-package@0.1.0")
+            .with_dep("Minimal", "biff/minimal@0.1.0")
+            .into_manifest();
+        root.git_sources.insert(
+            "biff/git-package".parse().unwrap(),
+            GitSource {
+                git: Url::from_directory_path(repo_dir).unwrap(),
+                rev: rev.to_owned(),
+            },
+        );
+
+        root
+    }
+
+    #[test]
+    fn install_pinned_revision() {
+        let (repo_dir, first) = bare_repository();
+
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/minimal@0.1.0"));
+
+        let checkouts = tempfile::tempdir().unwrap();
+        let root: Manifest = toml::from_str(&format!(
+            r#"
+                [package]
+                name = "biff/root"
+                version = "0.1.0"
+                registry = "test"
+                realm = "shared"
+
+                [dependencies]
+                GitPackage = "biff/git-package@0.1.0"
+                Minimal = "biff/minimal@0.1.0"
+
+                [git-sources]
+                "biff/git-package" = {{ git = "{}", rev = "{}" }}
+            "#,
+            Url::from_directory_path(repo_dir.path()).unwrap(),
+            first
+        ))
+        .unwrap();
+
+        let mut sources = PackageSourceMap::new(Box::new(registry.source()));
+        sources.add_git_sources(&root, checkouts.path());
+        let resolved = resolve(&root, &Default::default(), &sources).unwrap();
+
+        let git_package: PackageId = "biff/git-package@0.1.0".parse().unwrap();
+        assert!(matches!(
+            resolved.metadata[&git_package].source_registry,
+            PackageSourceId::GitPackage { .. }
+        ));
+        let minimal: PackageId = "biff/minimal@0.1.0".parse().unwrap();
+        assert_eq!(
+            resolved.metadata[&minimal].source_registry,
+            PackageSourceId::DefaultRegistry
+        );
+
+        let project = tempfile::tempdir().unwrap();
+        InstallationContext::new(project.path())
+            .install(sources, root.package_id(), resolved)
+            .unwrap();
+
+        let index = project.path().join("packages/_index");
+        let init = fs_err::read_to_string(index.join("biff_git-package@0.1.0/init.luau")).unwrap();
+        assert_eq!(init, "return \"first\"");
+        assert!(index.join("biff_minimal@0.1.0").is_dir());
+    }
+
+    #[test]
+    fn git_source_shadows_registry() {
+        let (repo_dir, _) = bare_repository();
+
+        // The registry has a package by the same name, which the pinned
+        // revision must win over.
+        let registry = InMemoryRegistry::new();
+        registry.publish(PackageBuilder::new("biff/git-package@0.1.0"));
+        registry.publish(PackageBuilder::new("biff/minimal@0.1.0"));
+
+        let checkouts = tempfile::tempdir().unwrap();
+        let root = root_manifest(repo_dir.path(), "main");
+
+        let mut sources = PackageSourceMap::new(Box::new(registry.source()));
+        sources.add_git_sources(&root, checkouts.path());
+        let resolved = resolve(&root, &Default::default(), &sources).unwrap();
+
+        let project = tempfile::tempdir().unwrap();
+        InstallationContext::new(project.path())
+            .install(sources, root.package_id(), resolved)
+            .unwrap();
+
+        let init = fs_err::read_to_string(
+            project
+                .path()
+                .join("packages/_index/biff_git-package@0.1.0/init.luau"),
+        )
+        .unwrap();
+        assert_eq!(init, "return \"second\"");
+    }
+
+    #[test]
+    fn mismatched_package_name() {
+        let (repo_dir, first) = bare_repository();
+        let checkouts = tempfile::tempdir().unwrap();
+
+        let source = GitPackageSource::new(
+            "biff/other-package".parse().unwrap(),
+            Url::from_directory_path(repo_dir.path()).unwrap(),
+            first,
+            checkouts.path(),
+        );
+
+        let error = source
+            .query(&"biff/other-package@0.1.0".parse().unwrap())
+            .unwrap_err();
+        assert!(error.to_string().contains("contains biff/git-package"));
+    }
+}
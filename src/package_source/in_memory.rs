@@ -2,18 +2,22 @@
 //! memory. It's useful for creating exact conditions for test cases for
 //! resolution, installation, upgrading, etc.
 
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Cursor, Read};
 use std::sync::Arc;
+use std::sync::Mutex;
 use std::sync::RwLock;
+use std::thread;
+use std::time::Duration;
 
-use anyhow::format_err;
+use anyhow::bail;
 
 use crate::{
     manifest::Manifest, package_id::PackageId, package_req::PackageReq,
     package_source::PackageSource, test_package::PackageBuilder,
 };
 
-use super::{PackageContents, PackageSourceId, PackageSourceProvider};
+use super::{PackageContents, PackageNotFound, PackageSourceId, PackageSourceProvider};
 
 /// An in-memory registry that can have packages published to it.
 ///
@@ -32,8 +36,14 @@ impl InMemoryRegistry {
 
     /// Publish a new package to the registry.
     pub fn publish(&self, builder: PackageBuilder) {
-        let mut storage = self.storage.contents.write().unwrap();
         let (manifest, contents) = builder.package();
+        self.publish_archive(manifest, contents);
+    }
+
+    /// Publish a package under the given manifest, serving `contents` as its
+    /// archive even if the archive doesn't match the manifest.
+    pub fn publish_archive(&self, manifest: Manifest, contents: PackageContents) {
+        let mut storage = self.storage.contents.write().unwrap();
 
         let scope = storage
             .entry(manifest.package.name.scope().to_owned())
@@ -46,10 +56,52 @@ impl InMemoryRegistry {
         entries.push(PackageEntry { manifest, contents });
     }
 
+    /// Make the next `count` downloads of a package fail, like a flaky
+    /// registry would.
+    pub fn fail_downloads(&self, package_id: &PackageId, count: usize) {
+        let mut failures = self.storage.failures.lock().unwrap();
+        failures.insert(package_id.clone(), count);
+    }
+
+    /// Stop serving a package's archive while still listing it, like a
+    /// registry does once a version is yanked.
+    pub fn yank(&self, package_id: &PackageId) {
+        let mut yanked = self.storage.yanked.lock().unwrap();
+        yanked.insert(package_id.clone());
+    }
+
+    /// Make every download take at least `delay`, like a slow registry would.
+    pub fn delay_downloads(&self, delay: Duration) {
+        self.storage.downloads.lock().unwrap().delay = delay;
+    }
+
+    /// Make downloads of one package take at least `delay`, on top of any
+    /// delay for every download.
+    pub fn delay_download(&self, package_id: &PackageId, delay: Duration) {
+        let mut downloads = self.storage.downloads.lock().unwrap();
+        downloads.package_delays.insert(package_id.clone(), delay);
+    }
+
+    /// How many downloads were started, including ones that failed.
+    pub fn downloads_started(&self) -> usize {
+        self.storage.downloads.lock().unwrap().started
+    }
+
+    /// How many downloads are in progress right now.
+    pub fn downloads_in_progress(&self) -> usize {
+        self.storage.downloads.lock().unwrap().in_progress
+    }
+
+    /// The most downloads that were ever in progress at once.
+    pub fn most_concurrent_downloads(&self) -> usize {
+        self.storage.downloads.lock().unwrap().most_in_progress
+    }
+
     /// Returns a handle to an object that can be used as a `PackageSource`.
     pub fn source(&self) -> PackageSource {
         PackageSource::InMemory(InMemoryRegistrySource {
             storage: self.storage.clone(),
+            download_timeout: None,
         })
     }
 }
@@ -59,6 +111,7 @@ impl InMemoryRegistry {
 #[derive(Clone)]
 pub struct InMemoryRegistrySource {
     storage: Storage,
+    download_timeout: Option<Duration>,
 }
 
 impl PackageSourceProvider for InMemoryRegistrySource {
@@ -93,25 +146,152 @@ impl PackageSourceProvider for InMemoryRegistrySource {
     }
 
     fn download_package(&self, package_id: &PackageId) -> anyhow::Result<PackageContents> {
+        let mut data = Vec::new();
+        self.request(package_id)?.read_to_end(&mut data)?;
+
+        Ok(PackageContents::from_buffer(data))
+    }
+
+    fn stream_package(
+        &self,
+        package_id: &PackageId,
+    ) -> anyhow::Result<Option<Box<dyn Read + Send>>> {
+        Ok(Some(Box::new(self.request(package_id)?)))
+    }
+
+    fn with_download_timeout(&self, timeout: Duration) -> Self {
+        Self {
+            download_timeout: Some(timeout),
+            ..self.clone()
+        }
+    }
+
+    fn fallback_sources(&self) -> anyhow::Result<Vec<PackageSourceId>> {
+        todo!("Implement in-memory fallback sources");
+    }
+}
+
+impl InMemoryRegistrySource {
+    /// Start downloading a package, the way a request to a registry would.
+    /// Failures and missing packages are answered straight away, while the
+    /// body only arrives once any delay has passed, unless the download times
+    /// out first.
+    fn request(&self, package_id: &PackageId) -> anyhow::Result<Body> {
+        if let Some(failures) = self.storage.failures.lock().unwrap().get_mut(package_id) {
+            if *failures > 0 {
+                *failures -= 1;
+                bail!("Downloading {} failed", package_id);
+            }
+        }
+
+        let delay = {
+            let mut downloads = self.storage.downloads.lock().unwrap();
+            downloads.started += 1;
+            let package_delay = downloads.package_delays.get(package_id).copied();
+            downloads.delay + package_delay.unwrap_or_default()
+        };
+
+        let not_found = || PackageNotFound {
+            package_id: package_id.clone(),
+        };
+
+        if self.storage.yanked.lock().unwrap().contains(package_id) {
+            return Err(not_found().into());
+        }
+
         let storage = self.storage.contents.read().unwrap();
         let scope = storage
             .get(package_id.name().scope())
-            .ok_or_else(|| format_err!("Package {} does not exist", package_id))?;
+            .ok_or_else(not_found)?;
 
-        let manifests = scope
-            .get(package_id.name().name())
-            .ok_or_else(|| format_err!("Package {} does not exist", package_id))?;
+        let manifests = scope.get(package_id.name().name()).ok_or_else(not_found)?;
 
         let entry = manifests
             .iter()
             .find(|entry| &entry.manifest.package.version == package_id.version())
-            .ok_or_else(|| format_err!("Package {} does not exist", package_id))?;
+            .ok_or_else(not_found)?;
 
-        Ok(entry.contents.clone())
+        Ok(Body {
+            package_id: package_id.clone(),
+            data: Cursor::new(entry.contents.data().to_vec()),
+            delay,
+            timeout: self.download_timeout,
+            downloads: self.storage.downloads.clone(),
+            state: BodyState::Waiting,
+        })
     }
+}
 
-    fn fallback_sources(&self) -> anyhow::Result<Vec<PackageSourceId>> {
-        todo!("Implement in-memory fallback sources");
+/// The body of a download, which starts arriving after the download's delay
+/// on the first read. It counts as in progress from then until it's read to
+/// the end or dropped, so streamed downloads are in progress for as long as
+/// they're being unpacked.
+struct Body {
+    package_id: PackageId,
+    data: Cursor<Vec<u8>>,
+    delay: Duration,
+    timeout: Option<Duration>,
+    downloads: Arc<Mutex<Downloads>>,
+    state: BodyState,
+}
+
+#[derive(PartialEq, Eq)]
+enum BodyState {
+    Waiting,
+    Arriving,
+    Finished,
+}
+
+impl Body {
+    fn finish(&mut self) {
+        if self.state == BodyState::Arriving {
+            self.downloads.lock().unwrap().in_progress -= 1;
+        }
+
+        self.state = BodyState::Finished;
+    }
+}
+
+impl Read for Body {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.state == BodyState::Waiting {
+            {
+                let mut downloads = self.downloads.lock().unwrap();
+                downloads.in_progress += 1;
+                downloads.most_in_progress = downloads.most_in_progress.max(downloads.in_progress);
+            }
+
+            self.state = BodyState::Arriving;
+
+            match self.timeout {
+                Some(timeout) if timeout < self.delay => {
+                    thread::sleep(timeout);
+                    self.finish();
+
+                    return Err(io::Error::new(
+                        io::ErrorKind::TimedOut,
+                        format!(
+                            "Downloading {} timed out after {:?}",
+                            self.package_id, timeout
+                        ),
+                    ));
+                }
+                _ => thread::sleep(self.delay),
+            }
+        }
+
+        let read = self.data.read(buf)?;
+        if read == 0 && !buf.is_empty() {
+            self.finish();
+        }
+
+        Ok(read)
+    }
+}
+
+impl Drop for Body {
+    fn drop(&mut self) {
+        self.finish();
     }
 }
 
@@ -123,4 +303,16 @@ struct PackageEntry {
 #[derive(Clone, Default)]
 struct Storage {
     contents: Arc<RwLock<HashMap<String, HashMap<String, Vec<PackageEntry>>>>>,
+    failures: Arc<Mutex<HashMap<PackageId, usize>>>,
+    yanked: Arc<Mutex<HashSet<PackageId>>>,
+    downloads: Arc<Mutex<Downloads>>,
+}
+
+#[derive(Default)]
+struct Downloads {
+    delay: Duration,
+    package_delays: HashMap<PackageId, Duration>,
+    started: usize,
+    in_progress: usize,
+    most_in_progress: usize,
 }
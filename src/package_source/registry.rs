@@ -1,10 +1,18 @@
-use std::io::Read;
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
 use std::sync::Arc;
+use std::time::Duration;
 
-use anyhow::bail;
+use anyhow::{bail, Context};
 use once_cell::sync::OnceCell;
-use reqwest::{blocking::Client, header::AUTHORIZATION};
+use reqwest::{
+    blocking::{Client, RequestBuilder, Response},
+    header::{
+        ACCEPT, AUTHORIZATION, CONTENT_RANGE, CONTENT_TYPE, ETAG, IF_RANGE, LAST_MODIFIED, RANGE,
+    },
+    Proxy, StatusCode,
+};
 use url::Url;
+use zip::ZipArchive;
 
 use crate::auth::AuthStore;
 use crate::manifest::Manifest;
@@ -13,29 +21,194 @@ use crate::package_index::PackageIndex;
 use crate::package_req::PackageReq;
 use crate::package_source::PackageContents;
 
-use super::{PackageSourceId, PackageSourceProvider};
+use super::{InvalidArchive, PackageNotFound, PackageSourceId, PackageSourceProvider};
 
 const VERSION: &str = env!("CARGO_PKG_VERSION");
 
+/// The signatures that zip archives start with: a local file header, or the
+/// end of central directory record of an empty archive.
+const ZIP_SIGNATURES: &[&[u8]] = &[b"PK\x03\x04", b"PK\x05\x06"];
+
+/// How many times a download that was cut off partway is resumed before
+/// giving up on it.
+const MAX_RESUMES: u32 = 3;
+
+/// Content types of archive formats that registries might send, but that
+/// this client can't unpack. Reading tarballs would need an archive crate
+/// this client doesn't depend on, so they're out of scope for now. These are
+/// never sent in `Accept`, and are only recognized so that a registry sending
+/// one anyway gets a clearer error than a failed zip signature check.
+const UNSUPPORTED_ARCHIVE_TYPES: &[&str] = &[
+    "application/gzip",
+    "application/x-gzip",
+    "application/x-tar",
+    "application/x-gtar",
+];
+
+/// Every archive reader along with the content type it reads, in order of
+/// preference. Only these content types are sent in `Accept`.
+const ARCHIVE_READERS: &[(ArchiveReader, &str)] = &[(ArchiveReader::Zip, "application/zip")];
+
+/// How to read a downloaded package archive, picked from the content type the
+/// registry sent it with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ArchiveReader {
+    Zip,
+}
+
+impl ArchiveReader {
+    /// The value of the `Accept` header sent with downloads, so registries
+    /// that can serve several formats know which ones we read. Anything else
+    /// is still accepted at a low preference, since registries that don't
+    /// negotiate can send zip archives as whatever content type they like.
+    fn accept_header() -> String {
+        let mut content_types: Vec<_> = ARCHIVE_READERS
+            .iter()
+            .map(|(_, content_type)| *content_type)
+            .collect();
+        content_types.push("*/*;q=0.1");
+
+        content_types.join(", ")
+    }
+
+    /// Picks the reader for an archive sent with the given content type.
+    /// Registries that don't negotiate send zip archives as whatever content
+    /// type they like, so unrecognized types are read as zip archives, which
+    /// are checked by signature before they're used.
+    fn for_content_type(
+        package_id: &PackageId,
+        content_type: Option<&str>,
+    ) -> anyhow::Result<Self> {
+        // Drop parameters like `; charset=binary`.
+        let mime_type = content_type
+            .and_then(|content_type| content_type.split(';').next())
+            .map(|mime_type| mime_type.trim().to_ascii_lowercase());
+
+        let mime_type = match mime_type {
+            Some(mime_type) => mime_type,
+            None => return Ok(ArchiveReader::Zip),
+        };
+
+        if let Some((reader, _)) = ARCHIVE_READERS
+            .iter()
+            .find(|(_, content_type)| *content_type == mime_type)
+        {
+            return Ok(*reader);
+        }
+
+        if UNSUPPORTED_ARCHIVE_TYPES.contains(&mime_type.as_str()) {
+            bail!(
+                "registry sent {} as {}, which this version of wally can't unpack",
+                package_id,
+                mime_type
+            );
+        }
+
+        Ok(ArchiveReader::Zip)
+    }
+}
+
+/// Settings for talking to registries.
+#[derive(Debug, Clone, Default)]
+pub struct ClientConfig {
+    /// Route all registry requests, including fetches of its index, through
+    /// this proxy. When unset, the `HTTP_PROXY`, `HTTPS_PROXY` and `NO_PROXY`
+    /// environment variables are respected instead.
+    pub proxy: Option<Url>,
+
+    /// The most idle connections to keep open to each host. Installs
+    /// download many packages from the same registry at once, and every
+    /// connection returned to the pool can be reused by a later download.
+    pub pool_max_idle_per_host: Option<usize>,
+
+    /// How long an idle connection is kept in the pool before it's closed.
+    pub pool_idle_timeout: Option<Duration>,
+
+    /// Send TCP keep-alive probes on open connections at this interval.
+    pub tcp_keepalive: Option<Duration>,
+
+    /// Give up on a request that hasn't finished after this long.
+    pub timeout: Option<Duration>,
+
+    /// Give up on connecting to a registry after this long.
+    pub connect_timeout: Option<Duration>,
+
+    /// Reuse a registry's index without fetching it if it was fetched within
+    /// this long. When unset, the index is fetched every time it's opened.
+    pub index_ttl: Option<Duration>,
+
+    /// Fetch registry indexes even if they were fetched within `index_ttl`.
+    pub refresh_index: bool,
+}
+
+impl ClientConfig {
+    /// Build a client from this config. Clones of the client share its
+    /// connection pool, so a registry and every clone of it made during an
+    /// install reuse the same connections.
+    pub fn build_client(&self) -> anyhow::Result<Client> {
+        let mut builder = Client::builder();
+
+        if let Some(proxy) = &self.proxy {
+            builder = builder.proxy(Proxy::all(proxy.as_str())?);
+        }
+
+        if let Some(max_idle) = self.pool_max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(max_idle);
+        }
+
+        if let Some(idle_timeout) = self.pool_idle_timeout {
+            builder = builder.pool_idle_timeout(idle_timeout);
+        }
+
+        if let Some(keepalive) = self.tcp_keepalive {
+            builder = builder.tcp_keepalive(keepalive);
+        }
+
+        if let Some(timeout) = self.timeout {
+            builder = builder.timeout(timeout);
+        }
+
+        if let Some(connect_timeout) = self.connect_timeout {
+            builder = builder.connect_timeout(connect_timeout);
+        }
+
+        Ok(builder.build()?)
+    }
+}
+
 #[derive(Clone)]
 pub struct Registry {
     index_url: Url,
     auth_token: OnceCell<Option<Arc<str>>>,
     index: OnceCell<Arc<PackageIndex>>,
+    index_ttl: Option<Duration>,
+    refresh_index: bool,
+    proxy: Option<Url>,
     client: Client,
+    download_timeout: Option<Duration>,
 }
 
 impl Registry {
     /// Create a `Registry` from a registry spec, which usually comes from the
     /// `registry` field of a package manifest.
     pub fn from_registry_spec(spec: &str) -> anyhow::Result<Self> {
+        Self::with_client_config(spec, &ClientConfig::default())
+    }
+
+    /// Like `from_registry_spec`, but downloads packages using a client built
+    /// from the given `ClientConfig`.
+    pub fn with_client_config(spec: &str, config: &ClientConfig) -> anyhow::Result<Self> {
         let index_url = Url::parse(spec)?;
 
         Ok(Self {
             index_url,
             auth_token: OnceCell::new(),
             index: OnceCell::new(),
-            client: Client::new(),
+            index_ttl: config.index_ttl,
+            refresh_index: config.refresh_index,
+            proxy: config.proxy.clone(),
+            client: config.build_client()?,
+            download_timeout: None,
         })
     }
 
@@ -49,14 +222,314 @@ impl Registry {
     }
 
     fn index(&self) -> anyhow::Result<&Arc<PackageIndex>> {
-        self.index
-            .get_or_try_init(|| Ok(Arc::new(PackageIndex::new(&self.index_url, None)?)))
+        self.index.get_or_try_init(|| {
+            let (ttl, force_refresh) = match self.index_ttl {
+                Some(ttl) => (ttl, self.refresh_index),
+                None => (Duration::from_secs(0), true),
+            };
+
+            let index = PackageIndex::new_cached(
+                &self.index_url,
+                None,
+                ttl,
+                force_refresh,
+                self.proxy.as_ref(),
+            )?;
+
+            Ok(Arc::new(index))
+        })
     }
 
     fn api_url(&self) -> anyhow::Result<Url> {
         let config = self.index()?.config()?;
         Ok(config.api)
     }
+
+    /// Request the contents of a package, failing if the registry doesn't
+    /// answer successfully. Also returns the response's content type and the
+    /// reader for the archive it holds.
+    fn request_package(
+        &self,
+        package_id: &PackageId,
+    ) -> anyhow::Result<(Response, Option<String>, ArchiveReader)> {
+        let url = self.package_url(package_id)?;
+        let response = send_package_request(
+            &self.client,
+            url,
+            self.auth_token()?.as_deref(),
+            self.download_timeout,
+        )?;
+
+        let status = response.status();
+        if !status.is_success() {
+            let message = format!(
+                "Failed to download package {} from registry: {}\n{} {}",
+                package_id,
+                self.api_url()?,
+                status,
+                response.text()?
+            );
+
+            if status == StatusCode::NOT_FOUND {
+                let not_found = PackageNotFound {
+                    package_id: package_id.clone(),
+                };
+                return Err(anyhow::Error::new(not_found).context(message));
+            }
+
+            bail!(message);
+        }
+
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+        let reader = ArchiveReader::for_content_type(package_id, content_type.as_deref())?;
+
+        Ok((response, content_type, reader))
+    }
+
+    fn package_url(&self, package_id: &PackageId) -> anyhow::Result<Url> {
+        let path = format!(
+            "/v1/package-contents/{}/{}/{}",
+            package_id.name().scope(),
+            package_id.name().name(),
+            package_id.version()
+        );
+
+        Ok(self.api_url()?.join(&path)?)
+    }
+}
+
+/// Sends a request for a package archive to `url`, telling the registry which
+/// archive formats we can read. The request fails if it hasn't finished
+/// within `timeout`, on top of any timeout the client has.
+fn send_package_request(
+    client: &Client,
+    url: Url,
+    token: Option<&str>,
+    timeout: Option<Duration>,
+) -> anyhow::Result<Response> {
+    Ok(package_request(client, url, token, timeout).send()?)
+}
+
+/// Like `send_package_request`, but only asks for the archive from byte
+/// `offset` on. When `validator` is given, the registry is told to send the
+/// whole archive instead if it no longer matches.
+fn resume_package_request(
+    client: &Client,
+    url: Url,
+    token: Option<&str>,
+    timeout: Option<Duration>,
+    offset: u64,
+    validator: Option<&str>,
+) -> anyhow::Result<Response> {
+    let mut request =
+        package_request(client, url, token, timeout).header(RANGE, format!("bytes={}-", offset));
+
+    if let Some(validator) = validator {
+        request = request.header(IF_RANGE, validator);
+    }
+
+    Ok(request.send()?)
+}
+
+fn package_request(
+    client: &Client,
+    url: Url,
+    token: Option<&str>,
+    timeout: Option<Duration>,
+) -> RequestBuilder {
+    let mut request = client
+        .get(url)
+        .header("Wally-Version", VERSION)
+        .header(ACCEPT, ArchiveReader::accept_header());
+
+    if let Some(token) = token {
+        request = request.header(AUTHORIZATION, format!("Bearer {}", token));
+    }
+
+    if let Some(timeout) = timeout {
+        request = request.timeout(timeout);
+    }
+
+    request
+}
+
+/// Reads a package download into a temporary file. Whenever the connection
+/// drops partway, the download is resumed with a `Resumer`. Registries that
+/// don't support ranges send the whole archive again, which starts the
+/// download over.
+///
+/// Returns the downloaded data, and whether the download had to be resumed.
+fn read_resumable(
+    mut response: Response,
+    resume: impl Fn(u64, Option<&str>) -> anyhow::Result<Response>,
+) -> anyhow::Result<(Vec<u8>, bool)> {
+    let mut resumer = Resumer::new(&response, resume);
+    let mut partial = tempfile::tempfile()?;
+    let mut received = 0;
+    let mut buffer = [0; 8192];
+
+    loop {
+        let cut_off = loop {
+            match response.read(&mut buffer) {
+                Ok(0) => break None,
+                Ok(read) => {
+                    partial.write_all(&buffer[..read])?;
+                    received += read as u64;
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => break Some(err),
+            }
+        };
+
+        let err = match cut_off {
+            Some(err) => err,
+            None => break,
+        };
+
+        let (resumed, started_over) = resumer.resume(received, err)?;
+        response = resumed;
+
+        if started_over {
+            partial.set_len(0)?;
+            partial.seek(SeekFrom::Start(0))?;
+            received = 0;
+        }
+    }
+
+    let mut data = Vec::with_capacity(received as usize);
+    partial.seek(SeekFrom::Start(0))?;
+    partial.read_to_end(&mut data)?;
+
+    Ok((data, resumer.resumes > 0))
+}
+
+/// Picks up package downloads that were cut off partway, by asking `resume`
+/// for the rest of the archive from the last byte received. `resume` is
+/// given that byte's offset and the validator the registry sent for the
+/// archive, if any.
+struct Resumer<F> {
+    resume: F,
+    validator: Option<String>,
+    resumes: u32,
+}
+
+impl<F: Fn(u64, Option<&str>) -> anyhow::Result<Response>> Resumer<F> {
+    fn new(response: &Response, resume: F) -> Self {
+        let validator = response
+            .headers()
+            .get(ETAG)
+            .or_else(|| response.headers().get(LAST_MODIFIED))
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_owned);
+
+        Self {
+            resume,
+            validator,
+            resumes: 0,
+        }
+    }
+
+    /// Ask for the rest of a download that `err` cut off after `received`
+    /// bytes. Returns the response with the rest, and whether it starts over
+    /// from the first byte instead.
+    fn resume(&mut self, received: u64, err: io::Error) -> anyhow::Result<(Response, bool)> {
+        if self.resumes == MAX_RESUMES {
+            return Err(err).context(format!(
+                "Download was cut off after {} bytes, {} times",
+                received,
+                self.resumes + 1
+            ));
+        }
+
+        log::debug!(
+            "Download was cut off after {} bytes ({}), resuming",
+            received,
+            err
+        );
+        self.resumes += 1;
+        let response = (self.resume)(received, self.validator.as_deref())?;
+
+        match response.status() {
+            StatusCode::PARTIAL_CONTENT => {
+                let start = response
+                    .headers()
+                    .get(CONTENT_RANGE)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(|range| range.strip_prefix("bytes "))
+                    .and_then(|range| range.split('-').next())
+                    .and_then(|start| start.parse::<u64>().ok());
+
+                if start != Some(received) {
+                    bail!(
+                        "Registry resumed the download at the wrong place (asked for byte {})",
+                        received
+                    );
+                }
+
+                Ok((response, false))
+            }
+            status if status.is_success() => {
+                log::debug!("Registry sent the whole archive again, starting over");
+                Ok((response, true))
+            }
+            status => bail!("Failed to resume download: {}", status),
+        }
+    }
+}
+
+/// A package download that's unpacked as it arrives, resuming whenever the
+/// connection drops partway like `read_resumable`. What already arrived may
+/// have been unpacked, so the download can't start over, and fails instead
+/// when the registry sends the whole archive again. Pieces that don't line
+/// up are caught by the checksums of the files unpacked from them.
+struct ResumableStream<F> {
+    response: Response,
+    resumer: Resumer<F>,
+    received: u64,
+}
+
+impl<F: Fn(u64, Option<&str>) -> anyhow::Result<Response>> ResumableStream<F> {
+    fn new(response: Response, resume: F) -> Self {
+        Self {
+            resumer: Resumer::new(&response, resume),
+            response,
+            received: 0,
+        }
+    }
+
+    fn resume(&mut self, err: io::Error) -> anyhow::Result<()> {
+        let (response, started_over) = self.resumer.resume(self.received, err)?;
+        if started_over {
+            bail!(
+                "Registry sent the whole archive again instead of resuming it after {} bytes",
+                self.received
+            );
+        }
+
+        self.response = response;
+        Ok(())
+    }
+}
+
+impl<F: Fn(u64, Option<&str>) -> anyhow::Result<Response>> Read for ResumableStream<F> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.response.read(buf) {
+                Ok(read) => {
+                    self.received += read as u64;
+                    return Ok(read);
+                }
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => {}
+                Err(err) => self
+                    .resume(err)
+                    .map_err(|err| io::Error::new(io::ErrorKind::Other, format!("{:#}", err)))?,
+            }
+        }
+    }
 }
 
 impl PackageSourceProvider for Registry {
@@ -79,36 +552,74 @@ impl PackageSourceProvider for Registry {
     }
 
     fn download_package(&self, package_id: &PackageId) -> anyhow::Result<PackageContents> {
-        let path = format!(
-            "/v1/package-contents/{}/{}/{}",
-            package_id.name().scope(),
-            package_id.name().name(),
-            package_id.version()
-        );
+        let (response, content_type, reader) = self.request_package(package_id)?;
+
+        let url = self.package_url(package_id)?;
+        let token = self.auth_token()?;
+        let (data, resumed) = read_resumable(response, |offset, validator| {
+            resume_package_request(
+                &self.client,
+                url.clone(),
+                token.as_deref(),
+                self.download_timeout,
+                offset,
+                validator,
+            )
+        })?;
 
-        let url = self.api_url()?.join(&path)?;
+        match reader {
+            ArchiveReader::Zip => {
+                let contents = archive_contents(package_id, data, content_type.as_deref())?;
 
-        let mut request = self.client.get(url).header("Wally-Version", VERSION);
+                if resumed {
+                    verify_archive(package_id, &contents)?;
+                }
 
-        if let Some(token) = self.auth_token()? {
-            request = request.header(AUTHORIZATION, format!("Bearer {}", token));
+                Ok(contents)
+            }
         }
-        let mut response = request.send()?;
+    }
 
-        if !response.status().is_success() {
-            bail!(
-                "Failed to download package {} from registry: {}\n{} {}",
-                package_id,
-                self.api_url()?,
-                response.status(),
-                response.text()?
-            );
+    fn stream_package(
+        &self,
+        package_id: &PackageId,
+    ) -> anyhow::Result<Option<Box<dyn Read + Send>>> {
+        let (response, content_type, reader) = self.request_package(package_id)?;
+
+        // Only zip archives can be unpacked as they arrive.
+        if reader != ArchiveReader::Zip {
+            return Ok(None);
         }
 
-        let mut data = Vec::new();
-        response.read_to_end(&mut data)?;
+        let client = self.client.clone();
+        let url = self.package_url(package_id)?;
+        let token = self.auth_token()?;
+        let timeout = self.download_timeout;
+        let mut stream = ResumableStream::new(response, move |offset, validator| {
+            resume_package_request(
+                &client,
+                url.clone(),
+                token.as_deref(),
+                timeout,
+                offset,
+                validator,
+            )
+        });
 
-        Ok(PackageContents::from_buffer(data))
+        // Only the start of the body is read up front, to check that the
+        // rest is worth unpacking.
+        let mut signature = Vec::with_capacity(4);
+        stream.by_ref().take(4).read_to_end(&mut signature)?;
+        check_archive_signature(package_id, &signature, content_type.as_deref())?;
+
+        Ok(Some(Box::new(Cursor::new(signature).chain(stream))))
+    }
+
+    fn with_download_timeout(&self, timeout: Duration) -> Self {
+        Self {
+            download_timeout: Some(timeout),
+            ..self.clone()
+        }
     }
 
     fn fallback_sources(&self) -> anyhow::Result<Vec<PackageSourceId>> {
@@ -122,3 +633,448 @@ impl PackageSourceProvider for Registry {
         Ok(sources)
     }
 }
+
+/// Wraps downloaded package data, first checking that it's actually a zip
+/// archive. Misbehaving registries and proxies sometimes answer with an HTML
+/// error page or a truncated body, which would otherwise only fail later with
+/// a confusing zip error.
+fn archive_contents(
+    package_id: &PackageId,
+    data: Vec<u8>,
+    content_type: Option<&str>,
+) -> anyhow::Result<PackageContents> {
+    check_archive_signature(package_id, &data, content_type)?;
+
+    Ok(PackageContents::from_buffer(data))
+}
+
+/// Checks every file in a downloaded archive against the CRC-32 checksum it
+/// was packed with. Resumed downloads are stitched together from several
+/// responses, and this catches pieces that don't line up.
+fn verify_archive(package_id: &PackageId, contents: &PackageContents) -> anyhow::Result<()> {
+    read_entries(contents.data())
+        .with_context(|| format!("Resumed download of {} is corrupt", package_id))
+}
+
+/// Reads every file in a zip archive, which checks them against their CRC-32
+/// checksums.
+fn read_entries(data: &[u8]) -> anyhow::Result<()> {
+    let mut archive = ZipArchive::new(Cursor::new(data))?;
+
+    for index in 0..archive.len() {
+        io::copy(&mut archive.by_index(index)?, &mut io::sink())?;
+    }
+
+    Ok(())
+}
+
+/// Checks that downloaded data starting with `head` is a zip archive.
+fn check_archive_signature(
+    package_id: &PackageId,
+    head: &[u8],
+    content_type: Option<&str>,
+) -> anyhow::Result<()> {
+    let invalid = |message| InvalidArchive {
+        package_id: package_id.clone(),
+        message,
+    };
+
+    if head.is_empty() {
+        return Err(invalid(format!(
+            "registry returned empty response for {}",
+            package_id
+        ))
+        .into());
+    }
+
+    if !ZIP_SIGNATURES
+        .iter()
+        .any(|signature| head.starts_with(signature))
+    {
+        return Err(invalid(format!(
+            "registry returned non-archive data for {} (got {})",
+            package_id,
+            content_type.unwrap_or("no content type")
+        ))
+        .into());
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Write;
+    use std::net::TcpListener;
+    use std::thread;
+
+    use crate::installation::RealFilesystem;
+
+    /// Answers requests to a local server with `responses`, which are raw
+    /// HTTP written one per request in turn. Each connection is closed after
+    /// its response unless the response keeps it alive, and the server stops
+    /// once every response is written. Returns the server's address, and a
+    /// handle that gives the requests it received, lowercased, along with how
+    /// many connections they came over.
+    fn serve(responses: Vec<Vec<u8>>) -> (String, thread::JoinHandle<(Vec<String>, usize)>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        let server = thread::spawn(move || {
+            let mut responses = responses.into_iter().peekable();
+            let mut requests = Vec::new();
+            let mut connections = 0;
+
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                connections += 1;
+
+                while let Some(response) = responses.peek() {
+                    let mut request = Vec::new();
+                    let mut buffer = [0; 1];
+                    while !request.ends_with(b"\r\n\r\n") {
+                        match stream.read(&mut buffer) {
+                            Ok(0) | Err(_) => break,
+                            Ok(_) => request.push(buffer[0]),
+                        }
+                    }
+
+                    // The client closed the connection rather than sending
+                    // another request on it.
+                    if !request.ends_with(b"\r\n\r\n") {
+                        break;
+                    }
+
+                    requests.push(String::from_utf8(request).unwrap().to_ascii_lowercase());
+                    stream.write_all(response).unwrap();
+
+                    let keep_alive = String::from_utf8_lossy(response)
+                        .to_ascii_lowercase()
+                        .contains("\r\nconnection: keep-alive\r\n");
+                    responses.next();
+
+                    if !keep_alive {
+                        break;
+                    }
+                }
+
+                if responses.peek().is_none() {
+                    break;
+                }
+            }
+
+            (requests, connections)
+        });
+
+        (address, server)
+    }
+
+    #[test]
+    fn requests_go_through_proxy() {
+        let (address, proxy) = serve(vec![
+            b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\nok".to_vec(),
+        ]);
+
+        let config = ClientConfig {
+            proxy: Some(Url::parse(&format!("http://{}", address)).unwrap()),
+            ..ClientConfig::default()
+        };
+        let client = config.build_client().unwrap();
+        let response = client
+            .get("http://registry.invalid/v1/package-contents/biff/minimal/0.1.0")
+            .send()
+            .unwrap();
+
+        assert_eq!(response.text().unwrap(), "ok");
+
+        let (requests, _) = proxy.join().unwrap();
+        assert!(
+            requests[0].starts_with(
+                "get http://registry.invalid/v1/package-contents/biff/minimal/0.1.0 http/1.1"
+            ),
+            "{}",
+            requests[0]
+        );
+    }
+
+    #[test]
+    fn non_archive_download_rejected() {
+        let package_id: PackageId = "biff/minimal@0.1.0".parse().unwrap();
+        let html = b"<!DOCTYPE html><html><body>Bad Gateway</body></html>".to_vec();
+
+        let err = archive_contents(&package_id, html, Some("text/html")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "registry returned non-archive data for biff/minimal@0.1.0 (got text/html)"
+        );
+
+        let empty_archive = b"PK\x05\x06\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0\0".to_vec();
+        assert!(archive_contents(&package_id, empty_archive, None).is_ok());
+    }
+
+    #[test]
+    fn empty_download_rejected() {
+        let package_id: PackageId = "biff/minimal@0.1.0".parse().unwrap();
+
+        let err = archive_contents(&package_id, Vec::new(), Some("application/zip")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "registry returned empty response for biff/minimal@0.1.0"
+        );
+    }
+
+    #[test]
+    fn archive_format_negotiated() {
+        let (address, server) = serve(vec![
+            b"HTTP/1.1 200 OK\r\nContent-Type: application/zip\r\n\
+              Content-Length: 0\r\nConnection: close\r\n\r\n"
+                .to_vec(),
+        ]);
+        let url = format!("http://{}/v1/package-contents/biff/minimal/0.1.0", address);
+
+        let client = ClientConfig::default().build_client().unwrap();
+        let response =
+            send_package_request(&client, Url::parse(&url).unwrap(), None, None).unwrap();
+        let content_type = response.headers()[CONTENT_TYPE]
+            .to_str()
+            .unwrap()
+            .to_owned();
+
+        // Formats without a reader aren't asked for.
+        let (requests, _) = server.join().unwrap();
+        assert!(
+            requests[0].contains("\r\naccept: application/zip, */*;q=0.1\r\n"),
+            "{}",
+            requests[0]
+        );
+
+        let package_id: PackageId = "biff/minimal@0.1.0".parse().unwrap();
+        let reader =
+            |content_type: Option<&str>| ArchiveReader::for_content_type(&package_id, content_type);
+
+        assert_eq!(
+            reader(Some(content_type.as_str())).unwrap(),
+            ArchiveReader::Zip
+        );
+        assert_eq!(
+            reader(Some("application/octet-stream")).unwrap(),
+            ArchiveReader::Zip
+        );
+        assert_eq!(reader(None).unwrap(), ArchiveReader::Zip);
+        assert_eq!(
+            reader(Some("application/gzip; charset=binary"))
+                .unwrap_err()
+                .to_string(),
+            "registry sent biff/minimal@0.1.0 as application/gzip, \
+             which this version of wally can't unpack"
+        );
+    }
+
+    #[test]
+    fn connections_reused_between_downloads() {
+        let response = b"HTTP/1.1 200 OK\r\nContent-Type: application/zip\r\n\
+              Content-Length: 2\r\nConnection: keep-alive\r\n\r\nPK";
+        let (address, server) = serve(vec![response.to_vec(); 3]);
+        let url = format!("http://{}/v1/package-contents/biff/minimal/0.1.0", address);
+
+        let config = ClientConfig {
+            pool_max_idle_per_host: Some(4),
+            pool_idle_timeout: Some(Duration::from_secs(30)),
+            tcp_keepalive: Some(Duration::from_secs(30)),
+            timeout: Some(Duration::from_secs(10)),
+            connect_timeout: Some(Duration::from_secs(10)),
+            ..ClientConfig::default()
+        };
+        let client = config.build_client().unwrap();
+
+        for _ in 0..3 {
+            let response =
+                send_package_request(&client, Url::parse(&url).unwrap(), None, None).unwrap();
+            assert_eq!(response.bytes().unwrap().as_ref(), b"PK");
+        }
+
+        let (_, connections) = server.join().unwrap();
+        assert_eq!(connections, 1);
+    }
+
+    #[test]
+    fn stalled_download_times_out() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = Url::parse(&format!(
+            "http://{}/v1/package-contents/biff/minimal/0.1.0",
+            listener.local_addr().unwrap()
+        ))
+        .unwrap();
+
+        // Accepts the connection, then never answers on it. The stream is
+        // kept open until the request has given up.
+        let server = thread::spawn(move || listener.accept().unwrap());
+
+        let registry = Registry::from_registry_spec("https://registry.invalid/index")
+            .unwrap()
+            .with_download_timeout(Duration::from_millis(200));
+        let err = send_package_request(&registry.client, url, None, registry.download_timeout)
+            .unwrap_err();
+
+        let timed_out = err.chain().any(|cause| {
+            cause
+                .downcast_ref::<reqwest::Error>()
+                .map_or(false, reqwest::Error::is_timeout)
+        });
+        assert!(timed_out, "{:#}", err);
+
+        drop(server.join().unwrap());
+    }
+
+    /// A zip archive holding one file that's stored rather than compressed,
+    /// so that any byte of it can be cut off at.
+    fn stored_archive() -> Vec<u8> {
+        let mut archive = Vec::new();
+        let mut writer = zip::ZipWriter::new(Cursor::new(&mut archive));
+        let options =
+            zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored);
+        writer.start_file("init.luau", options).unwrap();
+        writer.write_all(&[b'-'; 4096]).unwrap();
+        writer.finish().unwrap();
+        drop(writer);
+
+        archive
+    }
+
+    /// Serves `served` to the first request, but drops the connection after
+    /// half of it. The second request gets the other half when
+    /// `supports_ranges` is set, and the whole archive again otherwise.
+    /// Returns the URL to request, the offset the connection drops at, and a
+    /// handle that gives the requests that were made.
+    fn interrupted_server(
+        served: Vec<u8>,
+        supports_ranges: bool,
+    ) -> (Url, usize, thread::JoinHandle<(Vec<String>, usize)>) {
+        let half = served.len() / 2;
+        let whole_head = format!(
+            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nETag: \"v1\"\r\n\r\n",
+            served.len()
+        );
+
+        let mut cut_off = whole_head.clone().into_bytes();
+        cut_off.extend_from_slice(&served[..half]);
+
+        let rest = if supports_ranges {
+            let mut rest = format!(
+                "HTTP/1.1 206 Partial Content\r\nContent-Length: {}\r\n\
+                 Content-Range: bytes {}-{}/{}\r\n\r\n",
+                served.len() - half,
+                half,
+                served.len() - 1,
+                served.len()
+            )
+            .into_bytes();
+            rest.extend_from_slice(&served[half..]);
+            rest
+        } else {
+            let mut rest = whole_head.into_bytes();
+            rest.extend_from_slice(&served);
+            rest
+        };
+
+        let (address, server) = serve(vec![cut_off, rest]);
+        let url = Url::parse(&format!(
+            "http://{}/v1/package-contents/biff/minimal/0.1.0",
+            address
+        ))
+        .unwrap();
+
+        (url, half, server)
+    }
+
+    #[test]
+    fn interrupted_download_resumed() {
+        let archive = stored_archive();
+        let (url, half, server) = interrupted_server(archive.clone(), true);
+
+        let client = ClientConfig::default().build_client().unwrap();
+        let response = send_package_request(&client, url.clone(), None, None).unwrap();
+        let (data, resumed) = read_resumable(response, |offset, validator| {
+            resume_package_request(&client, url.clone(), None, None, offset, validator)
+        })
+        .unwrap();
+
+        let (requests, _) = server.join().unwrap();
+        assert!(resumed);
+        assert_eq!(data, archive);
+        assert!(
+            requests[1].contains(&format!("\r\nrange: bytes={}-\r\n", half)),
+            "{}",
+            requests[1]
+        );
+        assert!(
+            requests[1].contains("\r\nif-range: \"v1\"\r\n"),
+            "{}",
+            requests[1]
+        );
+
+        let package_id: PackageId = "biff/minimal@0.1.0".parse().unwrap();
+        verify_archive(&package_id, &PackageContents::from_buffer(data.clone())).unwrap();
+
+        let mut corrupt = data;
+        corrupt[half] ^= 0xff;
+        let err = verify_archive(&package_id, &PackageContents::from_buffer(corrupt)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Resumed download of biff/minimal@0.1.0 is corrupt"
+        );
+    }
+
+    #[test]
+    fn interrupted_stream_resumed() {
+        let archive = stored_archive();
+        let (url, half, server) = interrupted_server(archive, true);
+
+        let client = ClientConfig::default().build_client().unwrap();
+        let response = send_package_request(&client, url.clone(), None, None).unwrap();
+        let stream = ResumableStream::new(response, |offset, validator| {
+            resume_package_request(&client, url.clone(), None, None, offset, validator)
+        });
+
+        let dir = tempfile::tempdir().unwrap();
+        PackageContents::unpack_stream(stream, dir.path(), &RealFilesystem).unwrap();
+
+        let (requests, _) = server.join().unwrap();
+        assert!(
+            requests[1].contains(&format!("\r\nrange: bytes={}-\r\n", half)),
+            "{}",
+            requests[1]
+        );
+
+        let unpacked = fs_err::read(dir.path().join("init.luau")).unwrap();
+        assert_eq!(unpacked, vec![b'-'; 4096]);
+    }
+
+    #[test]
+    fn interrupted_stream_not_started_over() {
+        let archive = stored_archive();
+        let (url, half, server) = interrupted_server(archive, false);
+
+        let client = ClientConfig::default().build_client().unwrap();
+        let response = send_package_request(&client, url.clone(), None, None).unwrap();
+        let stream = ResumableStream::new(response, |offset, validator| {
+            resume_package_request(&client, url.clone(), None, None, offset, validator)
+        });
+
+        let dir = tempfile::tempdir().unwrap();
+        let err = PackageContents::unpack_stream(stream, dir.path(), &RealFilesystem).unwrap_err();
+
+        server.join().unwrap();
+        let message = format!("{:#}", err);
+        assert!(
+            message.contains(&format!(
+                "Registry sent the whole archive again instead of resuming it after {} bytes",
+                half
+            )),
+            "{}",
+            message
+        );
+    }
+}
@@ -1,10 +1,11 @@
 use std::cmp::Ordering;
 use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::io::{Read, Write};
 
 use anyhow::bail;
 use anyhow::format_err;
 use semver::Version;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
 
 use crate::manifest::{Manifest, Realm};
 use crate::package_id::PackageId;
@@ -15,7 +16,7 @@ use crate::package_source::{PackageSourceId, PackageSourceMap, PackageSourceProv
 ///
 /// State here is stored in multiple maps, all keyed by PackageId, to facilitate
 /// concurrent mutable access to unrelated information about different packages.
-#[derive(Debug, Default, Serialize, Clone)]
+#[derive(Debug, Default, Serialize, Deserialize, Clone)]
 pub struct Resolve {
     /// Set of all packages that have been chosen to be part of the package
     /// graph.
@@ -33,9 +34,31 @@ pub struct Resolve {
 
     /// Graph of all dependencies originating from the "dev" dependency realm.
     pub dev_dependencies: BTreeMap<PackageId, BTreeMap<String, PackageId>>,
+
+    /// The features declared by each package that declares any. Optional
+    /// dependencies are resolved like any other, and only left out at install.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub features: BTreeMap<PackageId, BTreeMap<String, Vec<String>>>,
+
+    /// The peer dependencies declared by each package that declares any.
+    /// They're never resolved, only checked against what was.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub peer_dependencies: BTreeMap<PackageId, BTreeMap<String, PackageReq>>,
 }
 
 impl Resolve {
+    /// Read a resolve serialized as JSON by `to_writer`, which lets a
+    /// resolve produced by another tool be installed.
+    pub fn from_reader(reader: impl Read) -> anyhow::Result<Self> {
+        Ok(serde_json::from_reader(reader)?)
+    }
+
+    /// Write this resolve as JSON, in the form `from_reader` reads.
+    pub fn to_writer(&self, writer: impl Write) -> anyhow::Result<()> {
+        serde_json::to_writer_pretty(writer, self)?;
+        Ok(())
+    }
+
     fn activate(&mut self, source: PackageId, dep_name: String, dep: PackageId) {
         self.activated.insert(dep.clone());
 
@@ -48,7 +71,7 @@ impl Resolve {
 /// Origin realm is the "most restrictive" realm the package can still be dependended
 /// upon. It is where the package gets placed during install.
 /// See [ origin_realm clarification ]. In the resolve function for more info.
-#[derive(Debug, Serialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct ResolvePackageMetadata {
     pub realm: Realm,
     pub origin_realm: Realm,
@@ -74,6 +97,19 @@ pub fn resolve(
         },
     );
 
+    if !root_manifest.features.is_empty() {
+        resolve
+            .features
+            .insert(root_manifest.package_id(), root_manifest.features.clone());
+    }
+
+    if !root_manifest.peer_dependencies.is_empty() {
+        resolve.peer_dependencies.insert(
+            root_manifest.package_id(),
+            root_manifest.peer_dependencies.clone(),
+        );
+    }
+
     // Queue of all dependency requests that need to be resolved.
     let mut packages_to_visit = VecDeque::new();
 
@@ -215,6 +251,18 @@ pub fn resolve(
                 },
             );
 
+            if !candidate.features.is_empty() {
+                resolve
+                    .features
+                    .insert(candidate_id.clone(), candidate.features.clone());
+            }
+
+            if !candidate.peer_dependencies.is_empty() {
+                resolve
+                    .peer_dependencies
+                    .insert(candidate_id.clone(), candidate.peer_dependencies.clone());
+            }
+
             for (alias, req) in &candidate.dependencies {
                 packages_to_visit.push_back(DependencyRequest {
                     request_source: candidate_id.clone(),
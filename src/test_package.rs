@@ -39,11 +39,17 @@ impl PackageBuilder {
                 include: Vec::new(),
                 exclude: Vec::new(),
                 private: false,
+                modules: Vec::new(),
+                platform_dir: None,
+                luau: None,
             },
             place: Default::default(),
             dependencies: Default::default(),
             server_dependencies: Default::default(),
             dev_dependencies: Default::default(),
+            peer_dependencies: Default::default(),
+            features: Default::default(),
+            git_sources: Default::default(),
         };
 
         Self {
@@ -79,6 +85,48 @@ impl PackageBuilder {
         self
     }
 
+    pub fn with_peer_dep<A, R>(mut self, alias: A, package_req: R) -> Self
+    where
+        A: Into<String>,
+        R: AsRef<str>,
+    {
+        let req: PackageReq = package_req.as_ref().parse().expect("invalid PackageReq");
+
+        self.manifest.peer_dependencies.insert(alias.into(), req);
+        self
+    }
+
+    pub fn with_feature<S>(mut self, feature: S, aliases: &[&str]) -> Self
+    where
+        S: Into<String>,
+    {
+        let aliases = aliases.iter().map(|alias| (*alias).to_owned()).collect();
+
+        self.manifest.features.insert(feature.into(), aliases);
+        self
+    }
+
+    pub fn with_module<S>(mut self, module: S) -> Self
+    where
+        S: Into<String>,
+    {
+        self.manifest.package.modules.push(module.into());
+        self
+    }
+
+    pub fn with_platform_dir<S>(mut self, platform_dir: S) -> Self
+    where
+        S: Into<String>,
+    {
+        self.manifest.package.platform_dir = Some(platform_dir.into());
+        self
+    }
+
+    pub fn with_luau(mut self, luau: &str) -> Self {
+        self.manifest.package.luau = Some(luau.parse().expect("invalid LuauVersion"));
+        self
+    }
+
     pub fn with_file<P, C>(mut self, path: P, contents: C) -> Self
     where
         P: Into<String>,
@@ -42,24 +42,45 @@ fn cross_realm_explicit_dependency() {
     run_test("cross-realm-explicit-dependency");
 }
 
+#[test]
+fn second_install_leaves_packages_alone() {
+    let source_project =
+        Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/test-projects",)).join("one-dependency");
+
+    let project = TempProject::new(&source_project).unwrap();
+    install(project.path());
+
+    // Nothing changed, so the package destination isn't cleaned and
+    // reinstalled.
+    let marker = project.path().join("packages/marker.txt");
+    fs_err::write(&marker, "").unwrap();
+    install(project.path());
+
+    assert!(marker.is_file());
+}
+
 fn run_test(name: &str) -> TempProject {
     let source_project =
         Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/test-projects",)).join(name);
 
     let project = TempProject::new(&source_project).unwrap();
+    install(project.path());
 
+    assert_dir_snapshot!(project.path());
+    project
+}
+
+fn install(project_path: &Path) {
     let args = Args {
         global: GlobalOptions {
             test_registry: true,
             ..Default::default()
         },
         subcommand: Subcommand::Install(InstallSubcommand {
-            project_path: project.path().to_owned(),
+            project_path: project_path.to_owned(),
+            options: Default::default(),
         }),
     };
 
     args.run().unwrap();
-
-    assert_dir_snapshot!(project.path());
-    project
 }
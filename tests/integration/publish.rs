@@ -49,7 +49,9 @@ fn check_mismatched_names() {
     let contents = PackageContents::pack_from_path(&test_projects.join("mismatched-name")).unwrap();
 
     let unpacked_contents = tempdir().unwrap();
-    contents.unpack_into_path(unpacked_contents.path()).unwrap();
+    contents
+        .unpack_into_path(unpacked_contents.path(), None)
+        .unwrap();
 
     let project_json_path = unpacked_contents.path().join("default.project.json");
 
@@ -22,7 +22,13 @@ pub fn read_path(path: &Path) -> anyhow::Result<Entry> {
     let meta = fs_err::metadata(path)?;
 
     if meta.is_dir() {
+        // The install state holds a hash that changes along with the crate
+        // version, so it's left out of snapshots.
         let children = fs_err::read_dir(path)?
+            .filter(|dir_entry| match dir_entry {
+                Ok(dir_entry) => dir_entry.file_name() != ".wally-install-state",
+                Err(_) => true,
+            })
             .map(|dir_entry| {
                 let path = dir_entry?.path();
                 let name = path.file_name().unwrap().to_str().unwrap().to_owned();
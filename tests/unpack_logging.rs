@@ -0,0 +1,67 @@
+//! Checks what unpacking logs. This needs its own logger, which would see
+//! every other test's messages too, so it's kept apart from the rest of the
+//! tests.
+
+use std::io::{Cursor, Write};
+use std::sync::Mutex;
+
+use libwally::package_contents::PackageContents;
+use zip::{write::FileOptions, ZipWriter};
+
+/// Keeps every message logged, so that the test can check what it logged.
+struct CapturingLogger {
+    messages: Mutex<Vec<String>>,
+}
+
+impl log::Log for CapturingLogger {
+    fn enabled(&self, _metadata: &log::Metadata) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record) {
+        let message = format!("{}", record.args());
+        self.messages.lock().unwrap().push(message);
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: CapturingLogger = CapturingLogger {
+    messages: Mutex::new(Vec::new()),
+};
+
+#[test]
+fn unpacked_files_traced() {
+    log::set_logger(&LOGGER).unwrap();
+    log::set_max_level(log::LevelFilter::Trace);
+
+    let mut data = Vec::new();
+    let mut archive = ZipWriter::new(Cursor::new(&mut data));
+    archive
+        .add_directory("src/", FileOptions::default())
+        .unwrap();
+    archive
+        .start_file("src/init.luau", FileOptions::default())
+        .unwrap();
+    archive.write_all(b"return {}").unwrap();
+    archive
+        .start_file("wally.toml", FileOptions::default())
+        .unwrap();
+    archive.finish().unwrap();
+    drop(archive);
+
+    let dir = tempfile::tempdir().unwrap();
+    PackageContents::from_buffer(data)
+        .unpack_into_path(dir.path(), None)
+        .unwrap();
+
+    let messages = LOGGER.messages.lock().unwrap();
+    for (name, size) in &[("src/init.luau", 9), ("wally.toml", 0)] {
+        let expected = format!(
+            "Unpacking {} ({} bytes)",
+            dir.path().join(name).display(),
+            size
+        );
+        assert!(messages.contains(&expected), "{:?}", expected);
+    }
+}
@@ -0,0 +1,94 @@
+//! Measures how much memory unpacking takes. This needs its own global
+//! allocator, so it's kept apart from the rest of the tests.
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::cell::Cell;
+use std::io::{Cursor, Write};
+
+use libwally::installation::RealFilesystem;
+use libwally::package_contents::PackageContents;
+use zip::{write::FileOptions, ZipWriter};
+
+/// Tracks how many bytes each thread has allocated, so that a test can
+/// measure its own peak memory use while other tests run.
+struct TrackingAllocator;
+
+thread_local! {
+    static ALLOCATED: Cell<usize> = const { Cell::new(0) };
+    static PEAK_ALLOCATED: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for TrackingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let _ = ALLOCATED.try_with(|allocated| {
+            let total = allocated.get() + layout.size();
+            allocated.set(total);
+
+            let _ = PEAK_ALLOCATED.try_with(|peak| peak.set(peak.get().max(total)));
+        });
+
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        // Memory freed by a different thread than allocated it is
+        // miscounted, which saturating keeps from wrapping around.
+        let _ = ALLOCATED
+            .try_with(|allocated| allocated.set(allocated.get().saturating_sub(layout.size())));
+
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: TrackingAllocator = TrackingAllocator;
+
+#[test]
+fn unpack_stream_memory_bounded() {
+    const FILE_SIZE: usize = 1 << 20;
+    const FILE_COUNT: usize = 16;
+
+    // Noise doesn't compress, so the archive is about as large as the
+    // files in it.
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    let mut noise = || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state as u8
+    };
+
+    let mut data = Vec::new();
+    let mut archive = ZipWriter::new(Cursor::new(&mut data));
+
+    for index in 0..FILE_COUNT {
+        let contents: Vec<u8> = (0..FILE_SIZE).map(|_| noise()).collect();
+
+        archive
+            .start_file(format!("src/{}.luau", index), FileOptions::default())
+            .unwrap();
+        archive.write_all(&contents).unwrap();
+    }
+
+    archive.finish().unwrap();
+    drop(archive);
+
+    assert!(data.len() > FILE_COUNT * FILE_SIZE / 2);
+
+    let dir = tempfile::tempdir().unwrap();
+    let baseline = ALLOCATED.with(Cell::get);
+    PEAK_ALLOCATED.with(|peak| peak.set(baseline));
+
+    PackageContents::unpack_stream(data.as_slice(), dir.path(), &RealFilesystem).unwrap();
+
+    let peak = PEAK_ALLOCATED.with(Cell::get) - baseline;
+    assert!(
+        peak < 4 * FILE_SIZE,
+        "unpacking a {} byte archive peaked at {} bytes",
+        data.len(),
+        peak
+    );
+
+    let unpacked = fs_err::read(dir.path().join("src/15.luau")).unwrap();
+    assert_eq!(unpacked.len(), FILE_SIZE);
+}